@@ -0,0 +1,215 @@
+use std::borrow::Cow;
+use std::env;
+use std::net::IpAddr;
+
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use sha2::Digest;
+
+use crate::body::{KeyValueMap, Line};
+use crate::error::AnonymizeError;
+use crate::processor::LineProcessor;
+
+// candidates only, matches are kept unless they parse as an address
+static IP_CANDIDATE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\b(?:\d{1,3}\.){3}\d{1,3}\b|[0-9A-Fa-f]*:[0-9A-Fa-f]*:[0-9A-Fa-f:.]*")
+        .expect("valid ip pattern")
+});
+
+/// The digest used to hash values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha256,
+    Sha512,
+}
+
+/// A field of a line replaced with its hash
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HashField {
+    App,
+    Env,
+    File,
+    Host,
+    /// The value of a label
+    Label(String),
+    /// The value of an annotation
+    Annotation(String),
+    /// Every IPv4 and IPv6 address in the line content
+    LineIps,
+}
+
+/// Where the salt prepended to every value comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Salt {
+    Static(Vec<u8>),
+    /// Read from an environment variable when the [`Anonymizer`] is constructed
+    Env(String),
+    /// Random for each [`Anonymizer`], hashes can only be correlated within the process
+    Random,
+}
+
+/// Replaces configured fields of log lines with a salted hash, in lowercase hex
+///
+/// Equal values hash to equal strings for the same salt, so lines can still be correlated
+/// without storing the raw identifiers.
+///
+/// # Example
+///
+/// ```rust
+/// # use logdna_client::anonymize::{Anonymizer, HashAlgorithm, HashField, Salt};
+/// # use logdna_client::body::Line;
+/// let mut anonymizer = Anonymizer::new(HashAlgorithm::Sha256, Salt::Static(b"pepper".to_vec()))
+///     .expect("Anonymizer::new()");
+/// anonymizer.field(HashField::Host).field(HashField::LineIps);
+///
+/// let mut line = Line::builder()
+///     .line("connection from 10.0.0.1")
+///     .host("node-us-0001")
+///     .build()
+///     .unwrap();
+/// anonymizer.anonymize(&mut line);
+/// assert!(!line.line.contains("10.0.0.1"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Anonymizer {
+    algorithm: HashAlgorithm,
+    salt: Vec<u8>,
+    fields: Vec<HashField>,
+}
+
+impl Anonymizer {
+    /// Constructs an Anonymizer without any fields, returning an error if the salt can't be read
+    pub fn new(algorithm: HashAlgorithm, salt: Salt) -> Result<Self, AnonymizeError> {
+        let salt = match salt {
+            Salt::Static(salt) => salt,
+            Salt::Env(var) => match env::var(&var) {
+                Ok(salt) if !salt.is_empty() => salt.into_bytes(),
+                _ => return Err(AnonymizeError::MissingSalt(var)),
+            },
+            Salt::Random => rand::random::<[u8; 32]>().to_vec(),
+        };
+        Ok(Self {
+            algorithm,
+            salt,
+            fields: Vec::new(),
+        })
+    }
+    /// Adds a field to hash
+    pub fn field(&mut self, field: HashField) -> &mut Self {
+        self.fields.push(field);
+        self
+    }
+    /// Returns true if no fields are hashed
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+    /// The salted hash of a value
+    pub fn hash(&self, value: &str) -> String {
+        match self.algorithm {
+            HashAlgorithm::Sha256 => {
+                let mut hasher = sha2::Sha256::new();
+                hasher.update(&self.salt);
+                hasher.update(value.as_bytes());
+                format!("{:x}", hasher.finalize())
+            }
+            HashAlgorithm::Sha512 => {
+                let mut hasher = sha2::Sha512::new();
+                hasher.update(&self.salt);
+                hasher.update(value.as_bytes());
+                format!("{:x}", hasher.finalize())
+            }
+        }
+    }
+    /// Hashes the configured fields of a line in place
+    pub fn anonymize(&self, line: &mut Line) {
+        for field in self.fields.iter() {
+            match field {
+                HashField::App => self.hash_field(&mut line.app),
+                HashField::Env => self.hash_field(&mut line.env),
+                HashField::File => self.hash_field(&mut line.file),
+                HashField::Host => self.hash_field(&mut line.host),
+                HashField::Label(key) => self.hash_entry(&mut line.labels, key),
+                HashField::Annotation(key) => self.hash_entry(&mut line.annotations, key),
+                HashField::LineIps => {
+                    let hashed = IP_CANDIDATE.replace_all(&line.line, |caps: &Captures| {
+                        let candidate = &caps[0];
+                        match candidate.parse::<IpAddr>() {
+                            Ok(_) => self.hash(candidate),
+                            Err(_) => candidate.to_string(),
+                        }
+                    });
+                    if let Cow::Owned(hashed) = hashed {
+                        line.line = hashed;
+                    }
+                }
+            }
+        }
+    }
+
+    fn hash_field(&self, value: &mut Option<String>) {
+        if let Some(hashed) = value.as_deref().map(|value| self.hash(value)) {
+            *value = Some(hashed);
+        }
+    }
+
+    fn hash_entry(&self, map: &mut Option<KeyValueMap>, key: &str) {
+        if let Some(value) = map.as_mut().and_then(|map| map.get_mut(key)) {
+            *value = self.hash(value);
+        }
+    }
+}
+
+impl LineProcessor for Anonymizer {
+    fn process(&self, mut line: Line) -> Option<Line> {
+        self.anonymize(&mut line);
+        Some(line)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hashes_configured_fields() {
+        let mut anonymizer =
+            Anonymizer::new(HashAlgorithm::Sha256, Salt::Static(b"salt".to_vec())).unwrap();
+        anonymizer
+            .field(HashField::Host)
+            .field(HashField::Label("user".into()))
+            .field(HashField::LineIps);
+
+        let mut line = Line::builder()
+            .line("from 192.168.0.1 and ::1 at 12:30:45, not 999.1.1.1")
+            .host("node-1")
+            .app("app")
+            .labels(KeyValueMap::new().add("user", "jane").add("team", "ops"))
+            .build()
+            .unwrap();
+        anonymizer.anonymize(&mut line);
+
+        let hash = |value: &str| anonymizer.hash(value);
+        assert_eq!(hash("node-1").len(), 64);
+        assert_ne!(hash("node-1"), hash("node-2"));
+        assert_eq!(line.host.as_deref(), Some(hash("node-1").as_str()));
+        assert_eq!(line.app.as_deref(), Some("app"));
+        let labels = line.labels.unwrap();
+        assert_eq!(labels["user"], hash("jane"));
+        assert_eq!(labels["team"], "ops");
+        assert_eq!(
+            line.line,
+            format!(
+                "from {} and {} at 12:30:45, not 999.1.1.1",
+                hash("192.168.0.1"),
+                hash("::1")
+            )
+        );
+
+        let other = Anonymizer::new(HashAlgorithm::Sha256, Salt::Random).unwrap();
+        assert_ne!(other.hash("node-1"), hash("node-1"));
+        assert!(matches!(
+            Anonymizer::new(HashAlgorithm::Sha512, Salt::Env("LOGDNA_UNSET_SALT".into())),
+            Err(AnonymizeError::MissingSalt(_))
+        ));
+    }
+}
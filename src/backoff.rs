@@ -0,0 +1,159 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+pub use ::backoff::backoff::Backoff;
+
+/// Exponentially growing delays, optionally randomized around the current interval
+///
+/// Every delay is `initial * multiplier^n` capped at `max`, with a randomization factor of
+/// `0.5` a 1s interval yields a delay between 0.5s and 1.5s.
+///
+/// # Example
+///
+/// ```rust
+/// # use std::time::Duration;
+/// # use logdna_client::backoff::{Backoff, Exponential};
+/// let mut backoff = Exponential::new(Duration::from_millis(100), Duration::from_secs(1));
+/// assert_eq!(backoff.next_backoff(), Some(Duration::from_millis(100)));
+/// assert_eq!(backoff.next_backoff(), Some(Duration::from_millis(200)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Exponential {
+    initial: Duration,
+    max: Duration,
+    multiplier: f64,
+    randomization_factor: f64,
+    current: Duration,
+}
+
+impl Exponential {
+    /// Constructs an Exponential backoff doubling from `initial` up to `max` without jitter
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            multiplier: 2.0,
+            randomization_factor: 0.0,
+            current: initial,
+        }
+    }
+    /// Set the factor the interval grows by after every delay
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+    /// Set how far a delay may deviate from the current interval, clamped to `0.0..=1.0`
+    pub fn randomization_factor(mut self, randomization_factor: f64) -> Self {
+        self.randomization_factor = randomization_factor.max(0.0).min(1.0);
+        self
+    }
+}
+
+impl Backoff for Exponential {
+    fn reset(&mut self) {
+        self.current = self.initial;
+    }
+
+    fn next_backoff(&mut self) -> Option<Duration> {
+        let interval = self.current;
+        self.current = self.max.min(interval.mul_f64(self.multiplier.max(1.0)));
+        if self.randomization_factor == 0.0 {
+            return Some(interval);
+        }
+        let delta = interval.as_secs_f64() * self.randomization_factor;
+        let secs = rand::thread_rng()
+            .gen_range(interval.as_secs_f64() - delta..=interval.as_secs_f64() + delta);
+        Some(Duration::from_secs_f64(secs))
+    }
+}
+
+/// Randomized delays growing from the previous delay, "decorrelated jitter"
+///
+/// Every delay is picked between `base` and three times the previous delay, capped at `max`.
+/// Spreads out clients that failed at the same time better than a randomized exponential
+/// backoff.
+#[derive(Debug, Clone)]
+pub struct DecorrelatedJitter {
+    base: Duration,
+    max: Duration,
+    previous: Duration,
+}
+
+impl DecorrelatedJitter {
+    /// Constructs a DecorrelatedJitter backoff starting at `base` and capped at `max`
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self {
+            base,
+            max,
+            previous: base,
+        }
+    }
+}
+
+impl Backoff for DecorrelatedJitter {
+    fn reset(&mut self) {
+        self.previous = self.base;
+    }
+
+    fn next_backoff(&mut self) -> Option<Duration> {
+        let upper = self.previous.mul_f64(3.0).max(self.base);
+        let secs = rand::thread_rng().gen_range(self.base.as_secs_f64()..=upper.as_secs_f64());
+        self.previous = self.max.min(Duration::from_secs_f64(secs));
+        Some(self.previous)
+    }
+}
+
+/// The same delay every time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fixed(pub Duration);
+
+impl Backoff for Fixed {
+    fn next_backoff(&mut self) -> Option<Duration> {
+        Some(self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn exponential_caps_at_max() {
+        let mut backoff = Exponential::new(Duration::from_secs(1), Duration::from_secs(5));
+        let delays: Vec<_> = (0..5)
+            .map(|_| backoff.next_backoff().unwrap().as_secs())
+            .collect();
+        assert_eq!(delays, vec![1, 2, 4, 5, 5]);
+
+        backoff.reset();
+        assert_eq!(backoff.next_backoff(), Some(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn exponential_randomizes_around_interval() {
+        let mut backoff = Exponential::new(Duration::from_secs(1), Duration::from_secs(1))
+            .randomization_factor(0.5);
+        for _ in 0..100 {
+            let delay = backoff.next_backoff().unwrap();
+            assert!(delay >= Duration::from_millis(500) && delay <= Duration::from_millis(1500));
+        }
+    }
+
+    #[test]
+    fn decorrelated_jitter_stays_within_bounds() {
+        let mut backoff =
+            DecorrelatedJitter::new(Duration::from_millis(100), Duration::from_secs(2));
+        for _ in 0..100 {
+            let delay = backoff.next_backoff().unwrap();
+            assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_secs(2));
+        }
+    }
+
+    #[test]
+    fn fixed_repeats() {
+        let mut backoff = Fixed(Duration::from_secs(3));
+        assert_eq!(backoff.next_backoff(), Some(Duration::from_secs(3)));
+        assert_eq!(backoff.next_backoff(), Some(Duration::from_secs(3)));
+    }
+}
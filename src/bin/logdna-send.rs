@@ -0,0 +1,196 @@
+//! Ships lines read from stdin or a file to LogDNA
+//!
+//! ```text
+//! echo "hello" | logdna-send --key <ingestion key> --app my-app
+//! ```
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::process;
+
+use logdna_client::body::{IngestBody, Line};
+use logdna_client::client::Client;
+use logdna_client::params::{Params, Tags};
+use logdna_client::request::{RequestTemplate, Schema};
+
+const USAGE: &str = "\
+Usage: logdna-send [OPTIONS]
+
+Sends every line read from stdin, or a file, to the LogDNA ingest API.
+
+Options:
+  -k, --key <KEY>            ingestion key, defaults to $LOGDNA_INGESTION_KEY
+      --host <HOST>          ingest host, defaults to $LOGDNA_HOST or logs.logdna.com
+      --hostname <NAME>      hostname the lines are reported from, defaults to $HOSTNAME
+      --app <APP>            app name of every line
+      --level <LEVEL>        level of every line
+      --tags <TAGS>          comma separated tags
+  -f, --file <PATH>          read lines from a file instead of stdin
+      --batch-size <LINES>   lines per request, defaults to 500
+      --http                 send over plain http
+  -h, --help                 print this help";
+
+struct Args {
+    key: String,
+    host: String,
+    hostname: String,
+    app: Option<String>,
+    level: Option<String>,
+    tags: Option<String>,
+    file: Option<String>,
+    batch_size: usize,
+    http: bool,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        key: std::env::var("LOGDNA_INGESTION_KEY").unwrap_or_default(),
+        host: std::env::var("LOGDNA_HOST").unwrap_or_else(|_| "logs.logdna.com".into()),
+        hostname: std::env::var("HOSTNAME").unwrap_or_else(|_| "logdna-send".into()),
+        app: None,
+        level: None,
+        tags: None,
+        file: None,
+        batch_size: 500,
+        http: false,
+    };
+
+    let mut argv = std::env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        let mut value = || {
+            argv.next()
+                .ok_or_else(|| format!("missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "-k" | "--key" => args.key = value()?,
+            "--host" => args.host = value()?,
+            "--hostname" => args.hostname = value()?,
+            "--app" => args.app = Some(value()?),
+            "--level" => args.level = Some(value()?),
+            "--tags" => args.tags = Some(value()?),
+            "-f" | "--file" => args.file = Some(value()?),
+            "--batch-size" => {
+                args.batch_size = value()?
+                    .parse()
+                    .map_err(|e| format!("invalid --batch-size: {}", e))?
+            }
+            "--http" => args.http = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ => return Err(format!("unknown argument {}", arg)),
+        }
+    }
+    if args.key.is_empty() {
+        return Err("an ingestion key is required, pass --key or set LOGDNA_INGESTION_KEY".into());
+    }
+    args.batch_size = args.batch_size.max(1);
+    Ok(args)
+}
+
+fn build_client(args: &Args) -> Result<Client, String> {
+    let mut params = Params::builder();
+    params.hostname(args.hostname.clone());
+    if let Some(tags) = args.tags.as_ref() {
+        params.tags(Tags::parse(tags.clone()));
+    }
+    let params = params.build().map_err(|e| e.to_string())?;
+
+    let template = RequestTemplate::builder()
+        .host(args.host.clone())
+        .schema(if args.http {
+            Schema::Http
+        } else {
+            Schema::Https
+        })
+        .params(params)
+        .api_key(args.key.clone())
+        .build()
+        .map_err(|e| e.to_string())?;
+    Client::builder(template)
+        .require_tls(!args.http)
+        .system_proxy(true)
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+fn build_line(args: &Args, line: String) -> Result<Line, String> {
+    let mut builder = Line::builder().line(line);
+    if let Some(app) = args.app.as_ref() {
+        builder = builder.app(app.clone());
+    }
+    if let Some(level) = args.level.as_ref() {
+        builder = builder.level(level.clone());
+    }
+    builder.build().map_err(|e| e.to_string())
+}
+
+// Returns the number of lines that were not accepted
+async fn send(client: &Client, lines: Vec<Line>) -> usize {
+    let count = lines.len();
+    match client.send(&IngestBody::new(lines)).await {
+        Ok(response) if response.is_sent() => 0,
+        Ok(response) => {
+            eprintln!(
+                "logdna-send: {} lines rejected: {} {}",
+                count,
+                response.status(),
+                response.reason().unwrap_or_default()
+            );
+            count
+        }
+        Err(e) => {
+            eprintln!("logdna-send: {} lines failed: {}", count, e);
+            count
+        }
+    }
+}
+
+async fn run(args: Args) -> Result<usize, String> {
+    let client = build_client(&args)?;
+    let input: Box<dyn BufRead> = match args.file.as_ref() {
+        Some(path) => Box::new(BufReader::new(
+            File::open(path).map_err(|e| format!("{}: {}", path, e))?,
+        )),
+        None => Box::new(BufReader::new(io::stdin())),
+    };
+
+    let mut failed = 0;
+    let mut batch = Vec::with_capacity(args.batch_size);
+    for line in input.lines() {
+        let line = line.map_err(|e| e.to_string())?;
+        if line.is_empty() {
+            continue;
+        }
+        batch.push(build_line(&args, line)?);
+        if batch.len() >= args.batch_size {
+            failed += send(&client, std::mem::take(&mut batch)).await;
+        }
+    }
+    if !batch.is_empty() {
+        failed += send(&client, batch).await;
+    }
+    Ok(failed)
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("logdna-send: {}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("tokio runtime");
+    match runtime.block_on(run(args)) {
+        Ok(0) => {}
+        Ok(_) => process::exit(1),
+        Err(e) => {
+            eprintln!("logdna-send: {}", e);
+            process::exit(1);
+        }
+    }
+}
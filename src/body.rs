@@ -1,37 +1,106 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
 use std::io::Read;
 use std::iter::FromIterator;
 use std::ops::{Deref, DerefMut};
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{self, Poll};
+use std::time::Instant;
 
 use async_trait::async_trait;
+use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use time::OffsetDateTime;
 
 use pin_project::pin_project;
 
-use crate::error::{IngestBufError, LineError, LineMetaError};
+use crate::error::{BodyError, IngestBufError, LimitError, LineError, LineMetaError};
+use crate::limits::Limits;
+use crate::processor::ProcessorChain;
 use crate::serialize::{
-    IngestBuffer, IngestLineSerialize, IngestLineSerializeError, SerializeI64, SerializeMap,
-    SerializeStr, SerializeUtf8, SerializeValue,
+    IngestBuffer, IngestLineSerialize, IngestLineSerializeError, LineData, SerializeI64,
+    SerializeMap, SerializeStr, SerializeUtf8, SerializeValue, Utf8Policy, INVALID_UTF8_LABEL,
 };
 
-use crate::segmented_buffer::{Buffer, SegmentedPoolBufBuilder};
+use crate::segmented_buffer::{AllocBufferFn, BodyReader, Buffer, SegmentedPoolBufBuilder};
+
+const BODY_SEGMENT_SIZE: usize = 2048;
+
+const BODY_POOL_RESERVE_SEGMENTS: usize = 512;
+
+// Bodies serialized from an IngestBody share one pool, so the segments of a sent body are
+// reused by the next one rather than freed with a pool of their own
+static BODY_POOL: Lazy<async_buf_pool::Pool<AllocBufferFn, Buffer>> = Lazy::new(|| {
+    async_buf_pool::Pool::<AllocBufferFn, Buffer>::with_max_reserve(
+        4,
+        BODY_POOL_RESERVE_SEGMENTS,
+        Arc::new(|| Buffer::new(bytes::BytesMut::with_capacity(BODY_SEGMENT_SIZE))),
+    )
+    .expect("valid pool reserve")
+});
+
+static MONOTONIC_CLOCK: Lazy<MonotonicClock> = Lazy::new(MonotonicClock::default);
+
+// Unix timestamps in milliseconds that never go backwards
+//
+// The last timestamp and the instant it was reached are kept under one lock, so concurrent
+// callers can't pair a timestamp with another caller's instant and run the clock ahead.
+#[derive(Debug, Default)]
+struct MonotonicClock {
+    last: std::sync::Mutex<Option<(i64, Instant)>>,
+}
+
+impl MonotonicClock {
+    // The wall clock time `wall`, or the last timestamp advanced by the monotonic clock if the
+    // wall clock is behind it
+    fn next(&self, wall: i64) -> i64 {
+        let mut last = self.last.lock().expect("monotonic clock lock poisoned");
+        let now = Instant::now();
+        let (timestamp, at) = match *last {
+            Some((timestamp, at)) if wall < timestamp => {
+                // only whole milliseconds are carried over, the remainder counts towards the next
+                let elapsed = now.saturating_duration_since(at).as_millis() as u64;
+                (
+                    timestamp + elapsed as i64,
+                    at + std::time::Duration::from_millis(elapsed),
+                )
+            }
+            _ => (wall, now),
+        };
+        *last = Some((timestamp, at));
+        timestamp
+    }
+}
+
+/// The current unix timestamp in seconds, never earlier than one returned before
+///
+/// While the system clock is behind the last timestamp, e.g after it stepped backwards, time
+/// carries on from the last timestamp at the rate of the monotonic clock.
+fn monotonic_unix_timestamp() -> i64 {
+    let wall = (OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000) as i64;
+    MONOTONIC_CLOCK.next(wall).div_euclid(1000)
+}
 
 #[pin_project]
 pub struct IngestBodyBuffer {
     #[pin]
     pub(crate) buf: IngestBuffer,
+    // The bytes once moved out of `buf` to be sent, clones share them instead of copying
+    frozen: Option<crate::serialize::FrozenBuf>,
+    // The frozen segments already sent
+    sent: usize,
+    line_count: Option<usize>,
+    idempotency_key: std::sync::Arc<str>,
 }
 
 impl core::fmt::Debug for IngestBodyBuffer {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         let buf = self
-            .buf
-            .buf
-            .bytes_reader()
+            .reader()
             .bytes()
             .collect::<Result<Vec<u8>, _>>()
             .unwrap();
@@ -59,41 +128,226 @@ impl PartialEq for IngestBodyBuffer {
     }
 }
 
+// The `lines` array of a serialized body, counted rather than deserialized
+#[cfg(feature = "client")]
+#[derive(Deserialize)]
+struct LineCount {
+    lines: CountedSeq,
+}
+
+#[cfg(feature = "client")]
+struct CountedSeq(usize);
+
+#[cfg(feature = "client")]
+impl<'de> Deserialize<'de> for CountedSeq {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct CountVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for CountVisitor {
+            type Value = CountedSeq;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("an array of lines")
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> Result<CountedSeq, A::Error> {
+                let mut count = 0;
+                while seq.next_element::<serde::de::IgnoredAny>()?.is_some() {
+                    count += 1;
+                }
+                Ok(CountedSeq(count))
+            }
+        }
+
+        deserializer.deserialize_seq(CountVisitor)
+    }
+}
+
 impl IngestBodyBuffer {
     pub fn from_buffer(ingest_buffer: IngestBuffer) -> Self {
-        Self { buf: ingest_buffer }
+        Self {
+            buf: ingest_buffer,
+            frozen: None,
+            sent: 0,
+            line_count: None,
+            idempotency_key: new_idempotency_key().into(),
+        }
+    }
+
+    /// A body sending `frozen`, `buf` is an empty buffer of the pool the bytes would come from
+    #[cfg(feature = "parallel-gzip")]
+    pub(crate) fn from_frozen(frozen: crate::serialize::FrozenBuf, buf: IngestBuffer) -> Self {
+        Self {
+            frozen: Some(frozen),
+            ..Self::from_buffer(buf)
+        }
+    }
+
+    /// Records the number of lines serialized into the buffer
+    pub fn with_line_count(mut self, line_count: usize) -> Self {
+        self.line_count = Some(line_count);
+        self
+    }
+
+    /// The number of lines in the buffer, if it was recorded
+    pub fn line_count(&self) -> Option<usize> {
+        self.line_count
+    }
+
+    /// Counts the lines of a body in the ingest format, skipping over their content
+    ///
+    /// For bodies built from a buffer without a recorded line count.
+    #[cfg(feature = "client")]
+    pub(crate) fn count_lines(&self) -> Result<usize, serde_json::Error> {
+        let mut de = serde_json::Deserializer::from_reader(self.reader());
+        let counted = LineCount::deserialize(&mut de)?;
+        de.end()?;
+        Ok(counted.lines.0)
+    }
+
+    /// Replaces the generated idempotency key, e.g with a hash of the lines
+    pub fn with_idempotency_key<T: Into<std::sync::Arc<str>>>(mut self, key: T) -> Self {
+        self.idempotency_key = key.into();
+        self
+    }
+
+    /// The key identifying this body, kept by every clone so retries reuse it
+    pub fn idempotency_key(&self) -> &str {
+        &self.idempotency_key
+    }
+
+    /// Rewinds a body that was sent, or partly sent, so the same buffer can be sent again
+    ///
+    /// Bodies carried by a [`HttpError`](crate::error::HttpError) or a failed
+    /// [`Response`](crate::response::Response) were never read and can be passed to
+    /// [`Client::send_serialized`](crate::client::Client::send_serialized) as they are.
+    pub fn reset_read(&mut self) {
+        self.sent = 0;
+        self.buf.reset_read()
     }
 
     pub fn reader(&self) -> impl std::io::Read + futures::AsyncBufRead + '_ {
-        self.buf.buf.bytes_reader()
+        match self.frozen.as_ref() {
+            Some(frozen) => BodyReader::Frozen(frozen.clone()),
+            None => BodyReader::Pooled(self.buf.buf.bytes_reader()),
+        }
+    }
+
+    /// Moves the bytes out of the pooled segments, after which clones share them
+    ///
+    /// The client does this once before sending, so retries and redirects reuse the same bytes.
+    #[cfg(feature = "client")]
+    pub(crate) fn share(&mut self) -> &crate::serialize::FrozenBuf {
+        let buf = &mut self.buf;
+        self.frozen.get_or_insert_with(|| buf.take_frozen())
+    }
+
+    /// Keeps only `range` of the body, sharing its bytes, e.g to strip an envelope
+    ///
+    /// The line count and idempotency key are kept.
+    #[cfg(feature = "client")]
+    pub(crate) fn slice(mut self, range: std::ops::Range<usize>) -> Self {
+        let sliced = self.share().slice(range);
+        self.frozen = Some(sliced);
+        self
+    }
+
+    /// The serialized body as indented JSON, for debugging and golden file tests
+    ///
+    /// Keys keep the order they were serialized in and the buffer sent is left as it is.
+    pub fn to_pretty_string(&self) -> Result<String, serde_json::Error> {
+        let mut de = serde_json::Deserializer::from_reader(self.reader());
+        let mut pretty = Vec::with_capacity(self.len() * 2);
+        serde_transcode::transcode(&mut de, &mut serde_json::Serializer::pretty(&mut pretty))?;
+        de.end()?;
+        Ok(String::from_utf8(pretty).expect("serde_json writes utf-8"))
+    }
+
+    /// Returns the segments of a body that is no longer needed to the pool it was serialized into
+    ///
+    /// Sending a body already returns its segments, its bytes are then shared with the request
+    /// until hyper drops it. Dropping a body also returns its segments, this makes it explicit.
+    pub fn recycle(mut self) {
+        self.buf.clear();
+    }
+
+    /// Moves the serialized body into an immutable buffer whose clones share the bytes
+    pub fn freeze(self) -> crate::serialize::FrozenBuf {
+        match self.frozen {
+            Some(frozen) => frozen,
+            None => self.buf.freeze(),
+        }
+    }
+
+    /// Reads the serialized body through the tokio io traits, e.g with `tokio::io::copy`
+    #[cfg(feature = "tokio-io")]
+    pub fn tokio_reader(&self) -> impl tokio::io::AsyncBufRead + Unpin + '_ {
+        match self.frozen.as_ref() {
+            Some(frozen) => BodyReader::Frozen(frozen.clone()),
+            None => BodyReader::Pooled(self.buf.buf.bytes_reader()),
+        }
     }
 
     pub fn len(&self) -> usize {
-        self.buf.len()
+        match self.frozen.as_ref() {
+            Some(frozen) => frozen.len(),
+            None => self.buf.len(),
+        }
     }
 
     pub fn is_empty(&self) -> bool {
-        self.buf.is_empty()
+        self.len() == 0
     }
 }
 
 impl Clone for IngestBodyBuffer {
     fn clone(&self) -> Self {
-        IngestBodyBuffer::from_buffer(self.buf.clone())
+        Self {
+            buf: self.buf.clone(),
+            frozen: self.frozen.clone(),
+            sent: 0,
+            line_count: self.line_count,
+            idempotency_key: self.idempotency_key.clone(),
+        }
     }
 }
 
-// TODO add test
+// A random version 4 UUID
+fn new_idempotency_key() -> String {
+    let bits: u128 = rand::random();
+    let bits = (bits & !(0xf << 76) & !(0x3 << 62)) | (0x4 << 76) | (0x2 << 62);
+    let hex = format!("{:032x}", bits);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+// Sending freezes the body and yields its segments without copying or consuming them, see
+// `reset_read`
+#[cfg(feature = "client")]
 impl hyper::body::HttpBody for IngestBodyBuffer {
-    type Data = async_buf_pool::Reusable<Buffer>;
+    type Data = bytes::Bytes;
     type Error = Box<IngestBufError>;
 
     fn poll_data(
         self: Pin<&mut Self>,
         _: &mut task::Context<'_>,
     ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
-        let mut this = self.project();
-        Poll::Ready(this.buf.buf.bufs.pop().map(Ok))
+        let this = self.get_mut();
+        let sent = this.sent;
+        let data = this.share().segments().get(sent).cloned();
+        if data.is_some() {
+            this.sent += 1;
+        }
+        Poll::Ready(data.map(Ok))
     }
 
     fn poll_trailers(
@@ -106,6 +360,7 @@ impl hyper::body::HttpBody for IngestBodyBuffer {
 
 /// Type used to construct a body for an IngestRequest
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default, Eq)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct IngestBody {
     lines: Vec<Line>,
 }
@@ -115,6 +370,67 @@ impl IngestBody {
     pub fn new(lines: Vec<Line>) -> Self {
         Self { lines }
     }
+
+    /// The lines of the body
+    pub fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+    /// Takes the lines of the body
+    pub fn into_lines(self) -> Vec<Line> {
+        self.lines
+    }
+    /// The number of lines in the body
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+    /// Whether the body has no lines
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+    /// Decodes the lines serialized into a buffer, e.g a body that failed to send
+    ///
+    /// Only buffers in the default ingest format without a serialization profile can be decoded.
+    pub fn from_buffer(buffer: &IngestBodyBuffer) -> Result<Self, BodyError> {
+        Ok(serde_json::from_reader(buffer.reader())?)
+    }
+    /// Decodes a serialized body, decompressing it first if it is gzipped
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, BodyError> {
+        use async_compression::futures::bufread::GzipDecoder;
+        use futures::io::AsyncReadExt;
+
+        if !bytes.starts_with(&[0x1f, 0x8b]) {
+            return Ok(serde_json::from_slice(bytes)?);
+        }
+        let mut decoded = Vec::new();
+        // reading from a slice never waits
+        futures::executor::block_on(GzipDecoder::new(bytes).read_to_end(&mut decoded))?;
+        Ok(serde_json::from_slice(&decoded)?)
+    }
+    /// Sorts the lines oldest first, lines with the same timestamp keep their order
+    pub fn sort_by_timestamp(&mut self) {
+        self.lines.sort_by_key(|line| line.timestamp)
+    }
+}
+
+impl Extend<Line> for IngestBody {
+    fn extend<I: IntoIterator<Item = Line>>(&mut self, iter: I) {
+        self.lines.extend(iter)
+    }
+}
+
+impl FromIterator<Line> for IngestBody {
+    fn from_iter<I: IntoIterator<Item = Line>>(iter: I) -> Self {
+        Self::new(iter.into_iter().collect())
+    }
+}
+
+impl IntoIterator for IngestBody {
+    type Item = Line;
+    type IntoIter = std::vec::IntoIter<Line>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.lines.into_iter()
+    }
 }
 
 #[async_trait]
@@ -122,6 +438,38 @@ pub trait IntoIngestBodyBuffer {
     type Error: std::error::Error;
 
     async fn into(self) -> Result<IngestBodyBuffer, Self::Error>;
+
+    /// Serializes the body after running each of its lines through `processors`
+    ///
+    /// By default the processors are ignored, which suits bodies that are already serialized.
+    async fn into_processed(
+        self,
+        _processors: &ProcessorChain,
+    ) -> Result<IngestBodyBuffer, Self::Error>
+    where
+        Self: Sized + Send,
+    {
+        self.into().await
+    }
+
+    /// Serializes the body on tokio's blocking pool, after running its lines through
+    /// `processors`
+    ///
+    /// Only bodies that can be moved to another thread are, by default the body is serialized
+    /// in place like [`into_processed`](IntoIngestBodyBuffer::into_processed).
+    #[cfg(feature = "client")]
+    async fn into_offloaded(
+        self,
+        processors: Option<Arc<ProcessorChain>>,
+    ) -> Result<IngestBodyBuffer, Self::Error>
+    where
+        Self: Sized + Send,
+    {
+        match processors {
+            Some(processors) => self.into_processed(&processors).await,
+            None => self.into().await,
+        }
+    }
 }
 
 #[async_trait]
@@ -139,12 +487,36 @@ impl IntoIngestBodyBuffer for IngestBody {
 
     async fn into(self) -> Result<IngestBodyBuffer, Self::Error> {
         let mut buf = SegmentedPoolBufBuilder::new()
-            .segment_size(2048)
-            .initial_capacity(8192)
-            .build();
+            .segment_size(BODY_SEGMENT_SIZE)
+            .with_pool(BODY_POOL.clone());
 
         serde_json::to_writer(&mut buf, &self)?;
-        Ok(IngestBodyBuffer::from_buffer(buf))
+        Ok(IngestBodyBuffer::from_buffer(buf).with_line_count(self.lines.len()))
+    }
+
+    async fn into_processed(
+        self,
+        processors: &ProcessorChain,
+    ) -> Result<IngestBodyBuffer, Self::Error> {
+        IntoIngestBodyBuffer::into(processors.process_body(self)).await
+    }
+
+    #[cfg(feature = "client")]
+    async fn into_offloaded(
+        self,
+        processors: Option<Arc<ProcessorChain>>,
+    ) -> Result<IngestBodyBuffer, Self::Error> {
+        let serialize = move || {
+            futures::executor::block_on(async move {
+                match processors {
+                    Some(processors) => self.into_processed(&processors).await,
+                    None => IntoIngestBodyBuffer::into(self).await,
+                }
+            })
+        };
+        tokio::task::spawn_blocking(serialize)
+            .await
+            .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
     }
 }
 
@@ -154,12 +526,27 @@ impl<'a> IntoIngestBodyBuffer for &'a IngestBody {
 
     async fn into(self) -> Result<IngestBodyBuffer, Self::Error> {
         let mut buf = SegmentedPoolBufBuilder::new()
-            .segment_size(2048)
-            .initial_capacity(8192)
-            .build();
+            .segment_size(BODY_SEGMENT_SIZE)
+            .with_pool(BODY_POOL.clone());
 
         serde_json::to_writer(&mut buf, &self)?;
-        Ok(IngestBodyBuffer::from_buffer(buf))
+        Ok(IngestBodyBuffer::from_buffer(buf).with_line_count(self.lines.len()))
+    }
+
+    async fn into_processed(
+        self,
+        processors: &ProcessorChain,
+    ) -> Result<IngestBodyBuffer, Self::Error> {
+        IntoIngestBodyBuffer::into(processors.process_body(self.clone())).await
+    }
+
+    /// The body is cloned to be moved to the blocking pool
+    #[cfg(feature = "client")]
+    async fn into_offloaded(
+        self,
+        processors: Option<Arc<ProcessorChain>>,
+    ) -> Result<IngestBodyBuffer, Self::Error> {
+        self.clone().into_offloaded(processors).await
     }
 }
 
@@ -201,6 +588,7 @@ pub trait LineBufferMut: LineMetaMut {
 
 /// Defines a log line, marking none required fields as Option
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct Line {
     /// The annotations field, which is a key value map
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -234,6 +622,71 @@ pub struct Line {
     pub timestamp: i64,
 }
 
+impl Line {
+    // The meta field as a string with sorted keys, so equal values are equal strings even when
+    // serde_json keeps the insertion order of objects
+    fn meta_key(&self) -> Option<String> {
+        self.meta
+            .as_ref()
+            .map(|meta| serde_json::to_string(&SortedKeys(meta)).expect("json values serialize"))
+    }
+}
+
+// Serializes a json value with the keys of every object in sorted order
+struct SortedKeys<'a>(&'a Value);
+
+impl Serialize for SortedKeys<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            Value::Object(map) => {
+                let mut entries: Vec<_> = map.iter().collect();
+                entries.sort_by(|a, b| a.0.cmp(b.0));
+                serializer.collect_map(entries.into_iter().map(|(k, v)| (k, SortedKeys(v))))
+            }
+            Value::Array(values) => serializer.collect_seq(values.iter().map(SortedKeys)),
+            value => value.serialize(serializer),
+        }
+    }
+}
+
+impl Hash for Line {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.annotations.hash(state);
+        self.app.hash(state);
+        self.env.hash(state);
+        self.file.hash(state);
+        self.host.hash(state);
+        self.labels.hash(state);
+        self.level.hash(state);
+        self.meta_key().hash(state);
+        self.line.hash(state);
+        self.timestamp.hash(state);
+    }
+}
+
+/// Lines are ordered by timestamp first, the other fields only break ties
+impl Ord for Line {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.timestamp
+            .cmp(&other.timestamp)
+            .then_with(|| self.line.cmp(&other.line))
+            .then_with(|| self.app.cmp(&other.app))
+            .then_with(|| self.level.cmp(&other.level))
+            .then_with(|| self.host.cmp(&other.host))
+            .then_with(|| self.env.cmp(&other.env))
+            .then_with(|| self.file.cmp(&other.file))
+            .then_with(|| self.labels.cmp(&other.labels))
+            .then_with(|| self.annotations.cmp(&other.annotations))
+            .then_with(|| self.meta_key().cmp(&other.meta_key()))
+    }
+}
+
+impl PartialOrd for Line {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 #[async_trait]
 impl<'a> IngestLineSerialize<String, bytes::Bytes, HashMap<String, String>> for &'a Line {
     type Ok = ();
@@ -373,6 +826,208 @@ impl Line {
     }
 }
 
+/// A log line borrowing its fields, e.g from the buffer a record was parsed out of
+///
+/// Serializes exactly like a [`Line`], through [`LineData`](crate::serialize::LineData), without
+/// an owned `String` per field. Fields that had to be unescaped or rewritten can be owned
+/// [`Cow`]s.
+///
+/// # Example
+///
+/// ```rust
+/// # use logdna_client::body::LineRef;
+/// let raw = "web-1 nginx GET /index.html";
+/// let mut line = LineRef::new(&raw[12..]);
+/// line.host = Some(raw[..5].into());
+/// line.app = Some(raw[6..11].into());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineRef<'a> {
+    pub annotations: Option<Cow<'a, KeyValueMap>>,
+    pub app: Option<Cow<'a, str>>,
+    pub env: Option<Cow<'a, str>>,
+    pub file: Option<Cow<'a, str>>,
+    pub host: Option<Cow<'a, str>>,
+    pub labels: Option<Cow<'a, KeyValueMap>>,
+    pub level: Option<Cow<'a, str>>,
+    pub meta: Option<Cow<'a, Value>>,
+    pub line: Cow<'a, str>,
+    /// Seconds since the unix epoch, now by default
+    pub timestamp: i64,
+}
+
+impl<'a> LineRef<'a> {
+    /// Constructs a LineRef for `line` timestamped now, without any other fields
+    pub fn new<T: Into<Cow<'a, str>>>(line: T) -> Self {
+        Self {
+            annotations: None,
+            app: None,
+            env: None,
+            file: None,
+            host: None,
+            labels: None,
+            level: None,
+            meta: None,
+            line: line.into(),
+            timestamp: OffsetDateTime::now_utc().unix_timestamp(),
+        }
+    }
+
+    /// Copies the fields into an owned [`Line`]
+    pub fn into_owned(self) -> Line {
+        Line {
+            annotations: self.annotations.map(Cow::into_owned),
+            app: self.app.map(Cow::into_owned),
+            env: self.env.map(Cow::into_owned),
+            file: self.file.map(Cow::into_owned),
+            host: self.host.map(Cow::into_owned),
+            labels: self.labels.map(Cow::into_owned),
+            level: self.level.map(Cow::into_owned),
+            meta: self.meta.map(Cow::into_owned),
+            line: self.line.into_owned(),
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+impl<'a> From<&'a Line> for LineRef<'a> {
+    fn from(line: &'a Line) -> Self {
+        Self {
+            annotations: line.annotations.as_ref().map(Cow::Borrowed),
+            app: line.app.as_deref().map(Cow::Borrowed),
+            env: line.env.as_deref().map(Cow::Borrowed),
+            file: line.file.as_deref().map(Cow::Borrowed),
+            host: line.host.as_deref().map(Cow::Borrowed),
+            labels: line.labels.as_ref().map(Cow::Borrowed),
+            level: line.level.as_deref().map(Cow::Borrowed),
+            meta: line.meta.as_ref().map(Cow::Borrowed),
+            line: Cow::Borrowed(&line.line),
+            timestamp: line.timestamp,
+        }
+    }
+}
+
+impl LineData for LineRef<'_> {
+    fn line(&self) -> &[u8] {
+        self.line.as_bytes()
+    }
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+    fn app(&self) -> Option<&str> {
+        self.app.as_deref()
+    }
+    fn env(&self) -> Option<&str> {
+        self.env.as_deref()
+    }
+    fn file(&self) -> Option<&str> {
+        self.file.as_deref()
+    }
+    fn host(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
+    fn level(&self) -> Option<&str> {
+        self.level.as_deref()
+    }
+    fn labels(&self) -> Option<&HashMap<String, String>> {
+        self.labels.as_deref().map(|labels| &labels.0)
+    }
+    fn annotations(&self) -> Option<&HashMap<String, String>> {
+        self.annotations
+            .as_deref()
+            .map(|annotations| &annotations.0)
+    }
+    fn meta(&self) -> Option<&Value> {
+        self.meta.as_deref()
+    }
+}
+
+/// A [`Line`] behind an `Arc`, cloned without copying its message, labels or metadata
+///
+/// Lets a line fanned out to several destinations, or held by retry queues, be referenced many
+/// times while stored once. Serializes like the line it wraps, either through serde or
+/// [`LineData`](crate::serialize::LineData).
+///
+/// # Example
+///
+/// ```rust
+/// # use logdna_client::body::{Line, SharedLine};
+/// let line = SharedLine::from(Line::builder().line("hello").build().unwrap());
+/// let copies = vec![line.clone(), line.clone()];
+/// assert!(SharedLine::ptr_eq(&copies[0], &copies[1]));
+/// assert_eq!(copies[0].line, "hello");
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(transparent)]
+pub struct SharedLine(Arc<Line>);
+
+impl SharedLine {
+    /// Whether two shared lines point to the same line
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        Arc::ptr_eq(&this.0, &other.0)
+    }
+    /// Takes the line, copying it if it is still shared
+    pub fn into_line(self) -> Line {
+        Arc::try_unwrap(self.0).unwrap_or_else(|line| (*line).clone())
+    }
+}
+
+impl From<Line> for SharedLine {
+    fn from(line: Line) -> Self {
+        Self(Arc::new(line))
+    }
+}
+
+impl From<Arc<Line>> for SharedLine {
+    fn from(line: Arc<Line>) -> Self {
+        Self(line)
+    }
+}
+
+impl Deref for SharedLine {
+    type Target = Line;
+
+    fn deref(&self) -> &Line {
+        &self.0
+    }
+}
+
+impl LineData for SharedLine {
+    fn line(&self) -> &[u8] {
+        self.0.line.as_bytes()
+    }
+    fn timestamp(&self) -> i64 {
+        self.0.timestamp
+    }
+    fn app(&self) -> Option<&str> {
+        self.0.app.as_deref()
+    }
+    fn env(&self) -> Option<&str> {
+        self.0.env.as_deref()
+    }
+    fn file(&self) -> Option<&str> {
+        self.0.file.as_deref()
+    }
+    fn host(&self) -> Option<&str> {
+        self.0.host.as_deref()
+    }
+    fn level(&self) -> Option<&str> {
+        self.0.level.as_deref()
+    }
+    fn labels(&self) -> Option<&HashMap<String, String>> {
+        self.0.labels.as_ref().map(|labels| &labels.0)
+    }
+    fn annotations(&self) -> Option<&HashMap<String, String>> {
+        self.0
+            .annotations
+            .as_ref()
+            .map(|annotations| &annotations.0)
+    }
+    fn meta(&self) -> Option<&Value> {
+        self.0.meta.as_ref()
+    }
+}
+
 /// Used to build a log line
 ///
 /// # Example
@@ -397,6 +1052,12 @@ pub struct LineBuilder {
     pub level: Option<String>,
     pub line: Option<String>,
     pub meta: Option<Value>,
+    #[serde(skip)]
+    limits: Option<Limits>,
+    #[serde(skip)]
+    utf8_policy: Option<Utf8Policy>,
+    #[serde(skip)]
+    monotonic_timestamp: bool,
 }
 
 impl LineBuilder {
@@ -412,6 +1073,9 @@ impl LineBuilder {
             level: None,
             line: None,
             meta: None,
+            limits: None,
+            utf8_policy: None,
+            monotonic_timestamp: false,
         }
     }
     /// Set the annotations field in the builder
@@ -459,10 +1123,36 @@ impl LineBuilder {
         self.meta = Some(meta.into());
         self
     }
+    /// Check the fields against the Ingest API limits when building, default is unchecked
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = Some(limits);
+        self
+    }
+    /// Set how lines set from bytes that aren't valid utf-8 are handled, default is strict
+    pub fn utf8_policy(mut self, utf8_policy: Utf8Policy) -> Self {
+        self.utf8_policy = Some(utf8_policy);
+        self
+    }
+    /// Never timestamp the line earlier than a line built before with this option, default is false
+    ///
+    /// Keeps lines in order when the system clock steps backwards, e.g on an NTP correction or
+    /// a VM resume.
+    pub fn monotonic_timestamp(mut self, monotonic_timestamp: bool) -> Self {
+        self.monotonic_timestamp = monotonic_timestamp;
+        self
+    }
     /// Construct a log line from the contents of this builder
     ///
-    /// Returning an error if required fields are missing
-    pub fn build(self) -> Result<Line, LineError> {
+    /// Returning an error if required fields are missing, or exceed the limits when set
+    pub fn build(mut self) -> Result<Line, LineError> {
+        if let Some(limits) = self.limits {
+            self.check_limits(&limits)?;
+        }
+        let timestamp = if self.monotonic_timestamp {
+            monotonic_unix_timestamp()
+        } else {
+            OffsetDateTime::now_utc().unix_timestamp()
+        };
         Ok(Line {
             annotations: self.annotations,
             app: self.app,
@@ -475,9 +1165,50 @@ impl LineBuilder {
             line: self
                 .line
                 .ok_or_else(|| LineError::RequiredField("line field is required".into()))?,
-            timestamp: OffsetDateTime::now_utc().unix_timestamp(),
+            timestamp,
         })
     }
+
+    fn check_limits(&mut self, limits: &Limits) -> Result<(), LimitError> {
+        if let Some(line) = self.line.as_mut() {
+            limits.check_str("line", line, limits.max_line_len)?;
+        }
+        let fields = [
+            ("app", &mut self.app),
+            ("env", &mut self.env),
+            ("file", &mut self.file),
+            ("host", &mut self.host),
+            ("level", &mut self.level),
+        ];
+        for (field, value) in fields {
+            if let Some(value) = value.as_mut() {
+                limits.check_str(field, value, limits.max_field_len)?;
+            }
+        }
+        if let Some(labels) = self.labels.as_mut() {
+            limits.check_map("labels", labels)?;
+        }
+        if let Some(annotations) = self.annotations.as_mut() {
+            limits.check_map("annotations", annotations)?;
+        }
+        limits.check_meta(&mut self.meta)
+    }
+
+    fn set_line_bytes(&mut self, line: Vec<u8>) -> Result<(), LineMetaError> {
+        let (line, replaced) = self
+            .utf8_policy
+            .unwrap_or(Utf8Policy::Strict)
+            .decode(line)
+            .map_err(|_| LineMetaError::Failed("line is not a UTF-8 string"))?;
+        if replaced {
+            self.labels
+                .get_or_insert_with(KeyValueMap::new)
+                .0
+                .insert(INVALID_UTF8_LABEL.into(), "true".into());
+        }
+        self.line = Some(line);
+        Ok(())
+    }
 }
 
 impl LineMeta for LineBuilder {
@@ -626,12 +1357,7 @@ impl LineBufferMut for LineBuilder {
     }
 
     fn set_line_buffer(&mut self, line: Vec<u8>) -> Result<(), LineMetaError> {
-        self.line = Some(
-            String::from_utf8(line)
-                // Only accept UTF-8 representations of the data for LineBuilder
-                .map_err(|_| LineMetaError::Failed("line is not a UTF-8 string"))?,
-        );
-        Ok(())
+        self.set_line_bytes(line)
     }
 }
 
@@ -699,12 +1425,7 @@ impl LineBufferMut for &mut LineBuilder {
         self.line.as_deref().map(|x| x.as_bytes())
     }
     fn set_line_buffer(&mut self, line: Vec<u8>) -> Result<(), LineMetaError> {
-        self.line = Some(
-            String::from_utf8(line)
-                // Only accept UTF-8 representations of the data for LineBuilder
-                .map_err(|_| LineMetaError::Failed("line is not a UTF-8 string"))?,
-        );
-        Ok(())
+        self.set_line_bytes(line)
     }
 }
 
@@ -775,9 +1496,18 @@ impl AsRef<IngestBody> for IngestBody {
 }
 
 /// Json key value map (json object with a depth of 1)
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+///
+/// Entries are serialized in key order.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct KeyValueMap(HashMap<String, String>);
 
+impl Serialize for KeyValueMap {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.sorted())
+    }
+}
+
 impl Deref for KeyValueMap {
     type Target = HashMap<String, String>;
 
@@ -809,6 +1539,32 @@ impl KeyValueMap {
     }
 }
 
+impl KeyValueMap {
+    fn sorted(&self) -> Vec<(&String, &String)> {
+        let mut entries: Vec<_> = self.0.iter().collect();
+        entries.sort_unstable();
+        entries
+    }
+}
+
+impl Hash for KeyValueMap {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.sorted().hash(state)
+    }
+}
+
+impl Ord for KeyValueMap {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.sorted().cmp(&other.sorted())
+    }
+}
+
+impl PartialOrd for KeyValueMap {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl Default for KeyValueMap {
     fn default() -> Self {
         Self::new()
@@ -932,6 +1688,8 @@ pub(crate) mod test {
             for line in ingest_body.lines.iter() {
                 tokio_test::block_on(se.write_line(line)).unwrap();
             }
+            assert_eq!(se.line_count(), ingest_body.lines.len());
+            assert_eq!(se.bytes_written(), serde_serialized.len());
             let serialized = se.end().unwrap();
             let mut buf = String::new();
             serialized.reader().read_to_string(&mut buf).unwrap();
@@ -959,4 +1717,558 @@ pub(crate) mod test {
             assert_eq!(serde_serialized.len(), buf.len());
         }
     }
+
+    #[test]
+    fn ingest_body_buffer_line_count() {
+        let line = Line::builder().line("a").build().unwrap();
+        let ingest_body = IngestBody::new(vec![line.clone(), line]);
+        let buffer = tokio_test::block_on(IntoIngestBodyBuffer::into(&ingest_body)).unwrap();
+        assert_eq!(buffer.line_count(), Some(2));
+        assert_eq!(buffer.clone().line_count(), Some(2));
+        assert_eq!(IngestBodyBuffer::from_buffer(buffer.buf).line_count(), None);
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn counts_lines_of_a_serialized_body() {
+        let line = Line::builder().line("[a]").build().unwrap();
+        let ingest_body = IngestBody::new(vec![line.clone(), line.clone(), line]);
+        let buffer = tokio_test::block_on(IntoIngestBodyBuffer::into(&ingest_body)).unwrap();
+        let unknown = IngestBodyBuffer::from_buffer(buffer.buf);
+        assert_eq!(unknown.count_lines().unwrap(), 3);
+
+        let empty = tokio_test::block_on(IntoIngestBodyBuffer::into(&IngestBody::new(vec![])));
+        assert_eq!(empty.unwrap().count_lines().unwrap(), 0);
+    }
+
+    #[cfg(feature = "tokio-io")]
+    #[test]
+    fn ingest_body_buffer_tokio_reader() {
+        let line = Line::builder().line("a".repeat(5000)).build().unwrap();
+        let body = IngestBody::new(vec![line; 4]);
+        let buffer = tokio_test::block_on(IntoIngestBodyBuffer::into(&body)).unwrap();
+
+        let mut copied = Vec::new();
+        tokio_test::block_on(tokio::io::copy(&mut buffer.tokio_reader(), &mut copied)).unwrap();
+        assert_eq!(copied, serde_json::to_vec(&body).unwrap());
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn ingest_body_buffer_resend_after_reset() {
+        let line = Line::builder().line("a".repeat(5000)).build().unwrap();
+        let body = IngestBody::new(vec![line; 4]);
+        let mut buffer = tokio_test::block_on(IntoIngestBodyBuffer::into(&body)).unwrap();
+        let expected = serde_json::to_vec(&body).unwrap();
+
+        let sent = tokio_test::block_on(hyper::body::to_bytes(&mut buffer)).unwrap();
+        assert_eq!(sent, expected);
+        let drained = tokio_test::block_on(hyper::body::to_bytes(&mut buffer)).unwrap();
+        assert!(drained.is_empty());
+        // a sent body is frozen, clones share its segments
+        let shared = buffer.clone().freeze();
+        assert_eq!(
+            shared.segments()[0].as_ptr(),
+            buffer.clone().freeze().segments()[0].as_ptr()
+        );
+
+        buffer.reset_read();
+        let resent = tokio_test::block_on(hyper::body::to_bytes(&mut buffer)).unwrap();
+        assert_eq!(resent, expected);
+        assert_eq!(buffer.len(), expected.len());
+    }
+
+    #[cfg(feature = "client")]
+    #[tokio::test]
+    async fn serializes_on_the_blocking_pool() {
+        use crate::sanitize::Sanitizer;
+
+        let line = Line::builder().line("moved\x07").build().unwrap();
+        let body = IngestBody::new(vec![line; 3]);
+        let mut processors = ProcessorChain::new();
+        processors.push(Sanitizer::strip());
+
+        let offloaded = (&body).into_offloaded(None).await.unwrap();
+        assert_eq!(offloaded.line_count(), Some(3));
+        assert_eq!(offloaded, IntoIngestBodyBuffer::into(&body).await.unwrap());
+
+        let processed = body
+            .into_offloaded(Some(Arc::new(processors)))
+            .await
+            .unwrap();
+        let value: serde_json::Value = serde_json::from_reader(processed.reader()).unwrap();
+        assert_eq!(value["lines"][0]["line"], "moved");
+    }
+
+    #[test]
+    fn ingest_body_buffer_pretty_string() {
+        let line = Line::builder().line("hello").app("app").build().unwrap();
+        let body = IngestBody::new(vec![line]);
+        let buffer = tokio_test::block_on(IntoIngestBodyBuffer::into(&body)).unwrap();
+        let pretty = buffer.to_pretty_string().unwrap();
+
+        assert!(pretty.starts_with("{\n  \"lines\": [\n    {\n"));
+        assert!(pretty.contains("\n      \"app\": \"app\",\n"));
+        let compact: serde_json::Value = serde_json::from_str(&pretty).unwrap();
+        assert_eq!(compact, serde_json::to_value(&body).unwrap());
+        // the wire format is untouched
+        assert_eq!(
+            buffer.reader().bytes().count(),
+            serde_json::to_vec(&body).unwrap().len()
+        );
+    }
+
+    #[test]
+    fn recycled_segments_are_reused_cleared() {
+        let body = |l: &str| IngestBody::new(vec![Line::builder().line(l).build().unwrap()]);
+        let large = body(&"a".repeat(10_000));
+        let buffer = tokio_test::block_on(IntoIngestBodyBuffer::into(&large)).unwrap();
+        buffer.recycle();
+
+        let small = body("b");
+        let buffer = tokio_test::block_on(IntoIngestBodyBuffer::into(&small)).unwrap();
+        let mut serialized = Vec::new();
+        buffer.reader().read_to_end(&mut serialized).unwrap();
+        assert_eq!(serialized, serde_json::to_vec(&small).unwrap());
+    }
+
+    #[test]
+    fn ingest_body_collect_and_extend() {
+        let line = |l: &str| Line::builder().line(l).build().unwrap();
+        let mut body: IngestBody = vec![line("a"), line("b")].into_iter().collect();
+        assert_eq!(body.len(), 2);
+        body.extend(IngestBody::new(vec![line("c")]));
+        assert_eq!(body.lines().last().unwrap().line, "c");
+        assert_eq!(body.into_lines().len(), 3);
+        assert!(IngestBody::default().is_empty());
+    }
+
+    #[test]
+    fn line_hash_and_order() {
+        use std::collections::HashSet;
+
+        let line = |l: &str, timestamp| {
+            let mut line = Line::builder()
+                .line(l)
+                .labels(KeyValueMap::new().add("a", "1").add("b", "2"))
+                .build()
+                .unwrap();
+            line.timestamp = timestamp;
+            line
+        };
+        let mut body = IngestBody::new(vec![line("c", 3), line("b", 1), line("a", 1)]);
+        body.sort_by_timestamp();
+        let lines: Vec<_> = body.lines().iter().map(|l| l.line.as_str()).collect();
+        assert_eq!(lines, vec!["b", "a", "c"]);
+        assert!(line("z", 1) < line("a", 2));
+        assert!(line("a", 1) < line("b", 1));
+
+        let set: HashSet<Line> = vec![line("a", 1), line("a", 1), line("a", 2)]
+            .into_iter()
+            .collect();
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn line_meta_hash_ignores_key_order() {
+        use std::collections::hash_map::DefaultHasher;
+
+        let line = |keys: &[&str]| {
+            let mut nested = serde_json::Map::new();
+            let mut meta = serde_json::Map::new();
+            for key in keys {
+                nested.insert(key.to_string(), Value::from(*key));
+            }
+            for key in keys {
+                meta.insert(key.to_string(), Value::from(nested.clone()));
+            }
+            Line::builder()
+                .line("a")
+                .meta(Value::Object(meta))
+                .build()
+                .unwrap()
+        };
+        let hash = |line: &Line| {
+            let mut hasher = DefaultHasher::new();
+            line.hash(&mut hasher);
+            hasher.finish()
+        };
+        let (forward, backward) = (line(&["a", "b", "c"]), line(&["c", "b", "a"]));
+        assert_eq!(forward.meta_key(), backward.meta_key());
+        assert_eq!(hash(&forward), hash(&backward));
+        assert_eq!(forward.cmp(&backward), Ordering::Equal);
+        assert_ne!(forward.meta_key(), line(&["a", "b"]).meta_key());
+    }
+
+    #[test]
+    fn key_value_map_sorted_serialization() {
+        let labels = (0..32).fold(KeyValueMap::new(), |map, i| {
+            map.add(format!("key{:02}", 31 - i), i.to_string())
+        });
+        let expected = (0..32)
+            .map(|i| format!(r#""key{:02}":"{}""#, i, 31 - i))
+            .collect::<Vec<_>>()
+            .join(",");
+        assert_eq!(
+            serde_json::to_string(&labels).unwrap(),
+            format!("{{{}}}", expected)
+        );
+
+        let line = Line::builder()
+            .line("a")
+            .labels(labels.clone())
+            .annotations(labels)
+            .build()
+            .unwrap();
+        let body = IngestBody::new(vec![line]);
+        let buffer = tokio_test::block_on(IntoIngestBodyBuffer::into(&body)).unwrap();
+        let mut buf = String::new();
+        buffer.reader().read_to_string(&mut buf).unwrap();
+        assert_eq!(buf.matches(&format!("{{{}}}", expected)).count(), 2);
+    }
+
+    #[cfg(feature = "schemars")]
+    #[test]
+    fn ingest_body_json_schema() {
+        let schema = serde_json::to_value(schemars::schema_for!(IngestBody)).unwrap();
+        assert!(schema["properties"]["lines"].is_object());
+        let line = &schema["definitions"]["Line"];
+        assert_eq!(line["required"], serde_json::json!(["line", "timestamp"]));
+        assert!(line["properties"]["label"].is_object());
+    }
+
+    #[test]
+    fn ingest_body_decode_buffer() {
+        use async_compression::futures::write::GzipEncoder;
+        use futures::io::AsyncWriteExt;
+
+        let line = Line::builder()
+            .line("hello")
+            .app("app")
+            .labels(KeyValueMap::new().add("a", "b"))
+            .meta(serde_json::json!({"k": [1, 2]}))
+            .build()
+            .unwrap();
+        let body = IngestBody::new(vec![line.clone(), line]);
+        let buffer = tokio_test::block_on(IntoIngestBodyBuffer::into(&body)).unwrap();
+        assert_eq!(IngestBody::from_buffer(&buffer).unwrap(), body);
+
+        let mut json = Vec::new();
+        buffer.reader().read_to_end(&mut json).unwrap();
+        assert_eq!(IngestBody::from_slice(&json).unwrap(), body);
+
+        let mut encoder = GzipEncoder::new(Vec::new());
+        tokio_test::block_on(async {
+            encoder.write_all(&json).await.unwrap();
+            encoder.close().await.unwrap();
+        });
+        assert_eq!(IngestBody::from_slice(&encoder.into_inner()).unwrap(), body);
+        assert!(IngestBody::from_slice(b"not json").is_err());
+    }
+
+    #[test]
+    fn serialize_lines_with_profile() {
+        use crate::serialize::{IngestBodySerializer, LineField, SerializationProfile};
+
+        let buf = SegmentedPoolBufBuilder::new()
+            .segment_size(2048)
+            .initial_capacity(8192)
+            .build();
+        let mut line = Line::builder()
+            .line("hello")
+            .app("app")
+            .labels(KeyValueMap::new().add("a", "b"))
+            .build()
+            .unwrap();
+        line.timestamp = 1;
+
+        let profile = SerializationProfile::new()
+            .rename(LineField::Labels, "labels")
+            .rename(LineField::Line, "message")
+            .omit(LineField::App);
+        let mut se = IngestBodySerializer::from_buffer(buf)
+            .unwrap()
+            .with_profile(profile);
+        tokio_test::block_on(se.write_line(&line)).unwrap();
+
+        let mut buf = String::new();
+        se.end().unwrap().reader().read_to_string(&mut buf).unwrap();
+        assert_eq!(
+            buf,
+            r#"{"lines":[{"labels":{"a":"b"},"message":"hello","timestamp":1}]}"#
+        );
+    }
+
+    #[test]
+    fn invalid_utf8_policy() {
+        use crate::serialize::{IngestBodySerializer, LineData, SerializationProfile, Utf8Policy};
+
+        struct Raw(&'static [u8]);
+
+        impl LineData for Raw {
+            fn line(&self) -> &[u8] {
+                self.0
+            }
+            fn timestamp(&self) -> i64 {
+                1
+            }
+        }
+
+        let write = |policy| {
+            let buf = SegmentedPoolBufBuilder::new()
+                .segment_size(2048)
+                .initial_capacity(8192)
+                .build();
+            let mut se = IngestBodySerializer::from_buffer(buf)
+                .unwrap()
+                .with_profile(SerializationProfile::new().utf8_policy(policy));
+            tokio_test::block_on(se.write_line(&Raw(b"bad \xff"))).map(|_| {
+                let mut buf = String::new();
+                se.end().unwrap().reader().read_to_string(&mut buf).unwrap();
+                buf
+            })
+        };
+        assert_eq!(
+            write(Utf8Policy::Lossy).unwrap(),
+            r#"{"lines":[{"line":"bad �","timestamp":1}]}"#
+        );
+        assert!(write(Utf8Policy::Strict).is_err());
+        assert_eq!(
+            write(Utf8Policy::ReplaceAndFlag).unwrap(),
+            r#"{"lines":[{"label":{"invalid_utf8":"true"},"line":"bad �","timestamp":1}]}"#
+        );
+
+        let mut builder = Line::builder().utf8_policy(Utf8Policy::ReplaceAndFlag);
+        builder.set_line_buffer(b"bad \xff".to_vec()).unwrap();
+        let line = builder.build().unwrap();
+        assert_eq!(line.line, "bad \u{fffd}");
+        assert_eq!(line.labels.unwrap().0["invalid_utf8"], "true");
+        assert!(Line::builder()
+            .set_line_buffer(b"bad \xff".to_vec())
+            .is_err());
+    }
+
+    #[test]
+    fn monotonic_timestamps_never_go_backwards() {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let line = || {
+            Line::builder()
+                .line("hello")
+                .monotonic_timestamp(true)
+                .build()
+                .unwrap()
+        };
+        assert!(line().timestamp >= now);
+
+        // as if the system clock stepped back by an hour
+        let ahead = (now + 3600) * 1000;
+        MONOTONIC_CLOCK.next(ahead);
+        let first = line().timestamp;
+        assert!(first >= now + 3600);
+        assert!(line().timestamp >= first);
+        assert!(Line::builder().line("hello").build().unwrap().timestamp < now + 3600);
+    }
+
+    #[test]
+    fn monotonic_clock_from_many_threads() {
+        let clock = Arc::new(MonotonicClock::default());
+        let began = Instant::now();
+        let start = clock.next(1_000_000);
+
+        // the wall clock stays behind, every timestamp is carried on by the monotonic clock
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let clock = clock.clone();
+                std::thread::spawn(move || (0..10_000).map(|_| clock.next(0)).collect::<Vec<_>>())
+            })
+            .collect();
+        for handle in handles {
+            let issued = handle.join().unwrap();
+            assert!(issued[0] >= start);
+            assert!(issued.windows(2).all(|pair| pair[0] <= pair[1]));
+        }
+        let last = clock.next(0);
+        assert!(last >= start);
+        assert!(last <= start + began.elapsed().as_millis() as i64);
+    }
+
+    #[test]
+    fn aborted_serializer_buffer_is_reused() {
+        use crate::serialize::IngestBodySerializer;
+
+        let buf = SegmentedPoolBufBuilder::new()
+            .segment_size(1024)
+            .initial_capacity(8192)
+            .build();
+        let line = |l: &str| Line::builder().line(l).build().unwrap();
+
+        let mut se = IngestBodySerializer::from_buffer(buf).unwrap();
+        for _ in 0..100 {
+            tokio_test::block_on(se.write_line(&line("discarded"))).unwrap();
+        }
+        let buf = se.abort();
+        assert!(buf.is_empty());
+        let segments = buf.segment_count();
+
+        let mut se = IngestBodySerializer::from_buffer(buf).unwrap();
+        tokio_test::block_on(se.write_line(&line("kept"))).unwrap();
+        let buf = se.end().unwrap();
+        assert_eq!(buf.segment_count(), segments);
+
+        let mut serialized = String::new();
+        buf.reader().read_to_string(&mut serialized).unwrap();
+        assert_eq!(
+            serialized,
+            serde_json::to_string(&IngestBody::new(vec![line("kept")])).unwrap()
+        );
+    }
+
+    #[test]
+    fn body_stream_splits_at_thresholds() {
+        use crate::serialize::{body_stream, SegmentedPoolBufBuilder};
+        use futures::StreamExt;
+
+        let lines: Vec<Line> = (0..10)
+            .map(|i| Line::builder().line(format!("line {}", i)).build().unwrap())
+            .collect();
+        let collect = |max_bytes, max_lines| {
+            let bodies = body_stream(
+                futures::stream::iter(lines.iter()),
+                SegmentedPoolBufBuilder::new().segment_size(1024),
+                max_bytes,
+                max_lines,
+            );
+            tokio_test::block_on(bodies.collect::<Vec<_>>())
+                .into_iter()
+                .map(|body| body.unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let bodies = collect(usize::MAX, 4);
+        let counts: Vec<_> = bodies.iter().map(|b| b.line_count().unwrap()).collect();
+        assert_eq!(counts, vec![4, 4, 2]);
+        let decoded: Vec<Line> = bodies
+            .iter()
+            .flat_map(|b| IngestBody::from_buffer(b).unwrap().into_lines())
+            .collect();
+        assert_eq!(decoded, lines);
+
+        // every line crosses a 1 byte threshold
+        assert_eq!(collect(1, usize::MAX).len(), 10);
+        assert_eq!(collect(usize::MAX, usize::MAX).len(), 1);
+        let none = body_stream(
+            futures::stream::iter(Vec::<&Line>::new()),
+            SegmentedPoolBufBuilder::new(),
+            1,
+            1,
+        );
+        assert_eq!(tokio_test::block_on(none.count()), 0);
+    }
+
+    #[test]
+    fn line_data_serializes_like_line() {
+        use crate::serialize::{IngestBodySerializer, LineData, SegmentedPoolBufBuilder};
+
+        struct Record {
+            message: String,
+            labels: HashMap<String, String>,
+        }
+
+        impl LineData for Record {
+            fn line(&self) -> &[u8] {
+                self.message.as_bytes()
+            }
+            fn timestamp(&self) -> i64 {
+                1
+            }
+            fn app(&self) -> Option<&str> {
+                Some("app")
+            }
+            fn labels(&self) -> Option<&HashMap<String, String>> {
+                Some(&self.labels)
+            }
+        }
+
+        let record = Record {
+            message: "hello".into(),
+            labels: vec![("a".to_string(), "b".to_string())]
+                .into_iter()
+                .collect(),
+        };
+        let mut se =
+            IngestBodySerializer::from_buffer(SegmentedPoolBufBuilder::new().build()).unwrap();
+        tokio_test::block_on(se.write_line(&record)).unwrap();
+
+        let mut line = Line::builder()
+            .line("hello")
+            .app("app")
+            .labels(KeyValueMap::new().add("a", "b"))
+            .build()
+            .unwrap();
+        line.timestamp = 1;
+        let mut buf = String::new();
+        se.end().unwrap().reader().read_to_string(&mut buf).unwrap();
+        assert_eq!(
+            buf,
+            serde_json::to_string(&IngestBody::new(vec![line])).unwrap()
+        );
+    }
+
+    #[test]
+    fn line_ref_serializes_like_line() {
+        use crate::serialize::{IngestBodySerializer, SegmentedPoolBufBuilder};
+
+        let line = Line::builder()
+            .line("hello")
+            .app("app")
+            .level("INFO")
+            .labels(KeyValueMap::new().add("a", "b"))
+            .meta(serde_json::json!({"k": 1}))
+            .build()
+            .unwrap();
+        let line_ref = LineRef::from(&line);
+        assert!(matches!(line_ref.line, Cow::Borrowed(_)));
+
+        let mut se =
+            IngestBodySerializer::from_buffer(SegmentedPoolBufBuilder::new().build()).unwrap();
+        tokio_test::block_on(se.write_line(&line_ref)).unwrap();
+        let mut buf = String::new();
+        se.end().unwrap().reader().read_to_string(&mut buf).unwrap();
+        assert_eq!(
+            buf,
+            serde_json::to_string(&IngestBody::new(vec![line.clone()])).unwrap()
+        );
+        assert_eq!(line_ref.into_owned(), line);
+    }
+
+    #[test]
+    fn shared_line_clones_share_the_line() {
+        use crate::serialize::{IngestBodySerializer, SegmentedPoolBufBuilder};
+
+        let line = Line::builder()
+            .line("hello")
+            .labels(KeyValueMap::new().add("a", "b"))
+            .build()
+            .unwrap();
+        let shared = SharedLine::from(line.clone());
+        let copy = shared.clone();
+        assert!(SharedLine::ptr_eq(&shared, &copy));
+        assert_eq!(
+            serde_json::to_string(&copy).unwrap(),
+            serde_json::to_string(&line).unwrap()
+        );
+
+        let mut se =
+            IngestBodySerializer::from_buffer(SegmentedPoolBufBuilder::new().build()).unwrap();
+        tokio_test::block_on(se.write_line(&copy)).unwrap();
+        let mut buf = String::new();
+        se.end().unwrap().reader().read_to_string(&mut buf).unwrap();
+        assert_eq!(
+            buf,
+            serde_json::to_string(&IngestBody::new(vec![line.clone()])).unwrap()
+        );
+
+        drop(shared);
+        assert_eq!(copy.into_line(), line);
+    }
 }
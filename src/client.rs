@@ -1,22 +1,529 @@
-use std::time::Duration;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use futures::{Stream, StreamExt};
 use hyper::client::HttpConnector;
 pub use hyper::{body, client::Builder as HyperBuilder, Client as HyperClient};
-use hyper_rustls::{ConfigBuilderExt, HttpsConnector};
-use rustls::client::ClientConfig as TlsClientConfig;
-use tokio::time::timeout;
+use hyper_rustls::HttpsConnector;
 
+use crate::backoff::Backoff;
 use crate::body::IngestBodyBuffer;
-use crate::dns::TrustDnsResolver;
-use crate::error::HttpError;
-use crate::request::RequestTemplate;
-use crate::response::{IngestResponse, Response};
+use crate::config::TemplateConfig;
+use crate::debug_dump::DebugDump;
+use crate::dedup::DedupCache;
+pub use crate::dns::IpPreference;
+use crate::dns::{DnsCache, TrustDnsResolver};
+use crate::enrichment::Enricher;
+#[cfg(feature = "http3")]
+use crate::error::Http3Error;
+use crate::error::{ClientError, HttpError, RequestContext};
+#[cfg(feature = "http3")]
+use crate::http3::Http3Client;
+use crate::metrics::ClientMetrics;
+use crate::observer::IngestObserver;
+use crate::params::{Params, ParamsHandle, Tags};
+use crate::pool::{ConnectionCounters, CountingConnector, PoolStats};
+use crate::processor::ProcessorChain;
+use crate::proxy::{Proxy, ProxyConnector};
+use crate::rate_limit::RateLimiter;
+use crate::redaction::Redactor;
+use crate::redirect::{same_origin, strip_credentials, RedirectPolicy};
+#[cfg(feature = "http3")]
+use crate::request::Transport;
+use crate::request::{RequestTemplate, REQUEST_ID_HEADER};
+use crate::request_log::{RequestLog, RequestSummary};
+use crate::response::{decode_body, failure_reason, IngestResponse, Response};
+use crate::retry::{is_retryable, RetryPolicy};
+use crate::runtime::{timeout, Timer, TokioTimer};
+use crate::tls::{AlpnProtocols, TlsFiles, TlsOptions, TlsReload};
+
+const DEFAULT_TLS_RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+const DEFAULT_HAPPY_EYEBALLS_TIMEOUT: Duration = Duration::from_millis(300);
+const DEFAULT_MAX_ERROR_BODY_SIZE: usize = 64 * 1024;
+const DEFAULT_TCP_KEEPALIVE: Duration = Duration::from_secs(120);
+const DEFAULT_POOL_MAX_IDLE_PER_HOST: usize = 20;
+const DEFAULT_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+type IngestHyperClient = HyperClient<
+    HttpsConnector<CountingConnector<ProxyConnector<HttpConnector<TrustDnsResolver>>>>,
+    IngestBodyBuffer,
+>;
+
+/// Used to build a Client with connection settings beyond [`Client::new`]
+///
+/// # Example
+///
+/// ```rust
+/// # use logdna_client::client::Client;
+/// # use logdna_client::params::Params;
+/// # use logdna_client::request::RequestTemplate;
+/// # let params = Params::builder().hostname("rust-client-test").build().unwrap();
+/// # let template = RequestTemplate::builder().params(params).api_key("key").build().unwrap();
+/// let client = Client::builder(template)
+///     .system_proxy(true)
+///     .build()
+///     .expect("Client::builder()");
+/// ```
+pub struct ClientBuilder {
+    template: RequestTemplate,
+    connector: ConnectorOptions,
+    system_proxy: bool,
+    tls_reload_interval: Option<Duration>,
+    redirect_policy: RedirectPolicy,
+    max_error_body_size: usize,
+    debug_dump: Option<DebugDump>,
+    request_log: RequestLog,
+    offload_encoding: bool,
+    dedup: Option<DedupCache>,
+    processors: ProcessorChain,
+    enricher: Option<Enricher>,
+    redactor: Option<Redactor>,
+}
+
+impl ClientBuilder {
+    /// Constructs a ClientBuilder requiring tls and connecting directly
+    pub fn new(template: RequestTemplate) -> Self {
+        Self {
+            template,
+            connector: ConnectorOptions {
+                require_tls: true,
+                proxy: None,
+                tls_server_name: None,
+                tls: TlsOptions::default(),
+                nameservers: None,
+                dns_cache: None,
+                ip_preference: IpPreference::default(),
+                happy_eyeballs_timeout: Some(DEFAULT_HAPPY_EYEBALLS_TIMEOUT),
+                tcp_keepalive: Some(DEFAULT_TCP_KEEPALIVE),
+                tcp_keepalive_interval: None,
+                tcp_keepalive_retries: None,
+                http2_keep_alive: None,
+                pool_max_idle_per_host: DEFAULT_POOL_MAX_IDLE_PER_HOST,
+                pool_idle_timeout: Some(DEFAULT_POOL_IDLE_TIMEOUT),
+                counters: Arc::new(ConnectionCounters::default()),
+                timer: Arc::new(TokioTimer),
+            },
+            system_proxy: false,
+            tls_reload_interval: Some(DEFAULT_TLS_RELOAD_INTERVAL),
+            redirect_policy: RedirectPolicy::default(),
+            max_error_body_size: DEFAULT_MAX_ERROR_BODY_SIZE,
+            debug_dump: None,
+            request_log: RequestLog::default(),
+            offload_encoding: false,
+            dedup: None,
+            processors: ProcessorChain::new(),
+            enricher: None,
+            redactor: None,
+        }
+    }
+    /// Set whether plain http ingest hosts are refused, default is true
+    pub fn require_tls(mut self, require_tls: bool) -> Self {
+        self.connector.require_tls = require_tls;
+        self
+    }
+    /// Connect through the proxy, takes precedence over the system proxy
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.connector.proxy = Some(proxy);
+        self
+    }
+    /// Set whether to use the proxy from `HTTPS_PROXY`/`HTTP_PROXY`/`NO_PROXY`, default is false
+    ///
+    /// The variables are read once when the client is built.
+    pub fn system_proxy(mut self, system_proxy: bool) -> Self {
+        self.system_proxy = system_proxy;
+        self
+    }
+    /// Set which `307`/`308` redirects are followed, default is none
+    pub fn redirect_policy(mut self, redirect_policy: RedirectPolicy) -> Self {
+        self.redirect_policy = redirect_policy;
+        self
+    }
+    /// Set the bytes of an error response body kept as the failure reason, default is 64KiB
+    ///
+    /// The rest of the body is not read and the reason ends with
+    /// [`TRUNCATED_SUFFIX`](crate::response::TRUNCATED_SUFFIX).
+    pub fn max_error_body_size(mut self, max_error_body_size: usize) -> Self {
+        self.max_error_body_size = max_error_body_size;
+        self
+    }
+    /// Write every request sent to files, with the ingestion key redacted
+    pub fn debug_dump(mut self, debug_dump: DebugDump) -> Self {
+        self.debug_dump = Some(debug_dump);
+        self
+    }
+    /// Set the levels every request is logged at, see [`RequestLog`]
+    pub fn request_log(mut self, request_log: RequestLog) -> Self {
+        self.request_log = request_log;
+        self
+    }
+    /// Refuse to send bodies identical to one sent within a window, see [`DedupCache`]
+    pub fn dedup(mut self, dedup: DedupCache) -> Self {
+        self.dedup = Some(dedup);
+        self
+    }
+    /// Run every line of a body through the processors before it is serialized, default is none
+    ///
+    /// Bodies passed to [`Client::send_serialized`] or already serialized are sent as they are.
+    pub fn processors(mut self, processors: ProcessorChain) -> Self {
+        self.processors = processors;
+        self
+    }
+    /// Stamp every line with the enricher's fields before the processors run, default is none
+    ///
+    /// Like the processors, only applies to bodies the client serializes.
+    pub fn enricher(mut self, enricher: Enricher) -> Self {
+        self.enricher = Some(enricher);
+        self
+    }
+    /// Redact every line after the enricher and processors ran, default is none
+    ///
+    /// Runs last so values they added are redacted as well, like the processors it only
+    /// applies to bodies the client serializes.
+    pub fn redactor(mut self, redactor: Redactor) -> Self {
+        self.redactor = Some(redactor);
+        self
+    }
+    /// Set the timer used for request timeouts, retry and rate limit waits and the resolver's
+    /// backoff, default is [`TokioTimer`]
+    ///
+    /// See [`Timer`] for what still needs a Tokio runtime.
+    pub fn timer(mut self, timer: Arc<dyn Timer>) -> Self {
+        self.connector.timer = timer;
+        self
+    }
+    /// Set whether bodies are serialized and compressed off the async worker threads
+    ///
+    /// Both run on tokio's blocking pool. An [`IngestBody`](crate::body::IngestBody) is moved
+    /// there and a borrowed one cloned, other bodies are still serialized in place.
+    /// Default is false.
+    pub fn offload_encoding(mut self, offload_encoding: bool) -> Self {
+        self.offload_encoding = offload_encoding;
+        self
+    }
+    /// Set the name the server certificate is validated against and sent as SNI
+    ///
+    /// Defaults to the host of the request template, overriding it allows connecting to an ip
+    /// address or internal load balancer while still validating the real certificate name.
+    pub fn tls_server_name<T: Into<String>>(mut self, tls_server_name: T) -> Self {
+        self.connector.tls_server_name = Some(tls_server_name.into());
+        self
+    }
+    /// Set the HTTP versions offered during the TLS handshake, default is both HTTP/1.1 and 2
+    pub fn alpn_protocols(mut self, alpn_protocols: AlpnProtocols) -> Self {
+        self.connector.tls.alpn_protocols = alpn_protocols;
+        self
+    }
+    /// Resolve the ingest host with these nameservers instead of the system configuration
+    ///
+    /// Useful where `/etc/resolv.conf` is missing or wrong, e.g minimal containers.
+    pub fn nameservers(mut self, nameservers: Vec<SocketAddr>) -> Self {
+        self.connector.nameservers = Some(nameservers);
+        self
+    }
+    /// Cache resolved addresses for their TTL, at most `max_ttl`, and failures for `negative_ttl`
+    ///
+    /// Saves resolving the ingest host for every new connection, see
+    /// [`Client::clear_dns_cache`] to force a new lookup. Failed lookups aren't retried with
+    /// backoff as they are without the cache, new connections fail fast until `negative_ttl`
+    /// expires.
+    pub fn dns_cache(mut self, max_ttl: Duration, negative_ttl: Duration) -> Self {
+        self.connector.dns_cache = Some(Arc::new(DnsCache::new(max_ttl, negative_ttl)));
+        self
+    }
+    /// Set which address families are connected to and in which order, default is resolver order
+    pub fn ip_preference(mut self, ip_preference: IpPreference) -> Self {
+        self.connector.ip_preference = ip_preference;
+        self
+    }
+    /// Set how long to wait on the first address family before racing the other, default is 300ms
+    ///
+    /// `None` disables racing, every address is tried one after the other.
+    pub fn happy_eyeballs_timeout(mut self, happy_eyeballs_timeout: Option<Duration>) -> Self {
+        self.connector.happy_eyeballs_timeout = happy_eyeballs_timeout;
+        self
+    }
+    /// Set the idle time before TCP keepalive probes are sent, `None` disables them
+    ///
+    /// Keeps NAT and firewall idle timeouts from silently dropping pooled connections.
+    /// Default is 120s.
+    pub fn tcp_keepalive(mut self, idle: Option<Duration>) -> Self {
+        self.connector.tcp_keepalive = idle;
+        self
+    }
+    /// Set the time between TCP keepalive probes, default is the system setting
+    pub fn tcp_keepalive_interval(mut self, interval: Option<Duration>) -> Self {
+        self.connector.tcp_keepalive_interval = interval;
+        self
+    }
+    /// Set how many unanswered TCP keepalive probes close the connection, default is the system
+    /// setting
+    pub fn tcp_keepalive_retries(mut self, retries: Option<u32>) -> Self {
+        self.connector.tcp_keepalive_retries = retries;
+        self
+    }
+    /// Send HTTP/2 pings every `interval`, closing connections that don't answer within `timeout`
+    ///
+    /// Pings are sent on idle pooled connections as well, `None` disables them. Default is
+    /// `None`.
+    pub fn http2_keep_alive(mut self, interval: Option<Duration>, timeout: Duration) -> Self {
+        self.connector.http2_keep_alive = interval.map(|interval| (interval, timeout));
+        self
+    }
+    /// Set how many idle connections to the ingest host are kept for reuse, default is 20
+    pub fn pool_max_idle_per_host(mut self, max_idle: usize) -> Self {
+        self.connector.pool_max_idle_per_host = max_idle;
+        self
+    }
+    /// Set how long an idle connection is kept for reuse, `None` keeps it until closed
+    ///
+    /// Should be shorter than the idle timeout of the ingest host or any load balancer in
+    /// front of it. Default is 90s.
+    pub fn pool_idle_timeout(mut self, idle_timeout: Option<Duration>) -> Self {
+        self.connector.pool_idle_timeout = idle_timeout;
+        self
+    }
+    /// Use a private CA bundle and/or client certificate read from PEM files
+    pub fn tls_files(mut self, tls_files: TlsFiles) -> Self {
+        self.connector.tls.files = Some(tls_files);
+        self
+    }
+    /// Set how often the tls files are checked for changes, `None` never reloads them
+    ///
+    /// When a file changed the connector is rebuilt before the next request, requests already
+    /// in flight finish on their old connection, HTTP/3 connections are reopened as well.
+    /// Default is every 60s.
+    pub fn tls_reload_interval(mut self, tls_reload_interval: Option<Duration>) -> Self {
+        self.tls_reload_interval = tls_reload_interval;
+        self
+    }
+    /// Accept any server certificate, leaving the connection open to interception
+    ///
+    /// Only meant for lab or staging ingest endpoints with self-signed certificates.
+    #[cfg(feature = "dangerous-tls")]
+    pub fn danger_accept_invalid_certs(mut self, accept_invalid_certs: bool) -> Self {
+        self.connector.tls.verifier = if accept_invalid_certs {
+            Some(Arc::new(crate::tls::NoVerification))
+        } else {
+            None
+        };
+        self
+    }
+    /// Accept only a server presenting exactly this DER encoded certificate
+    ///
+    /// Replaces validation against the system roots, the certificate is not checked for
+    /// expiry or its name.
+    #[cfg(feature = "dangerous-tls")]
+    pub fn danger_pin_certificate(mut self, der: Vec<u8>) -> Self {
+        self.connector.tls.verifier = Some(Arc::new(crate::tls::PinnedCertificate(der)));
+        self
+    }
+    /// Build a Client using the current builder
+    ///
+    /// Fails if the system DNS configuration or the tls files can't be read, the system DNS
+    /// configuration is not needed when nameservers are set.
+    pub fn build(self) -> Result<Client, ClientError> {
+        let mut connector = self.connector;
+        if connector.proxy.is_none() && self.system_proxy {
+            connector.proxy = Proxy::from_env();
+        }
+        let tls_reload = match (connector.tls.files.as_ref(), self.tls_reload_interval) {
+            (Some(files), Some(interval)) => {
+                Some(Arc::new(TlsReload::new(files.clone(), interval)))
+            }
+            _ => None,
+        };
+
+        let enricher = self.enricher.filter(|enricher| !enricher.is_empty());
+        let redactor = self.redactor.filter(|redactor| !redactor.is_empty());
+        let processors = match (enricher, redactor) {
+            (None, None) => self.processors,
+            (enricher, redactor) => {
+                let mut chain = ProcessorChain::new();
+                if let Some(enricher) = enricher {
+                    chain.push(enricher);
+                }
+                chain.push(self.processors);
+                if let Some(redactor) = redactor {
+                    chain.push(redactor);
+                }
+                chain
+            }
+        };
+
+        #[cfg(feature = "http3")]
+        let http3 = match self.template.transport {
+            Transport::Http3 => Some(Http3Client::new(
+                connector.tls.client_config()?,
+                connector.tls_server_name.clone(),
+                connector.resolver()?,
+            )?),
+            _ => None,
+        };
+
+        let timer = connector.timer.clone();
+        Ok(Client {
+            hyper: Mutex::new(Some(connector.hyper_client()?)),
+            #[cfg(feature = "http3")]
+            http3,
+            connector,
+            tls_reload,
+            in_flight: AtomicUsize::new(0),
+            params: ParamsHandle::new(self.template.params.clone()),
+            template: Arc::new(self.template),
+            timeout: Duration::from_secs(5),
+            observer: None,
+            timer,
+            metrics: Arc::new(ClientMetrics::default()),
+            rate_limiter: None,
+            redirect_policy: self.redirect_policy,
+            max_error_body_size: self.max_error_body_size,
+            debug_dump: self.debug_dump,
+            request_log: self.request_log,
+            offload_encoding: self.offload_encoding,
+            dedup: self.dedup,
+            processors: Some(processors)
+                .filter(|processors| !processors.is_empty())
+                .map(Arc::new),
+        })
+    }
+}
+
+// Everything needed to rebuild the hyper client, e.g after the tls files changed
+#[derive(Clone)]
+struct ConnectorOptions {
+    require_tls: bool,
+    proxy: Option<Proxy>,
+    tls_server_name: Option<String>,
+    tls: TlsOptions,
+    nameservers: Option<Vec<SocketAddr>>,
+    dns_cache: Option<Arc<DnsCache>>,
+    ip_preference: IpPreference,
+    happy_eyeballs_timeout: Option<Duration>,
+    tcp_keepalive: Option<Duration>,
+    tcp_keepalive_interval: Option<Duration>,
+    tcp_keepalive_retries: Option<u32>,
+    // ping interval and timeout
+    http2_keep_alive: Option<(Duration, Duration)>,
+    pool_max_idle_per_host: usize,
+    pool_idle_timeout: Option<Duration>,
+    // shared by every rebuilt hyper client
+    counters: Arc<ConnectionCounters>,
+    timer: Arc<dyn Timer>,
+}
+
+impl ConnectorOptions {
+    // Shared by the hyper connector and the HTTP/3 client, so both follow the dns settings
+    fn resolver(&self) -> Result<TrustDnsResolver, ClientError> {
+        let dns_resolver = match self.nameservers.as_ref() {
+            Some(nameservers) => TrustDnsResolver::with_nameservers(nameservers),
+            None => TrustDnsResolver::new().map_err(ClientError::Dns)?,
+        };
+        let dns_resolver = match self.dns_cache.as_ref() {
+            Some(dns_cache) => dns_resolver.with_cache(dns_cache.clone()),
+            None => dns_resolver,
+        };
+        Ok(dns_resolver
+            .with_ip_preference(self.ip_preference)
+            .with_timer(self.timer.clone()))
+    }
+
+    fn hyper_client(&self) -> Result<IngestHyperClient, ClientError> {
+        let dns_resolver = self.resolver()?;
+        let http_connector = {
+            let mut connector = HttpConnector::new_with_resolver(dns_resolver);
+            connector.set_happy_eyeballs_timeout(self.happy_eyeballs_timeout);
+            connector.enforce_http(false); // this is needed or https:// urls will error
+            connector.set_reuse_address(true);
+            connector.set_keepalive(self.tcp_keepalive);
+            connector.set_keepalive_interval(self.tcp_keepalive_interval);
+            connector.set_keepalive_retries(self.tcp_keepalive_retries);
+            connector
+        };
+        let proxy_connector = ProxyConnector::new(http_connector, self.proxy.clone());
+        let counting_connector = CountingConnector::new(proxy_connector, self.counters.clone());
+
+        let tls_config = self.tls.client_config()?;
+
+        let https_connector_builder =
+            hyper_rustls::HttpsConnectorBuilder::new().with_tls_config(tls_config);
+        let https_connector_builder = if self.require_tls {
+            https_connector_builder.https_only()
+        } else {
+            https_connector_builder.https_or_http()
+        };
+        let https_connector_builder = match self.tls_server_name.clone() {
+            Some(tls_server_name) => https_connector_builder.with_server_name(tls_server_name),
+            None => https_connector_builder,
+        };
+        let https_connector = match self.tls.alpn_protocols {
+            AlpnProtocols::Http1AndHttp2 => https_connector_builder
+                .enable_http1()
+                .enable_http2()
+                .wrap_connector(counting_connector),
+            AlpnProtocols::Http1Only => https_connector_builder
+                .enable_http1()
+                .wrap_connector(counting_connector),
+            AlpnProtocols::Http2Only => https_connector_builder
+                .enable_http2()
+                .wrap_connector(counting_connector),
+        };
+
+        let mut builder = HyperClient::builder();
+        builder
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .pool_idle_timeout(self.pool_idle_timeout)
+            .http2_only(self.tls.alpn_protocols == AlpnProtocols::Http2Only);
+        if let Some((interval, timeout)) = self.http2_keep_alive {
+            builder
+                .http2_keep_alive_interval(interval)
+                .http2_keep_alive_timeout(timeout)
+                .http2_keep_alive_while_idle(true);
+        }
+        Ok(builder.build(https_connector))
+    }
+}
+
+// Reads up to `limit` bytes of a body, and whether the rest was left unread
+async fn read_limited(mut body: body::Body, limit: usize) -> Result<(Vec<u8>, bool), hyper::Error> {
+    use hyper::body::HttpBody;
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if bytes.len() + chunk.len() > limit {
+            bytes.extend_from_slice(&chunk[..limit - bytes.len()]);
+            return Ok((bytes, true));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok((bytes, false))
+}
 
 /// Client for sending IngestRequests to LogDNA
 pub struct Client {
-    hyper: HyperClient<HttpsConnector<HttpConnector<TrustDnsResolver>>, IngestBodyBuffer>,
-    template: RequestTemplate,
+    // None once the client is shut down
+    hyper: Mutex<Option<IngestHyperClient>>,
+    #[cfg(feature = "http3")]
+    http3: Option<Http3Client>,
+    connector: ConnectorOptions,
+    tls_reload: Option<Arc<TlsReload>>,
+    in_flight: AtomicUsize,
+    template: Arc<RequestTemplate>,
+    params: ParamsHandle,
     timeout: Duration,
+    observer: Option<Arc<dyn IngestObserver>>,
+    timer: Arc<dyn Timer>,
+    metrics: Arc<ClientMetrics>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    redirect_policy: RedirectPolicy,
+    max_error_body_size: usize,
+    debug_dump: Option<DebugDump>,
+    request_log: RequestLog,
+    offload_encoding: bool,
+    dedup: Option<DedupCache>,
+    processors: Option<Arc<ProcessorChain>>,
 }
 
 impl Client {
@@ -44,44 +551,75 @@ impl Client {
     /// let client = Client::new(request_template);
     /// ```
     pub fn new(template: RequestTemplate, require_tls: Option<bool>) -> Self {
-        let dns_resolver =
-            TrustDnsResolver::new().expect("Could not read system DNS configuration");
-        let http_connector = {
-            let mut connector = HttpConnector::new_with_resolver(dns_resolver);
-            connector.enforce_http(false); // this is needed or https:// urls will error
-            connector.set_reuse_address(true);
-            connector.set_keepalive(Some(std::time::Duration::from_secs(120)));
-            connector
-        };
-
-        let tls_config = TlsClientConfig::builder()
-            .with_safe_defaults()
-            .with_native_roots()
-            .with_no_client_auth();
-
-        let https_connector_builder =
-            hyper_rustls::HttpsConnectorBuilder::new().with_tls_config(tls_config);
-        let https_connector_builder = if require_tls.unwrap_or(true) {
-            https_connector_builder.https_only()
-        } else {
-            https_connector_builder.https_or_http()
-        };
-        let https_connector_builder = https_connector_builder.enable_http1().enable_http2();
-
-        let https_connector = https_connector_builder.wrap_connector(http_connector);
+        Client::builder(template)
+            .require_tls(require_tls.unwrap_or(true))
+            .build()
+            .expect("Could not read system DNS configuration")
+    }
+    /// Constructs a ClientBuilder for configuring the connection in more detail
+    pub fn builder(template: RequestTemplate) -> ClientBuilder {
+        ClientBuilder::new(template)
+    }
+    /// Create a new client from a deserialized TemplateConfig
+    ///
+    /// Applies the configured timeout and tls requirement in addition to the template itself,
+    /// fails like [`ClientBuilder::build`] if the client can't be built.
+    pub fn from_config(config: TemplateConfig) -> Result<Self, ClientError> {
+        use std::convert::TryFrom;
 
-        Client {
-            hyper: HyperClient::builder()
-                .pool_max_idle_per_host(20)
-                .build(https_connector),
-            template,
-            timeout: Duration::from_secs(5),
+        let timeout = config.timeout();
+        let require_tls = config.require_tls;
+        let mut client = Client::builder(RequestTemplate::try_from(config)?)
+            .require_tls(require_tls.unwrap_or(true))
+            .build()?;
+        if let Some(timeout) = timeout {
+            client.set_timeout(timeout);
         }
+        Ok(client)
     }
     /// Sets the request timeout
     pub fn set_timeout(&mut self, timeout: Duration) {
         self.timeout = timeout
     }
+    /// Sets the timer used for request timeouts, retry and rate limit waits
+    ///
+    /// The resolver keeps the timer the client was built with, see [`ClientBuilder::timer`].
+    pub fn set_timer(&mut self, timer: Arc<dyn Timer>) {
+        self.timer = timer
+    }
+    /// The query parameters sent with every request, which can be updated at any time
+    ///
+    /// Initially the template's parameters, [`Client::send_with_params`] overrides them.
+    pub fn params(&self) -> ParamsHandle {
+        self.params.clone()
+    }
+    /// Request latency distributions of every request sent by this client
+    pub fn metrics(&self) -> Arc<ClientMetrics> {
+        self.metrics.clone()
+    }
+    /// Sets the rate limiter every body has to acquire tokens from before it is sent
+    ///
+    /// The lines of bodies without a recorded line count are counted before they are sent, bodies
+    /// that aren't in the ingest format only use byte tokens.
+    pub fn set_rate_limiter(&mut self, rate_limiter: Arc<RateLimiter>) {
+        self.rate_limiter = Some(rate_limiter)
+    }
+    /// The connections currently open and requests in flight
+    pub fn pool_stats(&self) -> PoolStats {
+        self.connector
+            .counters
+            .stats(self.in_flight.load(Ordering::Relaxed))
+    }
+    /// Drops every cached DNS lookup, a no-op unless the DNS cache is enabled
+    pub fn clear_dns_cache(&self) {
+        if let Some(dns_cache) = self.connector.dns_cache.as_ref() {
+            dns_cache.clear();
+        }
+    }
+    /// Sets the observer notified about every request sent
+    pub fn set_observer(&mut self, observer: Arc<dyn IngestObserver>) {
+        self.observer = Some(observer)
+    }
 
     /// Send an IngestBody to the LogDNA Ingest API
     ///
@@ -91,63 +629,513 @@ impl Client {
         T: crate::body::IntoIngestBodyBuffer + Send + Sync,
         T::Error: std::fmt::Debug + std::fmt::Display + Send + Sync + 'static,
     {
-        let body = body
-            .into()
-            .await
-            .map_err(move |e| HttpError::Other(Box::new(e)))?;
-
-        let counts = countme::get::<
-            crate::segmented_buffer::SegmentedBuf<
-                async_buf_pool::Reusable<crate::segmented_buffer::Buffer>,
-            >,
-        >();
-        log::debug!(
-            "live: {}, max_live: {}, total: {}",
-            counts.live,
-            counts.max_live,
-            counts.total
-        );
+        let body = self.serialize(body).await?;
+        self.send_buffer(body, None, 1).await
+    }
+
+    /// Send a body that was already serialized, e.g by an
+    /// [`IngestBodySerializer`](crate::serialize::IngestBodySerializer) or on another thread
+    pub async fn send_serialized(&self, body: IngestBodyBuffer) -> IngestResponse {
+        self.send_buffer(body, None, 1).await
+    }
+
+    /// Send an IngestBody with `params` instead of the template's query parameters
+    ///
+    /// Lets one client send on behalf of many hostnames or tag sets, e.g in an aggregator.
+    pub async fn send_with_params<T>(&self, body: T, params: &Params) -> IngestResponse
+    where
+        T: crate::body::IntoIngestBodyBuffer + Send + Sync,
+        T::Error: std::fmt::Debug + std::fmt::Display + Send + Sync + 'static,
+    {
+        let body = self.serialize(body).await?;
+        self.send_buffer(body, Some(params), 1).await
+    }
+
+    /// Send an IngestBody with `tags` added to the client's tags, for this request only
+    ///
+    /// Suits request scoped tags, e.g a deployment id during a canary. The client's
+    /// [`params`](Client::params) are left untouched, to replace its tags instead use
+    /// [`Client::send_with_params`].
+    pub async fn send_with_tags<T>(&self, body: T, tags: &Tags) -> IngestResponse
+    where
+        T: crate::body::IntoIngestBodyBuffer + Send + Sync,
+        T::Error: std::fmt::Debug + std::fmt::Display + Send + Sync + 'static,
+    {
+        let mut params = Params::clone(&self.params.load());
+        params.tags.get_or_insert_with(Tags::new).merge(tags);
+        self.send_with_params(body, &params).await
+    }
+
+    /// Send an IngestBody, retrying failed attempts as allowed by the policy
+    ///
+    /// Timeouts, connection errors, `429` and `5xx` responses are retried with exponential
+    /// backoff until the policy's attempts or total retry budget run out, the last response is
+    /// returned and the observer's [`on_give_up`](IngestObserver::on_give_up) is called.
+    pub async fn send_with_retry<T>(&self, body: T, policy: &RetryPolicy) -> IngestResponse
+    where
+        T: crate::body::IntoIngestBodyBuffer + Send + Sync,
+        T::Error: std::fmt::Debug + std::fmt::Display + Send + Sync + 'static,
+    {
+        let mut body = self.serialize(body).await?;
+        // every attempt shares the frozen bytes instead of copying the body
+        body.share();
 
-        let request = self.template.new_request(&body).await?;
-        let timeout = timeout(self.timeout, self.hyper.request(request));
+        let start = Instant::now();
+        let mut backoff = policy.backoff();
+        let mut attempt = 1;
+        loop {
+            let response = self.send_buffer(body.clone(), None, attempt).await;
+            if !is_retryable(&response) {
+                return response;
+            }
+            let delay = match backoff.next_backoff() {
+                Some(delay)
+                    if !policy.attempts_exhausted(attempt)
+                        && policy.within_budget(start.elapsed() + delay) =>
+                {
+                    delay
+                }
+                _ => {
+                    if let Some(observer) = self.observer.as_ref() {
+                        observer.on_give_up(&body, attempt);
+                    }
+                    return response;
+                }
+            };
+            if let Some(observer) = self.observer.as_ref() {
+                observer.on_retry(attempt, delay);
+            }
+            self.timer.sleep(delay).await;
+            attempt += 1;
+        }
+    }
 
-        let result = match timeout.await {
-            Ok(result) => result,
-            Err(_) => {
-                return Err(HttpError::Timeout(body));
+    async fn serialize<T>(&self, body: T) -> Result<IngestBodyBuffer, HttpError>
+    where
+        T: crate::body::IntoIngestBodyBuffer + Send + Sync,
+        T::Error: std::fmt::Debug + std::fmt::Display + Send + Sync + 'static,
+    {
+        let result = if self.offload_encoding {
+            body.into_offloaded(self.processors.clone()).await
+        } else {
+            match self.processors.as_ref() {
+                Some(processors) => body.into_processed(processors).await,
+                None => body.into().await,
             }
         };
+        result.map_err(move |e| HttpError::Other(Box::new(e)))
+    }
 
-        let response = match result {
-            Ok(response) => response,
-            Err(e) => {
-                return Err(HttpError::Send(body, e));
+    async fn new_request(
+        &self,
+        body: IngestBodyBuffer,
+        params: Option<&Params>,
+    ) -> Result<hyper::Request<IngestBodyBuffer>, HttpError> {
+        let current;
+        let params = match params {
+            Some(params) => params,
+            None => {
+                current = self.params.load();
+                &*current
             }
         };
+        if !self.offload_encoding {
+            return Ok(self.template.new_request_with_params(body, params).await?);
+        }
+        let template = self.template.clone();
+        let params = params.clone();
+        let request = tokio::task::spawn_blocking(move || {
+            futures::executor::block_on(template.new_request_with_params(body, &params))
+        })
+        .await
+        .map_err(|e| HttpError::Other(Box::new(e)))?;
+        Ok(request?)
+    }
+
+    async fn send_buffer(
+        &self,
+        mut body: IngestBodyBuffer,
+        params: Option<&Params>,
+        attempt: u32,
+    ) -> IngestResponse {
+        let _in_flight = InFlight::enter(&self.in_flight);
+        // every request of this send shares the bytes of the body
+        body.share();
+        self.reload_tls().await;
+        let hyper = match self.hyper() {
+            Some(hyper) => hyper,
+            None => return Err(HttpError::Shutdown(Box::new(body))),
+        };
+        // released if this send fails, so the body can be sent again
+        let reservation = match self.dedup.as_ref() {
+            Some(dedup) => match dedup.reserve(crate::dedup::key(&body, params)) {
+                Some(reservation) => Some(reservation),
+                None => {
+                    self.metrics.record_duplicate();
+                    return Err(HttpError::Duplicate(Box::new(body)));
+                }
+            },
+            None => None,
+        };
 
-        let counts = countme::get::<
-            crate::segmented_buffer::SegmentedBuf<
-                async_buf_pool::Reusable<crate::segmented_buffer::Buffer>,
-            >,
-        >();
-        log::debug!(
-            "live: {}, max_live: {}, total: {}",
-            counts.live,
-            counts.max_live,
-            counts.total
-        );
+        let bytes = body.len();
+        let limits_lines = self
+            .rate_limiter
+            .as_ref()
+            .map_or(false, |r| r.limits_lines());
+        if body.line_count().is_none() && limits_lines {
+            // recorded on the body, so retries don't count again
+            match body.count_lines() {
+                Ok(count) => body = body.with_line_count(count),
+                Err(e) => log::debug!("could not count the lines of the body: {}", e),
+            }
+        }
+        let lines = body.line_count();
+        if let Some(rate_limiter) = self.rate_limiter.as_ref() {
+            let acquired = rate_limiter
+                .acquire(&*self.timer, lines.unwrap_or(0), bytes)
+                .await;
+            if let Err(wait) = acquired {
+                return Err(HttpError::RateLimited(Box::new(body), wait));
+            }
+        }
+        if let Some(observer) = self.observer.as_ref() {
+            observer.on_batch_start(bytes, lines);
+        }
+        let start = Instant::now();
+
+        // redirects resend the encoded request, with the body shared rather than encoded again
+        let (mut parts, mut encoded) = self.new_request(body.clone(), params).await?.into_parts();
+        encoded.share();
+        let origin = parts.uri.clone();
+        let mut redirects = 0;
+        let (response, summary) = loop {
+            let mut request = hyper::Request::new(encoded.clone());
+            *request.method_mut() = parts.method.clone();
+            *request.uri_mut() = parts.uri.clone();
+            *request.version_mut() = parts.version;
+            *request.headers_mut() = parts.headers.clone();
+            if let Some(debug_dump) = self.debug_dump.as_ref() {
+                debug_dump.write(&request).await;
+            }
+            let summary = RequestSummary::new(&request, bytes);
+            let request_id = request
+                .headers()
+                .get(REQUEST_ID_HEADER)
+                .and_then(|id| id.to_str().ok())
+                .unwrap_or_default()
+                .to_string();
+            let uri = request.uri().clone();
+            let context = || RequestContext {
+                request_id: request_id.clone(),
+                uri: uri.to_string(),
+                elapsed: start.elapsed(),
+                attempt,
+                body_size: bytes,
+            };
+            let timeout = timeout(&*self.timer, self.timeout, self.dispatch(&hyper, request));
+
+            let result = match timeout.await {
+                Some(result) => result,
+                None => {
+                    self.notify_failed(None, start);
+                    self.request_log.log(&summary, None, start.elapsed());
+                    return Err(HttpError::Timeout(Box::new(body), context()));
+                }
+            };
+
+            let response = match result {
+                Ok(response) => response,
+                Err(e) => {
+                    self.notify_failed(None, start);
+                    self.request_log.log(&summary, None, start.elapsed());
+                    return Err(match e {
+                        SendError::Hyper(e) => HttpError::Send(Box::new(body), e, context()),
+                        #[cfg(feature = "http3")]
+                        SendError::Http3(e) => HttpError::Http3(Box::new(body), e, context()),
+                    });
+                }
+            };
+
+            let next =
+                self.redirect_policy
+                    .follow(&uri, response.status(), response.headers(), redirects);
+            match next {
+                Some(next) => {
+                    log::debug!("following {} redirect to {}", response.status(), next);
+                    // drain the body so the connection goes back to the pool
+                    let _ = body::to_bytes(response.into_body()).await;
+                    if !same_origin(&origin, &next) {
+                        strip_credentials(&mut parts.headers);
+                    }
+                    parts.uri = next;
+                    redirects += 1;
+                }
+                None => break (response, summary),
+            }
+        };
 
         let status_code = response.status();
+        self.request_log
+            .log(&summary, Some(status_code), start.elapsed());
         let status = status_code.as_u16();
         if !(200..300).contains(&status) {
-            let body_bytes = body::to_bytes(response.into_body()).await?;
+            self.notify_failed(Some(status_code), start);
+            let limit = self.max_error_body_size;
+            let headers = response.headers().clone();
+            let (body_bytes, truncated) = read_limited(response.into_body(), limit).await?;
+            // read one byte past the limit so a cut decompressed body is detected
+            let body_bytes = decode_body(&headers, &body_bytes, limit.saturating_add(1)).await;
             Ok(Response::Failed(
                 Box::new(body),
                 status_code,
-                std::str::from_utf8(&body_bytes)?.to_string(),
+                failure_reason(body_bytes, limit, truncated)?,
             ))
         } else {
-            Ok(Response::Sent)
+            let latency = start.elapsed();
+            self.metrics.record_success(latency);
+            if let Some(reservation) = reservation {
+                reservation.sent();
+            }
+            if let Some(observer) = self.observer.as_ref() {
+                observer.on_sent(bytes, lines, latency);
+            }
+            body.recycle();
+            Ok(Response::Sent {
+                status: status_code,
+                latency,
+                bytes,
+            })
+        }
+    }
+
+    /// Send several bodies concurrently, at most `concurrency` at a time
+    ///
+    /// Returns the response of every body in the order the bodies were given, a failed body
+    /// does not stop the others from being sent.
+    pub async fn send_all<T, I>(&self, bodies: I, concurrency: usize) -> Vec<IngestResponse>
+    where
+        I: IntoIterator<Item = T>,
+        T: crate::body::IntoIngestBodyBuffer + Send + Sync,
+        T::Error: std::fmt::Debug + std::fmt::Display + Send + Sync + 'static,
+    {
+        futures::stream::iter(bodies)
+            .map(|body| self.send(body))
+            .buffered(concurrency.max(1))
+            .collect()
+            .await
+    }
+
+    /// Send a stream of bodies, keeping up to `concurrency` requests in flight
+    ///
+    /// Responses are yielded as soon as they complete, tagged with the index of their body in
+    /// the input stream.
+    pub fn pipeline<'a, T, S>(
+        &'a self,
+        bodies: S,
+        concurrency: usize,
+    ) -> impl Stream<Item = (usize, IngestResponse)> + 'a
+    where
+        S: Stream<Item = T> + 'a,
+        T: crate::body::IntoIngestBodyBuffer + Send + Sync + 'a,
+        T::Error: std::fmt::Debug + std::fmt::Display + Send + Sync + 'static,
+    {
+        bodies
+            .enumerate()
+            .map(move |(i, body)| async move { (i, self.send(body).await) })
+            .buffer_unordered(concurrency.max(1))
+    }
+
+    /// Pre-establishes up to `n` pooled connections to the ingest host
+    ///
+    /// Sends `HEAD` requests to the ingest endpoint so the TCP and TLS handshakes are done
+    /// before the first body is sent, the ingest API doesn't accept them so they are expected to
+    /// fail with a `4xx`. One request is sent first: if the host answered over HTTP/2 every
+    /// request would be multiplexed on its connection, so no more are sent and 1 is returned.
+    /// Otherwise `n` concurrent requests open the other connections, one of them reusing the
+    /// first, and the number that got a response is returned.
+    pub async fn warm_up(&self, n: usize) -> usize {
+        let hyper = match self.hyper() {
+            Some(hyper) => hyper,
+            None => return 0,
+        };
+        if n == 0 {
+            return 0;
+        }
+        match self.warm_up_request(&hyper).await {
+            Some(http::Version::HTTP_2) => return 1,
+            Some(_) => {}
+            None => return 0,
+        }
+        futures::future::join_all((0..n).map(|_| self.warm_up_request(&hyper)))
+            .await
+            .into_iter()
+            .filter(Option::is_some)
+            .count()
+    }
+
+    // A HEAD request to the ingest endpoint, returning the HTTP version of the response
+    async fn warm_up_request(&self, hyper: &IngestHyperClient) -> Option<http::Version> {
+        let uri = format!(
+            "{}{}{}",
+            self.template.schema, self.template.host, self.template.endpoint
+        );
+        let request = hyper::Request::head(uri)
+            .header(http::header::USER_AGENT, self.template.user_agent.clone())
+            .body(IngestBodyBuffer::from_buffer(
+                crate::segmented_buffer::SegmentedPoolBufBuilder::new().build(),
+            ))
+            .ok()?;
+        match timeout(&*self.timer, self.timeout, hyper.request(request)).await {
+            Some(Ok(response)) => {
+                let version = response.version();
+                // drain the body so the connection goes back to the pool
+                body::to_bytes(response.into_body()).await.ok()?;
+                Some(version)
+            }
+            Some(Err(e)) => {
+                log::debug!("warm up request failed: {}", e);
+                None
+            }
+            None => {
+                log::debug!("warm up request timed out");
+                None
+            }
+        }
+    }
+
+    /// Stops accepting sends and waits up to `deadline` for in-flight requests to complete
+    ///
+    /// Sends started after the shutdown fail with `HttpError::Shutdown`. Idle pooled connections
+    /// are closed right away, the remaining ones once their requests complete. The HTTP/3
+    /// endpoint is closed once no requests are in flight, or at the deadline. Returns false if
+    /// requests were still in flight at the deadline.
+    pub async fn shutdown(&self, deadline: Duration) -> bool {
+        const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+        self.hyper.lock().expect("client lock poisoned").take();
+        let start = Instant::now();
+        let mut drained = true;
+        while self.in_flight.load(Ordering::Acquire) > 0 {
+            let elapsed = start.elapsed();
+            if elapsed >= deadline {
+                drained = false;
+                break;
+            }
+            self.timer
+                .sleep(POLL_INTERVAL.min(deadline - elapsed))
+                .await;
+        }
+        #[cfg(feature = "http3")]
+        if let Some(http3) = self.http3.as_ref() {
+            http3.close().await;
+        }
+        drained
+    }
+
+    // Swaps in a new connector once the tls files changed, keeping the old one on errors
+    //
+    // Checking and reading the files is blocking io, so it's done on the blocking pool.
+    async fn reload_tls(&self) {
+        let tls_reload = match self.tls_reload.as_ref() {
+            Some(tls_reload) if tls_reload.due() => tls_reload.clone(),
+            _ => return,
+        };
+        let connector = self.connector.clone();
+        #[cfg(feature = "http3")]
+        let http3 = self.http3.is_some();
+        let reloaded = tokio::task::spawn_blocking(move || -> Result<_, ClientError> {
+            if !tls_reload.changed() {
+                return Ok(None);
+            }
+            Ok(Some(ReloadedTls {
+                hyper: connector.hyper_client()?,
+                #[cfg(feature = "http3")]
+                http3: if http3 {
+                    Some(connector.tls.client_config()?)
+                } else {
+                    None
+                },
+            }))
+        })
+        .await;
+        let reloaded = match reloaded {
+            Ok(Ok(Some(reloaded))) => reloaded,
+            Ok(Ok(None)) => return,
+            Ok(Err(e)) => {
+                log::warn!("failed to reload tls certificates: {}", e);
+                return;
+            }
+            Err(e) => {
+                log::warn!("failed to reload tls certificates: {}", e);
+                return;
+            }
+        };
+        {
+            let mut current = self.hyper.lock().expect("client lock poisoned");
+            // the client was shut down meanwhile
+            if current.is_none() {
+                return;
+            }
+            *current = Some(reloaded.hyper);
+        }
+        #[cfg(feature = "http3")]
+        if let (Some(http3), Some(tls)) = (self.http3.as_ref(), reloaded.http3) {
+            http3.set_tls(tls).await;
+        }
+        log::info!("reloaded tls certificates");
+    }
+
+    async fn dispatch(
+        &self,
+        hyper: &IngestHyperClient,
+        request: hyper::Request<IngestBodyBuffer>,
+    ) -> Result<hyper::Response<body::Body>, SendError> {
+        #[cfg(feature = "http3")]
+        if let Some(http3) = self.http3.as_ref() {
+            return http3.send(request).await.map_err(SendError::Http3);
         }
+        hyper.request(request).await.map_err(SendError::Hyper)
+    }
+
+    fn hyper(&self) -> Option<IngestHyperClient> {
+        self.hyper.lock().expect("client lock poisoned").clone()
+    }
+
+    fn notify_failed(&self, status: Option<http::StatusCode>, start: Instant) {
+        self.metrics.record_failure(start.elapsed());
+        if let Some(observer) = self.observer.as_ref() {
+            observer.on_failed(status);
+        }
+    }
+}
+
+// The clients rebuilt with the changed tls files
+struct ReloadedTls {
+    hyper: IngestHyperClient,
+    #[cfg(feature = "http3")]
+    http3: Option<rustls::ClientConfig>,
+}
+
+// Why a request got no response
+enum SendError {
+    Hyper(hyper::Error),
+    #[cfg(feature = "http3")]
+    Http3(Http3Error),
+}
+
+// Counts a request as in flight until dropped
+struct InFlight<'a>(&'a AtomicUsize);
+
+impl<'a> InFlight<'a> {
+    fn enter(count: &'a AtomicUsize) -> Self {
+        count.fetch_add(1, Ordering::AcqRel);
+        InFlight(count)
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
     }
 }
@@ -0,0 +1,228 @@
+use std::convert::TryFrom;
+use std::time::Duration;
+
+use async_compression::Level;
+use serde::Deserialize;
+
+use crate::error::{ParamsError, TemplateError};
+use crate::params::{Params, Tags};
+use crate::request::{Encoding, RequestTemplate, Schema};
+
+const DEFAULT_GZIP_LEVEL: u32 = 2;
+
+/// Deserializable representation of [`Params`], e.g loaded from a TOML/YAML/JSON file
+#[derive(Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ParamsConfig {
+    /// the hostname parameter, e.g `node-001`
+    pub hostname: String,
+    /// the mac parameter (optional), e.g `C0:FF:EE:C0:FF:EE`
+    #[serde(default)]
+    pub mac: Option<String>,
+    /// the ip parameter (optional), e.g `127.0.0.1`
+    #[serde(default)]
+    pub ip: Option<String>,
+    /// the tags parameter (optional), e.g `this,is,a,test,tag`
+    #[serde(default)]
+    pub tags: Option<Tags>,
+}
+
+impl TryFrom<ParamsConfig> for Params {
+    type Error = ParamsError;
+
+    fn try_from(config: ParamsConfig) -> Result<Self, Self::Error> {
+        let mut builder = Params::builder();
+        builder.hostname(config.hostname);
+        if let Some(mac) = config.mac {
+            builder.mac(mac);
+        }
+        if let Some(ip) = config.ip {
+            builder.ip(ip);
+        }
+        if let Some(tags) = config.tags {
+            builder.tags(tags);
+        }
+        builder.build()
+    }
+}
+
+/// Body encoding as named in a configuration file
+#[derive(Deserialize, Debug, Clone, Copy, Default, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EncodingConfig {
+    Json,
+    #[default]
+    Gzip,
+    Msgpack,
+}
+
+/// Http schema as named in a configuration file
+#[derive(Deserialize, Debug, Clone, Copy, Default, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SchemaConfig {
+    Http,
+    #[default]
+    Https,
+}
+
+impl From<SchemaConfig> for Schema {
+    fn from(schema: SchemaConfig) -> Self {
+        match schema {
+            SchemaConfig::Http => Schema::Http,
+            SchemaConfig::Https => Schema::Https,
+        }
+    }
+}
+
+/// Deserializable representation of a [`RequestTemplate`] and the client settings that go with it
+///
+/// # Example
+///
+/// ```rust
+/// # use std::convert::TryFrom;
+/// # use logdna_client::config::TemplateConfig;
+/// # use logdna_client::request::RequestTemplate;
+/// let config: TemplateConfig = serde_json::from_str(r#"{
+///     "host": "logs.logdna.com",
+///     "api_key": "<your ingestion key>",
+///     "encoding": "gzip",
+///     "gzip_level": 6,
+///     "timeout_ms": 10000,
+///     "params": { "hostname": "rust-client-test", "tags": "this,is,a,test" }
+/// }"#).expect("TemplateConfig");
+/// let template = RequestTemplate::try_from(config).expect("RequestTemplate::try_from()");
+/// ```
+#[derive(Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateConfig {
+    /// Host / domain, default is logs.logdna.com
+    #[serde(default)]
+    pub host: Option<String>,
+    /// Ingest endpoint, default is /logs/ingest
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    /// Http schema, default is https
+    #[serde(default)]
+    pub schema: SchemaConfig,
+    /// Content encoding, default is gzip
+    #[serde(default)]
+    pub encoding: EncodingConfig,
+    /// Gzip compression level, only used with gzip encoding, default is 2
+    #[serde(default)]
+    pub gzip_level: Option<u32>,
+    /// User agent header, defaults to the crate name and version
+    #[serde(default)]
+    pub user_agent: Option<String>,
+    /// Request timeout in milliseconds, default is 5000
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Reject plain http connections, default is true
+    #[serde(default)]
+    pub require_tls: Option<bool>,
+    /// LogDNA ingestion key
+    pub api_key: String,
+    /// Query parameters appended to the url
+    pub params: ParamsConfig,
+}
+
+impl TemplateConfig {
+    /// The configured request timeout, if any
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout_ms.map(Duration::from_millis)
+    }
+
+    /// The configured encoding, resolving the gzip level
+    pub fn encoding(&self) -> Encoding {
+        match self.encoding {
+            EncodingConfig::Json => Encoding::Json,
+            EncodingConfig::Gzip => Encoding::GzipJson(Level::Precise(
+                self.gzip_level.unwrap_or(DEFAULT_GZIP_LEVEL) as i32,
+            )),
+            EncodingConfig::Msgpack => Encoding::MsgPack,
+        }
+    }
+}
+
+impl TryFrom<TemplateConfig> for RequestTemplate {
+    type Error = TemplateError;
+
+    fn try_from(config: TemplateConfig) -> Result<Self, Self::Error> {
+        let encoding = config.encoding();
+        let mut builder = RequestTemplate::builder();
+        builder
+            .schema(config.schema)
+            .encoding(encoding)
+            .api_key(config.api_key)
+            .params(Params::try_from(config.params)?);
+        if let Some(host) = config.host {
+            builder.host(host);
+        }
+        if let Some(endpoint) = config.endpoint {
+            builder.endpoint(endpoint);
+        }
+        if let Some(user_agent) = config.user_agent {
+            builder.user_agent(user_agent);
+        }
+        builder.build()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn template_config_from_json() {
+        let config: TemplateConfig = serde_json::from_str(
+            r#"{
+                "host": "logs.example.com",
+                "endpoint": "/custom/ingest",
+                "schema": "http",
+                "encoding": "gzip",
+                "gzip_level": 9,
+                "timeout_ms": 1500,
+                "api_key": "12345",
+                "params": { "hostname": "rust-client-test", "tags": "a,b,c" }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.timeout(), Some(Duration::from_millis(1500)));
+
+        let template = RequestTemplate::try_from(config).unwrap();
+        assert_eq!(template.host, "logs.example.com");
+        assert_eq!(template.endpoint, "/custom/ingest");
+        assert_eq!(template.schema.to_string(), "http://");
+        assert_eq!(template.params.hostname, "rust-client-test");
+        assert_eq!(template.params.tags, Some(Tags::parse("a,b,c")));
+        assert!(matches!(
+            template.encoding,
+            Encoding::GzipJson(Level::Precise(9))
+        ));
+    }
+
+    #[test]
+    fn template_config_defaults() {
+        let config: TemplateConfig = serde_json::from_str(
+            r#"{ "api_key": "12345", "params": { "hostname": "rust-client-test" } }"#,
+        )
+        .unwrap();
+        assert_eq!(config.timeout(), None);
+
+        let template = RequestTemplate::try_from(config).unwrap();
+        assert_eq!(template.host, "logs.logdna.com");
+        assert_eq!(template.endpoint, "/logs/ingest");
+        assert!(matches!(
+            template.encoding,
+            Encoding::GzipJson(Level::Precise(2))
+        ));
+    }
+
+    #[test]
+    fn template_config_rejects_empty_api_key() {
+        let config: TemplateConfig = serde_json::from_str(
+            r#"{ "api_key": "", "params": { "hostname": "rust-client-test" } }"#,
+        )
+        .unwrap();
+        assert!(RequestTemplate::try_from(config).is_err());
+    }
+}
@@ -0,0 +1,219 @@
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::body::{KeyValueMap, Line};
+use crate::error::CriError;
+
+const DEFAULT_MAX_LINE_SIZE: usize = 1024 * 1024;
+
+/// The stream a CRI log record was written to
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Stream::Stdout => "stdout",
+            Stream::Stderr => "stderr",
+        }
+    }
+}
+
+/// A single record in the CRI logging format, e.g `2021-01-01T00:00:00.000000000Z stdout F message`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CriRecord<'a> {
+    /// Unix timestamp of the record
+    pub timestamp: i64,
+    /// The stream the record was written to
+    pub stream: Stream,
+    /// `true` for a partial (`P`) record that continues in the next record on the same stream
+    pub partial: bool,
+    /// The record content
+    pub message: &'a str,
+}
+
+/// Parses a single CRI record without reassembling partial records
+pub fn parse_record(input: &str) -> Result<CriRecord<'_>, CriError> {
+    let input = input.strip_suffix('\n').unwrap_or(input);
+    let mut fields = input.splitn(4, ' ');
+    let mut next = || fields.next().ok_or(CriError::Invalid("missing field"));
+
+    let timestamp = OffsetDateTime::parse(next()?, &Rfc3339)
+        .map_err(|_| CriError::Invalid("malformed timestamp"))?
+        .unix_timestamp();
+    let stream = match next()? {
+        "stdout" => Stream::Stdout,
+        "stderr" => Stream::Stderr,
+        _ => return Err(CriError::Invalid("unknown stream")),
+    };
+    let partial = match next()? {
+        "P" => true,
+        "F" => false,
+        _ => return Err(CriError::Invalid("unknown tag")),
+    };
+    Ok(CriRecord {
+        timestamp,
+        stream,
+        partial,
+        message: fields.next().unwrap_or(""),
+    })
+}
+
+#[derive(Debug, Default)]
+struct Partial {
+    timestamp: i64,
+    message: String,
+}
+
+/// Converts the CRI records of a single container log file into Lines, joining partial records
+///
+/// Each Line has its file set, a `stream` label and the timestamp of its first record.
+///
+/// # Example
+///
+/// ```rust
+/// # use logdna_client::cri::CriReassembler;
+/// let mut cri = CriReassembler::new("/var/log/pods/app/0.log");
+/// assert!(cri.push("2021-01-01T00:00:00.000000000Z stdout P hello ").unwrap().is_none());
+/// let line = cri.push("2021-01-01T00:00:00.100000000Z stdout F world").unwrap().unwrap();
+/// assert_eq!(line.line, "hello world");
+/// assert_eq!(line.labels.unwrap()["stream"], "stdout");
+/// ```
+#[derive(Debug)]
+pub struct CriReassembler {
+    file: String,
+    max_line_size: usize,
+    stdout: Option<Partial>,
+    stderr: Option<Partial>,
+}
+
+impl CriReassembler {
+    /// Constructs a CriReassembler for the given log file
+    pub fn new<T: Into<String>>(file: T) -> Self {
+        Self {
+            file: file.into(),
+            max_line_size: DEFAULT_MAX_LINE_SIZE,
+            stdout: None,
+            stderr: None,
+        }
+    }
+    /// Set the size after which a partial line is emitted without waiting for the rest, default is 1MiB
+    pub fn max_line_size(mut self, max_line_size: usize) -> Self {
+        self.max_line_size = max_line_size;
+        self
+    }
+    /// Parses a record, returning a Line once a full (`F`) record completes it
+    pub fn push(&mut self, input: &str) -> Result<Option<Line>, CriError> {
+        let record = parse_record(input)?;
+        let max_line_size = self.max_line_size;
+        let pending = match record.stream {
+            Stream::Stdout => &mut self.stdout,
+            Stream::Stderr => &mut self.stderr,
+        };
+
+        let partial = pending.get_or_insert_with(|| Partial {
+            timestamp: record.timestamp,
+            message: String::new(),
+        });
+        partial.message.push_str(record.message);
+
+        if record.partial && partial.message.len() < max_line_size {
+            return Ok(None);
+        }
+        let partial = pending.take().unwrap_or_default();
+        self.build(record.stream, partial).map(Some)
+    }
+    /// Emits any incomplete partial lines, e.g when the file is rotated or closed
+    pub fn flush(&mut self) -> Result<Vec<Line>, CriError> {
+        let mut lines = Vec::new();
+        if let Some(partial) = self.stdout.take() {
+            lines.push(self.build(Stream::Stdout, partial)?);
+        }
+        if let Some(partial) = self.stderr.take() {
+            lines.push(self.build(Stream::Stderr, partial)?);
+        }
+        Ok(lines)
+    }
+
+    fn build(&self, stream: Stream, partial: Partial) -> Result<Line, CriError> {
+        let mut line = Line::builder()
+            .line(partial.message)
+            .file(self.file.clone())
+            .labels(KeyValueMap::new().add("stream", stream.as_str()))
+            .build()?;
+        line.timestamp = partial.timestamp;
+        Ok(line)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_full_record() {
+        let record = parse_record("2021-01-01T00:00:00.123456789Z stderr F oh no\n").unwrap();
+        assert_eq!(record.timestamp, 1609459200);
+        assert_eq!(record.stream, Stream::Stderr);
+        assert!(!record.partial);
+        assert_eq!(record.message, "oh no");
+    }
+
+    #[test]
+    fn parse_empty_message() {
+        let record = parse_record("2021-01-01T00:00:00Z stdout F").unwrap();
+        assert_eq!(record.message, "");
+    }
+
+    #[test]
+    fn parse_invalid_record() {
+        assert!(parse_record("not a timestamp stdout F message").is_err());
+        assert!(parse_record("2021-01-01T00:00:00Z stdin F message").is_err());
+        assert!(parse_record("2021-01-01T00:00:00Z stdout X message").is_err());
+        assert!(parse_record("2021-01-01T00:00:00Z").is_err());
+    }
+
+    #[test]
+    fn reassemble_interleaved_streams() {
+        let mut cri = CriReassembler::new("0.log");
+        assert!(cri
+            .push("2021-01-01T00:00:00Z stdout P out ")
+            .unwrap()
+            .is_none());
+        assert!(cri
+            .push("2021-01-01T00:00:01Z stderr P err ")
+            .unwrap()
+            .is_none());
+        let out = cri
+            .push("2021-01-01T00:00:02Z stdout F done")
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.line, "out done");
+        assert_eq!(out.timestamp, 1609459200);
+        assert_eq!(out.file.as_deref(), Some("0.log"));
+
+        let flushed = cri.flush().unwrap();
+        assert_eq!(flushed.len(), 1);
+        assert_eq!(flushed[0].line, "err ");
+        assert_eq!(flushed[0].labels.as_ref().unwrap()["stream"], "stderr");
+    }
+
+    #[test]
+    fn reassemble_respects_max_line_size() {
+        let mut cri = CriReassembler::new("0.log").max_line_size(4);
+        assert!(cri
+            .push("2021-01-01T00:00:00Z stdout P ab")
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            cri.push("2021-01-01T00:00:00Z stdout P cd")
+                .unwrap()
+                .unwrap()
+                .line,
+            "abcd"
+        );
+    }
+}
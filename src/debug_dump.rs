@@ -0,0 +1,136 @@
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use async_compression::futures::bufread::GzipDecoder;
+use futures::io::AsyncReadExt;
+use http::header::CONTENT_ENCODING;
+use http::Request;
+
+use crate::body::IngestBodyBuffer;
+
+/// Headers carrying credentials, their values are never written out
+pub(crate) const REDACTED_HEADERS: &[&str] = &["apikey", "authorization", "proxy-authorization"];
+
+/// Writes every request sent by a client to files in a directory, for debugging
+///
+/// Each file holds the request line, the headers with the ingestion key redacted and the
+/// decompressed body. Only the last `max_files` requests are kept, older files are overwritten.
+/// The files are written synchronously, so this is meant for diagnosing an ingestion problem
+/// rather than for production traffic.
+///
+/// # Example
+///
+/// ```rust
+/// # use logdna_client::debug_dump::DebugDump;
+/// let dump = DebugDump::new("/tmp/logdna-requests").max_files(20);
+/// ```
+#[derive(Debug)]
+pub struct DebugDump {
+    dir: PathBuf,
+    max_files: u64,
+    next: AtomicU64,
+}
+
+impl DebugDump {
+    /// Constructs a DebugDump writing into `dir`, keeping the last 100 requests
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self {
+            dir: dir.into(),
+            max_files: 100,
+            next: AtomicU64::new(0),
+        }
+    }
+    /// Set how many request files are kept
+    pub fn max_files(mut self, max_files: u64) -> Self {
+        self.max_files = max_files.max(1);
+        self
+    }
+
+    /// Writes a request, failures are logged rather than failing the send
+    pub(crate) async fn write(&self, request: &Request<IngestBodyBuffer>) {
+        let slot = self.next.fetch_add(1, Ordering::Relaxed) % self.max_files;
+        let path = self.dir.join(format!("request-{:04}.txt", slot));
+        let dump = render(request).await;
+        let result = std::fs::create_dir_all(&self.dir)
+            .and_then(|_| std::fs::File::create(&path))
+            .and_then(|mut file| file.write_all(&dump));
+        if let Err(e) = result {
+            log::warn!("failed to write request dump {}: {}", path.display(), e);
+        }
+    }
+}
+
+async fn render(request: &Request<IngestBodyBuffer>) -> Vec<u8> {
+    let mut dump = format!("{} {}\n", request.method(), request.uri()).into_bytes();
+    for (name, value) in request.headers() {
+        let value = if REDACTED_HEADERS.contains(&name.as_str()) {
+            "REDACTED"
+        } else {
+            value.to_str().unwrap_or("<binary>")
+        };
+        dump.extend_from_slice(format!("{}: {}\n", name, value).as_bytes());
+    }
+    dump.push(b'\n');
+
+    let mut body = Vec::with_capacity(request.body().len());
+    if let Err(e) = Read::read_to_end(&mut request.body().reader(), &mut body) {
+        log::warn!("failed to read request body: {}", e);
+    }
+    let gzipped = request
+        .headers()
+        .get(CONTENT_ENCODING)
+        .map_or(false, |e| e == "gzip");
+    if gzipped {
+        let mut decoded = Vec::new();
+        match GzipDecoder::new(&body[..]).read_to_end(&mut decoded).await {
+            Ok(_) => body = decoded,
+            Err(e) => log::warn!("failed to decompress request body: {}", e),
+        }
+    }
+    dump.extend_from_slice(&body);
+    dump
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::body::{IngestBody, IntoIngestBodyBuffer, Line};
+    use crate::params::Params;
+    use crate::request::RequestTemplate;
+
+    #[test]
+    fn dumps_redacted_decompressed_requests() {
+        let dir = std::env::temp_dir().join(format!("logdna-dump-{}", std::process::id()));
+        let dump = DebugDump::new(&dir).max_files(2);
+
+        let params = Params::builder().hostname("dump-test").build().unwrap();
+        let template = RequestTemplate::builder()
+            .params(params)
+            .api_key("secret-key")
+            .build()
+            .unwrap();
+        let line = Line::builder().line("hello dump").build().unwrap();
+        let body: IngestBodyBuffer =
+            tokio_test::block_on(IntoIngestBodyBuffer::into(&IngestBody::new(vec![line]))).unwrap();
+        let request = tokio_test::block_on(template.new_request(&body)).unwrap();
+
+        for _ in 0..3 {
+            tokio_test::block_on(dump.write(&request));
+        }
+        let mut files: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        files.sort();
+        assert_eq!(files, vec!["request-0000.txt", "request-0001.txt"]);
+
+        let contents = std::fs::read_to_string(dir.join("request-0000.txt")).unwrap();
+        assert!(contents.starts_with("POST https://logs.logdna.com/logs/ingest?hostname=dump-test"));
+        assert!(contents.contains("apikey: REDACTED\n"));
+        assert!(!contents.contains("secret-key"));
+        assert!(contents.contains(r#""line":"hello dump""#));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
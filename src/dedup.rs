@@ -0,0 +1,200 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::Hasher;
+use std::io::Read;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::body::IngestBodyBuffer;
+use crate::params::Params;
+
+const DEFAULT_MAX_ENTRIES: usize = 1024;
+
+/// Suppresses sending a body identical to one sent successfully within a window
+///
+/// Bodies are keyed by a hash of their serialized content, along with the query parameters
+/// when they were overridden for the send. A suppressed body fails with
+/// [`HttpError::Duplicate`](crate::error::HttpError::Duplicate) and is counted in
+/// [`ClientMetrics::duplicates_suppressed`](crate::metrics::ClientMetrics::duplicates_suppressed).
+/// An identical body sent concurrently is suppressed as well until the first send finishes.
+/// Meant to catch naive retry loops above the client, bodies retried by
+/// [`Client::send_with_retry`](crate::client::Client::send_with_retry) after a failure are
+/// never suppressed.
+///
+/// # Example
+///
+/// ```rust
+/// # use std::time::Duration;
+/// # use logdna_client::dedup::DedupCache;
+/// let dedup = DedupCache::new(Duration::from_secs(30)).max_entries(4096);
+/// ```
+#[derive(Debug)]
+pub struct DedupCache {
+    window: Duration,
+    max_entries: usize,
+    sent: Mutex<HashMap<u64, Entry>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Entry {
+    // reserved by a send that hasn't finished yet
+    InFlight,
+    Sent(Instant),
+}
+
+impl DedupCache {
+    /// Constructs a DedupCache remembering up to 1024 bodies for `window`
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            max_entries: DEFAULT_MAX_ENTRIES,
+            sent: Mutex::new(HashMap::new()),
+        }
+    }
+    /// Set how many sent bodies are remembered, the oldest are forgotten first
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries.max(1);
+        self
+    }
+
+    /// Reserves the key of a body about to be sent, None if a body with this key was sent
+    /// within the window or is being sent
+    ///
+    /// Checking and reserving under one lock keeps concurrent sends of the same body from both
+    /// going out. The key is released if the reservation is dropped without being marked sent.
+    pub(crate) fn reserve(&self, key: u64) -> Option<Reservation<'_>> {
+        let mut sent = self.sent.lock().expect("dedup lock poisoned");
+        match sent.get(&key) {
+            Some(Entry::InFlight) => return None,
+            Some(Entry::Sent(at)) if at.elapsed() < self.window => return None,
+            _ => {}
+        }
+        if sent.len() >= self.max_entries {
+            let window = self.window;
+            sent.retain(|_, entry| match entry {
+                Entry::InFlight => true,
+                Entry::Sent(at) => at.elapsed() < window,
+            });
+        }
+        if sent.len() >= self.max_entries {
+            let oldest = sent
+                .iter()
+                .filter_map(|(key, entry)| match entry {
+                    Entry::Sent(at) => Some((*key, *at)),
+                    Entry::InFlight => None,
+                })
+                .min_by_key(|(_, at)| *at);
+            if let Some((oldest, _)) = oldest {
+                sent.remove(&oldest);
+            }
+        }
+        sent.insert(key, Entry::InFlight);
+        Some(Reservation {
+            cache: self,
+            key,
+            sent: false,
+        })
+    }
+}
+
+/// A key reserved by [`DedupCache::reserve`], released on drop unless marked sent
+#[derive(Debug)]
+pub(crate) struct Reservation<'a> {
+    cache: &'a DedupCache,
+    key: u64,
+    sent: bool,
+}
+
+impl Reservation<'_> {
+    /// Remembers the body as sent now
+    pub(crate) fn sent(mut self) {
+        self.sent = true;
+        self.cache
+            .sent
+            .lock()
+            .expect("dedup lock poisoned")
+            .insert(self.key, Entry::Sent(Instant::now()));
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if self.sent {
+            return;
+        }
+        if let Ok(mut sent) = self.cache.sent.lock() {
+            if let Some(Entry::InFlight) = sent.get(&self.key) {
+                sent.remove(&self.key);
+            }
+        }
+    }
+}
+
+/// The key of a body sent with `params`, None if the template's params are used
+pub(crate) fn key(body: &IngestBodyBuffer, params: Option<&Params>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    let mut reader = body.reader();
+    let mut chunk = [0; 8192];
+    loop {
+        match reader.read(&mut chunk) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => hasher.write(&chunk[..n]),
+        }
+    }
+    if let Some(params) = params {
+        if let Ok(query) = serde_urlencoded::to_string(params) {
+            hasher.write(query.as_bytes());
+        }
+    }
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::body::{IngestBody, IntoIngestBodyBuffer, Line};
+
+    fn buffer(line: &str) -> IngestBodyBuffer {
+        let body = IngestBody::new(vec![Line::builder().line(line).build().unwrap()]);
+        tokio_test::block_on(IntoIngestBodyBuffer::into(&body)).unwrap()
+    }
+
+    #[test]
+    fn reserves_bodies_in_flight() {
+        let dedup = DedupCache::new(Duration::from_secs(60));
+        let a = key(&buffer("a"), None);
+
+        let reservation = dedup.reserve(a).unwrap();
+        // a concurrent send of the same body is refused while the first is in flight
+        assert!(dedup.reserve(a).is_none());
+        // a failed send releases the key so the body can be retried
+        drop(reservation);
+        dedup.reserve(a).unwrap().sent();
+        assert!(dedup.reserve(a).is_none());
+    }
+
+    #[test]
+    fn suppresses_within_window() {
+        let dedup = DedupCache::new(Duration::from_secs(60)).max_entries(1);
+        let a = key(&buffer("a"), None);
+        let b = key(&buffer("b"), None);
+        assert_eq!(a, key(&buffer("a"), None));
+        assert_ne!(a, b);
+
+        dedup.reserve(a).unwrap().sent();
+        assert!(dedup.reserve(a).is_none());
+
+        // the oldest body is forgotten to make room
+        dedup.reserve(b).unwrap().sent();
+        assert!(dedup.reserve(b).is_none());
+        assert!(dedup.reserve(a).is_some());
+
+        let params = Params::builder().hostname("other").build().unwrap();
+        assert_ne!(b, key(&buffer("b"), Some(&params)));
+
+        let expired = DedupCache::new(Duration::from_secs(0));
+        expired.reserve(a).unwrap().sent();
+        assert!(expired.reserve(a).is_some());
+    }
+}
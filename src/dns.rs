@@ -1,9 +1,11 @@
+use std::collections::HashMap;
 use std::future::Future;
 use std::io;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{self, Poll};
+use std::time::{Duration, Instant};
 
 use backoff::{backoff::Backoff, exponential::ExponentialBackoff, SystemClock};
 use hyper::client::connect::dns as hyper_dns;
@@ -11,11 +13,13 @@ use hyper::service::Service;
 use once_cell::sync::Lazy;
 use tokio::sync::Mutex;
 use trust_dns_resolver::{
-    config::{ResolverConfig, ResolverOpts},
-    lookup_ip::LookupIpIntoIter,
+    config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+    lookup_ip::LookupIp,
     system_conf, TokioAsyncResolver,
 };
 
+use crate::runtime::{Timer, TokioTimer};
+
 struct ResolverInner {
     resolver: TokioAsyncResolver,
     backoff: ExponentialBackoff<SystemClock>,
@@ -23,16 +27,82 @@ struct ResolverInner {
 
 type SharedResolver = Arc<Mutex<ResolverInner>>;
 
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
 static SYSTEM_CONF: Lazy<std::sync::Mutex<io::Result<(ResolverConfig, ResolverOpts)>>> =
     Lazy::new(|| std::sync::Mutex::new(system_conf::read_system_conf().map_err(io::Error::from)));
 
 #[derive(Clone)]
 pub(crate) struct TrustDnsResolver {
     state: Arc<Mutex<State>>,
+    // Fixed nameservers, None to follow the system configuration
+    config: Option<Arc<(ResolverConfig, ResolverOpts)>>,
+    cache: Option<Arc<DnsCache>>,
+    ip_preference: IpPreference,
+    // waits out the backoff between failed lookups
+    timer: Arc<dyn Timer>,
 }
 
 pub(crate) struct SocketAddrs {
-    iter: LookupIpIntoIter,
+    iter: std::vec::IntoIter<IpAddr>,
+}
+
+/// Lookup results shared by every resolver of a client, kept across connector rebuilds
+#[derive(Debug)]
+pub(crate) struct DnsCache {
+    max_ttl: Duration,
+    negative_ttl: Duration,
+    entries: std::sync::Mutex<HashMap<String, (Instant, Result<Vec<IpAddr>, String>)>>,
+}
+
+impl DnsCache {
+    /// Caches addresses for their record TTL up to `max_ttl`, failed lookups for `negative_ttl`
+    pub(crate) fn new(max_ttl: Duration, negative_ttl: Duration) -> Self {
+        Self {
+            max_ttl,
+            negative_ttl,
+            entries: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Removes every entry, the next connection resolves the host again
+    pub(crate) fn clear(&self) {
+        self.entries
+            .lock()
+            .expect("dns cache lock poisoned")
+            .clear();
+    }
+
+    fn get(&self, name: &str) -> Option<Result<Vec<IpAddr>, String>> {
+        let mut entries = self.entries.lock().expect("dns cache lock poisoned");
+        match entries.get(name) {
+            Some((expires, result)) if *expires > Instant::now() => Some(result.clone()),
+            Some(_) => {
+                entries.remove(name);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert(&self, name: &str, result: &Result<LookupIp, BoxError>) {
+        let now = Instant::now();
+        let entry = match result {
+            Ok(lookup) => {
+                let ttl = lookup.valid_until().saturating_duration_since(now);
+                (now + ttl.min(self.max_ttl), Ok(lookup.iter().collect()))
+            }
+            Err(e) => (now + self.negative_ttl, Err(e.to_string())),
+        };
+        self.insert_entry(name, entry);
+    }
+
+    fn insert_entry(&self, name: &str, entry: (Instant, Result<Vec<IpAddr>, String>)) {
+        self.entries
+            .lock()
+            .expect("dns cache lock poisoned")
+            .insert(name.to_string(), entry);
+    }
 }
 
 #[derive(Clone)]
@@ -56,8 +126,52 @@ impl TrustDnsResolver {
         // resolver.
         Ok(TrustDnsResolver {
             state: Arc::new(Mutex::new(State::Init(Some(ExponentialBackoff::default())))),
+            config: None,
+            cache: None,
+            ip_preference: IpPreference::default(),
+            timer: Arc::new(TokioTimer),
         })
     }
+
+    /// A resolver querying only the given nameservers, ignoring the system configuration
+    pub(crate) fn with_nameservers(nameservers: &[SocketAddr]) -> Self {
+        let mut group = NameServerConfigGroup::new();
+        for nameserver in nameservers {
+            group.merge(NameServerConfigGroup::from_ips_clear(
+                &[nameserver.ip()],
+                nameserver.port(),
+                true,
+            ));
+        }
+        TrustDnsResolver {
+            state: Arc::new(Mutex::new(State::Init(Some(ExponentialBackoff::default())))),
+            config: Some(Arc::new((
+                ResolverConfig::from_parts(None, vec![], group),
+                ResolverOpts::default(),
+            ))),
+            cache: None,
+            ip_preference: IpPreference::default(),
+            timer: Arc::new(TokioTimer),
+        }
+    }
+
+    /// Order or filter the resolved addresses by family
+    pub(crate) fn with_ip_preference(mut self, ip_preference: IpPreference) -> Self {
+        self.ip_preference = ip_preference;
+        self
+    }
+
+    /// Wait out the backoff between failed lookups with the timer instead of `tokio::time`
+    pub(crate) fn with_timer(mut self, timer: Arc<dyn Timer>) -> Self {
+        self.timer = timer;
+        self
+    }
+
+    /// Serve lookups from the cache, filling it with every lookup done
+    pub(crate) fn with_cache(mut self, cache: Arc<DnsCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
 }
 
 impl Service<hyper_dns::Name> for TrustDnsResolver {
@@ -72,62 +186,132 @@ impl Service<hyper_dns::Name> for TrustDnsResolver {
     fn call(&mut self, name: hyper_dns::Name) -> Self::Future {
         let resolver = self.clone();
         Box::pin(async move {
-            let mut lock = resolver.state.lock().await;
-
-            let resolver = match &mut *lock {
-                State::Init(backoff) => {
-                    let resolver = Arc::new(Mutex::new(ResolverInner {
-                        resolver: new_resolver().await?,
-                        backoff: backoff.take().expect("attempting to reinitialise resolver"),
-                    }));
-                    *lock = State::Ready(resolver.clone());
-                    resolver
+            let name = name.as_str();
+            let addrs = match resolver.cache.as_ref().and_then(|cache| cache.get(name)) {
+                Some(cached) => cached.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?,
+                None => {
+                    // with a cache the failure is cached right away instead of retried, later
+                    // connections fail fast until the negative ttl expires
+                    let result = resolver.lookup(name, resolver.cache.is_none()).await;
+                    if let Some(cache) = resolver.cache.as_ref() {
+                        cache.insert(name, &result);
+                    }
+                    result?.iter().collect()
                 }
-                State::Ready(resolver) => resolver.clone(),
             };
+            let addrs = resolver.ip_preference.apply(addrs);
+            if addrs.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no address of the preferred family for {}", name),
+                )
+                .into());
+            }
+            Ok(SocketAddrs {
+                iter: addrs.into_iter(),
+            })
+        })
+    }
+}
+
+impl TrustDnsResolver {
+    // Retries failed lookups with backoff if `retry`, otherwise the first failure is returned
+    async fn lookup(&self, name: &str, retry: bool) -> Result<LookupIp, BoxError> {
+        let config = self.config.clone();
+        let mut lock = self.state.lock().await;
+
+        let resolver = match &mut *lock {
+            State::Init(backoff) => {
+                let resolver = Arc::new(Mutex::new(ResolverInner {
+                    resolver: new_resolver(config.as_deref()).await?,
+                    backoff: backoff.take().expect("attempting to reinitialise resolver"),
+                }));
+                *lock = State::Ready(resolver.clone());
+                resolver
+            }
+            State::Ready(resolver) => resolver.clone(),
+        };
 
-            // Don't keep lock once the resolver is constructed, otherwise
-            // only one lookup could be done at a time.
-            drop(lock);
+        // Don't keep lock once the resolver is constructed, otherwise
+        // only one lookup could be done at a time.
+        drop(lock);
 
-            let lookup = loop {
-                let mut resolver = resolver.lock().await;
-                match resolver.resolver.lookup_ip(name.as_str()).await {
-                    Ok(lookup) => {
-                        resolver.backoff.reset();
-                        break lookup;
+        let lookup = loop {
+            let mut resolver = resolver.lock().await;
+            match resolver.resolver.lookup_ip(name).await {
+                Ok(lookup) => {
+                    resolver.backoff.reset();
+                    break lookup;
+                }
+                Err(e) if config.is_some() => {
+                    if let Some(delay) = retry.then(|| resolver.backoff.next_backoff()).flatten() {
+                        drop(resolver);
+                        self.timer.sleep(delay).await;
+                        continue;
                     }
-                    Err(e) => {
-                        let new_system_config =
-                            system_conf::read_system_conf().map_err(io::Error::from);
-                        if new_system_config.is_ok() {
-                            let mut system_config =
-                                SYSTEM_CONF.lock().expect("Failed to lock SYSTEM_CONF");
-                            match (new_system_config, system_config.as_mut()) {
-                                (Ok(ref mut new_system_config), Ok(system_config))
-                                    if new_system_config != system_config =>
-                                {
-                                    std::mem::swap(system_config, new_system_config);
-                                    let (config, opts) = system_config.clone();
-                                    resolver.resolver = TokioAsyncResolver::tokio(config, opts);
-                                }
-                                _ => (),
+                    return Err(e)?;
+                }
+                Err(e) => {
+                    let new_system_config =
+                        system_conf::read_system_conf().map_err(io::Error::from);
+                    if new_system_config.is_ok() {
+                        let mut system_config =
+                            SYSTEM_CONF.lock().expect("Failed to lock SYSTEM_CONF");
+                        match (new_system_config, system_config.as_mut()) {
+                            (Ok(ref mut new_system_config), Ok(system_config))
+                                if new_system_config != system_config =>
+                            {
+                                std::mem::swap(system_config, new_system_config);
+                                let (config, opts) = system_config.clone();
+                                resolver.resolver = TokioAsyncResolver::tokio(config, opts);
                             }
-                        };
-
-                        if let Some(delay) = resolver.backoff.next_backoff() {
-                            drop(resolver);
-                            tokio::time::sleep(delay).await;
-                            continue;
+                            _ => (),
                         }
-                        return Err(e)?;
+                    };
+
+                    if let Some(delay) = retry.then(|| resolver.backoff.next_backoff()).flatten() {
+                        drop(resolver);
+                        self.timer.sleep(delay).await;
+                        continue;
                     }
+                    return Err(e)?;
                 }
-            };
-            Ok(SocketAddrs {
-                iter: lookup.into_iter(),
-            })
-        })
+            }
+        };
+        Ok(lookup)
+    }
+}
+
+/// Which address families are connected to, and in what order
+///
+/// Connections are raced between families (Happy Eyeballs), the first family is tried first and
+/// the other after the happy eyeballs timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IpPreference {
+    /// Keep the order returned by the resolver
+    #[default]
+    Any,
+    /// Try IPv4 addresses first
+    Ipv4First,
+    /// Try IPv6 addresses first
+    Ipv6First,
+    /// Only connect to IPv4 addresses, e.g on networks with broken IPv6
+    Ipv4Only,
+    /// Only connect to IPv6 addresses
+    Ipv6Only,
+}
+
+impl IpPreference {
+    fn apply(self, mut addrs: Vec<IpAddr>) -> Vec<IpAddr> {
+        match self {
+            IpPreference::Any => {}
+            // sort_by_key is stable, the resolver order within a family is kept
+            IpPreference::Ipv4First => addrs.sort_by_key(|addr| addr.is_ipv6()),
+            IpPreference::Ipv6First => addrs.sort_by_key(|addr| addr.is_ipv4()),
+            IpPreference::Ipv4Only => addrs.retain(|addr| addr.is_ipv4()),
+            IpPreference::Ipv6Only => addrs.retain(|addr| addr.is_ipv6()),
+        }
+        addrs
     }
 }
 
@@ -139,7 +323,12 @@ impl Iterator for SocketAddrs {
     }
 }
 
-async fn new_resolver() -> Result<TokioAsyncResolver, Box<dyn std::error::Error + Send + Sync>> {
+async fn new_resolver(
+    config: Option<&(ResolverConfig, ResolverOpts)>,
+) -> Result<TokioAsyncResolver, Box<dyn std::error::Error + Send + Sync>> {
+    if let Some((config, opts)) = config {
+        return Ok(TokioAsyncResolver::tokio(config.clone(), opts.clone()));
+    }
     let (config, opts) = SYSTEM_CONF
         .lock()
         .expect("Failed to lock SYSTEM_CONF")
@@ -149,3 +338,51 @@ async fn new_resolver() -> Result<TokioAsyncResolver, Box<dyn std::error::Error
     let resolver = TokioAsyncResolver::tokio(config, opts);
     Ok(resolver)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cache_expires_entries() {
+        let cache = DnsCache::new(Duration::from_secs(60), Duration::from_secs(5));
+        let addrs = vec![IpAddr::from([127, 0, 0, 1])];
+        cache.insert_entry(
+            "fresh",
+            (Instant::now() + Duration::from_secs(60), Ok(addrs.clone())),
+        );
+        cache.insert_entry(
+            "failed",
+            (Instant::now() + Duration::from_secs(5), Err("nx".into())),
+        );
+        cache.insert_entry("stale", (Instant::now(), Ok(addrs.clone())));
+
+        assert_eq!(cache.get("fresh"), Some(Ok(addrs)));
+        assert_eq!(cache.get("failed"), Some(Err("nx".to_string())));
+        assert_eq!(cache.get("stale"), None);
+        assert_eq!(cache.get("unknown"), None);
+
+        cache.clear();
+        assert_eq!(cache.get("fresh"), None);
+    }
+
+    #[test]
+    fn orders_by_ip_preference() {
+        let v4 = IpAddr::from([10, 0, 0, 1]);
+        let v6 = IpAddr::from([0xfd00, 0, 0, 0, 0, 0, 0, 1]);
+        let other_v4 = IpAddr::from([10, 0, 0, 2]);
+        let addrs = vec![v6, v4, other_v4];
+
+        assert_eq!(IpPreference::Any.apply(addrs.clone()), addrs);
+        assert_eq!(
+            IpPreference::Ipv4First.apply(addrs.clone()),
+            vec![v4, other_v4, v6]
+        );
+        assert_eq!(IpPreference::Ipv6First.apply(vec![v4, v6]), vec![v6, v4]);
+        assert_eq!(
+            IpPreference::Ipv4Only.apply(addrs.clone()),
+            vec![v4, other_v4]
+        );
+        assert_eq!(IpPreference::Ipv6Only.apply(addrs), vec![v6]);
+    }
+}
@@ -0,0 +1,185 @@
+use std::env;
+
+use serde_json::{Map, Value};
+
+use crate::body::{KeyValueMap, Line};
+use crate::processor::LineProcessor;
+
+/// Where an enrichment field is written on a line
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Target {
+    Label,
+    Meta,
+}
+
+/// Stamps every line with a fixed set of fields, typically derived from the environment
+///
+/// Fields already present on a line are never overwritten. Meta fields are only added
+/// when the line's meta is absent or a json object.
+///
+/// # Example
+///
+/// ```rust
+/// # use logdna_client::enrichment::{Enricher, Target};
+/// let mut enricher = Enricher::new();
+/// enricher
+///     .env_var("pod", "POD_NAME", Target::Label)
+///     .env_var("namespace", "POD_NAMESPACE", Target::Label)
+///     .field("region", "us-east-1", Target::Meta);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Enricher {
+    fields: Vec<(String, String, Target)>,
+}
+
+impl Enricher {
+    /// Constructs an Enricher that doesn't add any fields
+    pub fn new() -> Self {
+        Self { fields: Vec::new() }
+    }
+    /// Constructs an Enricher pre-populated with the common kubernetes and cloud variables
+    ///
+    /// Labels: `pod` (`POD_NAME`), `namespace` (`POD_NAMESPACE`), `node` (`NODE_NAME`)
+    ///
+    /// Meta: `instance_id` (`INSTANCE_ID`), `region` (`REGION` or `AWS_REGION`), `process` (executable name)
+    pub fn with_defaults() -> Self {
+        let mut enricher = Self::new();
+        enricher
+            .env_var("pod", "POD_NAME", Target::Label)
+            .env_var("namespace", "POD_NAMESPACE", Target::Label)
+            .env_var("node", "NODE_NAME", Target::Label)
+            .env_var("instance_id", "INSTANCE_ID", Target::Meta)
+            .env_var("region", "REGION", Target::Meta)
+            .env_var("region", "AWS_REGION", Target::Meta)
+            .process_name("process", Target::Meta);
+        enricher
+    }
+    /// Adds a fixed field
+    pub fn field<K: Into<String>, V: Into<String>>(
+        &mut self,
+        key: K,
+        value: V,
+        target: Target,
+    ) -> &mut Self {
+        let key = key.into();
+        // The first source for a key wins, e.g REGION before AWS_REGION
+        if !self
+            .fields
+            .iter()
+            .any(|(k, _, t)| *k == key && *t == target)
+        {
+            self.fields.push((key, value.into(), target));
+        }
+        self
+    }
+    /// Adds a field read from an environment variable, skipped if the variable is unset or empty
+    pub fn env_var<K: Into<String>>(&mut self, key: K, var: &str, target: Target) -> &mut Self {
+        match env::var(var) {
+            Ok(value) if !value.is_empty() => self.field(key, value, target),
+            _ => self,
+        }
+    }
+    /// Adds the file name of the current executable
+    pub fn process_name<K: Into<String>>(&mut self, key: K, target: Target) -> &mut Self {
+        let name = env::current_exe().ok().and_then(|path| {
+            path.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        });
+        match name {
+            Some(name) => self.field(key, name, target),
+            None => self,
+        }
+    }
+    /// Returns true if no fields will be added
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+    /// Adds the configured fields to a line in place
+    pub fn enrich(&self, line: &mut Line) {
+        for (key, value, target) in self.fields.iter() {
+            match target {
+                Target::Label => {
+                    let labels = line.labels.get_or_insert_with(KeyValueMap::new);
+                    if !labels.contains_key(key) {
+                        labels.insert(key.clone(), value.clone());
+                    }
+                }
+                Target::Meta => {
+                    let meta = line.meta.get_or_insert_with(|| Value::Object(Map::new()));
+                    if let Value::Object(meta) = meta {
+                        meta.entry(key.clone())
+                            .or_insert_with(|| Value::String(value.clone()));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl LineProcessor for Enricher {
+    fn process(&self, mut line: Line) -> Option<Line> {
+        self.enrich(&mut line);
+        Some(line)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn enrich_labels_and_meta() {
+        let mut enricher = Enricher::new();
+        enricher
+            .field("pod", "pod-0", Target::Label)
+            .field("region", "us-east-1", Target::Meta)
+            .field("region", "eu-west-1", Target::Meta);
+
+        let mut line = Line::builder().line("test").build().unwrap();
+        enricher.enrich(&mut line);
+        assert_eq!(line.labels.unwrap()["pod"], "pod-0");
+        assert_eq!(
+            line.meta.unwrap(),
+            serde_json::json!({"region": "us-east-1"})
+        );
+    }
+
+    #[test]
+    fn enrich_does_not_overwrite() {
+        let mut enricher = Enricher::new();
+        enricher
+            .field("pod", "pod-0", Target::Label)
+            .field("region", "us-east-1", Target::Meta);
+
+        let mut line = Line::builder()
+            .line("test")
+            .labels(KeyValueMap::new().add("pod", "original"))
+            .meta(serde_json::json!({"region": "original"}))
+            .build()
+            .unwrap();
+        enricher.enrich(&mut line);
+        assert_eq!(line.labels.unwrap()["pod"], "original");
+        assert_eq!(
+            line.meta.unwrap(),
+            serde_json::json!({"region": "original"})
+        );
+    }
+
+    #[test]
+    fn enrich_env_var() {
+        env::set_var("LOGDNA_ENRICHMENT_TEST_POD", "pod-1");
+        env::set_var("LOGDNA_ENRICHMENT_TEST_EMPTY", "");
+        let mut enricher = Enricher::new();
+        enricher
+            .env_var("pod", "LOGDNA_ENRICHMENT_TEST_POD", Target::Label)
+            .env_var("empty", "LOGDNA_ENRICHMENT_TEST_EMPTY", Target::Label)
+            .env_var("missing", "LOGDNA_ENRICHMENT_TEST_MISSING", Target::Label);
+
+        let line = enricher
+            .process(Line::builder().line("test").build().unwrap())
+            .unwrap();
+        let labels = line.labels.unwrap();
+        assert_eq!(labels.len(), 1);
+        assert_eq!(labels["pod"], "pod-1");
+    }
+}
@@ -1,139 +1,495 @@
+#[cfg(feature = "client")]
 use std::fmt::{Debug, Display, Error as FmtError, Formatter};
 
+#[cfg(feature = "client")]
+use crate::body::IngestBodyBuffer;
+
 use thiserror::Error;
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum RequestError {
-    #[error("{0}")]
+    #[error(transparent)]
     Build(#[from] http::Error),
-    #[error("{0}")]
+    #[error(transparent)]
     BuildIo(#[from] std::io::Error),
-    #[error("{0}")]
+    #[error(transparent)]
     Body(#[from] BodyError),
 }
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum IngestBufError {
     #[error("{0}")]
     Any(&'static str),
 }
 
-pub enum HttpError<T>
-where
-    T: Send + 'static,
-{
-    Build(RequestError),
-    Send(T, hyper::Error),
-    Timeout(T),
-    Hyper(hyper::Error),
-    Utf8(std::str::Utf8Error),
-    FromUtf8(std::string::FromUtf8Error),
-    Serialization(serde_json::Error),
-    Other(Box<dyn std::error::Error + Send + 'static>),
+/// Context of a failed request, included in its error
+#[cfg(feature = "client")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestContext {
+    /// The `x-request-id` sent with the request
+    pub request_id: String,
+    /// The request target
+    pub uri: String,
+    /// Time from sending the request until it failed
+    pub elapsed: std::time::Duration,
+    /// The attempt number, starting at 1
+    pub attempt: u32,
+    /// The serialized body size in bytes
+    pub body_size: usize,
 }
 
-impl<T> From<RequestError> for HttpError<T>
-where
-    T: Send + 'static,
-{
-    fn from(e: RequestError) -> HttpError<T> {
-        HttpError::Build(e)
+#[cfg(feature = "client")]
+impl Display for RequestContext {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        write!(
+            f,
+            "request_id={} uri={} elapsed={:?} attempt={} body_size={}",
+            self.request_id, self.uri, self.elapsed, self.attempt, self.body_size
+        )
     }
 }
 
-impl<T> From<hyper::Error> for HttpError<T>
-where
-    T: Send + 'static,
-{
-    fn from(e: hyper::Error) -> HttpError<T> {
-        HttpError::Hyper(e)
-    }
+/// Errors sending a body with the [`Client`](crate::client::Client)
+///
+/// Errors wrapping another error expose it through [`std::error::Error::source`].
+#[cfg(feature = "client")]
+#[derive(Error)]
+#[non_exhaustive]
+pub enum HttpError {
+    #[error(transparent)]
+    Build(#[from] RequestError),
+    #[error("request failed ({2})")]
+    Send(
+        Box<IngestBodyBuffer>,
+        #[source] hyper::Error,
+        RequestContext,
+    ),
+    #[error("request timed out! ({1})")]
+    Timeout(Box<IngestBodyBuffer>, RequestContext),
+    #[error("rate limited, tokens available in {1:?}")]
+    RateLimited(Box<IngestBodyBuffer>, std::time::Duration),
+    #[error("client is shut down")]
+    Shutdown(Box<IngestBodyBuffer>),
+    #[error("an identical body was sent recently")]
+    Duplicate(Box<IngestBodyBuffer>),
+    #[cfg(feature = "http3")]
+    #[error("HTTP/3 request failed ({2})")]
+    Http3(Box<IngestBodyBuffer>, #[source] Http3Error, RequestContext),
+    #[error(transparent)]
+    Hyper(#[from] hyper::Error),
+    #[error(transparent)]
+    Utf8(#[from] std::str::Utf8Error),
+    #[error(transparent)]
+    FromUtf8(#[from] std::string::FromUtf8Error),
+    #[error(transparent)]
+    Serialization(#[from] serde_json::Error),
+    #[error(transparent)]
+    Other(Box<dyn std::error::Error + Send + 'static>),
 }
 
-impl<T> From<std::string::FromUtf8Error> for HttpError<T>
-where
-    T: Send + 'static,
-{
-    fn from(e: std::string::FromUtf8Error) -> HttpError<T> {
-        HttpError::FromUtf8(e)
+#[cfg(feature = "client")]
+impl HttpError {
+    /// The body that was not sent, if the error carries it
+    pub fn body(&self) -> Option<&IngestBodyBuffer> {
+        match self {
+            HttpError::Send(body, ..)
+            | HttpError::Timeout(body, _)
+            | HttpError::RateLimited(body, _)
+            | HttpError::Shutdown(body)
+            | HttpError::Duplicate(body) => Some(body),
+            #[cfg(feature = "http3")]
+            HttpError::Http3(body, ..) => Some(body),
+            _ => None,
+        }
+    }
+    /// Takes the body that was not sent, e.g to retry it later
+    pub fn into_body(self) -> Option<IngestBodyBuffer> {
+        match self {
+            HttpError::Send(body, ..)
+            | HttpError::Timeout(body, _)
+            | HttpError::RateLimited(body, _)
+            | HttpError::Shutdown(body)
+            | HttpError::Duplicate(body) => Some(*body),
+            #[cfg(feature = "http3")]
+            HttpError::Http3(body, ..) => Some(*body),
+            _ => None,
+        }
+    }
+    /// The context of the failed request, if it was sent
+    pub fn context(&self) -> Option<&RequestContext> {
+        match self {
+            HttpError::Send(_, _, context) | HttpError::Timeout(_, context) => Some(context),
+            #[cfg(feature = "http3")]
+            HttpError::Http3(_, _, context) => Some(context),
+            _ => None,
+        }
+    }
+    /// The category of the error
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            HttpError::Build(RequestError::Body(_)) => ErrorCode::SerializationFailed,
+            HttpError::Build(_) => ErrorCode::InvalidRequest,
+            HttpError::Send(_, e, _) | HttpError::Hyper(e) => hyper_error_code(e),
+            HttpError::Timeout(..) => ErrorCode::Timeout,
+            HttpError::RateLimited(..) => ErrorCode::RateLimited,
+            HttpError::Shutdown(_) => ErrorCode::Shutdown,
+            HttpError::Duplicate(_) => ErrorCode::Duplicate,
+            #[cfg(feature = "http3")]
+            HttpError::Http3(_, e, _) => match e {
+                Http3Error::Connect(_) | Http3Error::Connection(_) | Http3Error::Resolve(..) => {
+                    ErrorCode::ConnectFailed
+                }
+                _ => ErrorCode::SendFailed,
+            },
+            HttpError::Utf8(_) | HttpError::FromUtf8(_) => ErrorCode::InvalidResponse,
+            HttpError::Serialization(_) => ErrorCode::SerializationFailed,
+            HttpError::Other(_) => ErrorCode::Other,
+        }
     }
 }
 
-impl<T> From<std::str::Utf8Error> for HttpError<T>
-where
-    T: Send + 'static,
-{
-    fn from(e: std::str::Utf8Error) -> HttpError<T> {
-        HttpError::Utf8(e)
+#[cfg(feature = "client")]
+fn hyper_error_code(e: &hyper::Error) -> ErrorCode {
+    if is_tls_error(e) {
+        ErrorCode::TlsFailed
+    } else if e.is_connect() {
+        ErrorCode::ConnectFailed
+    } else if e.is_timeout() {
+        ErrorCode::Timeout
+    } else {
+        ErrorCode::SendFailed
     }
 }
 
-impl<T> From<serde_json::Error> for HttpError<T>
-where
-    T: Send + 'static,
-{
-    fn from(e: serde_json::Error) -> HttpError<T> {
-        HttpError::Serialization(e)
+// Whether a rustls error is anywhere in the source chain
+#[cfg(feature = "client")]
+fn is_tls_error(e: &(dyn std::error::Error + 'static)) -> bool {
+    let mut next = Some(e);
+    while let Some(e) = next {
+        if e.is::<rustls::Error>() {
+            return true;
+        }
+        // io::Error::source skips the wrapped error itself
+        if let Some(inner) = e
+            .downcast_ref::<std::io::Error>()
+            .and_then(|io| io.get_ref())
+        {
+            if inner.is::<rustls::Error>() {
+                return true;
+            }
+        }
+        next = e.source();
     }
+    false
 }
 
-impl<T> Display for HttpError<T>
-where
-    T: Send + 'static,
-{
-    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+/// Stable categories of failed sends, see [`HttpError::code`] and
+/// [`Response::code`](crate::response::Response::code)
+///
+/// The names returned by [`ErrorCode::as_str`] never change, so they can be used as metric
+/// labels or in alerting rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorCode {
+    /// The request timed out
+    Timeout,
+    /// No connection could be established to the ingest host or proxy
+    ConnectFailed,
+    /// The tls handshake failed, e.g an untrusted certificate
+    TlsFailed,
+    /// The connection failed while sending the request or reading the response
+    SendFailed,
+    /// The send was rate limited, by the client or with a `429` response
+    RateLimited,
+    /// The ingestion key was rejected with a `401` or `403` response
+    Unauthorized,
+    /// The body was rejected with another `4xx` response
+    Rejected,
+    /// The ingest API failed with a `5xx` response
+    ServerError,
+    /// The body could not be serialized
+    SerializationFailed,
+    /// The request could not be built from the template
+    InvalidRequest,
+    /// The response could not be read
+    InvalidResponse,
+    /// The client was shut down
+    Shutdown,
+    /// An identical body was sent recently, see [`DedupCache`](crate::dedup::DedupCache)
+    Duplicate,
+    /// Any other error
+    Other,
+}
+
+impl ErrorCode {
+    /// The stable snake_case name of the code
+    pub fn as_str(&self) -> &'static str {
         match self {
-            HttpError::Send(_, ref e) => write!(f, "{}", e),
-            HttpError::Timeout(_) => write!(f, "request timed out!"),
-            HttpError::Hyper(ref e) => write!(f, "{}", e),
-            HttpError::Build(ref e) => write!(f, "{}", e),
-            HttpError::Utf8(ref e) => write!(f, "{}", e),
-            HttpError::FromUtf8(ref e) => write!(f, "{}", e),
-            HttpError::Serialization(ref e) => write!(f, "{}", e),
-            HttpError::Other(ref e) => write!(f, "{}", e),
+            ErrorCode::Timeout => "timeout",
+            ErrorCode::ConnectFailed => "connect_failed",
+            ErrorCode::TlsFailed => "tls_failed",
+            ErrorCode::SendFailed => "send_failed",
+            ErrorCode::RateLimited => "rate_limited",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::Rejected => "rejected",
+            ErrorCode::ServerError => "server_error",
+            ErrorCode::SerializationFailed => "serialization_failed",
+            ErrorCode::InvalidRequest => "invalid_request",
+            ErrorCode::InvalidResponse => "invalid_response",
+            ErrorCode::Shutdown => "shutdown",
+            ErrorCode::Duplicate => "duplicate",
+            ErrorCode::Other => "other",
         }
     }
 }
 
-impl<T> Debug for HttpError<T>
-where
-    T: Send + 'static,
-{
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "client")]
+impl Debug for HttpError {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
         Display::fmt(self, f)
     }
 }
 
+/// Errors building a [`Client`](crate::client::Client)
+#[cfg(feature = "client")]
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum ClientError {
+    #[error(transparent)]
+    Dns(std::io::Error),
+    #[error("error reading {}", .0.display())]
+    TlsFile(std::path::PathBuf, #[source] std::io::Error),
+    #[error("no private key found in {}", .0.display())]
+    NoPrivateKey(std::path::PathBuf),
+    #[error(transparent)]
+    Tls(#[from] rustls::Error),
+    #[error(transparent)]
+    Template(#[from] TemplateError),
+    #[cfg(feature = "http3")]
+    #[error("failed to open a QUIC endpoint")]
+    Quic(#[source] std::io::Error),
+}
+
+/// Errors sending a request over HTTP/3
+#[cfg(feature = "http3")]
 #[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Http3Error {
+    #[error(transparent)]
+    Connect(#[from] quinn::ConnectError),
+    #[error(transparent)]
+    Connection(#[from] quinn::ConnectionError),
+    #[error(transparent)]
+    H3(#[from] h3::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("could not resolve {0}")]
+    Resolve(
+        String,
+        #[source] Option<Box<dyn std::error::Error + Send + Sync>>,
+    ),
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum BodyError {
-    #[error("{0}")]
+    #[error(transparent)]
     Json(#[from] serde_json::Error),
-    #[error("{0}")]
+    #[error(transparent)]
     Gzip(#[from] std::io::Error),
+    #[error(transparent)]
+    MsgPack(#[from] rmp_serde::encode::Error),
+    #[error("{0}")]
+    Payload(&'static str),
 }
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum TemplateError {
-    #[error("{0}")]
+    #[error(transparent)]
     InvalidHeader(#[from] http::header::InvalidHeaderValue),
     #[error("{0}")]
     RequiredField(std::string::String),
+    #[error(transparent)]
+    Params(#[from] ParamsError),
 }
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum ParamsError {
     #[error("{0}")]
     RequiredField(std::string::String),
+    #[error(transparent)]
+    Limit(#[from] LimitError),
 }
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum LineError {
     #[error("{0}")]
     RequiredField(std::string::String),
+    #[error(transparent)]
+    Limit(#[from] LimitError),
+}
+
+/// A field over its [`Limits`](crate::limits::Limits)
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LimitError {
+    #[error("{field} is {len} bytes, the limit is {max}")]
+    TooLong {
+        field: std::string::String,
+        len: usize,
+        max: usize,
+    },
+    #[error("{field} has {len} entries, the limit is {max}")]
+    TooManyEntries {
+        field: std::string::String,
+        len: usize,
+        max: usize,
+    },
 }
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum LineMetaError {
     #[error("{0}")]
     Failed(&'static str),
 }
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum RedactionError {
+    #[error(transparent)]
+    Regex(#[from] regex::Error),
+}
+
+#[cfg(feature = "anonymize")]
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum AnonymizeError {
+    #[error("salt variable {0} is unset or empty")]
+    MissingSalt(std::string::String),
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum MultilineError {
+    #[error("{0}")]
+    RequiredField(std::string::String),
+    #[error(transparent)]
+    Regex(#[from] regex::Error),
+}
+
+#[cfg(feature = "syslog")]
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum SyslogError {
+    #[error("invalid syslog message: {0}")]
+    Invalid(&'static str),
+    #[error(transparent)]
+    Line(#[from] LineError),
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum CriError {
+    #[error("invalid CRI log record: {0}")]
+    Invalid(&'static str),
+    #[error(transparent)]
+    Line(#[from] LineError),
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum ExtractError {
+    #[error(transparent)]
+    Regex(#[from] regex::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(feature = "client")]
+    fn buffer() -> Box<IngestBodyBuffer> {
+        use crate::body::{IngestBody, IntoIngestBodyBuffer};
+
+        let buffer = IntoIngestBodyBuffer::into(&IngestBody::new(vec![]));
+        Box::new(tokio_test::block_on(buffer).unwrap())
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn http_error_codes() {
+        let context = RequestContext {
+            request_id: "id".into(),
+            uri: "https://logs.logdna.com/logs/ingest".into(),
+            elapsed: std::time::Duration::from_secs(5),
+            attempt: 1,
+            body_size: 0,
+        };
+        let timeout = HttpError::Timeout(buffer(), context);
+        assert_eq!(timeout.code(), ErrorCode::Timeout);
+        let limited = HttpError::RateLimited(buffer(), std::time::Duration::from_secs(1));
+        assert_eq!(limited.code(), ErrorCode::RateLimited);
+        let body = HttpError::Build(RequestError::Body(BodyError::Payload("bad")));
+        assert_eq!(body.code(), ErrorCode::SerializationFailed);
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn recovers_unsent_bodies() {
+        let buffer = buffer();
+        let key = buffer.idempotency_key().to_string();
+        let shutdown = HttpError::Shutdown(buffer);
+        assert_eq!(shutdown.body().unwrap().idempotency_key(), key);
+        assert_eq!(shutdown.into_body().unwrap().idempotency_key(), key);
+
+        let body = HttpError::Build(RequestError::Body(BodyError::Payload("bad")));
+        assert!(body.into_body().is_none());
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn finds_wrapped_tls_errors() {
+        let io = std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            rustls::Error::General("bad certificate".into()),
+        );
+        assert!(is_tls_error(&io));
+        assert!(!is_tls_error(&std::io::Error::from(
+            std::io::ErrorKind::ConnectionRefused
+        )));
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn causes_are_displayed_once() {
+        use std::error::Error as _;
+
+        let io = || std::io::Error::new(std::io::ErrorKind::NotFound, "no such file");
+        let tls = ClientError::TlsFile("/etc/ca.pem".into(), io());
+        assert_eq!(tls.to_string(), "error reading /etc/ca.pem");
+        assert_eq!(tls.source().unwrap().to_string(), "no such file");
+
+        // wrappers display and expose the wrapped error's own source
+        let build = HttpError::Build(RequestError::BuildIo(io()));
+        assert_eq!(build.to_string(), "no such file");
+        assert!(build.source().is_none());
+    }
+
+    #[test]
+    fn error_code_names() {
+        assert_eq!(ErrorCode::Unauthorized.to_string(), "unauthorized");
+        assert_eq!(ErrorCode::TlsFailed.as_str(), "tls_failed");
+    }
+}
@@ -0,0 +1,183 @@
+use regex::Regex;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::body::Line;
+use crate::error::ExtractError;
+use crate::processor::LineProcessor;
+
+const DEFAULT_LEVEL_PATTERN: &str =
+    r"(?i)\b(TRACE|DEBUG|INFO|NOTICE|WARN(?:ING)?|ERROR|ERR|CRIT(?:ICAL)?|FATAL|PANIC)\b";
+
+const DEFAULT_TIMESTAMP_PATTERN: &str =
+    r"^\[?(\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}:\d{2}(?:[.,]\d+)?(?:Z|[+-]\d{2}:?\d{2})?)";
+
+/// Extracts the level and timestamp of plain text lines
+///
+/// The level regex is matched anywhere in the line and the timestamp regex is expected to
+/// match a prefix, both use their first capture group (or the whole match if there is none).
+/// Levels are upper-cased, e.g `warn` becomes `WARN`, and only set on lines without a level.
+/// Timestamps must be ISO 8601 date-times, a missing zone is treated as UTC.
+///
+/// # Example
+///
+/// ```rust
+/// # use logdna_client::body::Line;
+/// # use logdna_client::extract::Extractor;
+/// let mut line = Line::builder()
+///     .line("2021-01-01 00:00:00,123 [main] warn something happened")
+///     .build()
+///     .unwrap();
+/// Extractor::new().extract(&mut line);
+/// assert_eq!(line.level.as_deref(), Some("WARN"));
+/// assert_eq!(line.timestamp, 1609459200);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Extractor {
+    level: Option<Regex>,
+    timestamp: Option<Regex>,
+}
+
+impl Extractor {
+    /// Constructs an Extractor using the default level and timestamp patterns
+    pub fn new() -> Self {
+        Self {
+            level: Some(Regex::new(DEFAULT_LEVEL_PATTERN).expect("DEFAULT_LEVEL_PATTERN")),
+            timestamp: Some(
+                Regex::new(DEFAULT_TIMESTAMP_PATTERN).expect("DEFAULT_TIMESTAMP_PATTERN"),
+            ),
+        }
+    }
+    /// Replaces the level pattern, `None` disables level extraction
+    pub fn level_pattern(mut self, pattern: Option<&str>) -> Result<Self, ExtractError> {
+        self.level = pattern.map(Regex::new).transpose()?;
+        Ok(self)
+    }
+    /// Replaces the timestamp pattern, `None` disables timestamp extraction
+    pub fn timestamp_pattern(mut self, pattern: Option<&str>) -> Result<Self, ExtractError> {
+        self.timestamp = pattern.map(Regex::new).transpose()?;
+        Ok(self)
+    }
+    /// Extracts the level and timestamp of a line in place
+    pub fn extract(&self, line: &mut Line) {
+        if line.level.is_none() {
+            if let Some(level) = self
+                .level
+                .as_ref()
+                .and_then(|re| first_capture(re, &line.line))
+            {
+                line.level = Some(level.to_ascii_uppercase());
+            }
+        }
+        if let Some(timestamp) = self
+            .timestamp
+            .as_ref()
+            .and_then(|re| first_capture(re, &line.line))
+            .and_then(parse_timestamp)
+        {
+            line.timestamp = timestamp;
+        }
+    }
+}
+
+impl Default for Extractor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LineProcessor for Extractor {
+    fn process(&self, mut line: Line) -> Option<Line> {
+        self.extract(&mut line);
+        Some(line)
+    }
+}
+
+fn first_capture<'a>(re: &Regex, line: &'a str) -> Option<&'a str> {
+    let captures = re.captures(line)?;
+    captures
+        .get(1)
+        .or_else(|| captures.get(0))
+        .map(|m| m.as_str())
+}
+
+// Normalises an ISO 8601 date-time into RFC 3339 before parsing it
+fn parse_timestamp(timestamp: &str) -> Option<i64> {
+    let mut normalized = timestamp.replacen(' ', "T", 1).replacen(',', ".", 1);
+    let zone = normalized
+        .rfind(|c: char| c == 'Z' || c == '+' || c == '-')
+        .filter(|i| *i > 10);
+    match zone {
+        None => normalized.push('Z'),
+        Some(i) if !normalized[i..].contains(':') && normalized.len() - i == 5 => {
+            normalized.insert(i + 3, ':')
+        }
+        Some(_) => (),
+    }
+    OffsetDateTime::parse(&normalized, &Rfc3339)
+        .ok()
+        .map(|ts| ts.unix_timestamp())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn extract(extractor: &Extractor, l: &str) -> Line {
+        let mut line = Line::builder().line(l).build().unwrap();
+        extractor.extract(&mut line);
+        line
+    }
+
+    #[test]
+    fn extracts_levels() {
+        let extractor = Extractor::new();
+        for (raw, level) in [
+            ("something ERROR happened", Some("ERROR")),
+            ("[warning] disk", Some("WARNING")),
+            ("level=debug msg=hi", Some("DEBUG")),
+            ("informational", None),
+        ] {
+            assert_eq!(extract(&extractor, raw).level.as_deref(), level, "{}", raw);
+        }
+    }
+
+    #[test]
+    fn keeps_existing_level() {
+        let mut line = Line::builder()
+            .line("ERROR boom")
+            .level("INFO")
+            .build()
+            .unwrap();
+        Extractor::new().extract(&mut line);
+        assert_eq!(line.level.as_deref(), Some("INFO"));
+    }
+
+    #[test]
+    fn extracts_timestamps() {
+        let extractor = Extractor::new();
+        for raw in [
+            "2021-01-01T00:00:00Z INFO ok",
+            "2021-01-01 00:00:00 INFO ok",
+            "2021-01-01T01:00:00+01:00 INFO ok",
+            "2021-01-01T01:00:00+0100 INFO ok",
+            "[2021-01-01 00:00:00.123] INFO ok",
+        ] {
+            assert_eq!(extract(&extractor, raw).timestamp, 1609459200, "{}", raw);
+        }
+    }
+
+    #[test]
+    fn custom_patterns() {
+        let extractor = Extractor::new()
+            .level_pattern(Some(r"lvl=(\w+)"))
+            .unwrap()
+            .timestamp_pattern(None)
+            .unwrap();
+        let line = extract(&extractor, "2021-01-01T00:00:00Z lvl=notice ERROR");
+        assert_eq!(line.level.as_deref(), Some("NOTICE"));
+        assert_ne!(line.timestamp, 1609459200);
+
+        assert!(Extractor::new().level_pattern(Some("(")).is_err());
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use bytes::{Buf, Bytes, BytesMut};
+use http::Request;
+use hyper::client::connect::dns::Name;
+use hyper::service::Service;
+use hyper::Body;
+
+use crate::body::IngestBodyBuffer;
+use crate::dns::TrustDnsResolver;
+use crate::error::{ClientError, Http3Error};
+
+type SendRequest = h3::client::SendRequest<h3_quinn::OpenStreams, Bytes>;
+
+/// Sends requests over HTTP/3, reusing one QUIC connection per host and port until it fails
+///
+/// Used by the client for templates with [`Transport::Http3`](crate::request::Transport::Http3).
+pub(crate) struct Http3Client {
+    endpoint: quinn::Endpoint,
+    // replaced when the tls files are reloaded, new connections use the current one
+    config: std::sync::Mutex<quinn::ClientConfig>,
+    tls_server_name: Option<String>,
+    // the client's resolver, with its nameservers, cache and ip preference
+    resolver: TrustDnsResolver,
+    connections: tokio::sync::Mutex<HashMap<String, SendRequest>>,
+}
+
+impl Http3Client {
+    pub(crate) fn new(
+        tls: rustls::ClientConfig,
+        tls_server_name: Option<String>,
+        resolver: TrustDnsResolver,
+    ) -> Result<Self, ClientError> {
+        let bind: SocketAddr = "[::]:0".parse().expect("valid bind address");
+        let endpoint = quinn::Endpoint::client(bind).map_err(ClientError::Quic)?;
+        Ok(Self {
+            endpoint,
+            config: std::sync::Mutex::new(quic_config(tls)),
+            tls_server_name,
+            resolver,
+            connections: tokio::sync::Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Sends a request, reading the whole response body
+    pub(crate) async fn send(
+        &self,
+        request: Request<IngestBodyBuffer>,
+    ) -> Result<hyper::Response<Body>, Http3Error> {
+        let key = connection_key(request.uri());
+        let result = self.send_request(request).await;
+        if result.is_err() {
+            // the next request to the host opens a new connection
+            self.connections.lock().await.remove(&key);
+        }
+        result
+    }
+
+    async fn send_request(
+        &self,
+        request: Request<IngestBodyBuffer>,
+    ) -> Result<hyper::Response<Body>, Http3Error> {
+        let (parts, mut body) = request.into_parts();
+        let mut send_request = self.connection(&parts.uri).await?;
+
+        let mut stream = send_request
+            .send_request(Request::from_parts(parts, ()))
+            .await?;
+        // the segments are sent as they are, sharing the bytes of the body
+        for segment in body.share().segments() {
+            stream.send_data(segment.clone()).await?;
+        }
+        stream.finish().await?;
+
+        let response = stream.recv_response().await?;
+        let mut data = BytesMut::new();
+        while let Some(mut chunk) = stream.recv_data().await? {
+            data.extend_from_slice(&chunk.copy_to_bytes(chunk.remaining()));
+        }
+        let (parts, ()) = response.into_parts();
+        Ok(hyper::Response::from_parts(
+            parts,
+            Body::from(data.freeze()),
+        ))
+    }
+
+    /// Uses `tls` for new connections, closing the current ones once their requests complete
+    pub(crate) async fn set_tls(&self, tls: rustls::ClientConfig) {
+        *self.config.lock().expect("http3 config lock poisoned") = quic_config(tls);
+        self.connections.lock().await.clear();
+    }
+
+    /// Closes every connection and the endpoint, requests still in flight fail
+    pub(crate) async fn close(&self) {
+        self.connections.lock().await.clear();
+        self.endpoint
+            .close(quinn::VarInt::from_u32(0), b"client shut down");
+    }
+
+    async fn connection(&self, uri: &http::Uri) -> Result<SendRequest, Http3Error> {
+        let key = connection_key(uri);
+        let mut connections = self.connections.lock().await;
+        if let Some(send_request) = connections.get(&key) {
+            return Ok(send_request.clone());
+        }
+
+        let host = uri
+            .host()
+            .ok_or_else(|| Http3Error::Resolve(uri.to_string(), None))?;
+        let port = uri.port_u16().unwrap_or(443);
+        let addr = SocketAddr::new(self.resolve(host).await?, port);
+        let server_name = self.tls_server_name.as_deref().unwrap_or(host);
+        let config = self
+            .config
+            .lock()
+            .expect("http3 config lock poisoned")
+            .clone();
+        let quic = self
+            .endpoint
+            .connect_with(config, addr, server_name)?
+            .await?;
+
+        let (mut driver, send_request) = h3::client::new(h3_quinn::Connection::new(quic)).await?;
+        tokio::spawn(async move {
+            if let Err(e) = futures::future::poll_fn(|cx| driver.poll_close(cx)).await {
+                log::debug!("http/3 connection closed: {}", e);
+            }
+        });
+        connections.insert(key, send_request.clone());
+        Ok(send_request)
+    }
+
+    // Resolves through the client resolver, QUIC connects to the first preferred address
+    async fn resolve(&self, host: &str) -> Result<IpAddr, Http3Error> {
+        // bracketed in uris, e.g `[::1]`
+        let literal = host.trim_start_matches('[').trim_end_matches(']');
+        if let Ok(ip) = literal.parse() {
+            return Ok(ip);
+        }
+        let name = Name::from_str(host)
+            .map_err(|e| Http3Error::Resolve(host.to_string(), Some(e.into())))?;
+        let mut addrs = self
+            .resolver
+            .clone()
+            .call(name)
+            .await
+            .map_err(|e| Http3Error::Resolve(host.to_string(), Some(e)))?;
+        addrs
+            .next()
+            .map(|addr| addr.ip())
+            .ok_or_else(|| Http3Error::Resolve(host.to_string(), None))
+    }
+}
+
+fn quic_config(mut tls: rustls::ClientConfig) -> quinn::ClientConfig {
+    tls.alpn_protocols = vec![b"h3".to_vec()];
+    quinn::ClientConfig::new(Arc::new(tls))
+}
+
+// Requests share a connection when they go to the same host and port, e.g not across redirects
+fn connection_key(uri: &http::Uri) -> String {
+    format!(
+        "{}:{}",
+        uri.host().unwrap_or_default(),
+        uri.port_u16().unwrap_or(443)
+    )
+}
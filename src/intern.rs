@@ -0,0 +1,112 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, RwLock};
+
+use once_cell::sync::Lazy;
+
+const DEFAULT_CAPACITY: usize = 4096;
+
+const SHARDS: usize = 16;
+
+static GLOBAL: Lazy<Interner> = Lazy::new(Interner::default);
+
+// A pool of shared strings, so repeated values are stored once
+//
+// Strings are spread over shards by hash, so threads interning different values rarely contend
+// and lookups of values already pooled only take a read lock. Once a shard holds its share of
+// `capacity` strings new values are no longer added, which bounds the memory of the pool when a
+// field has unexpectedly many distinct values.
+#[derive(Debug)]
+pub(crate) struct Interner {
+    shards: Vec<RwLock<HashSet<Arc<str>>>>,
+    shard_capacity: usize,
+}
+
+impl Interner {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            shards: (0..SHARDS).map(|_| RwLock::default()).collect(),
+            shard_capacity: (capacity + SHARDS - 1) / SHARDS,
+        }
+    }
+
+    // Returns the pooled copy of `s`, adding it if its shard has room
+    pub(crate) fn intern(&self, s: &str) -> Arc<str> {
+        let shard = self.shard(s);
+        if let Some(interned) = shard.read().expect("interner lock poisoned").get(s) {
+            return interned.clone();
+        }
+        let mut strings = shard.write().expect("interner lock poisoned");
+        // another thread may have added it between the two locks
+        if let Some(interned) = strings.get(s) {
+            return interned.clone();
+        }
+        let interned: Arc<str> = Arc::from(s);
+        if strings.len() < self.shard_capacity {
+            strings.insert(interned.clone());
+        }
+        interned
+    }
+
+    fn shard(&self, s: &str) -> &RwLock<HashSet<Arc<str>>> {
+        let mut hasher = DefaultHasher::new();
+        s.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % SHARDS]
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.read().expect("interner lock poisoned").len())
+            .sum()
+    }
+}
+
+impl Default for Interner {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+/// Interns `s` in the global pool
+pub(crate) fn intern(s: &str) -> Arc<str> {
+    GLOBAL.intern(s)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn shares_equal_strings_up_to_capacity() {
+        let interner = Interner::new(SHARDS);
+        let a = interner.intern("production");
+        assert!(Arc::ptr_eq(&a, &interner.intern("production")));
+
+        // every shard holds a single string, so some of these don't fit
+        let values: Vec<String> = (0..SHARDS * 2).map(|i| format!("env-{}", i)).collect();
+        let unpooled = values
+            .iter()
+            .filter(|value| !Arc::ptr_eq(&interner.intern(value), &interner.intern(value)))
+            .count();
+        assert!(unpooled >= SHARDS);
+        assert!(interner.len() <= SHARDS);
+        assert_eq!(&*interner.intern("env-0"), "env-0");
+    }
+
+    #[test]
+    fn interns_from_many_threads() {
+        let interner = Arc::new(Interner::default());
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let interner = interner.clone();
+                std::thread::spawn(move || interner.intern("shared"))
+            })
+            .collect();
+        let interned: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert!(interned.iter().all(|s| Arc::ptr_eq(s, &interned[0])));
+        assert_eq!(interner.len(), 1);
+    }
+}
@@ -0,0 +1,153 @@
+use serde_json::{Map, Value};
+
+use crate::body::Line;
+use crate::error::LineError;
+use crate::level::severity_level;
+
+/// Converts the fields of a systemd journal entry into a Line
+///
+/// | journal field | Line field |
+/// |---|---|
+/// | `MESSAGE` | `line` (required) |
+/// | `PRIORITY` | `level` |
+/// | `_SYSTEMD_UNIT`, else `SYSLOG_IDENTIFIER`, else `_COMM` | `app` |
+/// | `_HOSTNAME` | `host` |
+/// | `_SOURCE_REALTIME_TIMESTAMP`, else `__REALTIME_TIMESTAMP` | `timestamp` |
+///
+/// Every other field is stored in `meta`, except the `__` prefixed address fields
+/// (e.g `__CURSOR`) which only make sense to the local journal.
+///
+/// # Example
+///
+/// ```rust
+/// # use logdna_client::journald;
+/// let line = journald::from_fields(vec![
+///     ("MESSAGE", "Started Daily apt upgrade."),
+///     ("PRIORITY", "6"),
+///     ("_SYSTEMD_UNIT", "apt-daily-upgrade.service"),
+///     ("_HOSTNAME", "node-001"),
+///     ("_PID", "1"),
+/// ]).expect("journald::from_fields()");
+/// assert_eq!(line.level.as_deref(), Some("INFO"));
+/// assert_eq!(line.app.as_deref(), Some("apt-daily-upgrade.service"));
+/// assert_eq!(line.meta.unwrap()["_PID"], "1");
+/// ```
+pub fn from_fields<I, K, V>(fields: I) -> Result<Line, LineError>
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: Into<String>,
+    V: Into<String>,
+{
+    let mut meta = Map::new();
+    let mut builder = Line::builder();
+    let mut unit = None;
+    let mut identifier = None;
+    let mut comm = None;
+    let mut source_timestamp = None;
+    let mut timestamp = None;
+
+    for (key, value) in fields {
+        let key = key.into();
+        let value = value.into();
+        match key.as_str() {
+            "MESSAGE" => builder = builder.line(value),
+            "PRIORITY" => match value.parse::<u8>() {
+                Ok(priority) => builder = builder.level(severity_level(priority)),
+                Err(_) => {
+                    meta.insert(key, Value::from(value));
+                }
+            },
+            "_HOSTNAME" => builder = builder.host(value),
+            "_SYSTEMD_UNIT" => unit = Some(value),
+            "SYSLOG_IDENTIFIER" => identifier = Some(value),
+            "_COMM" => comm = Some(value),
+            "_SOURCE_REALTIME_TIMESTAMP" => source_timestamp = parse_micros(&value),
+            "__REALTIME_TIMESTAMP" => timestamp = parse_micros(&value),
+            _ if key.starts_with("__") => (),
+            _ => {
+                meta.insert(key, Value::from(value));
+            }
+        }
+    }
+
+    // The identifiers that weren't used for the app are kept as meta
+    let mut app = None;
+    for (key, value) in [
+        ("_SYSTEMD_UNIT", unit),
+        ("SYSLOG_IDENTIFIER", identifier),
+        ("_COMM", comm),
+    ] {
+        match (value, app.is_some()) {
+            (Some(value), false) => app = Some(value),
+            (Some(value), true) => {
+                meta.insert(key.into(), Value::from(value));
+            }
+            (None, _) => (),
+        }
+    }
+    if let Some(app) = app {
+        builder = builder.app(app);
+    }
+    if !meta.is_empty() {
+        builder = builder.meta(Value::Object(meta));
+    }
+
+    let mut line = builder.build()?;
+    if let Some(timestamp) = source_timestamp.or(timestamp) {
+        line.timestamp = timestamp;
+    }
+    Ok(line)
+}
+
+// Journal timestamps are microseconds since the epoch
+fn parse_micros(value: &str) -> Option<i64> {
+    value.parse::<i64>().ok().map(|micros| micros / 1_000_000)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn maps_fields() {
+        let line = from_fields(vec![
+            ("MESSAGE", "hello"),
+            ("PRIORITY", "3"),
+            ("_SYSTEMD_UNIT", "app.service"),
+            ("SYSLOG_IDENTIFIER", "app"),
+            ("_HOSTNAME", "node-001"),
+            ("__REALTIME_TIMESTAMP", "1609459200123456"),
+            ("__CURSOR", "s=abc"),
+            ("_PID", "42"),
+        ])
+        .unwrap();
+        assert_eq!(line.line, "hello");
+        assert_eq!(line.level.as_deref(), Some("ERROR"));
+        assert_eq!(line.app.as_deref(), Some("app.service"));
+        assert_eq!(line.host.as_deref(), Some("node-001"));
+        assert_eq!(line.timestamp, 1609459200);
+        assert_eq!(
+            line.meta.unwrap(),
+            serde_json::json!({"_PID": "42", "SYSLOG_IDENTIFIER": "app"})
+        );
+    }
+
+    #[test]
+    fn prefers_source_timestamp_and_falls_back_app() {
+        let line = from_fields(vec![
+            ("MESSAGE", "hello"),
+            ("__REALTIME_TIMESTAMP", "1609459300000000"),
+            ("_SOURCE_REALTIME_TIMESTAMP", "1609459200000000"),
+            ("_COMM", "bash"),
+        ])
+        .unwrap();
+        assert_eq!(line.timestamp, 1609459200);
+        assert_eq!(line.app.as_deref(), Some("bash"));
+        assert_eq!(line.meta, None);
+    }
+
+    #[test]
+    fn requires_message() {
+        assert!(from_fields(vec![("PRIORITY", "6")]).is_err());
+    }
+}
@@ -0,0 +1,216 @@
+use serde_json::{Map, Value};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::body::Line;
+use crate::processor::LineProcessor;
+
+// Unix timestamps above this are assumed to be in milliseconds
+const MILLIS_THRESHOLD: i64 = 100_000_000_000;
+
+/// Detects lines that are themselves a json object and lifts their well known keys into the Line
+///
+/// The first matching level, message and timestamp keys are moved to `level`, `line` and
+/// `timestamp`, every remaining key is merged into `meta` (existing meta keys win). Lines
+/// that are not a json object are passed through unchanged.
+///
+/// # Example
+///
+/// ```rust
+/// # use logdna_client::body::Line;
+/// # use logdna_client::json_detect::JsonDetector;
+/// # use logdna_client::processor::LineProcessor;
+/// let line = Line::builder()
+///     .line(r#"{"level":"warn","message":"disk full","disk":"/dev/sda1"}"#)
+///     .build()
+///     .unwrap();
+/// let line = JsonDetector::new().process(line).unwrap();
+/// assert_eq!(line.level.as_deref(), Some("warn"));
+/// assert_eq!(line.line, "disk full");
+/// assert_eq!(line.meta.unwrap()["disk"], "/dev/sda1");
+/// ```
+#[derive(Debug, Clone)]
+pub struct JsonDetector {
+    level_keys: Vec<String>,
+    message_keys: Vec<String>,
+    timestamp_keys: Vec<String>,
+}
+
+impl JsonDetector {
+    /// Constructs a JsonDetector with the default keys
+    ///
+    /// level: `level`, `severity`, `lvl`; message: `message`, `msg`; timestamp: `timestamp`, `time`, `ts`, `@timestamp`
+    pub fn new() -> Self {
+        let keys = |keys: &[&str]| keys.iter().map(|k| k.to_string()).collect();
+        Self {
+            level_keys: keys(&["level", "severity", "lvl"]),
+            message_keys: keys(&["message", "msg"]),
+            timestamp_keys: keys(&["timestamp", "time", "ts", "@timestamp"]),
+        }
+    }
+    /// Set the keys checked, in order, for the level
+    pub fn level_keys<T: Into<String>>(mut self, keys: impl IntoIterator<Item = T>) -> Self {
+        self.level_keys = keys.into_iter().map(Into::into).collect();
+        self
+    }
+    /// Set the keys checked, in order, for the message
+    pub fn message_keys<T: Into<String>>(mut self, keys: impl IntoIterator<Item = T>) -> Self {
+        self.message_keys = keys.into_iter().map(Into::into).collect();
+        self
+    }
+    /// Set the keys checked, in order, for the timestamp
+    pub fn timestamp_keys<T: Into<String>>(mut self, keys: impl IntoIterator<Item = T>) -> Self {
+        self.timestamp_keys = keys.into_iter().map(Into::into).collect();
+        self
+    }
+    /// Lifts the json fields of a line in place, returning false if the line is not a json object
+    pub fn detect(&self, line: &mut Line) -> bool {
+        if !line.line.trim_start().starts_with('{') {
+            return false;
+        }
+        let mut object = match serde_json::from_str::<Value>(&line.line) {
+            Ok(Value::Object(object)) => object,
+            _ => return false,
+        };
+
+        if let Some(level) = take_first(&mut object, &self.level_keys, |v| match v {
+            Value::String(s) => Some(s.clone()),
+            _ => None,
+        }) {
+            line.level = Some(level);
+        }
+        if let Some(timestamp) = take_first(&mut object, &self.timestamp_keys, parse_timestamp) {
+            line.timestamp = timestamp;
+        }
+        // Without a message key the line keeps its raw json
+        if let Some(message) = take_first(&mut object, &self.message_keys, |v| match v {
+            Value::String(s) => Some(s.clone()),
+            _ => None,
+        }) {
+            line.line = message;
+        }
+
+        match line.meta.as_mut() {
+            Some(Value::Object(meta)) => {
+                for (key, value) in object {
+                    meta.entry(key).or_insert(value);
+                }
+            }
+            Some(_) => (),
+            None => line.meta = Some(Value::Object(object)),
+        }
+        true
+    }
+}
+
+impl Default for JsonDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LineProcessor for JsonDetector {
+    fn process(&self, mut line: Line) -> Option<Line> {
+        self.detect(&mut line);
+        Some(line)
+    }
+}
+
+// Removes and converts the first key present with a convertible value
+fn take_first<T>(
+    object: &mut Map<String, Value>,
+    keys: &[String],
+    convert: impl Fn(&Value) -> Option<T>,
+) -> Option<T> {
+    for key in keys {
+        if let Some(converted) = object.get(key).and_then(&convert) {
+            object.remove(key);
+            return Some(converted);
+        }
+    }
+    None
+}
+
+/// Converts a json number (seconds or milliseconds since the epoch) or RFC 3339 string to unix seconds
+pub(crate) fn parse_timestamp(value: &Value) -> Option<i64> {
+    match value {
+        Value::Number(n) => n
+            .as_i64()
+            .or_else(|| n.as_f64().map(|f| f as i64))
+            .map(|ts| if ts > MILLIS_THRESHOLD { ts / 1000 } else { ts }),
+        Value::String(s) => OffsetDateTime::parse(s, &Rfc3339)
+            .ok()
+            .map(|ts| ts.unix_timestamp()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn line(l: &str) -> Line {
+        Line::builder().line(l).build().unwrap()
+    }
+
+    #[test]
+    fn lifts_known_keys() {
+        let mut line = line(
+            r#"{"severity":"ERROR","msg":"boom","time":"2021-01-01T00:00:00Z","user":{"id":1}}"#,
+        );
+        assert!(JsonDetector::new().detect(&mut line));
+        assert_eq!(line.level.as_deref(), Some("ERROR"));
+        assert_eq!(line.line, "boom");
+        assert_eq!(line.timestamp, 1609459200);
+        assert_eq!(line.meta.unwrap(), serde_json::json!({"user": {"id": 1}}));
+    }
+
+    #[test]
+    fn millisecond_timestamps() {
+        let mut line = line(r#"{"message":"ok","ts":1609459200123}"#);
+        assert!(JsonDetector::new().detect(&mut line));
+        assert_eq!(line.timestamp, 1609459200);
+    }
+
+    #[test]
+    fn keeps_raw_line_without_message() {
+        let raw = r#"{"level":"info","count":3}"#;
+        let mut line = line(raw);
+        assert!(JsonDetector::new().detect(&mut line));
+        assert_eq!(line.line, raw);
+        assert_eq!(line.level.as_deref(), Some("info"));
+        assert_eq!(line.meta.unwrap(), serde_json::json!({"count": 3}));
+    }
+
+    #[test]
+    fn merges_into_existing_meta() {
+        let mut line = Line::builder()
+            .line(r#"{"message":"ok","a":1,"b":2}"#)
+            .meta(serde_json::json!({"a": 0}))
+            .build()
+            .unwrap();
+        assert!(JsonDetector::new().detect(&mut line));
+        assert_eq!(line.meta.unwrap(), serde_json::json!({"a": 0, "b": 2}));
+    }
+
+    #[test]
+    fn ignores_non_objects() {
+        for raw in ["plain text", "[1,2,3]", "{not json"] {
+            let mut line = line(raw);
+            assert!(!JsonDetector::new().detect(&mut line));
+            assert_eq!(line.line, raw);
+            assert_eq!(line.meta, None);
+        }
+    }
+
+    #[test]
+    fn custom_keys() {
+        let mut line = line(r#"{"lvl":"debug","text":"hi"}"#);
+        let detector = JsonDetector::new()
+            .message_keys(vec!["text"])
+            .level_keys(vec!["nope"]);
+        assert!(detector.detect(&mut line));
+        assert_eq!(line.line, "hi");
+        assert_eq!(line.level, None);
+    }
+}
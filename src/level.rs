@@ -0,0 +1,13 @@
+/// Maps a syslog severity (0-7), as used by syslog and journald priorities, to a LogDNA level
+pub fn severity_level(severity: u8) -> &'static str {
+    match severity {
+        0 => "EMERGENCY",
+        1 => "ALERT",
+        2 => "CRITICAL",
+        3 => "ERROR",
+        4 => "WARNING",
+        5 => "NOTICE",
+        6 => "INFO",
+        _ => "DEBUG",
+    }
+}
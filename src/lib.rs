@@ -78,7 +78,7 @@
 //! If the reponse is not polled (spawned on a runtime) nothing will happen
 //! ```
 //! # use logdna_client::response::Response;
-//! assert_eq!(Response::Sent, rt.block_on(response).unwrap())
+//! assert!(matches!(rt.block_on(response).unwrap(), Response::Sent { .. }))
 //! ```
 //! [LogDNA]: https://logdna.com/
 //! [Ingest API]: https://docs.logdna.com/v1.0/reference#api
@@ -86,34 +86,390 @@
 //! [Tokio]: https://github.com/tokio-rs/tokio
 //! [Tokio Runtume]: https://docs.rs/tokio/latest/tokio/runtime/index.html
 
+/// Salted hashing of identifying fields
+#[cfg(feature = "anonymize")]
+pub mod anonymize;
+/// Backoff and jitter strategies
+pub mod backoff;
 /// Log line and body types
 pub mod body;
 /// Http client
+#[cfg(feature = "client")]
 pub mod client;
+/// Deserializable client configuration
+#[cfg(feature = "client")]
+pub mod config;
+/// Kubernetes CRI log parsing
+pub mod cri;
+/// Request dumps for debugging
+#[cfg(feature = "client")]
+pub mod debug_dump;
+/// Suppression of duplicate bodies
+#[cfg(feature = "client")]
+pub mod dedup;
+/// Environment metadata enrichment
+pub mod enrichment;
 /// Error types
 pub mod error;
+/// Level and timestamp extraction from plain text
+pub mod extract;
+/// systemd journal field mapping
+pub mod journald;
+/// Json log line detection
+pub mod json_detect;
+/// Log level helpers
+pub mod level;
+/// Ingest API field limits
+pub mod limits;
+/// Client request metrics
+pub mod metrics;
+/// Multiline event aggregation
+pub mod multiline;
+/// Non-blocking background sender
+#[cfg(feature = "client")]
+pub mod non_blocking;
+/// Request lifecycle observers
+pub mod observer;
+/// OpenTelemetry logs export
+#[cfg(feature = "otel")]
+pub mod otel;
+/// Multi-threaded gzip of large bodies
+#[cfg(feature = "parallel-gzip")]
+pub mod parallel_gzip;
 /// Query parameters
 pub mod params;
+/// Connection pool statistics
+#[cfg(feature = "client")]
+pub mod pool;
+/// Line processing middleware
+pub mod processor;
+/// Http proxy support
+#[cfg(feature = "client")]
+pub mod proxy;
+/// Client-side rate limiting
+pub mod rate_limit;
+/// Conversion from log records
+#[cfg(feature = "log-record")]
+pub mod record;
+/// Sensitive data redaction
+pub mod redaction;
+/// Redirect following
+#[cfg(feature = "client")]
+pub mod redirect;
 /// Request types
+#[cfg(feature = "client")]
 pub mod request;
+/// Request summary logging
+#[cfg(feature = "client")]
+pub mod request_log;
 /// Response types
+#[cfg(feature = "client")]
 pub mod response;
+/// Retry policies
+#[cfg(feature = "client")]
+pub mod retry;
+/// Async runtime abstractions
+pub mod runtime;
+/// Control character sanitizing
+pub mod sanitize;
 /// Log line and body serialization
 pub mod serialize;
+/// Syslog message parsing
+#[cfg(feature = "syslog")]
+pub mod syslog;
+/// TLS certificate configuration
+#[cfg(feature = "client")]
+pub mod tls;
+/// W3C trace context propagation
+pub mod trace_context;
+/// Conversion from tracing events
+#[cfg(feature = "tracing")]
+pub mod tracing;
+/// Line emitting io::Write and AsyncWrite adapters
+pub mod writer;
 
+#[cfg(feature = "client")]
 mod dns;
+#[cfg(feature = "http3")]
+mod http3;
+#[cfg(feature = "client")]
+mod intern;
 mod segmented_buffer;
 
-#[cfg(test)]
+#[cfg(all(test, feature = "client"))]
 mod tests {
     use std::env;
 
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
     use crate::body::{IngestBody, KeyValueMap, Line};
     use crate::client::Client;
+    use crate::enrichment::{Enricher, Target};
     use crate::params::{Params, Tags};
-    use crate::request::RequestTemplate;
+    use crate::request::{Encoding, RequestTemplate, Schema};
     use crate::response::Response;
 
+    // Answers one request with a 200, returning its body
+    async fn receive_body(listener: TcpListener) -> Vec<u8> {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = BufReader::new(stream);
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            stream.read_line(&mut header).await.unwrap();
+            let header = header.trim_end().to_ascii_lowercase();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(len) = header.strip_prefix("content-length:") {
+                content_length = len.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; content_length];
+        stream.read_exact(&mut body).await.unwrap();
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+            .await
+            .unwrap();
+        body
+    }
+
+    // Answers every request on the first connection with `status` and `body`, until it is closed
+    async fn answer_with(listener: TcpListener, status: &'static str, body: &'static str) {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = BufReader::new(stream);
+        loop {
+            let mut content_length = 0;
+            loop {
+                let mut header = String::new();
+                if stream.read_line(&mut header).await.unwrap() == 0 {
+                    return;
+                }
+                let header = header.trim_end().to_ascii_lowercase();
+                if header.is_empty() {
+                    break;
+                }
+                if let Some(len) = header.strip_prefix("content-length:") {
+                    content_length = len.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; content_length];
+            stream.read_exact(&mut body).await.unwrap();
+            let response = format!(
+                "HTTP/1.1 {}\r\ncontent-length: {}\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn client_gives_up_after_retries() {
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
+
+        use crate::body::IngestBodyBuffer;
+        use crate::observer::IngestObserver;
+        use crate::retry::RetryPolicy;
+
+        #[derive(Default)]
+        struct DeadLetters(Mutex<Vec<(usize, u32)>>);
+
+        impl IngestObserver for DeadLetters {
+            fn on_give_up(&self, body: &IngestBodyBuffer, attempts: u32) {
+                self.0.lock().unwrap().push((body.len(), attempts));
+            }
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(answer_with(listener, "503 Service Unavailable", ""));
+
+        let template = RequestTemplate::builder()
+            .schema(Schema::Http)
+            .host(addr.to_string())
+            .encoding(Encoding::Json)
+            .content_length(true)
+            .api_key("key")
+            .build()
+            .unwrap();
+        let mut client = Client::builder(template)
+            .require_tls(false)
+            .nameservers(vec![addr])
+            .build()
+            .unwrap();
+        let dead_letters = Arc::new(DeadLetters::default());
+        client.set_observer(dead_letters.clone());
+
+        let body = IngestBody::new(vec![Line::builder().line("lost").build().unwrap()]);
+        let policy = RetryPolicy::new()
+            .max_attempts(Some(2))
+            .initial_interval(Duration::from_millis(1));
+        let response = client.send_with_retry(&body, &policy).await.unwrap();
+        assert!(matches!(response, Response::Failed(..)));
+        let given_up = dead_letters.0.lock().unwrap().clone();
+        assert_eq!(
+            given_up,
+            vec![(serde_json::to_vec(&body).unwrap().len(), 2)]
+        );
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn client_reads_error_bodies_without_a_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(answer_with(listener, "400 Bad Request", "invalid key"));
+
+        let template = RequestTemplate::builder()
+            .schema(Schema::Http)
+            .host(addr.to_string())
+            .encoding(Encoding::Json)
+            .content_length(true)
+            .api_key("key")
+            .build()
+            .unwrap();
+        let client = Client::builder(template)
+            .require_tls(false)
+            .nameservers(vec![addr])
+            .max_error_body_size(usize::MAX)
+            .build()
+            .unwrap();
+
+        let body = IngestBody::new(vec![Line::builder().line("rejected").build().unwrap()]);
+        match client.send(&body).await.unwrap() {
+            Response::Failed(_, status, reason) => {
+                assert_eq!(status, http::StatusCode::BAD_REQUEST);
+                assert_eq!(reason, "invalid key");
+            }
+            other => panic!("expected a failed response, got {:?}", other),
+        }
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn client_refuses_to_send_after_shutdown() {
+        use std::time::Duration;
+
+        use crate::error::HttpError;
+
+        let template = RequestTemplate::builder()
+            .schema(Schema::Http)
+            .host("127.0.0.1:9")
+            .api_key("key")
+            .build()
+            .unwrap();
+        let client = Client::builder(template)
+            .require_tls(false)
+            .nameservers(vec!["127.0.0.1:53".parse().unwrap()])
+            .build()
+            .unwrap();
+        assert!(client.shutdown(Duration::from_millis(10)).await);
+
+        let body = IngestBody::new(vec![Line::builder().line("late").build().unwrap()]);
+        match client.send(&body).await {
+            Err(HttpError::Shutdown(unsent)) => {
+                assert_eq!(unsent.len(), serde_json::to_vec(&body).unwrap().len())
+            }
+            other => panic!("expected a shutdown error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn client_enriches_lines() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(receive_body(listener));
+
+        let template = RequestTemplate::builder()
+            .schema(Schema::Http)
+            .host(addr.to_string())
+            .encoding(Encoding::Json)
+            .content_length(true)
+            .api_key("key")
+            .build()
+            .unwrap();
+        let mut enricher = Enricher::new();
+        enricher
+            .field("pod", "pod-0", Target::Label)
+            .field("region", "us-east-1", Target::Meta);
+        let client = Client::builder(template)
+            .require_tls(false)
+            // the ip host is never resolved, this only skips reading the system configuration
+            .nameservers(vec![addr])
+            .enricher(enricher)
+            .build()
+            .unwrap();
+
+        let line = Line::builder().line("hello").build().unwrap();
+        let response = client.send(&IngestBody::new(vec![line])).await.unwrap();
+        assert!(matches!(response, Response::Sent { .. }));
+
+        let body = IngestBody::from_slice(&server.await.unwrap()).unwrap();
+        let line = &body.lines()[0];
+        assert_eq!(line.labels.as_ref().unwrap()["pod"], "pod-0");
+        assert_eq!(
+            line.meta.as_ref().unwrap(),
+            &serde_json::json!({"region": "us-east-1"})
+        );
+    }
+
+    #[tokio::test]
+    async fn client_redacts_enriched_lines() {
+        use crate::redaction::Redactor;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(receive_body(listener));
+
+        let template = RequestTemplate::builder()
+            .schema(Schema::Http)
+            .host(addr.to_string())
+            .encoding(Encoding::Json)
+            .content_length(true)
+            .api_key("key")
+            .build()
+            .unwrap();
+        let mut enricher = Enricher::new();
+        enricher.field("password", "hunter2", Target::Label);
+        let mut redactor = Redactor::new();
+        redactor.substring("hunter2", "*******");
+        let client = Client::builder(template)
+            .require_tls(false)
+            .nameservers(vec![addr])
+            .redactor(redactor)
+            .enricher(enricher)
+            .build()
+            .unwrap();
+
+        let line = Line::builder().line("login hunter2").build().unwrap();
+        let response = client.send(&IngestBody::new(vec![line])).await.unwrap();
+        assert!(matches!(response, Response::Sent { .. }));
+
+        let body = IngestBody::from_slice(&server.await.unwrap()).unwrap();
+        let line = &body.lines()[0];
+        assert_eq!(line.line, "login *******");
+        assert_eq!(line.labels.as_ref().unwrap()["password"], "*******");
+    }
+
+    #[test]
+    fn client_from_invalid_config() {
+        use crate::config::TemplateConfig;
+        use crate::error::{ClientError, TemplateError};
+
+        let config: TemplateConfig = serde_json::from_str(
+            r#"{ "api_key": "", "params": { "hostname": "rust-client-test" } }"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            Client::from_config(config),
+            Err(ClientError::Template(TemplateError::RequiredField(_)))
+        ));
+    }
+
     #[tokio::test]
     async fn it_works() {
         env_logger::init();
@@ -148,9 +504,12 @@ mod tests {
             "{}",
             serde_json::to_string(&IngestBody::new(vec![line.clone()])).unwrap()
         );
-        assert_eq!(
-            Response::Sent,
-            client.send(&IngestBody::new(vec![line])).await.unwrap()
-        )
+        match client.send(&IngestBody::new(vec![line])).await.unwrap() {
+            Response::Sent { status, bytes, .. } => {
+                assert!(status.is_success());
+                assert!(bytes > 0);
+            }
+            response => panic!("unexpected response {:?}", response),
+        }
     }
 }
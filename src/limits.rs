@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use crate::error::LimitError;
+
+/// What happens to a field over its limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitMode {
+    /// Building fails with a [`LimitError`]
+    Strict,
+    /// The field is truncated, or dropped when it can't be, and building carries on
+    Lenient,
+}
+
+/// Ingest API field limits, checked by [`LineBuilder::build`](crate::body::LineBuilder::build)
+/// and [`ParamsBuilder::build`](crate::params::ParamsBuilder::build) when set
+///
+/// The defaults follow the documented limits of the Ingest API, which otherwise truncates or
+/// rejects the offending lines after they were sent. Lengths are in bytes.
+///
+/// # Example
+///
+/// ```rust
+/// # use logdna_client::body::Line;
+/// # use logdna_client::limits::Limits;
+/// let line = Line::builder()
+///     .line("x".repeat(64 * 1024))
+///     .limits(Limits::lenient())
+///     .build()
+///     .unwrap();
+/// assert_eq!(line.line.len(), Limits::lenient().max_line_len);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Whether a field over its limit fails the build or is truncated
+    pub mode: LimitMode,
+    /// The message of a line
+    pub max_line_len: usize,
+    /// The app, env, file, host and level of a line
+    pub max_field_len: usize,
+    /// The hostname query parameter
+    pub max_hostname_len: usize,
+    /// Entries in the labels or annotations of a line
+    pub max_map_entries: usize,
+    /// A label or annotation key
+    pub max_key_len: usize,
+    /// A label or annotation value
+    pub max_value_len: usize,
+    /// The meta of a line, serialized as json
+    pub max_meta_size: usize,
+    /// Tags in the query parameters
+    pub max_tags: usize,
+    /// A single tag
+    pub max_tag_len: usize,
+}
+
+impl Limits {
+    /// The documented limits, failing builds that exceed them
+    pub fn strict() -> Self {
+        Self {
+            mode: LimitMode::Strict,
+            max_line_len: 16 * 1024,
+            max_field_len: 512,
+            max_hostname_len: 256,
+            max_map_entries: 100,
+            max_key_len: 256,
+            max_value_len: 1024,
+            max_meta_size: 32 * 1024,
+            max_tags: 80,
+            max_tag_len: 128,
+        }
+    }
+    /// The documented limits, truncating the fields that exceed them
+    pub fn lenient() -> Self {
+        Self {
+            mode: LimitMode::Lenient,
+            ..Self::strict()
+        }
+    }
+
+    /// Checks a string field against `max` bytes, truncating it on a char boundary if lenient
+    pub(crate) fn check_str(
+        &self,
+        field: &str,
+        value: &mut String,
+        max: usize,
+    ) -> Result<(), LimitError> {
+        if value.len() <= max {
+            return Ok(());
+        }
+        match self.mode {
+            LimitMode::Strict => Err(LimitError::TooLong {
+                field: field.into(),
+                len: value.len(),
+                max,
+            }),
+            LimitMode::Lenient => {
+                log::debug!("truncating {} of {} bytes to {}", field, value.len(), max);
+                let mut end = max;
+                while !value.is_char_boundary(end) {
+                    end -= 1;
+                }
+                value.truncate(end);
+                Ok(())
+            }
+        }
+    }
+
+    /// Checks the entries of a labels or annotations map, keeping the lowest keys if lenient
+    ///
+    /// Entries whose truncated keys collide are dropped except for the lowest original key.
+    pub(crate) fn check_map(
+        &self,
+        field: &str,
+        map: &mut HashMap<String, String>,
+    ) -> Result<(), LimitError> {
+        if map.len() > self.max_map_entries {
+            if self.mode == LimitMode::Strict {
+                return Err(LimitError::TooManyEntries {
+                    field: field.into(),
+                    len: map.len(),
+                    max: self.max_map_entries,
+                });
+            }
+            let mut keys: Vec<String> = map.keys().cloned().collect();
+            keys.sort_unstable();
+            for key in &keys[self.max_map_entries..] {
+                map.remove(key);
+            }
+        }
+        // in key order, so the entry kept when truncated keys collide doesn't depend on the map
+        let mut entries: Vec<(String, String)> = std::mem::take(map).into_iter().collect();
+        entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        for (mut key, mut value) in entries {
+            self.check_str(&format!("{} key", field), &mut key, self.max_key_len)?;
+            // keys are only truncated when lenient, the lowest of the colliding keys is kept
+            if map.contains_key(&key) {
+                log::debug!(
+                    "dropping {} entry, its truncated key {} is taken",
+                    field,
+                    key
+                );
+                continue;
+            }
+            self.check_str(
+                &format!("{} {}", field, key),
+                &mut value,
+                self.max_value_len,
+            )?;
+            map.insert(key, value);
+        }
+        Ok(())
+    }
+
+    /// Checks the serialized size of meta, dropping it if lenient as it can't be truncated
+    pub(crate) fn check_meta(&self, meta: &mut Option<Value>) -> Result<(), LimitError> {
+        let len = match meta.as_ref() {
+            Some(value) => serde_json::to_vec(value).map_or(0, |json| json.len()),
+            None => return Ok(()),
+        };
+        if len <= self.max_meta_size {
+            return Ok(());
+        }
+        match self.mode {
+            LimitMode::Strict => Err(LimitError::TooLong {
+                field: "meta".into(),
+                len,
+                max: self.max_meta_size,
+            }),
+            LimitMode::Lenient => {
+                log::debug!("dropping meta of {} bytes", len);
+                *meta = None;
+                Ok(())
+            }
+        }
+    }
+
+    /// Checks the number and length of tags, dropping the last ones if lenient
+    pub(crate) fn check_tags(&self, tags: &mut Vec<String>) -> Result<(), LimitError> {
+        if tags.len() > self.max_tags {
+            if self.mode == LimitMode::Strict {
+                return Err(LimitError::TooManyEntries {
+                    field: "tags".into(),
+                    len: tags.len(),
+                    max: self.max_tags,
+                });
+            }
+            tags.truncate(self.max_tags);
+        }
+        for tag in tags.iter_mut() {
+            self.check_str("tag", tag, self.max_tag_len)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::strict()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn strict_fails_and_lenient_truncates() {
+        let mut value = "é".repeat(10);
+        assert_eq!(
+            Limits::strict().check_str("app", &mut value.clone(), 5),
+            Err(LimitError::TooLong {
+                field: "app".into(),
+                len: 20,
+                max: 5,
+            })
+        );
+        Limits::lenient().check_str("app", &mut value, 5).unwrap();
+        assert_eq!(value, "éé");
+
+        let limits = Limits {
+            max_map_entries: 2,
+            max_value_len: 3,
+            ..Limits::lenient()
+        };
+        let mut map: HashMap<String, String> = vec![("c", "3"), ("a", "1111"), ("b", "2")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        limits.check_map("labels", &mut map).unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map["a"], "111");
+        assert_eq!(map["b"], "2");
+
+        let mut meta = Some(serde_json::json!({ "k": "v".repeat(100) }));
+        let limits = Limits {
+            max_meta_size: 16,
+            ..Limits::strict()
+        };
+        assert!(limits.check_meta(&mut meta).is_err());
+        Limits {
+            mode: LimitMode::Lenient,
+            ..limits
+        }
+        .check_meta(&mut meta)
+        .unwrap();
+        assert!(meta.is_none());
+    }
+
+    #[test]
+    fn truncated_keys_keep_the_lowest_entry() {
+        let limits = Limits {
+            max_key_len: 3,
+            ..Limits::lenient()
+        };
+        for _ in 0..8 {
+            let mut map: HashMap<String, String> =
+                vec![("abcz", "z"), ("abc", "exact"), ("abcd", "d"), ("xyz", "x")]
+                    .into_iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect();
+            limits.check_map("labels", &mut map).unwrap();
+            assert_eq!(map.len(), 2);
+            assert_eq!(map["abc"], "exact");
+            assert_eq!(map["xyz"], "x");
+        }
+    }
+
+    #[test]
+    fn builders_check_limits() {
+        use crate::body::Line;
+        use crate::error::{LineError, ParamsError};
+        use crate::params::{Params, Tags};
+
+        let long = "a".repeat(1024);
+        let err = Line::builder()
+            .line("hello")
+            .app(long.clone())
+            .limits(Limits::strict())
+            .build()
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            LineError::Limit(LimitError::TooLong { max: 512, .. })
+        ));
+        let line = Line::builder()
+            .line("hello")
+            .app(long.clone())
+            .limits(Limits::lenient())
+            .build()
+            .unwrap();
+        assert_eq!(line.app.unwrap().len(), 512);
+        assert!(Line::builder().line(long.clone()).build().is_ok());
+
+        let tags = Tags::parse(vec!["t"; 100].join(","));
+        let err = Params::builder()
+            .hostname("host")
+            .tags(tags.clone())
+            .limits(Limits::strict())
+            .build()
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            ParamsError::Limit(LimitError::TooManyEntries { len: 100, .. })
+        ));
+        let params = Params::builder()
+            .hostname(long)
+            .tags(tags)
+            .limits(Limits::lenient())
+            .build()
+            .unwrap();
+        assert_eq!(params.hostname.len(), 256);
+    }
+}
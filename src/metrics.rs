@@ -0,0 +1,165 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds of the latency buckets in milliseconds, a final bucket holds everything slower
+const BUCKET_BOUNDS_MS: [u64; 11] = [5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+/// A lock-free histogram of request latencies using fixed buckets
+#[derive(Debug, Default)]
+pub struct LatencyHistogram {
+    buckets: [AtomicU64; BUCKET_BOUNDS_MS.len() + 1],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl LatencyHistogram {
+    /// Records a single latency
+    pub fn record(&self, latency: Duration) {
+        let ms = latency.as_millis() as u64;
+        let bucket = BUCKET_BOUNDS_MS
+            .iter()
+            .position(|bound| ms <= *bound)
+            .unwrap_or(BUCKET_BOUNDS_MS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+    /// Takes a point in time copy of the histogram
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        HistogramSnapshot {
+            buckets: self
+                .buckets
+                .iter()
+                .enumerate()
+                .map(|(i, count)| {
+                    let bound = BUCKET_BOUNDS_MS.get(i).map(|ms| Duration::from_millis(*ms));
+                    (bound, count.load(Ordering::Relaxed))
+                })
+                .collect(),
+            count: self.count.load(Ordering::Relaxed),
+            sum: Duration::from_micros(self.sum_micros.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// A copy of a [`LatencyHistogram`] at one point in time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistogramSnapshot {
+    /// The count of every bucket along with its inclusive upper bound, None for the last bucket
+    pub buckets: Vec<(Option<Duration>, u64)>,
+    /// The number of latencies recorded
+    pub count: u64,
+    /// The sum of every latency recorded
+    pub sum: Duration,
+}
+
+impl HistogramSnapshot {
+    /// The mean latency, None if nothing was recorded
+    pub fn mean(&self) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        // in u128 nanoseconds, dividing the Duration would truncate counts beyond u32::MAX
+        let nanos = self.sum.as_nanos() / u128::from(self.count);
+        Some(Duration::new(
+            (nanos / 1_000_000_000) as u64,
+            (nanos % 1_000_000_000) as u32,
+        ))
+    }
+    /// The upper bound of the bucket containing the quantile, e.g `0.99`
+    ///
+    /// Returns None if nothing was recorded or the quantile falls into the unbounded bucket.
+    pub fn quantile(&self, quantile: f64) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((quantile.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bound, count) in &self.buckets {
+            seen += count;
+            if seen >= rank {
+                return *bound;
+            }
+        }
+        None
+    }
+}
+
+/// Request latency distributions and counters collected by a client
+#[derive(Debug, Default)]
+pub struct ClientMetrics {
+    success: LatencyHistogram,
+    failure: LatencyHistogram,
+    duplicates_suppressed: AtomicU64,
+}
+
+impl ClientMetrics {
+    /// Latencies of requests accepted by the ingest API
+    pub fn success(&self) -> HistogramSnapshot {
+        self.success.snapshot()
+    }
+    /// Latencies of requests that failed, timed out or were rejected
+    pub fn failure(&self) -> HistogramSnapshot {
+        self.failure.snapshot()
+    }
+    /// Bodies not sent because an identical body was sent recently
+    pub fn duplicates_suppressed(&self) -> u64 {
+        self.duplicates_suppressed.load(Ordering::Relaxed)
+    }
+
+    #[cfg_attr(not(feature = "client"), allow(dead_code))]
+    pub(crate) fn record_success(&self, latency: Duration) {
+        self.success.record(latency)
+    }
+
+    #[cfg_attr(not(feature = "client"), allow(dead_code))]
+    pub(crate) fn record_failure(&self, latency: Duration) {
+        self.failure.record(latency)
+    }
+
+    #[cfg_attr(not(feature = "client"), allow(dead_code))]
+    pub(crate) fn record_duplicate(&self) {
+        self.duplicates_suppressed.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn records_into_buckets() {
+        let histogram = LatencyHistogram::default();
+        for ms in [1, 7, 7, 40, 20_000] {
+            histogram.record(Duration::from_millis(ms));
+        }
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.count, 5);
+        assert_eq!(snapshot.buckets[0], (Some(Duration::from_millis(5)), 1));
+        assert_eq!(snapshot.buckets[1], (Some(Duration::from_millis(10)), 2));
+        assert_eq!(snapshot.buckets[3], (Some(Duration::from_millis(50)), 1));
+        assert_eq!(snapshot.buckets[11], (None, 1));
+        assert_eq!(snapshot.mean(), Some(Duration::from_millis(4011)));
+        assert_eq!(snapshot.quantile(0.5), Some(Duration::from_millis(10)));
+        assert_eq!(snapshot.quantile(0.8), Some(Duration::from_millis(50)));
+
+        let many = HistogramSnapshot {
+            buckets: Vec::new(),
+            count: u64::from(u32::MAX) + 1,
+            sum: Duration::from_millis(u64::from(u32::MAX) + 1),
+        };
+        assert_eq!(many.mean(), Some(Duration::from_millis(1)));
+        let empty = LatencyHistogram::default().snapshot();
+        assert_eq!(empty.mean(), None);
+        assert_eq!(snapshot.quantile(1.0), None);
+    }
+
+    #[test]
+    fn empty_snapshot() {
+        let snapshot = ClientMetrics::default().success();
+        assert_eq!(snapshot.count, 0);
+        assert_eq!(snapshot.mean(), None);
+        assert_eq!(snapshot.quantile(0.5), None);
+    }
+}
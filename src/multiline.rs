@@ -0,0 +1,308 @@
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "tokio")]
+use futures::stream::{Stream, StreamExt};
+use regex::Regex;
+
+use crate::body::Line;
+use crate::error::MultilineError;
+
+const DEFAULT_MAX_LINES: usize = 500;
+
+const DEFAULT_FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Merges continuation lines, e.g stack traces, into the line that started them
+///
+/// A line matching the start pattern begins a new event, every other line is appended
+/// (separated by `\n`) to the pending event. The pending event keeps the metadata of
+/// its first line and is emitted when the next event starts, when it reaches the
+/// maximum number of lines or when no line was appended to it for the flush timeout.
+///
+/// # Example
+///
+/// ```rust
+/// # use logdna_client::body::Line;
+/// # use logdna_client::multiline::MultilineAggregator;
+/// let mut aggregator = MultilineAggregator::builder()
+///     .start_pattern(r"^\S")
+///     .build()
+///     .expect("MultilineAggregator::builder()");
+///
+/// let line = |l: &str| Line::builder().line(l).build().unwrap();
+/// assert!(aggregator.push(line("Exception in thread \"main\"")).is_none());
+/// assert!(aggregator.push(line("    at com.example.Main.main(Main.java:5)")).is_none());
+/// let event = aggregator.push(line("next event")).unwrap();
+/// assert_eq!(event.line, "Exception in thread \"main\"\n    at com.example.Main.main(Main.java:5)");
+/// ```
+#[derive(Debug)]
+pub struct MultilineAggregator {
+    start_pattern: Regex,
+    max_lines: usize,
+    flush_timeout: Duration,
+    pending: Option<Pending>,
+}
+
+#[derive(Debug)]
+struct Pending {
+    line: Line,
+    count: usize,
+    // when the last line was appended, the flush timeout counts from here
+    updated: Instant,
+}
+
+impl MultilineAggregator {
+    /// Constructs a new MultilineBuilder
+    pub fn builder() -> MultilineBuilder {
+        MultilineBuilder::new()
+    }
+    /// Adds a line, returning a completed event if this line closed one
+    pub fn push(&mut self, line: Line) -> Option<Line> {
+        let is_start = self.start_pattern.is_match(&line.line);
+        if let Some(pending) = self.pending.as_mut() {
+            if !is_start && pending.count < self.max_lines {
+                pending.line.line.push('\n');
+                pending.line.line.push_str(&line.line);
+                pending.count += 1;
+                pending.updated = Instant::now();
+                return None;
+            }
+        }
+        self.pending.replace(Pending::new(line)).map(|p| p.line)
+    }
+    /// Emits the pending event, if any
+    pub fn flush(&mut self) -> Option<Line> {
+        self.pending.take().map(|p| p.line)
+    }
+    /// Emits the pending event if no line was appended to it for the flush timeout
+    pub fn flush_expired(&mut self, now: Instant) -> Option<Line> {
+        match self.deadline() {
+            Some(deadline) if deadline <= now => self.flush(),
+            _ => None,
+        }
+    }
+    /// The instant at which the pending event, if any, should be flushed
+    pub fn deadline(&self) -> Option<Instant> {
+        self.pending
+            .as_ref()
+            .map(|pending| pending.updated + self.flush_timeout)
+    }
+    /// Returns true if there is no pending event
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_none()
+    }
+}
+
+impl Pending {
+    fn new(line: Line) -> Self {
+        Self {
+            line,
+            count: 1,
+            updated: Instant::now(),
+        }
+    }
+}
+
+/// Used to build a MultilineAggregator
+#[derive(Debug, Clone)]
+pub struct MultilineBuilder {
+    start_pattern: Option<String>,
+    max_lines: usize,
+    flush_timeout: Duration,
+}
+
+impl MultilineBuilder {
+    /// Constructs a new MultilineBuilder
+    pub fn new() -> Self {
+        Self {
+            start_pattern: None,
+            max_lines: DEFAULT_MAX_LINES,
+            flush_timeout: DEFAULT_FLUSH_TIMEOUT,
+        }
+    }
+    /// Set the regex a line must match to start a new event, required
+    pub fn start_pattern<T: Into<String>>(mut self, start_pattern: T) -> Self {
+        self.start_pattern = Some(start_pattern.into());
+        self
+    }
+    /// Set the maximum number of lines merged into one event, default is 500
+    pub fn max_lines(mut self, max_lines: usize) -> Self {
+        self.max_lines = std::cmp::max(max_lines, 1);
+        self
+    }
+    /// Set how long an event may stay pending without new lines, default is 1 second
+    pub fn flush_timeout(mut self, flush_timeout: Duration) -> Self {
+        self.flush_timeout = flush_timeout;
+        self
+    }
+    /// Build a MultilineAggregator, returning an error if the start pattern is missing or invalid
+    pub fn build(self) -> Result<MultilineAggregator, MultilineError> {
+        let start_pattern = self.start_pattern.ok_or_else(|| {
+            MultilineError::RequiredField("start_pattern is required in a MultilineBuilder".into())
+        })?;
+        Ok(MultilineAggregator {
+            start_pattern: Regex::new(&start_pattern)?,
+            max_lines: self.max_lines,
+            flush_timeout: self.flush_timeout,
+            pending: None,
+        })
+    }
+}
+
+impl Default for MultilineBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Aggregates a stream of lines, flushing pending events when the flush timeout elapses
+///
+/// This must be polled from within a Tokio Runtime
+#[cfg(feature = "tokio")]
+pub fn aggregate<S>(lines: S, aggregator: MultilineAggregator) -> impl Stream<Item = Line>
+where
+    S: Stream<Item = Line> + Unpin,
+{
+    futures::stream::unfold(
+        (lines, aggregator, false),
+        |(mut lines, mut aggregator, done)| async move {
+            if done {
+                return None;
+            }
+            loop {
+                let next = match aggregator.deadline() {
+                    Some(deadline) => {
+                        let deadline = tokio::time::Instant::from_std(deadline);
+                        match tokio::time::timeout_at(deadline, lines.next()).await {
+                            Ok(next) => next,
+                            Err(_) => match aggregator.flush() {
+                                Some(line) => return Some((line, (lines, aggregator, false))),
+                                None => continue,
+                            },
+                        }
+                    }
+                    None => lines.next().await,
+                };
+                match next {
+                    Some(line) => {
+                        if let Some(line) = aggregator.push(line) {
+                            return Some((line, (lines, aggregator, false)));
+                        }
+                    }
+                    None => {
+                        return aggregator
+                            .flush()
+                            .map(|line| (line, (lines, aggregator, true)))
+                    }
+                }
+            }
+        },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn line(l: &str) -> Line {
+        Line::builder().line(l).app("java").build().unwrap()
+    }
+
+    fn aggregator(max_lines: usize) -> MultilineAggregator {
+        MultilineAggregator::builder()
+            .start_pattern(r"^\d{4}-\d{2}-\d{2}")
+            .max_lines(max_lines)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn joins_continuation_lines() {
+        let mut aggregator = aggregator(10);
+        assert!(aggregator.push(line("2021-01-01 ERROR boom")).is_none());
+        assert!(aggregator.push(line("\tat a.b.C(C.java:1)")).is_none());
+        assert!(aggregator.push(line("\tat a.b.D(D.java:2)")).is_none());
+
+        let event = aggregator.push(line("2021-01-01 INFO ok")).unwrap();
+        assert_eq!(
+            event.line,
+            "2021-01-01 ERROR boom\n\tat a.b.C(C.java:1)\n\tat a.b.D(D.java:2)"
+        );
+        assert_eq!(event.app.as_deref(), Some("java"));
+        assert_eq!(aggregator.flush().unwrap().line, "2021-01-01 INFO ok");
+        assert!(aggregator.is_empty());
+    }
+
+    #[test]
+    fn respects_max_lines() {
+        let mut aggregator = aggregator(2);
+        assert!(aggregator.push(line("2021-01-01 ERROR boom")).is_none());
+        assert!(aggregator.push(line("one")).is_none());
+        assert_eq!(
+            aggregator.push(line("two")).unwrap().line,
+            "2021-01-01 ERROR boom\none"
+        );
+        assert_eq!(aggregator.flush().unwrap().line, "two");
+    }
+
+    #[test]
+    fn flushes_expired() {
+        let mut aggregator = MultilineAggregator::builder()
+            .start_pattern("^start")
+            .flush_timeout(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        assert!(aggregator.push(line("start")).is_none());
+        let now = Instant::now();
+        assert!(aggregator
+            .flush_expired(now - Duration::from_secs(1))
+            .is_none());
+        assert_eq!(
+            aggregator
+                .flush_expired(now + Duration::from_millis(20))
+                .unwrap()
+                .line,
+            "start"
+        );
+    }
+
+    #[test]
+    fn appended_lines_extend_the_deadline() {
+        let mut aggregator = MultilineAggregator::builder()
+            .start_pattern("^start")
+            .flush_timeout(Duration::from_millis(50))
+            .build()
+            .unwrap();
+        assert!(aggregator.push(line("start")).is_none());
+        let first = aggregator.deadline().unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(aggregator.push(line("  continued")).is_none());
+        assert!(aggregator.deadline().unwrap() > first);
+        assert!(aggregator.flush_expired(first).is_none());
+    }
+
+    #[test]
+    fn builder_requires_start_pattern() {
+        assert!(MultilineAggregator::builder().build().is_err());
+        assert!(MultilineAggregator::builder()
+            .start_pattern("(")
+            .build()
+            .is_err());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn aggregate_stream() {
+        let lines = futures::stream::iter(vec![
+            line("2021-01-01 ERROR boom"),
+            line("\tat a.b.C(C.java:1)"),
+            line("2021-01-01 INFO ok"),
+        ]);
+        let events: Vec<Line> = aggregate(lines, aggregator(10)).collect().await;
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            events[0].line,
+            "2021-01-01 ERROR boom\n\tat a.b.C(C.java:1)"
+        );
+        assert_eq!(events[1].line, "2021-01-01 INFO ok");
+    }
+}
@@ -0,0 +1,863 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use derivative::Derivative;
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures::StreamExt;
+use tokio::time::{timeout_at, Instant};
+
+use crate::body::{IngestBody, Line};
+use crate::client::Client;
+use crate::intern::intern;
+use crate::processor::{LineProcessor, ProcessorChain};
+use crate::redaction::Redactor;
+use crate::response::Response;
+use crate::retry::RetryPolicy;
+use crate::writer::LineSink;
+
+const DEFAULT_BUFFERED_LINES_LIMIT: usize = 128_000;
+const DEFAULT_MAX_BATCH_LINES: usize = 500;
+const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_millis(250);
+const DEFAULT_HIGH_PRIORITY_WEIGHT: usize = 4;
+
+/// Lines the client gave up on, handed to the dead-letter callback
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeadLetter {
+    /// A line dropped because the buffered lines limit was reached or the worker stopped
+    Dropped(Line),
+    /// A batch of lines the ingest API did not accept, along with the reason
+    Failed { lines: Vec<Line>, reason: String },
+}
+
+type DeadLetterFn = Arc<dyn Fn(DeadLetter) + Send + Sync>;
+type PriorityFn = Arc<dyn Fn(&Line) -> Priority + Send + Sync>;
+
+/// The lane a line is queued in, see [`NonBlockingBuilder::priority`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Priority {
+    /// Batched ahead of normal lines and only dropped when no normal line is left to drop
+    High,
+    Normal,
+}
+
+impl Priority {
+    /// Classifies ERROR and more severe levels as high priority
+    pub fn from_level(line: &Line) -> Priority {
+        match line.level.as_deref() {
+            Some(level)
+                if ["ERROR", "CRITICAL", "ALERT", "EMERGENCY", "FATAL"]
+                    .iter()
+                    .any(|high| level.eq_ignore_ascii_case(high)) =>
+            {
+                Priority::High
+            }
+            _ => Priority::Normal,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Message {
+    Line(Line, Priority),
+    Shutdown,
+}
+
+/// Counters shared by the senders and the worker
+#[derive(Debug, Default)]
+struct Counters {
+    // queued lines, either in the channel or waiting in the worker
+    pending: AtomicUsize,
+    // queued normal lines not already claimed by an eviction
+    normal_pending: AtomicUsize,
+    // normal lines the worker has to drop to make room for high priority ones
+    evictions: AtomicUsize,
+    dropped: AtomicUsize,
+    empty_dropped: AtomicUsize,
+}
+
+impl Counters {
+    // Claims a queued normal line for eviction, returning false if there is none
+    fn evict_normal(&self) -> bool {
+        let claimed = self
+            .normal_pending
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1))
+            .is_ok();
+        if claimed {
+            self.evictions.fetch_add(1, Ordering::AcqRel);
+        }
+        claimed
+    }
+
+    // A queued line left the queue, whether sent or dropped
+    fn release(&self, priority: Priority) {
+        self.pending.fetch_sub(1, Ordering::AcqRel);
+        if priority == Priority::Normal
+            && self
+                .evictions
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1))
+                .is_err()
+        {
+            self.normal_pending.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+}
+
+// A line waiting in the worker, its short fields are shared with the other queued lines
+// holding the same value rather than copied into each of them
+struct Queued {
+    line: Line,
+    app: Option<Arc<str>>,
+    env: Option<Arc<str>>,
+    file: Option<Arc<str>>,
+    host: Option<Arc<str>>,
+    level: Option<Arc<str>>,
+}
+
+impl Queued {
+    fn new(mut line: Line) -> Self {
+        let take = |field: &mut Option<String>| field.take().as_deref().map(intern);
+        Self {
+            app: take(&mut line.app),
+            env: take(&mut line.env),
+            file: take(&mut line.file),
+            host: take(&mut line.host),
+            level: take(&mut line.level),
+            line,
+        }
+    }
+
+    fn into_line(self) -> Line {
+        let restore = |field: Option<Arc<str>>| field.as_deref().map(String::from);
+        Line {
+            app: restore(self.app),
+            env: restore(self.env),
+            file: restore(self.file),
+            host: restore(self.host),
+            level: restore(self.level),
+            ..self.line
+        }
+    }
+}
+
+// Lines received by the worker and not yet batched, one queue per priority
+struct Lanes {
+    high: VecDeque<Queued>,
+    normal: VecDeque<Queued>,
+    high_weight: usize,
+}
+
+impl Lanes {
+    fn len(&self) -> usize {
+        self.high.len() + self.normal.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.high.is_empty() && self.normal.is_empty()
+    }
+
+    fn push(&mut self, line: Line, priority: Priority) {
+        let line = Queued::new(line);
+        match priority {
+            Priority::High => self.high.push_back(line),
+            Priority::Normal => self.normal.push_back(line),
+        }
+    }
+
+    // Drops the oldest normal lines claimed by evictions
+    fn evict(&mut self, counters: &Counters) -> Vec<Line> {
+        let mut evicted = Vec::new();
+        while !self.normal.is_empty() && counters.evictions.load(Ordering::Acquire) > 0 {
+            evicted.extend(self.normal.pop_front().map(Queued::into_line));
+            counters.release(Priority::Normal);
+            counters.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        evicted
+    }
+
+    // Takes up to `high_weight` high priority lines for every normal one
+    fn batch(&mut self, max: usize, counters: &Counters) -> Vec<Line> {
+        let mut batch = Vec::with_capacity(max.min(self.len()));
+        while batch.len() < max && !self.is_empty() {
+            for _ in 0..self.high_weight {
+                match self.high.pop_front() {
+                    Some(line) if batch.len() < max => {
+                        counters.release(Priority::High);
+                        batch.push(line.into_line());
+                    }
+                    Some(line) => {
+                        self.high.push_front(line);
+                        break;
+                    }
+                    None => break,
+                }
+            }
+            if batch.len() < max {
+                if let Some(line) = self.normal.pop_front() {
+                    counters.release(Priority::Normal);
+                    batch.push(line.into_line());
+                }
+            }
+        }
+        batch
+    }
+}
+
+/// Hands Lines to a dedicated worker thread without ever blocking the caller
+///
+/// Lines are queued on a lock-free channel, once the buffered lines limit is reached new lines
+/// are dropped and counted instead. A high priority line over the limit takes the place of the
+/// oldest queued normal line, and is only dropped if there is none. Cloning is cheap, every
+/// clone feeds the same worker.
+#[derive(Clone)]
+pub struct NonBlockingSender {
+    tx: UnboundedSender<Message>,
+    counters: Arc<Counters>,
+    limit: usize,
+    priority: Option<PriorityFn>,
+    dead_letter: Option<DeadLetterFn>,
+}
+
+impl NonBlockingSender {
+    /// Queues a line, returning false if it was dropped
+    pub fn send(&self, line: Line) -> bool {
+        let priority = self
+            .priority
+            .as_ref()
+            .map_or(Priority::Normal, |priority| priority(&line));
+        let counters = &self.counters;
+        let admitted = counters.pending.fetch_add(1, Ordering::AcqRel) < self.limit
+            || (priority == Priority::High && counters.evict_normal());
+        let line = if !admitted {
+            line
+        } else {
+            if priority == Priority::Normal {
+                counters.normal_pending.fetch_add(1, Ordering::AcqRel);
+            }
+            match self.tx.unbounded_send(Message::Line(line, priority)) {
+                Ok(()) => return true,
+                Err(e) => match e.into_inner() {
+                    Message::Line(line, priority) => {
+                        if priority == Priority::Normal {
+                            counters.normal_pending.fetch_sub(1, Ordering::AcqRel);
+                        }
+                        line
+                    }
+                    Message::Shutdown => unreachable!(),
+                },
+            }
+        };
+        counters.pending.fetch_sub(1, Ordering::AcqRel);
+        counters.dropped.fetch_add(1, Ordering::Relaxed);
+        if let Some(dead_letter) = self.dead_letter.as_ref() {
+            dead_letter(DeadLetter::Dropped(line));
+        }
+        false
+    }
+    /// The number of lines dropped since the worker was started
+    pub fn dropped_lines(&self) -> usize {
+        self.counters.dropped.load(Ordering::Relaxed)
+    }
+    /// The number of empty or whitespace-only lines dropped by the worker, see
+    /// [`NonBlockingBuilder::drop_empty_lines`]
+    pub fn empty_lines_dropped(&self) -> usize {
+        self.counters.empty_dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl LineSink for NonBlockingSender {
+    fn send_line(&mut self, line: Line) -> io::Result<()> {
+        // A full queue is lossy by design, only a stopped worker is an error
+        if !self.send(line) && self.tx.is_closed() {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "non-blocking worker stopped",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for NonBlockingSender {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NonBlockingSender")
+            .field("counters", &self.counters)
+            .field("limit", &self.limit)
+            .finish()
+    }
+}
+
+/// Flushes the queued lines and stops the worker thread when dropped
+///
+/// Keep it alive for as long as lines are sent, e.g by binding it in `main`.
+#[must_use = "dropping the guard stops the worker"]
+#[derive(Debug)]
+pub struct WorkerGuard {
+    tx: UnboundedSender<Message>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for WorkerGuard {
+    fn drop(&mut self) {
+        let _ = self.tx.unbounded_send(Message::Shutdown);
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                log::error!("non-blocking worker panicked");
+            }
+        }
+    }
+}
+
+/// Starts a worker thread sending lines with the client using the default settings
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use logdna_client::body::Line;
+/// # use logdna_client::client::Client;
+/// # use logdna_client::non_blocking;
+/// # use logdna_client::request::RequestTemplate;
+/// # let template = RequestTemplate::builder().api_key("key").build().unwrap();
+/// let (sender, _guard) = non_blocking::non_blocking(Client::new(template, None));
+/// sender.send(Line::builder().line("hello").build().unwrap());
+/// ```
+pub fn non_blocking(client: Client) -> (NonBlockingSender, WorkerGuard) {
+    NonBlockingBuilder::new().build(client)
+}
+
+/// Builder for a [`NonBlockingSender`] and its worker
+#[derive(Derivative, Clone)]
+#[derivative(Debug)]
+pub struct NonBlockingBuilder {
+    buffered_lines_limit: usize,
+    max_batch_lines: usize,
+    flush_interval: Duration,
+    drop_empty_lines: bool,
+    high_priority_weight: usize,
+    retry_policy: Option<RetryPolicy>,
+    #[derivative(Debug = "ignore")]
+    priority: Option<PriorityFn>,
+    #[derivative(Debug = "ignore")]
+    dead_letter: Option<DeadLetterFn>,
+    #[derivative(Debug = "ignore")]
+    processors: Option<Arc<ProcessorChain>>,
+    redactor: Option<Redactor>,
+}
+
+impl NonBlockingBuilder {
+    /// Constructs a NonBlockingBuilder with the default settings
+    pub fn new() -> Self {
+        Self {
+            buffered_lines_limit: DEFAULT_BUFFERED_LINES_LIMIT,
+            max_batch_lines: DEFAULT_MAX_BATCH_LINES,
+            flush_interval: DEFAULT_FLUSH_INTERVAL,
+            drop_empty_lines: false,
+            high_priority_weight: DEFAULT_HIGH_PRIORITY_WEIGHT,
+            retry_policy: None,
+            priority: None,
+            dead_letter: None,
+            processors: None,
+            redactor: None,
+        }
+    }
+    /// Set the number of queued lines after which new lines are dropped, default is 128000
+    pub fn buffered_lines_limit(mut self, limit: usize) -> Self {
+        self.buffered_lines_limit = limit;
+        self
+    }
+    /// Set the maximum number of lines sent in one request, default is 500
+    pub fn max_batch_lines(mut self, max_batch_lines: usize) -> Self {
+        self.max_batch_lines = max_batch_lines.max(1);
+        self
+    }
+    /// Set how long a partial batch waits for more lines before it is sent, default is 250ms
+    pub fn flush_interval(mut self, flush_interval: Duration) -> Self {
+        self.flush_interval = flush_interval;
+        self
+    }
+    /// Drop lines that are empty or only whitespace instead of batching them, default is false
+    ///
+    /// The ingest API has no use for them, dropping them saves their serialization and
+    /// bandwidth. They are counted by [`NonBlockingSender::empty_lines_dropped`].
+    pub fn drop_empty_lines(mut self, drop_empty_lines: bool) -> Self {
+        self.drop_empty_lines = drop_empty_lines;
+        self
+    }
+    /// Run every line through the processors on the worker thread before it is batched
+    ///
+    /// Lines dropped by a processor are not counted as dropped. Default is none.
+    pub fn processors(mut self, processors: ProcessorChain) -> Self {
+        self.processors = Some(Arc::new(processors));
+        self
+    }
+    /// Redact every line on the worker thread after the processors ran, default is none
+    pub fn redactor(mut self, redactor: Redactor) -> Self {
+        self.redactor = Some(redactor).filter(|redactor| !redactor.is_empty());
+        self
+    }
+    /// Set a callback choosing the priority of every line, by default all lines are normal
+    ///
+    /// High priority lines are queued separately, so they skip ahead of a backlog of normal
+    /// lines, e.g with [`Priority::from_level`] errors are sent before queued debug lines.
+    pub fn priority<F>(mut self, priority: F) -> Self
+    where
+        F: Fn(&Line) -> Priority + Send + Sync + 'static,
+    {
+        self.priority = Some(Arc::new(priority));
+        self
+    }
+    /// Set how many high priority lines are batched for every normal line while both are
+    /// queued, default is 4
+    pub fn high_priority_weight(mut self, weight: usize) -> Self {
+        self.high_priority_weight = weight.max(1);
+        self
+    }
+    /// Retry failed batches with the policy before handing them to the dead-letter callback
+    ///
+    /// By default a failed batch is not retried.
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(retry_policy);
+        self
+    }
+    /// Set a callback invoked with every line that is dropped or failed to send
+    ///
+    /// The callback runs on the sending thread for dropped lines and on the worker thread for
+    /// failed batches and lines dropped for high priority ones, so it should be quick, e.g
+    /// append to a local file or forward to a channel.
+    pub fn dead_letter<F>(mut self, dead_letter: F) -> Self
+    where
+        F: Fn(DeadLetter) + Send + Sync + 'static,
+    {
+        self.dead_letter = Some(Arc::new(dead_letter));
+        self
+    }
+    /// Starts the worker thread, which owns the client and its own Tokio runtime
+    pub fn build(self, client: Client) -> (NonBlockingSender, WorkerGuard) {
+        let client = Arc::new(client);
+        let dead_letter = self.dead_letter.clone();
+        let retry_policy = self.retry_policy.clone();
+        self.spawn(move |lines| {
+            let client = client.clone();
+            let dead_letter = dead_letter.clone();
+            let retry_policy = retry_policy.clone();
+            async move {
+                let body = IngestBody::new(lines);
+                let response = match retry_policy.as_ref() {
+                    Some(policy) => client.send_with_retry(&body, policy).await,
+                    None => client.send(&body).await,
+                };
+                let reason = match response {
+                    Ok(Response::Sent { .. }) => return,
+                    Ok(Response::Failed(_, status, reason)) => format!("{} {}", status, reason),
+                    Err(e) => e.to_string(),
+                };
+                log::warn!("failed to send lines: {}", reason);
+                if let Some(dead_letter) = dead_letter {
+                    dead_letter(DeadLetter::Failed {
+                        lines: body.into_lines(),
+                        reason,
+                    });
+                }
+            }
+        })
+    }
+
+    fn spawn<F, Fut>(self, send: F) -> (NonBlockingSender, WorkerGuard)
+    where
+        F: FnMut(Vec<Line>) -> Fut + Send + 'static,
+        Fut: Future<Output = ()>,
+    {
+        let (tx, rx) = unbounded();
+        let limit = self.buffered_lines_limit;
+        let priority = self.priority.clone();
+        let dead_letter = self.dead_letter.clone();
+        let counters = Arc::new(Counters::default());
+        let worker_counters = counters.clone();
+        let handle = std::thread::Builder::new()
+            .name("logdna-non-blocking".into())
+            .spawn(move || {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .expect("non-blocking worker runtime");
+                runtime.block_on(self.run(rx, &worker_counters, send));
+            })
+            .expect("non-blocking worker thread");
+
+        let sender = NonBlockingSender {
+            tx: tx.clone(),
+            counters,
+            limit,
+            priority,
+            dead_letter,
+        };
+        let guard = WorkerGuard {
+            tx,
+            handle: Some(handle),
+        };
+        (sender, guard)
+    }
+
+    async fn run<F, Fut>(
+        &self,
+        mut rx: UnboundedReceiver<Message>,
+        counters: &Counters,
+        mut send: F,
+    ) where
+        F: FnMut(Vec<Line>) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let mut lanes = Lanes {
+            high: VecDeque::new(),
+            normal: VecDeque::new(),
+            high_weight: self.high_priority_weight,
+        };
+        let mut deadline = Instant::now();
+        let mut open = true;
+        while open {
+            let message = if lanes.is_empty() {
+                rx.next().await
+            } else {
+                match timeout_at(deadline, rx.next()).await {
+                    Ok(message) => message,
+                    Err(_) => {
+                        self.flush(&mut lanes, counters, &mut send).await;
+                        continue;
+                    }
+                }
+            };
+            if lanes.is_empty() {
+                deadline = Instant::now() + self.flush_interval;
+            }
+            open = self.receive(&mut lanes, message, counters);
+            if lanes.len() >= self.max_batch_lines {
+                // Take in the lines queued meanwhile, so high priority ones skip the backlog
+                while open {
+                    match rx.try_next() {
+                        Ok(message) => open = self.receive(&mut lanes, message, counters),
+                        Err(_) => break,
+                    }
+                }
+                self.flush(&mut lanes, counters, &mut send).await;
+            }
+        }
+
+        // Drain whatever was queued before the shutdown
+        rx.close();
+        while let Ok(Some(message)) = rx.try_next() {
+            self.receive(&mut lanes, Some(message), counters);
+        }
+        while !lanes.is_empty() {
+            self.flush(&mut lanes, counters, &mut send).await;
+        }
+    }
+
+    // Sends the next batch, unless evictions left nothing to send
+    async fn flush<F, Fut>(&self, lanes: &mut Lanes, counters: &Counters, send: &mut F)
+    where
+        F: FnMut(Vec<Line>) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        self.evict(lanes, counters);
+        let batch = lanes.batch(self.max_batch_lines, counters);
+        if !batch.is_empty() {
+            send(batch).await;
+        }
+    }
+
+    // Queues a line in its lane, returning false once the worker should stop
+    fn receive(&self, lanes: &mut Lanes, message: Option<Message>, counters: &Counters) -> bool {
+        let (line, priority) = match message {
+            Some(Message::Line(line, priority)) => (line, priority),
+            Some(Message::Shutdown) | None => return false,
+        };
+        let mut line = match self.processors.as_ref() {
+            Some(processors) => match processors.process(line) {
+                Some(line) => line,
+                None => {
+                    counters.release(priority);
+                    return true;
+                }
+            },
+            None => line,
+        };
+        if let Some(redactor) = self.redactor.as_ref() {
+            redactor.redact_line(&mut line);
+        }
+        if self.drop_empty_lines && line.line.trim().is_empty() {
+            counters.release(priority);
+            counters.empty_dropped.fetch_add(1, Ordering::Relaxed);
+            return true;
+        }
+        lanes.push(line, priority);
+        self.evict(lanes, counters);
+        true
+    }
+
+    fn evict(&self, lanes: &mut Lanes, counters: &Counters) {
+        for line in lanes.evict(counters) {
+            if let Some(dead_letter) = self.dead_letter.as_ref() {
+                dead_letter(DeadLetter::Dropped(line));
+            }
+        }
+    }
+}
+
+impl Default for NonBlockingBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    fn line(l: &str) -> Line {
+        Line::builder().line(l).build().unwrap()
+    }
+
+    #[test]
+    fn flushes_on_drop() {
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let sent = batches.clone();
+        let (sender, guard) = NonBlockingBuilder::new()
+            .max_batch_lines(2)
+            .flush_interval(Duration::from_secs(60))
+            .spawn(move |lines: Vec<Line>| {
+                sent.lock().unwrap().push(lines.len());
+                async {}
+            });
+        for l in ["a", "b", "c"] {
+            assert!(sender.send(line(l)));
+        }
+        drop(guard);
+
+        assert_eq!(*batches.lock().unwrap(), vec![2, 1]);
+        assert!(!sender.send(line("late")));
+    }
+
+    #[test]
+    fn flushes_partial_batches_after_interval() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let (sender, _guard) = NonBlockingBuilder::new()
+            .flush_interval(Duration::from_millis(10))
+            .spawn(move |lines: Vec<Line>| {
+                tx.send(lines.len()).unwrap();
+                async {}
+            });
+        sender.send(line("a"));
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(1));
+    }
+
+    #[test]
+    fn drops_over_limit() {
+        let (tx, rx) = unbounded();
+        let sender = NonBlockingSender {
+            tx,
+            counters: Arc::new(Counters::default()),
+            limit: 2,
+            priority: None,
+            dead_letter: None,
+        };
+        let sent = (0..5).filter(|_| sender.send(line("x"))).count();
+        assert_eq!(sent, 2);
+        assert_eq!(sender.dropped_lines(), 3);
+
+        drop(rx);
+        let mut sink = sender.clone();
+        sink.counters.pending.store(0, Ordering::Relaxed);
+        assert!(sink.send_line(line("x")).is_err());
+    }
+
+    #[test]
+    fn drops_empty_lines() {
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let sent = batches.clone();
+        let (sender, guard) = NonBlockingBuilder::new()
+            .drop_empty_lines(true)
+            .flush_interval(Duration::from_secs(60))
+            .spawn(move |lines: Vec<Line>| {
+                sent.lock()
+                    .unwrap()
+                    .extend(lines.into_iter().map(|l| l.line));
+                async {}
+            });
+        for l in ["a", "", " \t\n", "b"] {
+            assert!(sender.send(line(l)));
+        }
+        drop(guard);
+
+        assert_eq!(*batches.lock().unwrap(), vec!["a", "b"]);
+        assert_eq!(sender.empty_lines_dropped(), 2);
+    }
+
+    #[test]
+    fn processes_lines_before_batching() {
+        use crate::processor::{Filter, Map};
+
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let sent = batches.clone();
+        let mut processors = ProcessorChain::new();
+        processors
+            .push(Filter(|line: &Line| line.line != "drop"))
+            .push(Map(|line: &mut Line| line.line.make_ascii_uppercase()));
+        let (sender, guard) = NonBlockingBuilder::new()
+            .processors(processors)
+            .flush_interval(Duration::from_secs(60))
+            .spawn(move |lines: Vec<Line>| {
+                sent.lock()
+                    .unwrap()
+                    .extend(lines.into_iter().map(|l| l.line));
+                async {}
+            });
+        for l in ["a", "drop", "b"] {
+            assert!(sender.send(line(l)));
+        }
+        drop(guard);
+
+        assert_eq!(*batches.lock().unwrap(), vec!["A", "B"]);
+        assert_eq!(sender.dropped_lines(), 0);
+    }
+
+    #[test]
+    fn redacts_lines_after_processing() {
+        use crate::processor::Map;
+
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let sent = batches.clone();
+        let mut processors = ProcessorChain::new();
+        processors.push(Map(|line: &mut Line| line.line.push_str(" hunter2")));
+        let mut redactor = Redactor::new();
+        redactor.substring("hunter2", "*******");
+        let (sender, guard) = NonBlockingBuilder::new()
+            .processors(processors)
+            .redactor(redactor)
+            .flush_interval(Duration::from_secs(60))
+            .spawn(move |lines: Vec<Line>| {
+                sent.lock()
+                    .unwrap()
+                    .extend(lines.into_iter().map(|l| l.line));
+                async {}
+            });
+        assert!(sender.send(line("password")));
+        drop(guard);
+
+        assert_eq!(*batches.lock().unwrap(), vec!["password *******"]);
+    }
+
+    #[test]
+    fn high_priority_lines_skip_ahead() {
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let sent = batches.clone();
+        let (sender, guard) = NonBlockingBuilder::new()
+            .priority(Priority::from_level)
+            .high_priority_weight(2)
+            .flush_interval(Duration::from_secs(60))
+            .spawn(move |lines: Vec<Line>| {
+                sent.lock()
+                    .unwrap()
+                    .push(lines.into_iter().map(|l| l.line).collect::<Vec<_>>());
+                async {}
+            });
+        for (l, level) in [
+            ("d1", "DEBUG"),
+            ("d2", "DEBUG"),
+            ("e1", "ERROR"),
+            ("d3", "DEBUG"),
+            ("e2", "error"),
+            ("e3", "FATAL"),
+        ] {
+            assert!(sender.send(Line::builder().line(l).level(level).build().unwrap()));
+        }
+        drop(guard);
+
+        assert_eq!(
+            *batches.lock().unwrap(),
+            vec![vec!["e1", "e2", "d1", "e3", "d2", "d3"]]
+        );
+    }
+
+    #[test]
+    fn high_priority_lines_displace_normal_ones() {
+        let (tx, mut rx) = unbounded();
+        let sender = NonBlockingSender {
+            tx,
+            counters: Arc::new(Counters::default()),
+            limit: 2,
+            priority: Some(Arc::new(Priority::from_level)),
+            dead_letter: None,
+        };
+        let line = |l: &str, level: &str| Line::builder().line(l).level(level).build().unwrap();
+        assert!(sender.send(line("n1", "INFO")));
+        assert!(sender.send(line("n2", "INFO")));
+        assert!(!sender.send(line("n3", "INFO")));
+        assert!(sender.send(line("h1", "ERROR")));
+        assert!(sender.send(line("h2", "ERROR")));
+        assert!(!sender.send(line("h3", "ERROR")));
+
+        let builder = NonBlockingBuilder::new();
+        let counters = &sender.counters;
+        let mut lanes = Lanes {
+            high: VecDeque::new(),
+            normal: VecDeque::new(),
+            high_weight: 1,
+        };
+        while let Ok(message) = rx.try_next() {
+            builder.receive(&mut lanes, message, counters);
+        }
+        let batch: Vec<_> = lanes
+            .batch(10, counters)
+            .into_iter()
+            .map(|l| l.line)
+            .collect();
+        assert_eq!(batch, vec!["h1", "h2"]);
+        assert_eq!(sender.dropped_lines(), 4);
+        assert_eq!(counters.pending.load(Ordering::Relaxed), 0);
+        assert_eq!(counters.normal_pending.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn queued_lines_share_fields() {
+        let line = |l: &str| {
+            Line::builder()
+                .line(l)
+                .app("app")
+                .host("node-1")
+                .level("INFO")
+                .build()
+                .unwrap()
+        };
+        let b = line("b");
+        let a = Queued::new(line("a"));
+        let queued = Queued::new(b.clone());
+        assert!(Arc::ptr_eq(
+            a.host.as_ref().unwrap(),
+            queued.host.as_ref().unwrap()
+        ));
+        assert_eq!(a.line.host, None);
+        assert_eq!(queued.into_line(), b);
+    }
+
+    #[test]
+    fn dead_letters_dropped_lines() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let tx = Mutex::new(tx);
+        let (sender, guard) = NonBlockingBuilder::new()
+            .dead_letter(move |dead| tx.lock().unwrap().send(dead).unwrap())
+            .spawn(|_: Vec<Line>| async {});
+        drop(guard);
+
+        assert!(!sender.send(line("lost")));
+        match rx.try_recv().unwrap() {
+            DeadLetter::Dropped(dropped) => assert_eq!(dropped.line, "lost"),
+            other => panic!("unexpected dead letter {:?}", other),
+        }
+    }
+}
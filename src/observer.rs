@@ -0,0 +1,42 @@
+use std::time::Duration;
+
+use http::StatusCode;
+
+use crate::body::IngestBodyBuffer;
+
+/// Hooks into the lifecycle of ingest requests, e.g for custom metrics or logging
+///
+/// Every hook has an empty default implementation. The client calls `on_batch_start`,
+/// `on_sent` and `on_failed` from [`Client::send`](crate::client::Client::send), `on_retry`
+/// and `on_give_up` are called by
+/// [`Client::send_with_retry`](crate::client::Client::send_with_retry) before every retry and
+/// when it stops retrying.
+///
+/// # Example
+///
+/// ```rust
+/// # use std::sync::atomic::{AtomicUsize, Ordering};
+/// # use std::time::Duration;
+/// # use logdna_client::observer::IngestObserver;
+/// #[derive(Default)]
+/// struct SentBytes(AtomicUsize);
+///
+/// impl IngestObserver for SentBytes {
+///     fn on_sent(&self, bytes: usize, _lines: Option<usize>, _latency: Duration) {
+///         self.0.fetch_add(bytes, Ordering::Relaxed);
+///     }
+/// }
+/// ```
+pub trait IngestObserver: Send + Sync {
+    /// Called once the body is serialized, before the request is sent
+    fn on_batch_start(&self, _bytes: usize, _lines: Option<usize>) {}
+    /// Called when the ingest API accepted a body
+    fn on_sent(&self, _bytes: usize, _lines: Option<usize>, _latency: Duration) {}
+    /// Called before a failed body is sent again
+    fn on_retry(&self, _attempt: u32, _delay: Duration) {}
+    /// Called when a body was not accepted, `None` if no response was received
+    fn on_failed(&self, _status: Option<StatusCode>) {}
+    /// Called when a body that failed with a retryable error ran out of attempts or retry
+    /// budget, e.g to write it to a dead letter queue
+    fn on_give_up(&self, _body: &IngestBodyBuffer, _attempts: u32) {}
+}
@@ -0,0 +1,245 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use derivative::Derivative;
+use opentelemetry::logs::{AnyValue, LogError, LogResult};
+use opentelemetry_sdk::export::logs::{LogData, LogExporter};
+use serde_json::{Map, Value};
+
+use crate::body::{IngestBody, KeyValueMap, Line, LineBuilder};
+use crate::client::Client;
+use crate::error::LineError;
+use crate::response::Response;
+
+/// Maps OpenTelemetry log records into Lines
+///
+/// | OpenTelemetry | Line |
+/// |---|---|
+/// | severity text, else severity number | `level` |
+/// | body | `line`, non string bodies as json |
+/// | timestamp, else observed timestamp | `timestamp` |
+/// | attributes | `meta`, label attributes as `labels` |
+/// | trace and span id | `meta.trace_id`, `meta.span_id` |
+/// | resource `service.name`, `host.name` | `app`, `host` |
+/// | other resource attributes | `meta.resource` |
+#[derive(Debug, Clone, Default)]
+pub struct OtelMapping {
+    label_attributes: HashSet<String>,
+}
+
+impl OtelMapping {
+    /// Constructs an OtelMapping storing every attribute in meta
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Set the attributes stored as labels instead of meta
+    pub fn label_attributes<T: Into<String>>(mut self, keys: impl IntoIterator<Item = T>) -> Self {
+        self.label_attributes = keys.into_iter().map(Into::into).collect();
+        self
+    }
+    /// Maps a log record along with its resource into a Line
+    pub fn line(&self, data: &LogData) -> Result<Line, LineError> {
+        let record = &data.record;
+        let mut builder = LineBuilder::new().line(match record.body.as_ref() {
+            Some(AnyValue::String(s)) => s.to_string(),
+            Some(body) => to_json(body).to_string(),
+            None => String::new(),
+        });
+        if let Some(level) = record.severity_text.as_ref() {
+            builder = builder.level(level.to_string());
+        } else if let Some(severity) = record.severity_number {
+            builder = builder.level(severity_level(severity as i32));
+        }
+
+        let mut meta = Map::new();
+        let mut labels = KeyValueMap::new();
+        for (key, value) in record.attributes.iter().flatten() {
+            if self.label_attributes.contains(key.as_str()) {
+                labels.insert(key.to_string(), value_to_string(value));
+            } else {
+                meta.insert(key.to_string(), to_json(value));
+            }
+        }
+        if let Some(context) = record.trace_context.as_ref() {
+            meta.insert("trace_id".into(), Value::from(context.trace_id.to_string()));
+            meta.insert("span_id".into(), Value::from(context.span_id.to_string()));
+        }
+
+        let mut resource = Map::new();
+        for (key, value) in data.resource.iter() {
+            match key.as_str() {
+                "service.name" => builder = builder.app(value.to_string()),
+                "host.name" => builder = builder.host(value.to_string()),
+                key => {
+                    resource.insert(key.to_string(), Value::from(value.to_string()));
+                }
+            }
+        }
+        if !resource.is_empty() {
+            meta.insert("resource".into(), Value::Object(resource));
+        }
+        if !meta.is_empty() {
+            builder = builder.meta(Value::Object(meta));
+        }
+        if !labels.is_empty() {
+            builder = builder.labels(labels);
+        }
+
+        let mut line = builder.build()?;
+        if let Some(timestamp) = record.timestamp.or(record.observed_timestamp) {
+            line.timestamp = unix_timestamp(timestamp);
+        }
+        Ok(line)
+    }
+}
+
+// Severity numbers come in groups of four per level
+fn severity_level(severity: i32) -> &'static str {
+    match severity {
+        1..=4 => "TRACE",
+        5..=8 => "DEBUG",
+        9..=12 => "INFO",
+        13..=16 => "WARN",
+        17..=20 => "ERROR",
+        _ => "FATAL",
+    }
+}
+
+fn unix_timestamp(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(before) => -(before.duration().as_secs() as i64),
+    }
+}
+
+fn to_json(value: &AnyValue) -> Value {
+    match value {
+        AnyValue::Int(i) => Value::from(*i),
+        AnyValue::Double(f) => Value::from(*f),
+        AnyValue::String(s) => Value::from(s.to_string()),
+        AnyValue::Boolean(b) => Value::from(*b),
+        AnyValue::Bytes(bytes) => Value::from(bytes.clone()),
+        AnyValue::ListAny(values) => Value::Array(values.iter().map(to_json).collect()),
+        AnyValue::Map(map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| (key.to_string(), to_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+fn value_to_string(value: &AnyValue) -> String {
+    match value {
+        AnyValue::String(s) => s.to_string(),
+        value => to_json(value).to_string(),
+    }
+}
+
+/// An OpenTelemetry [`LogExporter`] sending every exported batch with a [`Client`]
+#[derive(Derivative, Clone)]
+#[derivative(Debug)]
+pub struct LogDnaExporter {
+    #[derivative(Debug = "ignore")]
+    client: Arc<Client>,
+    mapping: OtelMapping,
+}
+
+impl LogDnaExporter {
+    /// Constructs a LogDnaExporter using the default mapping
+    pub fn new(client: Client) -> Self {
+        Self {
+            client: Arc::new(client),
+            mapping: OtelMapping::new(),
+        }
+    }
+    /// Set the mapping from log records to Lines
+    pub fn mapping(mut self, mapping: OtelMapping) -> Self {
+        self.mapping = mapping;
+        self
+    }
+}
+
+#[async_trait]
+impl LogExporter for LogDnaExporter {
+    async fn export(&mut self, batch: Vec<LogData>) -> LogResult<()> {
+        let lines = batch
+            .iter()
+            .map(|data| self.mapping.line(data))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| LogError::Other(Box::new(e)))?;
+        match self.client.send(IngestBody::new(lines)).await {
+            Ok(Response::Sent { .. }) => Ok(()),
+            Ok(Response::Failed(_, status, reason)) => Err(LogError::Other(
+                format!("ingest request failed: {} {}", status, reason).into(),
+            )),
+            Err(e) => Err(LogError::Other(e.to_string().into())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+    use std::time::Duration;
+
+    use opentelemetry::logs::{LogRecord, Severity};
+    use opentelemetry::KeyValue;
+    use opentelemetry_sdk::Resource;
+
+    use super::*;
+
+    fn log_data(record: LogRecord) -> LogData {
+        LogData {
+            record,
+            resource: Cow::Owned(Resource::new(vec![
+                KeyValue::new("service.name", "checkout"),
+                KeyValue::new("deployment.environment", "prod"),
+            ])),
+            instrumentation: Default::default(),
+        }
+    }
+
+    #[test]
+    fn maps_log_record() {
+        let mut record = LogRecord::default();
+        record.body = Some(AnyValue::from("payment declined"));
+        record.severity_number = Some(Severity::Warn);
+        record.timestamp = Some(UNIX_EPOCH + Duration::from_secs(1609459200));
+        record.attributes = Some(vec![
+            ("tenant".into(), AnyValue::from("acme")),
+            ("attempt".into(), AnyValue::Int(2)),
+        ]);
+
+        let line = OtelMapping::new()
+            .label_attributes(vec!["tenant"])
+            .line(&log_data(record))
+            .unwrap();
+        assert_eq!(line.line, "payment declined");
+        assert_eq!(line.level.as_deref(), Some("WARN"));
+        assert_eq!(line.app.as_deref(), Some("checkout"));
+        assert_eq!(line.timestamp, 1609459200);
+        assert_eq!(line.labels.unwrap()["tenant"], "acme");
+        assert_eq!(
+            line.meta.unwrap(),
+            serde_json::json!({
+                "attempt": 2,
+                "resource": {"deployment.environment": "prod"}
+            })
+        );
+    }
+
+    #[test]
+    fn maps_structured_body() {
+        let mut record = LogRecord::default();
+        record.body = Some(AnyValue::ListAny(vec![
+            AnyValue::Int(1),
+            AnyValue::Boolean(true),
+        ]));
+        record.severity_text = Some("notice".into());
+        let line = OtelMapping::new().line(&log_data(record)).unwrap();
+        assert_eq!(line.line, "[1,true]");
+        assert_eq!(line.level.as_deref(), Some("notice"));
+    }
+}
@@ -0,0 +1,301 @@
+use std::io;
+
+use async_compression::Level;
+use bytes::Bytes;
+use flate2::{Compress, Compression, Crc, FlushCompress, Status};
+
+use crate::segmented_buffer::FrozenBuf;
+
+const DEFAULT_CHUNK_SIZE: usize = 128 * 1024;
+
+const MIN_CHUNK_SIZE: usize = 1024;
+
+const WINDOW_SIZE: usize = 32 * 1024;
+
+const GZIP_HEADER: [u8; 10] = [0x1f, 0x8b, 0x08, 0, 0, 0, 0, 0, 0, 0xff];
+
+/// Compresses large `Encoding::GzipJson` bodies on several threads
+///
+/// Bodies of at least `threshold` bytes are split into chunks that are deflated independently,
+/// pigz style, and joined into a single gzip member. Smaller bodies keep using the streaming
+/// encoder.
+///
+/// With the `zlib-ng` feature each chunk is primed with the last 32KiB of the previous one, the
+/// default miniz_oxide backend can't set a dictionary so its chunks start without that history
+/// and the output is a little larger than a single threaded encode.
+///
+/// # Example
+///
+/// ```rust
+/// # use logdna_client::parallel_gzip::ParallelGzip;
+/// let parallel = ParallelGzip::new(4 * 1024 * 1024).threads(4);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParallelGzip {
+    threshold: usize,
+    chunk_size: usize,
+    threads: usize,
+}
+
+impl ParallelGzip {
+    /// Constructs a ParallelGzip for bodies of at least `threshold` bytes, using every core
+    pub fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            threads: std::thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+    /// Set the size of the independently deflated chunks, default is 128KiB
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(MIN_CHUNK_SIZE);
+        self
+    }
+    /// Set the number of compression threads
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Whether a serialized body of `len` bytes is compressed in parallel
+    pub(crate) fn applies(&self, len: usize) -> bool {
+        len >= self.threshold
+    }
+
+    /// Gzips `input` into a single member
+    ///
+    /// The chunks are deflated on tokio's blocking pool so the calling task's worker isn't held
+    /// up, or on the calling thread outside of a runtime. They are slices of the input segments
+    /// and the compressed chunks become the segments of the output, so nothing is copied.
+    pub(crate) async fn compress(&self, input: FrozenBuf, level: Level) -> io::Result<FrozenBuf> {
+        let compression = compression(level);
+        let chunks = split(&input, self.chunk_size);
+        let last = chunks.len() - 1;
+        let jobs: Vec<Job> = chunks
+            .iter()
+            .enumerate()
+            .map(|(index, chunk)| Job {
+                chunk: chunk.clone(),
+                dictionary: window(&chunks[..index]),
+                last: index == last,
+            })
+            .collect();
+        let per_thread = (jobs.len() + self.threads - 1) / self.threads;
+        let mut groups = Vec::with_capacity(self.threads);
+        let mut jobs = jobs.into_iter().peekable();
+        while jobs.peek().is_some() {
+            groups.push(jobs.by_ref().take(per_thread).collect::<Vec<_>>());
+        }
+
+        let results: Vec<io::Result<(Bytes, Crc)>> = match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                let tasks: Vec<_> = groups
+                    .into_iter()
+                    .map(|group| handle.spawn_blocking(move || run(group, compression)))
+                    .collect();
+                let mut results = Vec::with_capacity(chunks.len());
+                for task in tasks {
+                    results.extend(
+                        task.await
+                            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?,
+                    );
+                }
+                results
+            }
+            Err(_) => groups
+                .into_iter()
+                .flat_map(|group| run(group, compression))
+                .collect(),
+        };
+
+        let mut segments = Vec::with_capacity(results.len() + 2);
+        segments.push(Bytes::from_static(&GZIP_HEADER));
+        let mut crc = Crc::new();
+        for result in results {
+            let (deflated, chunk_crc) = result?;
+            segments.push(deflated);
+            crc.combine(&chunk_crc);
+        }
+        let mut trailer = Vec::with_capacity(8);
+        trailer.extend_from_slice(&crc.sum().to_le_bytes());
+        trailer.extend_from_slice(&crc.amount().to_le_bytes());
+        segments.push(Bytes::from(trailer));
+        Ok(FrozenBuf::from_segments(segments))
+    }
+}
+
+// A chunk to deflate, with the history zlib-ng primes it with
+struct Job {
+    chunk: Vec<Bytes>,
+    dictionary: Vec<Bytes>,
+    last: bool,
+}
+
+fn run(jobs: Vec<Job>, compression: Compression) -> Vec<io::Result<(Bytes, Crc)>> {
+    jobs.into_iter()
+        .map(|job| {
+            let mut crc = Crc::new();
+            for slice in job.chunk.iter() {
+                crc.update(slice);
+            }
+            let deflated = deflate(&job.chunk, &job.dictionary, compression, job.last)?;
+            Ok((deflated, crc))
+        })
+        .collect()
+}
+
+// Slices the input into chunks of `chunk_size` bytes, a chunk may span several segments
+fn split(input: &FrozenBuf, chunk_size: usize) -> Vec<Vec<Bytes>> {
+    let mut chunks = Vec::with_capacity(input.len() / chunk_size + 1);
+    let mut chunk = Vec::new();
+    let mut len = 0;
+    for segment in input.segments() {
+        let mut segment = segment.clone();
+        while !segment.is_empty() {
+            let take = segment.len().min(chunk_size - len);
+            chunk.push(segment.split_to(take));
+            len += take;
+            if len == chunk_size {
+                chunks.push(std::mem::take(&mut chunk));
+                len = 0;
+            }
+        }
+    }
+    if !chunk.is_empty() || chunks.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+// The last WINDOW_SIZE bytes of the preceding chunks
+fn window(preceding: &[Vec<Bytes>]) -> Vec<Bytes> {
+    let mut window = Vec::new();
+    let mut len = 0;
+    for slice in preceding.iter().rev().flat_map(|chunk| chunk.iter().rev()) {
+        if len == WINDOW_SIZE {
+            break;
+        }
+        let take = slice.len().min(WINDOW_SIZE - len);
+        window.push(slice.slice(slice.len() - take..));
+        len += take;
+    }
+    window.reverse();
+    window
+}
+
+// Raw deflate of one chunk, byte aligned with a sync flush unless it ends the stream
+fn deflate(
+    chunk: &[Bytes],
+    dictionary: &[Bytes],
+    compression: Compression,
+    last: bool,
+) -> io::Result<Bytes> {
+    let empty = [Bytes::new()];
+    let slices = if chunk.is_empty() { &empty[..] } else { chunk };
+    let mut compress = Compress::new(compression, false);
+    prime(&mut compress, dictionary)?;
+    let len: usize = slices.iter().map(Bytes::len).sum();
+    let mut out = Vec::with_capacity(len / 2 + 64);
+    for (i, slice) in slices.iter().enumerate() {
+        let flush = if i + 1 < slices.len() {
+            FlushCompress::None
+        } else if last {
+            FlushCompress::Finish
+        } else {
+            FlushCompress::Sync
+        };
+        let start = compress.total_in();
+        loop {
+            let consumed = (compress.total_in() - start) as usize;
+            let status = compress
+                .compress_vec(&slice[consumed..], &mut out, flush)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            let all_in = (compress.total_in() - start) as usize == slice.len();
+            let done = match flush {
+                FlushCompress::Finish => status == Status::StreamEnd,
+                FlushCompress::None => all_in,
+                _ => all_in && out.len() < out.capacity(),
+            };
+            if done {
+                break;
+            }
+            out.reserve(out.capacity().max(64));
+        }
+    }
+    Ok(Bytes::from(out))
+}
+
+#[cfg(feature = "zlib-ng")]
+fn prime(compress: &mut Compress, dictionary: &[Bytes]) -> io::Result<()> {
+    if !dictionary.is_empty() {
+        compress
+            .set_dictionary(&dictionary.concat())
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "zlib-ng"))]
+fn prime(_: &mut Compress, _: &[Bytes]) -> io::Result<()> {
+    Ok(())
+}
+
+fn compression(level: Level) -> Compression {
+    match level {
+        Level::Fastest => Compression::fast(),
+        Level::Best => Compression::best(),
+        Level::Precise(n) => Compression::new(n.clamp(0, 9) as u32),
+        _ => Compression::default(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bytes::Buf;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    fn gunzip(compressed: FrozenBuf) -> Vec<u8> {
+        let mut decoded = Vec::new();
+        GzDecoder::new(compressed.reader())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        decoded
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn parallel_output_is_a_valid_gzip_stream() {
+        let input: Vec<u8> = (0..20_000u32)
+            .flat_map(|i| format!("{{\"line\":\"message {}\"}},", i % 977).into_bytes())
+            .collect();
+        // segments that don't line up with the chunks
+        let segmented = FrozenBuf::from_segments(input.chunks(3000).map(Bytes::copy_from_slice));
+        let parallel = ParallelGzip::new(0).chunk_size(4096).threads(3);
+
+        for level in [Level::Fastest, Level::Precise(2), Level::Best] {
+            let compressed = parallel.compress(segmented.clone(), level).await.unwrap();
+            assert!(compressed.len() < input.len());
+            assert_eq!(gunzip(compressed), input);
+        }
+
+        let compressed = parallel
+            .compress(FrozenBuf::default(), Level::Default)
+            .await
+            .unwrap();
+        assert!(gunzip(compressed).is_empty());
+    }
+
+    #[test]
+    fn compresses_outside_of_a_runtime() {
+        let input = b"{\"line\":\"outside\"}".repeat(1000);
+        let parallel = ParallelGzip::new(0).chunk_size(1024).threads(2);
+        let compressed = futures::executor::block_on(parallel.compress(
+            FrozenBuf::from_segments(vec![Bytes::from(input.clone())]),
+            Level::Default,
+        ))
+        .unwrap();
+        assert_eq!(gunzip(compressed), input);
+    }
+}
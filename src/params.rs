@@ -1,14 +1,23 @@
+use std::collections::HashSet;
+use std::env;
 use std::fmt;
+#[cfg(feature = "client")]
+use std::sync::Arc;
+
+#[cfg(feature = "client")]
+use arc_swap::ArcSwap;
 
 use serde::de::Visitor;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::error::ParamsError;
+use crate::limits::Limits;
 
 /// Represents the query parameters that are passed to the IngestAPI
 ///
 /// e.g `?hostname=test&now=42343234234`
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct Params {
     /// the hostname parameter, e.g `node-001`
     pub hostname: String,
@@ -23,6 +32,7 @@ pub struct Params {
     /// Note this is set by the client upon every request
     pub now: i64,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "schemars", schemars(with = "Option<String>"))]
     /// the tags parameter (optional), e.g `this,is,a,test,tag`
     pub tags: Option<Tags>,
 }
@@ -46,6 +56,8 @@ pub struct ParamsBuilder {
     mac: Option<String>,
     ip: Option<String>,
     tags: Option<Tags>,
+    #[serde(skip)]
+    limits: Option<Limits>,
 }
 
 impl ParamsBuilder {
@@ -56,6 +68,7 @@ impl ParamsBuilder {
             mac: None,
             ip: None,
             tags: None,
+            limits: None,
         }
     }
     /// Sets the hostname field, required
@@ -78,16 +91,30 @@ impl ParamsBuilder {
         self.tags = Some(tags.into());
         self
     }
+    /// Check the hostname and tags against the Ingest API limits when building, default is
+    /// unchecked
+    pub fn limits(&mut self, limits: Limits) -> &mut Self {
+        self.limits = Some(limits);
+        self
+    }
     /// Builds a Params instance from the current ParamsBuilder
     pub fn build(&mut self) -> Result<Params, ParamsError> {
+        let mut hostname = self.hostname.clone().ok_or_else(|| {
+            ParamsError::RequiredField("hostname is required in a ParamsBuilder".into())
+        })?;
+        let mut tags = self.tags.clone();
+        if let Some(limits) = self.limits.as_ref() {
+            limits.check_str("hostname", &mut hostname, limits.max_hostname_len)?;
+            if let Some(tags) = tags.as_mut() {
+                limits.check_tags(&mut tags.inner)?;
+            }
+        }
         Ok(Params {
-            hostname: self.hostname.clone().ok_or_else(|| {
-                ParamsError::RequiredField("hostname is required in a ParamsBuilder".into())
-            })?,
+            hostname,
             mac: self.mac.clone(),
             ip: self.ip.clone(),
             now: 0,
-            tags: self.tags.clone(),
+            tags,
         })
     }
 }
@@ -98,6 +125,57 @@ impl Default for ParamsBuilder {
     }
 }
 
+/// The query parameters of a client, which can be updated while it is sending
+///
+/// Every clone refers to the same parameters, see [`Client::params`](crate::client::Client::params).
+/// Requests read the parameters as they are created, so an update applies to every request
+/// created after it, e.g when a host is re-labeled.
+#[cfg(feature = "client")]
+#[derive(Debug, Clone)]
+pub struct ParamsHandle {
+    inner: Arc<ArcSwap<Params>>,
+}
+
+#[cfg(feature = "client")]
+impl ParamsHandle {
+    pub(crate) fn new(params: Params) -> Self {
+        Self {
+            inner: Arc::new(ArcSwap::from_pointee(params)),
+        }
+    }
+    /// The current parameters
+    pub fn load(&self) -> Arc<Params> {
+        self.inner.load_full()
+    }
+    /// Replaces the parameters
+    pub fn store(&self, params: Params) {
+        self.inner.store(Arc::new(params));
+    }
+    /// Updates a copy of the current parameters and stores it
+    ///
+    /// The closure may run more than once if the parameters are updated concurrently.
+    pub fn update<F: Fn(&mut Params)>(&self, update: F) {
+        self.inner.rcu(|current| {
+            let mut params = Params::clone(current);
+            update(&mut params);
+            params
+        });
+    }
+    /// Sets the hostname parameter
+    pub fn set_hostname<T: Into<String>>(&self, hostname: T) {
+        let hostname = hostname.into();
+        self.update(|params| params.hostname = hostname.clone());
+    }
+    /// Sets or clears the ip parameter
+    pub fn set_ip(&self, ip: Option<String>) {
+        self.update(|params| params.ip = ip.clone());
+    }
+    /// Sets or clears the tags parameter
+    pub fn set_tags(&self, tags: Option<Tags>) {
+        self.update(|params| params.tags = tags.clone());
+    }
+}
+
 /// Defines a comma separated list of tags, e.g `this,is,a,test`
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Tags {
@@ -111,12 +189,17 @@ impl Tags {
     }
     /// Parses a comma separated list of tags into a Tags instance, e.g `this,is,a,test`
     pub fn parse<T: Into<String>>(tags: T) -> Self {
-        Self {
-            inner: tags
-                .into()
-                .split_terminator(',')
-                .map(|s| s.to_string())
-                .collect(),
+        let mut parsed = Self::new();
+        parsed.extend_parsed(&tags.into());
+        parsed
+    }
+    /// Parses the comma separated tags of an environment variable, e.g `LOGDNA_TAGS`
+    ///
+    /// Returns None if the variable is unset or empty.
+    pub fn from_env(var: &str) -> Option<Self> {
+        match env::var(var) {
+            Ok(tags) if !tags.is_empty() => Some(Self::parse(tags)),
+            _ => None,
         }
     }
     /// Manually adds a tag to the list of tags
@@ -124,6 +207,23 @@ impl Tags {
         self.inner.push(tag.into());
         self
     }
+    /// Adds the tags of `other` that aren't in the list yet
+    pub fn merge(&mut self, other: &Tags) -> &mut Self {
+        for tag in other.inner.iter() {
+            if !self.inner.contains(tag) {
+                self.inner.push(tag.clone());
+            }
+        }
+        self
+    }
+}
+
+impl Tags {
+    // Splits on commas like the parse path, a trailing comma doesn't add an empty tag
+    fn extend_parsed(&mut self, tags: &str) {
+        self.inner
+            .extend(tags.split_terminator(',').map(|s| s.to_string()));
+    }
 }
 
 impl Default for Tags {
@@ -144,6 +244,25 @@ impl<'a> From<&'a str> for Tags {
     }
 }
 
+impl<'a> From<&'a [&'a str]> for Tags {
+    fn from(input: &'a [&'a str]) -> Self {
+        let mut tags = Tags::new();
+        input.iter().for_each(|t| tags.extend_parsed(t));
+        tags
+    }
+}
+
+/// The tags are sorted, as sets have no order
+impl From<HashSet<String>> for Tags {
+    fn from(input: HashSet<String>) -> Self {
+        let mut input: Vec<String> = input.into_iter().collect();
+        input.sort_unstable();
+        let mut tags = Tags::new();
+        input.iter().for_each(|t| tags.extend_parsed(t));
+        tags
+    }
+}
+
 impl From<Vec<String>> for Tags {
     fn from(input: Vec<String>) -> Self {
         let mut tags = Tags::new();
@@ -178,12 +297,44 @@ impl<'de> Deserialize<'de> for Tags {
             }
 
             fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> {
-                Ok(Tags {
-                    inner: v.split_terminator(',').map(|s| s.to_string()).collect(),
-                })
+                Ok(Tags::parse(v))
             }
         }
 
         deserializer.deserialize_str(StrVisitor {})
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn params_handle_updates_every_clone() {
+        let params = Params::builder().hostname("before").build().unwrap();
+        let handle = ParamsHandle::new(params.clone());
+        let other = handle.clone();
+        let loaded = handle.load();
+
+        other.set_hostname("after");
+        other.set_tags(Some(Tags::parse("a,b")));
+        assert_eq!(*loaded, params);
+        assert_eq!(handle.load().hostname, "after");
+        assert_eq!(handle.load().tags, Some(Tags::parse("a,b")));
+    }
+
+    #[test]
+    fn tag_sources_are_parsed_alike() {
+        let parsed = Tags::parse("a,b,c,");
+        assert_eq!(Tags::from(&["a", "b,c"][..]), parsed);
+        let set: HashSet<String> = vec!["c", "a", "b"].into_iter().map(String::from).collect();
+        assert_eq!(Tags::from(set), parsed);
+
+        env::set_var("LOGDNA_TEST_TAGS", "a,b,c,");
+        assert_eq!(Tags::from_env("LOGDNA_TEST_TAGS"), Some(parsed));
+        env::set_var("LOGDNA_TEST_TAGS", "");
+        assert_eq!(Tags::from_env("LOGDNA_TEST_TAGS"), None);
+        assert_eq!(Tags::from_env("LOGDNA_UNSET_TAGS"), None);
+    }
+}
@@ -0,0 +1,188 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{self, Poll};
+
+use http::Uri;
+use hyper::client::connect::{Connected, Connection};
+use hyper::service::Service;
+use pin_project::pin_project;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// A point in time view of a client's connections, see
+/// [`Client::pool_stats`](crate::client::Client::pool_stats)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Connections currently open, whether idle in the pool or in use
+    pub open_connections: usize,
+    /// Connections opened since the client was built
+    pub connections_opened: u64,
+    /// Requests currently being sent
+    pub in_flight: usize,
+}
+
+/// Counts the connections made through a [`CountingConnector`]
+#[derive(Debug, Default)]
+pub(crate) struct ConnectionCounters {
+    open: AtomicUsize,
+    opened: AtomicU64,
+}
+
+impl ConnectionCounters {
+    pub(crate) fn stats(&self, in_flight: usize) -> PoolStats {
+        PoolStats {
+            open_connections: self.open.load(Ordering::Relaxed),
+            connections_opened: self.opened.load(Ordering::Relaxed),
+            in_flight,
+        }
+    }
+}
+
+/// Connector counting the connections it opens until they are closed
+#[derive(Debug, Clone)]
+pub(crate) struct CountingConnector<C> {
+    inner: C,
+    counters: Arc<ConnectionCounters>,
+}
+
+impl<C> CountingConnector<C> {
+    pub(crate) fn new(inner: C, counters: Arc<ConnectionCounters>) -> Self {
+        Self { inner, counters }
+    }
+}
+
+impl<C> Service<Uri> for CountingConnector<C>
+where
+    C: Service<Uri>,
+    C::Response: Send + 'static,
+    C::Future: Send + 'static,
+{
+    type Response = Counted<C::Response>;
+    type Error = C::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, dst: Uri) -> Self::Future {
+        let connecting = self.inner.call(dst);
+        let counters = self.counters.clone();
+        Box::pin(async move {
+            let stream = connecting.await?;
+            counters.opened.fetch_add(1, Ordering::Relaxed);
+            counters.open.fetch_add(1, Ordering::Relaxed);
+            Ok(Counted {
+                inner: stream,
+                _open: Open(counters),
+            })
+        })
+    }
+}
+
+// Decrements the open connections when the connection is dropped
+struct Open(Arc<ConnectionCounters>);
+
+impl Drop for Open {
+    fn drop(&mut self) {
+        self.0.open.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A connection counted as open until dropped
+#[pin_project]
+pub(crate) struct Counted<S> {
+    #[pin]
+    inner: S,
+    _open: Open,
+}
+
+impl<S: Connection> Connection for Counted<S> {
+    fn connected(&self) -> Connected {
+        self.inner.connected()
+    }
+}
+
+impl<S: AsyncRead> AsyncRead for Counted<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.project().inner.poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite> AsyncWrite for Counted<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.project().inner.poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.project().inner.poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        self.project().inner.poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Connect;
+
+    impl Service<Uri> for Connect {
+        type Response = ();
+        type Error = io::Error;
+        type Future = futures::future::Ready<io::Result<()>>;
+
+        fn poll_ready(&mut self, _: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: Uri) -> Self::Future {
+            futures::future::ready(Ok(()))
+        }
+    }
+
+    #[test]
+    fn counts_open_connections() {
+        let counters = Arc::new(ConnectionCounters::default());
+        let mut connector = CountingConnector::new(Connect, counters.clone());
+        let uri: Uri = "http://localhost".parse().unwrap();
+        let first = tokio_test::block_on(connector.call(uri.clone())).unwrap();
+        let second = tokio_test::block_on(connector.call(uri)).unwrap();
+        assert_eq!(counters.stats(1).open_connections, 2);
+
+        drop(first);
+        drop(second);
+        assert_eq!(
+            counters.stats(0),
+            PoolStats {
+                open_connections: 0,
+                connections_opened: 2,
+                in_flight: 0,
+            }
+        );
+    }
+}
@@ -0,0 +1,167 @@
+use crate::body::{IngestBody, Line};
+use crate::redaction::Redactor;
+use crate::sanitize::Sanitizer;
+
+/// A single stage that inspects, mutates or drops a log line before it is sent
+///
+/// Returning `None` drops the line, any stage after it will not see it
+pub trait LineProcessor: Send + Sync {
+    fn process(&self, line: Line) -> Option<Line>;
+}
+
+impl<F> LineProcessor for F
+where
+    F: Fn(Line) -> Option<Line> + Send + Sync,
+{
+    fn process(&self, line: Line) -> Option<Line> {
+        self(line)
+    }
+}
+
+impl LineProcessor for Redactor {
+    fn process(&self, mut line: Line) -> Option<Line> {
+        self.redact_line(&mut line);
+        Some(line)
+    }
+}
+
+impl LineProcessor for Sanitizer {
+    fn process(&self, mut line: Line) -> Option<Line> {
+        self.sanitize_line(&mut line);
+        Some(line)
+    }
+}
+
+/// Keeps only the lines matching a predicate
+pub struct Filter<F>(pub F);
+
+impl<F> LineProcessor for Filter<F>
+where
+    F: Fn(&Line) -> bool + Send + Sync,
+{
+    fn process(&self, line: Line) -> Option<Line> {
+        if (self.0)(&line) {
+            Some(line)
+        } else {
+            None
+        }
+    }
+}
+
+/// Mutates every line in place
+pub struct Map<F>(pub F);
+
+impl<F> LineProcessor for Map<F>
+where
+    F: Fn(&mut Line) + Send + Sync,
+{
+    fn process(&self, mut line: Line) -> Option<Line> {
+        (self.0)(&mut line);
+        Some(line)
+    }
+}
+
+/// An ordered stack of processors, a line passes through each in turn
+///
+/// # Example
+///
+/// ```rust
+/// # use logdna_client::body::Line;
+/// # use logdna_client::processor::{Filter, LineProcessor, Map, ProcessorChain};
+/// let mut chain = ProcessorChain::new();
+/// chain
+///     .push(Filter(|line: &Line| line.level.as_deref() != Some("DEBUG")))
+///     .push(Map(|line: &mut Line| line.app = Some("rust-client".into())));
+///
+/// let line = Line::builder().line("hello").level("INFO").build().unwrap();
+/// assert_eq!(chain.process(line).unwrap().app.as_deref(), Some("rust-client"));
+/// ```
+#[derive(Default)]
+pub struct ProcessorChain {
+    processors: Vec<Box<dyn LineProcessor>>,
+}
+
+impl ProcessorChain {
+    /// Constructs an empty chain, which passes every line through unchanged
+    pub fn new() -> Self {
+        Self {
+            processors: Vec::new(),
+        }
+    }
+    /// Appends a processor to the end of the chain
+    pub fn push<P: LineProcessor + 'static>(&mut self, processor: P) -> &mut Self {
+        self.processors.push(Box::new(processor));
+        self
+    }
+    /// Number of processors in the chain
+    pub fn len(&self) -> usize {
+        self.processors.len()
+    }
+    /// Returns true if the chain has no processors
+    pub fn is_empty(&self) -> bool {
+        self.processors.is_empty()
+    }
+    /// Runs every line of a body through the chain, dropping filtered lines
+    pub fn process_body(&self, body: IngestBody) -> IngestBody {
+        IngestBody::new(
+            body.into_lines()
+                .into_iter()
+                .filter_map(|line| self.process(line))
+                .collect(),
+        )
+    }
+}
+
+impl LineProcessor for ProcessorChain {
+    fn process(&self, line: Line) -> Option<Line> {
+        self.processors
+            .iter()
+            .try_fold(line, |line, processor| processor.process(line))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn line(level: &str) -> Line {
+        Line::builder().line("test").level(level).build().unwrap()
+    }
+
+    #[test]
+    fn chain_filters_and_mutates_in_order() {
+        let mut chain = ProcessorChain::new();
+        chain
+            .push(Map(|line: &mut Line| line.line.push_str(" first")))
+            .push(Filter(|line: &Line| line.level.as_deref() != Some("DEBUG")))
+            .push(|mut line: Line| {
+                line.line.push_str(" second");
+                Some(line)
+            });
+
+        assert_eq!(chain.len(), 3);
+        assert!(chain.process(line("DEBUG")).is_none());
+        assert_eq!(
+            chain.process(line("INFO")).unwrap().line,
+            "test first second"
+        );
+    }
+
+    #[test]
+    fn process_body_drops_lines() {
+        let mut chain = ProcessorChain::new();
+        chain.push(Filter(|line: &Line| line.level.as_deref() == Some("ERROR")));
+
+        let body = IngestBody::new(vec![line("INFO"), line("ERROR"), line("DEBUG")]);
+        let body = chain.process_body(body);
+        assert_eq!(body.lines().len(), 1);
+        assert_eq!(body.lines()[0].level.as_deref(), Some("ERROR"));
+    }
+
+    #[test]
+    fn empty_chain_passes_through() {
+        let chain = ProcessorChain::new();
+        let line = line("INFO");
+        assert_eq!(chain.process(line.clone()), Some(line));
+    }
+}
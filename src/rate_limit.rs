@@ -0,0 +1,216 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::runtime::Timer;
+
+/// What the client does when a body exceeds the available tokens
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitMode {
+    /// Wait until enough tokens are available
+    Wait,
+    /// Fail the send with [`HttpError::RateLimited`](crate::error::HttpError::RateLimited)
+    Reject,
+}
+
+/// A token bucket refilling at a constant rate up to its capacity
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    fn new(rate: u64, burst: u64) -> Self {
+        let capacity = burst.max(1) as f64;
+        Self {
+            rate: rate.max(1) as f64,
+            capacity,
+            state: Mutex::new((capacity, Instant::now())),
+        }
+    }
+
+    /// Refills the bucket, returning the time until `amount` tokens are available
+    fn refill(state: &mut (f64, Instant), rate: f64, capacity: f64, amount: f64) -> Duration {
+        let now = Instant::now();
+        state.0 = capacity.min(state.0 + now.duration_since(state.1).as_secs_f64() * rate);
+        state.1 = now;
+        if state.0 >= amount {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs_f64((amount - state.0) / rate)
+        }
+    }
+}
+
+/// Caps the lines and bytes per second sent by a client
+///
+/// Each limit is a token bucket which holds up to a burst worth of tokens, one second of the
+/// rate by default. A body larger than the burst is let through once the bucket is full, so it
+/// can never block forever.
+///
+/// # Example
+///
+/// ```rust
+/// # use logdna_client::rate_limit::{RateLimitMode, RateLimiter};
+/// let limiter = RateLimiter::new()
+///     .lines_per_sec(10_000)
+///     .bytes_per_sec(2 * 1024 * 1024)
+///     .mode(RateLimitMode::Reject);
+/// assert!(limiter.try_acquire(500, 64 * 1024).is_ok());
+/// ```
+#[derive(Debug)]
+pub struct RateLimiter {
+    lines: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+    mode: RateLimitMode,
+}
+
+impl RateLimiter {
+    /// Constructs a RateLimiter without limits that waits for tokens
+    pub fn new() -> Self {
+        Self {
+            lines: None,
+            bytes: None,
+            mode: RateLimitMode::Wait,
+        }
+    }
+    /// Limit the lines sent per second
+    pub fn lines_per_sec(mut self, rate: u64) -> Self {
+        self.lines = Some(TokenBucket::new(rate, rate));
+        self
+    }
+    /// Limit the lines sent per second, allowing bursts of up to `burst` lines
+    pub fn lines_per_sec_with_burst(mut self, rate: u64, burst: u64) -> Self {
+        self.lines = Some(TokenBucket::new(rate, burst));
+        self
+    }
+    /// Limit the serialized bytes sent per second
+    pub fn bytes_per_sec(mut self, rate: u64) -> Self {
+        self.bytes = Some(TokenBucket::new(rate, rate));
+        self
+    }
+    /// Limit the serialized bytes sent per second, allowing bursts of up to `burst` bytes
+    pub fn bytes_per_sec_with_burst(mut self, rate: u64, burst: u64) -> Self {
+        self.bytes = Some(TokenBucket::new(rate, burst));
+        self
+    }
+    /// Set whether sends wait for tokens or are rejected, default is to wait
+    pub fn mode(mut self, mode: RateLimitMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Returns true if the lines sent per second are limited
+    pub(crate) fn limits_lines(&self) -> bool {
+        self.lines.is_some()
+    }
+
+    /// Takes tokens for a body, or returns how long to wait until they are available
+    ///
+    /// Tokens are only taken if both limits allow the body.
+    pub fn try_acquire(&self, lines: usize, bytes: usize) -> Result<(), Duration> {
+        let mut lines_state = self.lines.as_ref().map(|b| b.state.lock().unwrap());
+        let mut bytes_state = self.bytes.as_ref().map(|b| b.state.lock().unwrap());
+
+        let mut wait = Duration::from_secs(0);
+        let mut amounts = (0.0, 0.0);
+        if let (Some(bucket), Some(state)) = (self.lines.as_ref(), lines_state.as_mut()) {
+            amounts.0 = bucket.capacity.min(lines as f64);
+            wait = wait.max(TokenBucket::refill(
+                state,
+                bucket.rate,
+                bucket.capacity,
+                amounts.0,
+            ));
+        }
+        if let (Some(bucket), Some(state)) = (self.bytes.as_ref(), bytes_state.as_mut()) {
+            amounts.1 = bucket.capacity.min(bytes as f64);
+            wait = wait.max(TokenBucket::refill(
+                state,
+                bucket.rate,
+                bucket.capacity,
+                amounts.1,
+            ));
+        }
+        if wait > Duration::from_secs(0) {
+            return Err(wait);
+        }
+
+        if let Some(state) = lines_state.as_mut() {
+            state.0 -= amounts.0;
+        }
+        if let Some(state) = bytes_state.as_mut() {
+            state.0 -= amounts.1;
+        }
+        Ok(())
+    }
+
+    /// Takes tokens for a body, waiting for them unless the mode is [`RateLimitMode::Reject`]
+    #[cfg_attr(not(feature = "client"), allow(dead_code))]
+    pub(crate) async fn acquire(
+        &self,
+        timer: &dyn Timer,
+        lines: usize,
+        bytes: usize,
+    ) -> Result<(), Duration> {
+        loop {
+            match self.try_acquire(lines, bytes) {
+                Ok(()) => return Ok(()),
+                Err(wait) if self.mode == RateLimitMode::Reject => return Err(wait),
+                Err(wait) => timer.sleep(wait).await,
+            }
+        }
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unlimited_by_default() {
+        let limiter = RateLimiter::new();
+        for _ in 0..1000 {
+            assert!(limiter.try_acquire(usize::MAX, usize::MAX).is_ok());
+        }
+    }
+
+    #[test]
+    fn waits_once_burst_is_used() {
+        let limiter = RateLimiter::new().lines_per_sec(100);
+        assert!(limiter.try_acquire(60, 0).is_ok());
+        let wait = limiter.try_acquire(60, 0).unwrap_err();
+        assert!(wait > Duration::from_millis(100) && wait <= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn burst_smaller_than_the_rate_caps_the_bucket() {
+        let limiter = RateLimiter::new().lines_per_sec_with_burst(1000, 10);
+        assert!(limiter.try_acquire(5, 0).is_ok());
+        let wait = limiter.try_acquire(6, 0).unwrap_err();
+        assert!(wait <= Duration::from_millis(1));
+    }
+
+    #[test]
+    fn takes_tokens_only_if_every_limit_allows() {
+        let limiter = RateLimiter::new().lines_per_sec(100).bytes_per_sec(1000);
+        assert!(limiter.try_acquire(10, 1000).is_ok());
+        assert!(limiter.try_acquire(10, 500).is_err());
+        // the rejected body did not use any line tokens
+        assert!(limiter.try_acquire(90, 0).is_ok());
+    }
+
+    #[test]
+    fn lets_oversized_bodies_through_when_full() {
+        let limiter = RateLimiter::new().bytes_per_sec(100);
+        assert!(limiter.try_acquire(0, 10_000).is_ok());
+        assert!(limiter.try_acquire(0, 1).is_err());
+    }
+}
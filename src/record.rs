@@ -0,0 +1,184 @@
+use std::collections::HashSet;
+
+use serde_json::{Map, Value};
+
+use crate::body::{KeyValueMap, LineBuilder};
+
+/// Maps [`log::Record`]s into LineBuilders
+///
+/// The formatted arguments become the line and the level its upper-case name, e.g `WARN`.
+/// The target, module path, source file and source line are stored in meta under `target`,
+/// `module_path`, `file` and `line`. With the `log-kv` feature the record's key-values are
+/// stored in meta too, except for the label keys which become labels. Fields like app or host
+/// are left for the caller to set.
+///
+/// # Example
+///
+/// ```rust
+/// # use logdna_client::record::RecordMapping;
+/// let record = log::Record::builder()
+///     .args(format_args!("listening on {}", 8080))
+///     .level(log::Level::Info)
+///     .target("server")
+///     .build();
+/// let line = RecordMapping::new()
+///     .line_builder(&record)
+///     .app("server")
+///     .build()
+///     .unwrap();
+/// assert_eq!(line.line, "listening on 8080");
+/// assert_eq!(line.level.as_deref(), Some("INFO"));
+/// assert_eq!(line.meta.unwrap()["target"], "server");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RecordMapping {
+    #[cfg_attr(not(feature = "log-kv"), allow(dead_code))]
+    label_keys: HashSet<String>,
+}
+
+impl RecordMapping {
+    /// Constructs a RecordMapping storing every key-value in meta
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Set the record keys stored as labels instead of meta
+    pub fn label_keys<T: Into<String>>(mut self, keys: impl IntoIterator<Item = T>) -> Self {
+        self.label_keys = keys.into_iter().map(Into::into).collect();
+        self
+    }
+    /// Maps a record into a LineBuilder
+    pub fn line_builder(&self, record: &log::Record<'_>) -> LineBuilder {
+        let mut meta = Map::new();
+        #[allow(unused_mut)]
+        let mut labels = KeyValueMap::new();
+        #[cfg(feature = "log-kv")]
+        {
+            let mut visitor = KeyValues {
+                label_keys: &self.label_keys,
+                labels: &mut labels,
+                meta: &mut meta,
+            };
+            // Visiting only fails if the visitor does
+            let _ = record.key_values().visit(&mut visitor);
+        }
+
+        meta.insert("target".into(), Value::from(record.target()));
+        if let Some(module_path) = record.module_path() {
+            meta.insert("module_path".into(), Value::from(module_path));
+        }
+        if let Some(file) = record.file() {
+            meta.insert("file".into(), Value::from(file));
+        }
+        if let Some(line) = record.line() {
+            meta.insert("line".into(), Value::from(line));
+        }
+        let mut builder = LineBuilder::new()
+            .line(record.args().to_string())
+            .level(record.level().as_str())
+            .meta(Value::Object(meta));
+        if !labels.is_empty() {
+            builder = builder.labels(labels);
+        }
+        builder
+    }
+}
+
+/// Maps a [`log::Record`] into a LineBuilder with the default [`RecordMapping`]
+///
+/// [`TryFrom`](std::convert::TryFrom) is available through the blanket impl.
+impl From<&log::Record<'_>> for LineBuilder {
+    fn from(record: &log::Record<'_>) -> Self {
+        RecordMapping::new().line_builder(record)
+    }
+}
+
+#[cfg(feature = "log-kv")]
+struct KeyValues<'a> {
+    label_keys: &'a HashSet<String>,
+    labels: &'a mut KeyValueMap,
+    meta: &'a mut Map<String, Value>,
+}
+
+#[cfg(feature = "log-kv")]
+impl<'kvs> log::kv::VisitSource<'kvs> for KeyValues<'_> {
+    fn visit_pair(
+        &mut self,
+        key: log::kv::Key<'kvs>,
+        value: log::kv::Value<'kvs>,
+    ) -> Result<(), log::kv::Error> {
+        let key = key.as_str();
+        if self.label_keys.contains(key) {
+            self.labels.insert(key.to_string(), value.to_string());
+            return Ok(());
+        }
+        let value = if let Some(b) = value.to_bool() {
+            Value::from(b)
+        } else if let Some(i) = value.to_i64() {
+            Value::from(i)
+        } else if let Some(u) = value.to_u64() {
+            Value::from(u)
+        } else if let Some(f) = value.to_f64() {
+            Value::from(f)
+        } else {
+            Value::from(value.to_string())
+        };
+        self.meta.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::TryFrom;
+
+    use super::*;
+
+    #[test]
+    fn maps_record() {
+        let record = log::Record::builder()
+            .args(format_args!("hello {}", "world"))
+            .level(log::Level::Warn)
+            .target("app::db")
+            .module_path(Some("app::db::pool"))
+            .file(Some("src/db/pool.rs"))
+            .line(Some(42))
+            .build();
+        let line = LineBuilder::try_from(&record).unwrap().build().unwrap();
+        assert_eq!(line.line, "hello world");
+        assert_eq!(line.level.as_deref(), Some("WARN"));
+        assert_eq!(
+            line.meta.unwrap(),
+            serde_json::json!({
+                "target": "app::db",
+                "module_path": "app::db::pool",
+                "file": "src/db/pool.rs",
+                "line": 42
+            })
+        );
+    }
+
+    #[cfg(feature = "log-kv")]
+    #[test]
+    fn maps_key_values() {
+        let kvs: &[(&str, log::kv::Value<'_>)] = &[
+            ("tenant", log::kv::Value::from("acme")),
+            ("attempt", log::kv::Value::from(3)),
+            ("cached", log::kv::Value::from(true)),
+        ];
+        let record = log::Record::builder()
+            .args(format_args!("query"))
+            .target("app")
+            .key_values(&kvs)
+            .build();
+        let line = RecordMapping::new()
+            .label_keys(vec!["tenant"])
+            .line_builder(&record)
+            .build()
+            .unwrap();
+        assert_eq!(line.labels.unwrap()["tenant"], "acme");
+        assert_eq!(
+            line.meta.unwrap(),
+            serde_json::json!({"target": "app", "attempt": 3, "cached": true})
+        );
+    }
+}
@@ -0,0 +1,266 @@
+use http::header::{AUTHORIZATION, LOCATION};
+use http::{HeaderMap, StatusCode, Uri};
+
+/// Decides whether the client follows `307`/`308` redirects from the ingest host
+///
+/// Only redirects preserving the method are followed, the encoded body is sent again to the new
+/// location. The ingestion key is only sent along while the location keeps the scheme, host and
+/// port of the original request, it is dropped for the rest of the redirects once they change.
+/// By default no redirect is followed, with [`RedirectPolicy::limited`] only redirects to the
+/// same host are followed unless allowed with [`RedirectPolicy::same_host_only`]. A redirect
+/// from https to http is never followed.
+///
+/// # Example
+///
+/// ```rust
+/// # use logdna_client::redirect::RedirectPolicy;
+/// let policy = RedirectPolicy::limited(3).same_host_only(false);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RedirectPolicy {
+    max_redirects: usize,
+    same_host_only: bool,
+}
+
+impl RedirectPolicy {
+    /// A policy surfacing every redirect as a failed response
+    pub fn none() -> Self {
+        Self {
+            max_redirects: 0,
+            same_host_only: true,
+        }
+    }
+    /// A policy following up to `max_redirects` redirects to the same host
+    pub fn limited(max_redirects: usize) -> Self {
+        Self {
+            max_redirects,
+            same_host_only: true,
+        }
+    }
+    /// Set whether redirects to other hosts are refused, default is true
+    pub fn same_host_only(mut self, same_host_only: bool) -> Self {
+        self.same_host_only = same_host_only;
+        self
+    }
+
+    /// The location to send the body to next, None if the response is final
+    pub(crate) fn follow(
+        &self,
+        uri: &Uri,
+        status: StatusCode,
+        headers: &HeaderMap,
+        redirects: usize,
+    ) -> Option<Uri> {
+        if status != StatusCode::TEMPORARY_REDIRECT && status != StatusCode::PERMANENT_REDIRECT {
+            return None;
+        }
+        if redirects >= self.max_redirects {
+            return None;
+        }
+        let location = resolve(uri, headers.get(LOCATION)?.to_str().ok()?)?;
+
+        if uri.scheme_str() == Some("https") && location.scheme_str() != Some("https") {
+            log::warn!("refusing to follow redirect from https to {}", location);
+            return None;
+        }
+        if self.same_host_only && location.host() != uri.host() {
+            log::warn!("refusing to follow redirect to another host {}", location);
+            return None;
+        }
+        Some(location)
+    }
+}
+
+/// Whether two uris share their scheme, host and port
+pub(crate) fn same_origin(a: &Uri, b: &Uri) -> bool {
+    a.scheme() == b.scheme() && a.host() == b.host() && port(a) == port(b)
+}
+
+/// Removes the headers carrying the ingestion key
+pub(crate) fn strip_credentials(headers: &mut HeaderMap) {
+    headers.remove("apikey");
+    headers.remove(AUTHORIZATION);
+}
+
+fn port(uri: &Uri) -> Option<u16> {
+    uri.port_u16().or_else(|| match uri.scheme_str() {
+        Some("https") => Some(443),
+        Some("http") => Some(80),
+        _ => None,
+    })
+}
+
+// Resolves a Location value, which may be relative, against the uri that was redirected
+fn resolve(uri: &Uri, location: &str) -> Option<Uri> {
+    let location = location.split('#').next()?;
+    if location.starts_with("//") {
+        return format!("{}:{}", uri.scheme_str()?, location).parse().ok();
+    }
+    if let Ok(absolute) = location.parse::<Uri>() {
+        if absolute.scheme().is_some() {
+            return Some(absolute);
+        }
+    }
+    let path_and_query = if location.starts_with('/') {
+        location.to_string()
+    } else if location.starts_with('?') {
+        format!("{}{}", uri.path(), location)
+    } else {
+        let base = uri.path();
+        let dir = base.rfind('/').map_or("/", |i| &base[..=i]);
+        format!("{}{}", dir, location)
+    };
+    Uri::builder()
+        .scheme(uri.scheme()?.clone())
+        .authority(uri.authority()?.clone())
+        .path_and_query(remove_dot_segments(&path_and_query))
+        .build()
+        .ok()
+}
+
+// Drops the `.` and `..` segments of a path, see RFC 3986 section 5.2.4
+fn remove_dot_segments(path_and_query: &str) -> String {
+    let (path, query) = match path_and_query.find('?') {
+        Some(i) => path_and_query.split_at(i),
+        None => (path_and_query, ""),
+    };
+    let mut segments = Vec::new();
+    for segment in path.split('/').skip(1) {
+        match segment {
+            "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    // a trailing dot segment still names a directory
+    if path.ends_with("/.") || path.ends_with("/..") {
+        segments.push("");
+    }
+    format!("/{}{}", segments.join("/"), query)
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn headers(location: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(LOCATION, location.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn follows_same_host_redirects() {
+        let uri: Uri = "https://logs.logdna.com/logs/ingest?hostname=a"
+            .parse()
+            .unwrap();
+        let policy = RedirectPolicy::limited(2);
+        let redirect = StatusCode::TEMPORARY_REDIRECT;
+
+        assert_eq!(
+            policy.follow(&uri, redirect, &headers("/v2/ingest?hostname=a"), 0),
+            Some(
+                "https://logs.logdna.com/v2/ingest?hostname=a"
+                    .parse()
+                    .unwrap()
+            )
+        );
+        assert_eq!(
+            policy.follow(&uri, StatusCode::PERMANENT_REDIRECT, &headers("/v2"), 1),
+            Some("https://logs.logdna.com/v2".parse().unwrap())
+        );
+        assert_eq!(policy.follow(&uri, redirect, &headers("/v2"), 2), None);
+        assert_eq!(
+            policy.follow(&uri, StatusCode::FOUND, &headers("/v2"), 0),
+            None
+        );
+        assert_eq!(
+            RedirectPolicy::none().follow(&uri, redirect, &headers("/v2"), 0),
+            None
+        );
+    }
+
+    #[test]
+    fn resolves_relative_locations() {
+        let uri: Uri = "https://logs.logdna.com/logs/ingest?hostname=a"
+            .parse()
+            .unwrap();
+        let policy = RedirectPolicy::limited(1).same_host_only(false);
+        let follow = |location: &str| {
+            policy
+                .follow(&uri, StatusCode::TEMPORARY_REDIRECT, &headers(location), 0)
+                .map(|location| location.to_string())
+        };
+
+        assert_eq!(
+            follow("v2?hostname=a").as_deref(),
+            Some("https://logs.logdna.com/logs/v2?hostname=a")
+        );
+        assert_eq!(
+            follow("../v2/./ingest").as_deref(),
+            Some("https://logs.logdna.com/v2/ingest")
+        );
+        assert_eq!(
+            follow("?hostname=b").as_deref(),
+            Some("https://logs.logdna.com/logs/ingest?hostname=b")
+        );
+        assert_eq!(
+            follow("//logs.eu.logdna.com/logs/ingest#top").as_deref(),
+            Some("https://logs.eu.logdna.com/logs/ingest")
+        );
+    }
+
+    #[test]
+    fn credentials_stay_with_the_origin() {
+        let uri: Uri = "https://logs.logdna.com/logs/ingest".parse().unwrap();
+        let same = "https://logs.logdna.com:443/v2".parse().unwrap();
+        assert!(same_origin(&uri, &same));
+        for other in [
+            "https://logs.eu.logdna.com/logs/ingest",
+            "https://logs.logdna.com:8443/logs/ingest",
+        ] {
+            assert!(!same_origin(&uri, &other.parse().unwrap()));
+        }
+
+        let mut headers = headers("/v2");
+        headers.insert("apikey", "secret".parse().unwrap());
+        headers.insert(AUTHORIZATION, "Token secret".parse().unwrap());
+        strip_credentials(&mut headers);
+        assert_eq!(headers.len(), 1);
+    }
+
+    #[test]
+    fn refuses_other_hosts_and_downgrades() {
+        let uri: Uri = "https://logs.logdna.com/logs/ingest".parse().unwrap();
+        let redirect = StatusCode::TEMPORARY_REDIRECT;
+        let other_host = headers("https://logs.eu.logdna.com/logs/ingest");
+
+        assert_eq!(
+            RedirectPolicy::limited(1).follow(&uri, redirect, &other_host, 0),
+            None
+        );
+        assert_eq!(
+            RedirectPolicy::limited(1)
+                .same_host_only(false)
+                .follow(&uri, redirect, &other_host, 0),
+            Some("https://logs.eu.logdna.com/logs/ingest".parse().unwrap())
+        );
+        assert_eq!(
+            RedirectPolicy::limited(1).follow(
+                &uri,
+                redirect,
+                &headers("http://logs.logdna.com/logs/ingest"),
+                0
+            ),
+            None
+        );
+    }
+}
@@ -1,13 +1,17 @@
 use std::convert::{Into, TryInto};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
 use async_compression::futures::write::GzipEncoder;
 use async_compression::Level;
 use derivative::Derivative;
 use futures::io::AsyncWriteExt;
+use http::header::HeaderName;
 use http::header::HeaderValue;
 use http::header::ACCEPT_CHARSET;
+use http::header::ACCEPT_ENCODING;
 use http::header::CONTENT_ENCODING;
+use http::header::CONTENT_LENGTH;
 use http::header::CONTENT_TYPE;
 use http::header::USER_AGENT;
 use http::request::Builder as RequestBuilder;
@@ -15,9 +19,20 @@ use http::Method;
 use hyper::Request;
 use time::OffsetDateTime;
 
-use crate::error::{RequestError, TemplateError};
+use crate::error::{BodyError, RequestError, TemplateError};
+#[cfg(feature = "parallel-gzip")]
+use crate::parallel_gzip::ParallelGzip;
 use crate::params::Params;
-use crate::segmented_buffer::{AllocBufferFn, Buffer};
+use crate::segmented_buffer::{
+    alloc_buffer_fn, AllocBufferFn, Buffer, SegmentAllocator, Watermarks,
+};
+use crate::trace_context::{SharedTraceContextProvider, TraceContextProvider};
+
+/// Header carrying the id generated for every request
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Header carrying the idempotency key of the body, identical across retries of the body
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
 
 const SERIALIZATION_BUF_SEGMENT_SIZE: usize = 1024 * 16;
 
@@ -51,6 +66,20 @@ pub struct RequestTemplate {
     pub params: Params,
     /// LogDNA ingestion key
     pub api_key: String,
+    /// Payload shape and authentication, default is the classic ingest API
+    pub payload_format: PayloadFormat,
+    /// Checksum header of the sent body, default is none
+    pub checksum: Option<Checksum>,
+    /// Send a `Content-Length` instead of a chunked body, default is false
+    pub content_length: bool,
+    /// Transport requests are sent over, default is tcp
+    #[cfg(feature = "http3")]
+    pub transport: Transport,
+    /// Compresses gzip bodies above a size on several threads, default is off
+    #[cfg(feature = "parallel-gzip")]
+    pub parallel_gzip: Option<ParallelGzip>,
+    #[derivative(Debug = "ignore")]
+    trace_context: Option<SharedTraceContextProvider>,
 }
 
 impl RequestTemplate {
@@ -62,26 +91,92 @@ impl RequestTemplate {
     pub async fn new_request(
         &self,
         body: &crate::body::IngestBodyBuffer,
+    ) -> Result<Request<crate::body::IngestBodyBuffer>, RequestError> {
+        self.new_request_with_params(body.clone(), &self.params)
+            .await
+    }
+    /// Uses the template to create a new request, with `params` instead of the template's
+    ///
+    /// Takes the body so it can be sent as it is, without a copy, when it isn't re-encoded.
+    pub async fn new_request_with_params(
+        &self,
+        body: crate::body::IngestBodyBuffer,
+        params: &Params,
+    ) -> Result<Request<crate::body::IngestBodyBuffer>, RequestError> {
+        let mut request = self.encoded_request(body, params).await?;
+        if let Some(checksum) = self.checksum {
+            let value = checksum.digest(request.body())?;
+            request.headers_mut().insert(checksum.header_name(), value);
+        }
+        if self.content_length {
+            let len = request.body().len();
+            request
+                .headers_mut()
+                .insert(CONTENT_LENGTH, HeaderValue::from(len));
+        }
+        Ok(request)
+    }
+
+    async fn encoded_request(
+        &self,
+        body: crate::body::IngestBodyBuffer,
+        params: &Params,
     ) -> Result<Request<crate::body::IngestBodyBuffer>, RequestError> {
         let builder = RequestBuilder::new();
 
         let params = serde_urlencoded::to_string(
-            self.params
+            params
                 .clone()
                 .set_now(OffsetDateTime::now_utc().unix_timestamp()),
         )
         .expect("cant'fail!");
 
+        let content = match self.encoding {
+            Encoding::MsgPack => HeaderValue::from_static("application/msgpack"),
+            _ => self.content.clone(),
+        };
         let builder = builder
             .method(self.method.clone())
             .header(ACCEPT_CHARSET, self.charset.clone())
-            .header(CONTENT_TYPE, self.content.clone())
+            .header(ACCEPT_ENCODING, HeaderValue::from_static("gzip"))
+            .header(CONTENT_TYPE, content)
             .header(USER_AGENT, self.user_agent.clone())
-            .header("apiKey", self.api_key.clone())
+            .header(REQUEST_ID_HEADER, new_request_id())
+            .header(IDEMPOTENCY_KEY_HEADER, body.idempotency_key())
+            .header(self.payload_format.auth_header(), self.api_key.clone())
             .uri(self.schema.to_string() + &self.host + &self.endpoint + "?" + &params);
 
+        let builder = match self.trace_context.as_ref().and_then(|p| p.current()) {
+            Some(context) => {
+                let builder = builder.header("traceparent", context.traceparent_header());
+                match context.tracestate_header() {
+                    Some(tracestate) => builder.header("tracestate", tracestate),
+                    None => builder,
+                }
+            }
+            None => builder,
+        };
+
+        let body = match self.payload_format {
+            PayloadFormat::Ingest => body,
+            PayloadFormat::Pipeline => self.pipeline_body(body)?,
+        };
+
         match &self.encoding {
             Encoding::GzipJson(level) => {
+                #[cfg(feature = "parallel-gzip")]
+                if let Some(parallel) = self.parallel_gzip.filter(|p| p.applies(body.len())) {
+                    let mut body = body;
+                    let compressed = parallel.compress(body.share().clone(), *level).await?;
+                    let buf = crate::segmented_buffer::SegmentedPoolBufBuilder::new()
+                        .segment_size(SERIALIZATION_BUF_SEGMENT_SIZE)
+                        .with_pool(self.pool.clone());
+
+                    return Ok(builder
+                        .header(CONTENT_ENCODING, HeaderValue::from_static("gzip"))
+                        .body(crate::body::IngestBodyBuffer::from_frozen(compressed, buf))?);
+                }
+
                 let buf = crate::segmented_buffer::SegmentedPoolBufBuilder::new()
                     .segment_size(SERIALIZATION_BUF_SEGMENT_SIZE)
                     .initial_capacity(SERIALIZATION_BUF_SEGMENT_SIZE)
@@ -101,11 +196,140 @@ impl RequestTemplate {
                     .header(CONTENT_ENCODING, HeaderValue::from_static("gzip"))
                     .body(body)?)
             }
-            Encoding::Json => Ok(builder.body(body.clone())?),
+            Encoding::Json => Ok(builder.body(body)?),
+            Encoding::MsgPack => {
+                let mut buf = crate::segmented_buffer::SegmentedPoolBufBuilder::new()
+                    .segment_size(SERIALIZATION_BUF_SEGMENT_SIZE)
+                    .initial_capacity(SERIALIZATION_BUF_SEGMENT_SIZE)
+                    .with_pool(self.pool.clone());
+
+                // The body is always serialized as json, transcode it without buffering a Value
+                let mut deserializer = serde_json::Deserializer::from_reader(body.reader());
+                let mut serializer = rmp_serde::Serializer::new(&mut buf);
+                serde_transcode::transcode(&mut deserializer, &mut serializer)
+                    .map_err(BodyError::from)?;
+
+                let body: crate::body::IngestBodyBuffer =
+                    crate::body::IngestBodyBuffer::from_buffer(buf);
+
+                Ok(builder.body(body)?)
+            }
+        }
+    }
+
+    // Unwraps `{"lines":[...]}` into the bare array accepted by Pipeline http sources, the
+    // array keeps sharing the bytes of the serialized body
+    fn pipeline_body(
+        &self,
+        mut body: crate::body::IngestBodyBuffer,
+    ) -> Result<crate::body::IngestBodyBuffer, RequestError> {
+        let prefix = crate::serialize::body_prefix();
+        let frozen = body.share();
+        let len = frozen.len();
+        let bytes = |range: std::ops::Range<usize>| {
+            let sliced = frozen.slice(range);
+            sliced
+                .segments()
+                .iter()
+                .flat_map(|segment| segment.iter().copied())
+                .collect::<Vec<u8>>()
+        };
+        if len <= prefix.len() || bytes(0..prefix.len()) != prefix || bytes(len - 1..len) != b"}" {
+            return Err(BodyError::Payload("body is not an ingest lines object").into());
         }
+        Ok(body.slice(prefix.len()..len - 1))
     }
 }
 
+/// The shape of the request body and how the key is sent
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum PayloadFormat {
+    /// `{"lines": [...]}` with an `apiKey` header, for the LogDNA ingest API
+    #[default]
+    Ingest,
+    /// A bare `[...]` array with an `Authorization` header, for Mezmo Pipeline http sources
+    Pipeline,
+}
+
+impl PayloadFormat {
+    fn auth_header(&self) -> &'static str {
+        match self {
+            PayloadFormat::Ingest => "apiKey",
+            PayloadFormat::Pipeline => "Authorization",
+        }
+    }
+}
+
+/// The transport requests are sent over
+#[cfg(feature = "http3")]
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Transport {
+    /// HTTP/1.1 or HTTP/2 over tcp
+    #[default]
+    Tcp,
+    /// Experimental HTTP/3 over QUIC, the ingest host has to accept it on the https port
+    ///
+    /// Proxies and the connection pool settings don't apply, the whole response is read before
+    /// it is returned.
+    Http3,
+}
+
+/// Checksum of the sent body, after compression, attached as a header
+///
+/// Lets gateways detect a body truncated or corrupted in transit.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Checksum {
+    /// Base64 MD5 digest sent as `Content-MD5`
+    Md5,
+    /// Hex SHA-256 digest sent as `x-content-sha256`
+    Sha256,
+}
+
+impl Checksum {
+    fn header_name(&self) -> HeaderName {
+        match self {
+            Checksum::Md5 => HeaderName::from_static("content-md5"),
+            Checksum::Sha256 => HeaderName::from_static("x-content-sha256"),
+        }
+    }
+
+    fn digest(&self, body: &crate::body::IngestBodyBuffer) -> Result<HeaderValue, RequestError> {
+        use base64::Engine;
+        use sha2::Digest;
+
+        let mut reader = body.reader();
+        let digest = match self {
+            Checksum::Md5 => {
+                let mut hasher = md5::Md5::new();
+                std::io::copy(&mut reader, &mut hasher)?;
+                base64::engine::general_purpose::STANDARD.encode(hasher.finalize())
+            }
+            Checksum::Sha256 => {
+                let mut hasher = sha2::Sha256::new();
+                std::io::copy(&mut reader, &mut hasher)?;
+                format!("{:x}", hasher.finalize())
+            }
+        };
+        Ok(HeaderValue::from_str(&digest).expect("digest is a valid header value"))
+    }
+}
+
+// Unique within the process and unlikely to collide across processes
+fn new_request_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let nanos = OffsetDateTime::now_utc().unix_timestamp_nanos() as u64;
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!(
+        "{:016x}-{:04x}-{:08x}",
+        nanos,
+        std::process::id() & 0xffff,
+        count
+    )
+}
+
 #[test]
 fn test_builder() {}
 
@@ -121,14 +345,27 @@ pub struct TemplateBuilder {
     endpoint: String,
     params: Option<Params>,
     api_key: Option<String>,
+    payload_format: PayloadFormat,
+    checksum: Option<Checksum>,
+    content_length: bool,
+    #[cfg(feature = "http3")]
+    transport: Transport,
+    #[cfg(feature = "parallel-gzip")]
+    parallel_gzip: Option<ParallelGzip>,
+    segment_allocator: Option<SegmentAllocator>,
+    pool_watermarks: Option<Arc<Watermarks>>,
+    trace_context: Option<SharedTraceContextProvider>,
     err: Option<TemplateError>,
 }
 
 /// Represents the encoding to be used when sending an IngestRequest
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum Encoding {
     Json,
     GzipJson(Level),
+    /// MessagePack, sent as `application/msgpack`
+    MsgPack,
 }
 
 impl TemplateBuilder {
@@ -149,6 +386,16 @@ impl TemplateBuilder {
             endpoint: "/logs/ingest".into(),
             params: None,
             api_key: None,
+            payload_format: PayloadFormat::Ingest,
+            checksum: None,
+            content_length: false,
+            #[cfg(feature = "http3")]
+            transport: Transport::Tcp,
+            #[cfg(feature = "parallel-gzip")]
+            parallel_gzip: None,
+            segment_allocator: None,
+            pool_watermarks: None,
+            trace_context: None,
             err: None,
         }
     }
@@ -247,6 +494,57 @@ impl TemplateBuilder {
         self.params = Some(params.into());
         self
     }
+    /// Set the payload_format field
+    pub fn payload_format(&mut self, payload_format: PayloadFormat) -> &mut Self {
+        self.payload_format = payload_format;
+        self
+    }
+    /// Set the checksum header of the sent body
+    pub fn checksum(&mut self, checksum: Checksum) -> &mut Self {
+        self.checksum = Some(checksum);
+        self
+    }
+    /// Set whether bodies are sent with a `Content-Length` rather than chunked
+    ///
+    /// Some load balancers and firewalls in front of on-prem gateways reject chunked uploads.
+    pub fn content_length(&mut self, content_length: bool) -> &mut Self {
+        self.content_length = content_length;
+        self
+    }
+    /// Set the function allocating the segments encoded bodies are written to
+    ///
+    /// See [`SegmentedPoolBufBuilder::allocator`](crate::serialize::SegmentedPoolBufBuilder::allocator).
+    pub fn segment_allocator<F>(&mut self, allocator: F) -> &mut Self
+    where
+        F: Fn(usize) -> bytes::BytesMut + Send + Sync + 'static,
+    {
+        self.segment_allocator = Some(Arc::new(allocator));
+        self
+    }
+    /// Set the watermarks the segments of encoded bodies are counted against
+    ///
+    /// Keep a clone of the `Arc` to read [`Watermarks::live`] from the host application.
+    pub fn pool_watermarks(&mut self, watermarks: Arc<Watermarks>) -> &mut Self {
+        self.pool_watermarks = Some(watermarks);
+        self
+    }
+    /// Set the transport requests are sent over
+    #[cfg(feature = "http3")]
+    pub fn transport(&mut self, transport: Transport) -> &mut Self {
+        self.transport = transport;
+        self
+    }
+    /// Compress `Encoding::GzipJson` bodies above the configured size on several threads
+    #[cfg(feature = "parallel-gzip")]
+    pub fn parallel_gzip(&mut self, parallel_gzip: ParallelGzip) -> &mut Self {
+        self.parallel_gzip = Some(parallel_gzip);
+        self
+    }
+    /// Set a provider whose trace context is sent as `traceparent`/`tracestate` headers
+    pub fn trace_context<T: TraceContextProvider + 'static>(&mut self, provider: T) -> &mut Self {
+        self.trace_context = Some(Arc::new(provider));
+        self
+    }
     /// Build a RequestTemplate using the current builder
     pub fn build(&mut self) -> Result<RequestTemplate, TemplateError> {
         if let Some(e) = self.err.take() {
@@ -256,11 +554,11 @@ impl TemplateBuilder {
             pool: async_buf_pool::Pool::<AllocBufferFn, Buffer>::with_max_reserve(
                 SERIALIZATION_BUF_INITIAL_CAPACITY,
                 SERIALIZATION_BUF_RESERVE_SEGMENTS,
-                Arc::new(|| {
-                    Buffer::new(bytes::BytesMut::with_capacity(
-                        SERIALIZATION_BUF_SEGMENT_SIZE,
-                    ))
-                }),
+                alloc_buffer_fn(
+                    SERIALIZATION_BUF_SEGMENT_SIZE,
+                    self.segment_allocator.clone(),
+                    self.pool_watermarks.clone(),
+                ),
             )
             .unwrap(),
             method: self.method.clone(),
@@ -277,6 +575,14 @@ impl TemplateBuilder {
             api_key: self.api_key.clone().ok_or_else(|| {
                 TemplateError::RequiredField("api_key is required in a TemplateBuilder".to_string())
             })?,
+            payload_format: self.payload_format,
+            checksum: self.checksum,
+            content_length: self.content_length,
+            #[cfg(feature = "http3")]
+            transport: self.transport,
+            #[cfg(feature = "parallel-gzip")]
+            parallel_gzip: self.parallel_gzip,
+            trace_context: self.trace_context.clone(),
         })
     }
 }
@@ -289,11 +595,19 @@ impl Default for TemplateBuilder {
 
 /// Represents HTTP vs HTTPS for requests
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum Schema {
     Http,
     Https,
 }
 
+impl Schema {
+    /// Whether requests are sent over tls
+    pub fn is_tls(&self) -> bool {
+        matches!(self, Schema::Https)
+    }
+}
+
 impl std::fmt::Display for Schema {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         use crate::request::Schema::*;
@@ -342,4 +656,235 @@ mod test {
             assert_eq!(s, serde_serialized);
         }
     }
+
+    #[test]
+    fn request_template_params_override() {
+        let params = Params::builder().hostname("template-host").build().unwrap();
+        let request_template = RequestTemplate::builder()
+            .params(params)
+            .api_key("12345")
+            .build()
+            .unwrap();
+        let body: IngestBodyBuffer =
+            tokio_test::block_on(IntoIngestBodyBuffer::into(&IngestBody::new(vec![]))).unwrap();
+
+        let other = Params::builder().hostname("other-host").build().unwrap();
+        let request =
+            tokio_test::block_on(request_template.new_request_with_params(body, &other)).unwrap();
+        let query = request.uri().query().unwrap();
+        assert!(query.contains("hostname=other-host"));
+        assert!(!query.contains("template-host"));
+    }
+
+    #[test]
+    fn request_template_checksum_header() {
+        use bytes::buf::Buf;
+        use sha2::Digest;
+        use std::io::Read;
+
+        let params = Params::builder()
+            .hostname("rust-client-test")
+            .build()
+            .unwrap();
+        let request_template = RequestTemplate::builder()
+            .params(params)
+            .api_key("12345")
+            .checksum(Checksum::Sha256)
+            .build()
+            .unwrap();
+        let line = crate::body::Line::builder().line("hello").build().unwrap();
+        let body: IngestBodyBuffer =
+            tokio_test::block_on(IntoIngestBodyBuffer::into(&IngestBody::new(vec![line]))).unwrap();
+
+        let mut request = tokio_test::block_on(request_template.new_request(&body)).unwrap();
+        let header = request.headers()["x-content-sha256"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let sent = tokio_test::block_on(hyper::body::to_bytes(request.body_mut())).unwrap();
+        let mut bytes = Vec::new();
+        sent.reader().read_to_end(&mut bytes).unwrap();
+        assert_eq!(header, format!("{:x}", sha2::Sha256::digest(&bytes)));
+    }
+
+    #[test]
+    fn request_template_content_length() {
+        let params = Params::builder()
+            .hostname("rust-client-test")
+            .build()
+            .unwrap();
+        let mut builder = RequestTemplate::builder();
+        builder.params(params).api_key("12345");
+        let line = crate::body::Line::builder().line("hello").build().unwrap();
+        let body: IngestBodyBuffer =
+            tokio_test::block_on(IntoIngestBodyBuffer::into(&IngestBody::new(vec![line]))).unwrap();
+
+        let chunked = tokio_test::block_on(builder.build().unwrap().new_request(&body)).unwrap();
+        assert!(chunked.headers().get(CONTENT_LENGTH).is_none());
+
+        let sized = builder.content_length(true).build().unwrap();
+        let mut request = tokio_test::block_on(sized.new_request(&body)).unwrap();
+        let len: usize = request.headers()[CONTENT_LENGTH]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        let sent = tokio_test::block_on(hyper::body::to_bytes(request.body_mut())).unwrap();
+        assert_eq!(len, sent.len());
+    }
+
+    #[test]
+    fn request_template_msgpack_body() {
+        use bytes::buf::Buf;
+        use std::io::Read;
+
+        let params = Params::builder()
+            .hostname("rust-client-test")
+            .build()
+            .expect("Params::builder()");
+        let request_template = RequestTemplate::builder()
+            .params(params)
+            .api_key("12345")
+            .encoding(Encoding::MsgPack)
+            .build()
+            .unwrap();
+
+        let line = crate::body::Line::builder()
+            .line("hello")
+            .meta(serde_json::json!({"nested": {"count": 3}}))
+            .build()
+            .unwrap();
+        let ingest_body = IngestBody::new(vec![line]);
+        let body: IngestBodyBuffer =
+            tokio_test::block_on(IntoIngestBodyBuffer::into(&ingest_body)).unwrap();
+
+        let mut request = tokio_test::block_on(request_template.new_request(&body)).unwrap();
+        assert_eq!(request.headers()[CONTENT_TYPE], "application/msgpack");
+        assert_eq!(request.headers().get_all(CONTENT_TYPE).iter().count(), 1);
+
+        let req_body_bytes =
+            tokio_test::block_on(hyper::body::to_bytes(request.body_mut())).unwrap();
+        let mut bytes = Vec::new();
+        req_body_bytes.reader().read_to_end(&mut bytes).unwrap();
+        let decoded: serde_json::Value = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(decoded, serde_json::to_value(&ingest_body).unwrap());
+    }
+
+    #[test]
+    fn request_template_pipeline_payload() {
+        use bytes::buf::Buf;
+        use std::io::Read;
+
+        let params = Params::builder()
+            .hostname("rust-client-test")
+            .build()
+            .expect("Params::builder()");
+        let request_template = RequestTemplate::builder()
+            .params(params)
+            .api_key("12345")
+            .encoding(Encoding::Json)
+            .payload_format(PayloadFormat::Pipeline)
+            .build()
+            .unwrap();
+
+        let line = crate::body::Line::builder().line("hello").build().unwrap();
+        let ingest_body = IngestBody::new(vec![line.clone()]);
+        let body: IngestBodyBuffer =
+            tokio_test::block_on(IntoIngestBodyBuffer::into(&ingest_body)).unwrap();
+
+        let mut request = tokio_test::block_on(request_template.new_request(&body)).unwrap();
+        assert_eq!(request.headers()["Authorization"], "12345");
+        assert!(request.headers().get("apiKey").is_none());
+
+        let req_body_bytes =
+            tokio_test::block_on(hyper::body::to_bytes(request.body_mut())).unwrap();
+        let mut s = String::new();
+        req_body_bytes.reader().read_to_string(&mut s).unwrap();
+        assert_eq!(s, serde_json::to_string(&vec![line]).unwrap());
+    }
+
+    #[test]
+    fn request_template_request_ids() {
+        let params = Params::builder()
+            .hostname("rust-client-test")
+            .build()
+            .expect("Params::builder()");
+        let request_template = RequestTemplate::builder()
+            .params(params)
+            .api_key("12345")
+            .build()
+            .unwrap();
+        let body: IngestBodyBuffer =
+            tokio_test::block_on(IntoIngestBodyBuffer::into(&IngestBody::new(vec![]))).unwrap();
+
+        let first = tokio_test::block_on(request_template.new_request(&body)).unwrap();
+        let second = tokio_test::block_on(request_template.new_request(&body)).unwrap();
+        assert!(!first.headers()[REQUEST_ID_HEADER].is_empty());
+        assert_eq!(first.headers()[ACCEPT_ENCODING], "gzip");
+        assert_ne!(
+            first.headers()[REQUEST_ID_HEADER],
+            second.headers()[REQUEST_ID_HEADER]
+        );
+    }
+
+    #[test]
+    fn request_template_idempotency_key() {
+        let params = Params::builder()
+            .hostname("rust-client-test")
+            .build()
+            .expect("Params::builder()");
+        let request_template = RequestTemplate::builder()
+            .params(params)
+            .api_key("12345")
+            .build()
+            .unwrap();
+        let body: IngestBodyBuffer =
+            tokio_test::block_on(IntoIngestBodyBuffer::into(&IngestBody::new(vec![]))).unwrap();
+        let other: IngestBodyBuffer =
+            tokio_test::block_on(IntoIngestBodyBuffer::into(&IngestBody::new(vec![]))).unwrap();
+
+        let first = tokio_test::block_on(request_template.new_request(&body)).unwrap();
+        let retry = tokio_test::block_on(request_template.new_request(&body.clone())).unwrap();
+        let different = tokio_test::block_on(request_template.new_request(&other)).unwrap();
+        assert_eq!(
+            first.headers()[IDEMPOTENCY_KEY_HEADER],
+            body.idempotency_key()
+        );
+        assert_eq!(
+            first.headers()[IDEMPOTENCY_KEY_HEADER],
+            retry.headers()[IDEMPOTENCY_KEY_HEADER]
+        );
+        assert_ne!(
+            first.headers()[IDEMPOTENCY_KEY_HEADER],
+            different.headers()[IDEMPOTENCY_KEY_HEADER]
+        );
+    }
+
+    #[test]
+    fn request_template_trace_context_headers() {
+        use crate::trace_context::TraceContext;
+
+        let params = Params::builder()
+            .hostname("rust-client-test")
+            .build()
+            .expect("Params::builder()");
+        let request_template = RequestTemplate::builder()
+            .params(params)
+            .api_key("12345")
+            .trace_context(|| {
+                TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+                    .map(|context| context.tracestate("congo=t61rcWkgMzE"))
+            })
+            .build()
+            .unwrap();
+
+        let body: IngestBodyBuffer =
+            tokio_test::block_on(IntoIngestBodyBuffer::into(&IngestBody::new(vec![]))).unwrap();
+        let request = tokio_test::block_on(request_template.new_request(&body)).unwrap();
+        assert_eq!(
+            request.headers()["traceparent"],
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+        );
+        assert_eq!(request.headers()["tracestate"], "congo=t61rcWkgMzE");
+    }
 }
@@ -0,0 +1,170 @@
+use std::time::Duration;
+
+use http::{Method, Request, StatusCode, Uri};
+use log::Level;
+
+use crate::body::IngestBodyBuffer;
+use crate::debug_dump::REDACTED_HEADERS;
+use crate::request::REQUEST_ID_HEADER;
+
+/// Logs a summary of every request sent by a client through the `log` facade
+///
+/// Each record has the method, the uri, the request id, the name of the header carrying the
+/// ingestion key with its value redacted, the serialized and sent body sizes with the
+/// compression ratio, the status and the latency.
+///
+/// Nothing is logged unless enabled with
+/// [`ClientBuilder::request_log`](crate::client::ClientBuilder::request_log), [`RequestLog::new`]
+/// logs accepted requests at `debug` and failed ones at `warn`.
+///
+/// # Example
+///
+/// ```rust
+/// # use logdna_client::request_log::RequestLog;
+/// let request_log = RequestLog::new()
+///     .success_level(Some(log::Level::Info))
+///     .failure_level(Some(log::Level::Error));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestLog {
+    success_level: Option<Level>,
+    failure_level: Option<Level>,
+}
+
+impl RequestLog {
+    /// Constructs a RequestLog logging accepted requests at debug and failed ones at warn
+    pub fn new() -> Self {
+        Self {
+            success_level: Some(Level::Debug),
+            failure_level: Some(Level::Warn),
+        }
+    }
+    /// Constructs a RequestLog that logs nothing
+    pub fn disabled() -> Self {
+        Self {
+            success_level: None,
+            failure_level: None,
+        }
+    }
+    /// Set the level accepted requests are logged at, None to not log them
+    pub fn success_level(mut self, level: Option<Level>) -> Self {
+        self.success_level = level;
+        self
+    }
+    /// Set the level failed requests are logged at, None to not log them
+    pub fn failure_level(mut self, level: Option<Level>) -> Self {
+        self.failure_level = level;
+        self
+    }
+
+    /// Logs a request, `status` is None if no response was received
+    pub(crate) fn log(
+        &self,
+        request: &RequestSummary,
+        status: Option<StatusCode>,
+        latency: Duration,
+    ) {
+        let success = status.map_or(false, |status| status.is_success());
+        let level = if success {
+            self.success_level
+        } else {
+            self.failure_level
+        };
+        let level = match level {
+            Some(level) if log::log_enabled!(level) => level,
+            _ => return,
+        };
+        let status = status.map_or_else(|| "no response".to_string(), |s| s.to_string());
+        log::log!(
+            level,
+            "{} {} request_id={} auth={} bytes={} sent_bytes={} ratio={:.2} status={} latency={:?}",
+            request.method,
+            request.uri,
+            request.request_id,
+            request.auth,
+            request.bytes,
+            request.sent_bytes,
+            request.ratio(),
+            status,
+            latency
+        );
+    }
+}
+
+/// Logs nothing, like [`RequestLog::disabled`]
+impl Default for RequestLog {
+    fn default() -> Self {
+        Self::disabled()
+    }
+}
+
+/// What is logged about a request, captured before it is sent
+pub(crate) struct RequestSummary {
+    method: Method,
+    uri: Uri,
+    request_id: String,
+    // the credential headers sent, with their values redacted
+    auth: String,
+    bytes: usize,
+    sent_bytes: usize,
+}
+
+impl RequestSummary {
+    /// `bytes` is the size of the serialized body before it was encoded
+    pub(crate) fn new(request: &Request<IngestBodyBuffer>, bytes: usize) -> Self {
+        let auth = request
+            .headers()
+            .keys()
+            .filter(|name| REDACTED_HEADERS.contains(&name.as_str()))
+            .map(|name| format!("{}:REDACTED", name))
+            .collect::<Vec<_>>();
+        Self {
+            method: request.method().clone(),
+            uri: request.uri().clone(),
+            request_id: request
+                .headers()
+                .get(REQUEST_ID_HEADER)
+                .and_then(|id| id.to_str().ok())
+                .unwrap_or_default()
+                .to_string(),
+            auth: if auth.is_empty() {
+                "none".to_string()
+            } else {
+                auth.join(",")
+            },
+            bytes,
+            sent_bytes: request.body().len(),
+        }
+    }
+
+    // Sent bytes per serialized byte, lower is better compression
+    fn ratio(&self) -> f64 {
+        if self.bytes == 0 {
+            1.0
+        } else {
+            self.sent_bytes as f64 / self.bytes as f64
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::body::{IngestBody, IntoIngestBodyBuffer};
+
+    #[test]
+    fn redacts_the_key_header() {
+        let body = tokio_test::block_on(IntoIngestBodyBuffer::into(&IngestBody::new(vec![])));
+        let request = Request::post("https://logs.logdna.com/logs/ingest?hostname=a")
+            .header("apikey", "secret")
+            .header(REQUEST_ID_HEADER, "id-1")
+            .body(body.unwrap())
+            .unwrap();
+        let summary = RequestSummary::new(&request, request.body().len());
+        assert_eq!(summary.auth, "apikey:REDACTED");
+        assert_eq!(summary.request_id, "id-1");
+        assert_eq!(summary.ratio(), 1.0);
+        assert_eq!(RequestLog::default(), RequestLog::disabled());
+    }
+}
@@ -1,14 +1,176 @@
-use http::StatusCode;
+use std::time::Duration;
 
-use crate::error::HttpError;
+use async_compression::futures::bufread::GzipDecoder;
+use futures::io::AsyncReadExt;
+use http::header::CONTENT_ENCODING;
+use http::{HeaderMap, StatusCode};
+
+use crate::error::{ErrorCode, HttpError};
 
 /// A response from the LogDNA Ingest API
 #[derive(Debug, PartialEq)]
+#[non_exhaustive]
 pub enum Response {
-    Sent,
+    // contains the status code, the round-trip duration and the serialized body size in bytes
+    Sent {
+        status: StatusCode,
+        latency: Duration,
+        bytes: usize,
+    },
     // contains the failed body, a status code and a reason the request failed(String)
     Failed(Box<crate::body::IngestBodyBuffer>, StatusCode, String),
 }
 
+impl Response {
+    /// Constructs a failed response for a body
+    pub fn failed(body: crate::body::IngestBodyBuffer, status: StatusCode, reason: String) -> Self {
+        Response::Failed(Box::new(body), status, reason)
+    }
+    /// Whether the body was accepted
+    pub fn is_sent(&self) -> bool {
+        matches!(self, Response::Sent { .. })
+    }
+    /// The status code returned by the ingest API
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Response::Sent { status, .. } => *status,
+            Response::Failed(_, status, _) => *status,
+        }
+    }
+    /// Why the body was rejected, None if it was sent
+    pub fn reason(&self) -> Option<&str> {
+        match self {
+            Response::Failed(_, _, reason) => Some(reason),
+            _ => None,
+        }
+    }
+    /// The category of the rejection, None if the body was sent
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            Response::Failed(_, status, _) => Some(match status.as_u16() {
+                401 | 403 => ErrorCode::Unauthorized,
+                429 => ErrorCode::RateLimited,
+                500..=599 => ErrorCode::ServerError,
+                _ => ErrorCode::Rejected,
+            }),
+            _ => None,
+        }
+    }
+    /// Takes the rejected body, None if it was sent
+    pub fn into_failed_body(self) -> Option<crate::body::IngestBodyBuffer> {
+        match self {
+            Response::Failed(body, ..) => Some(*body),
+            _ => None,
+        }
+    }
+}
+
 /// Type alias for a response from `Client::send`
-pub type IngestResponse = Result<Response, HttpError<crate::body::IngestBodyBuffer>>;
+pub type IngestResponse = Result<Response, HttpError>;
+
+/// Decompresses an error response body according to its `Content-Encoding`
+///
+/// At most `limit` bytes are decompressed. Bodies with an unknown encoding, or which fail to
+/// decompress, are returned as received.
+#[cfg_attr(not(feature = "client"), allow(dead_code))]
+pub(crate) async fn decode_body(headers: &HeaderMap, body: &[u8], limit: usize) -> Vec<u8> {
+    let encoding = headers
+        .get(CONTENT_ENCODING)
+        .and_then(|e| e.to_str().ok())
+        .map(str::trim);
+    match encoding {
+        Some(e) if e.eq_ignore_ascii_case("gzip") || e.eq_ignore_ascii_case("x-gzip") => {
+            let mut decoded = Vec::new();
+            let result = GzipDecoder::new(body)
+                .take(limit as u64)
+                .read_to_end(&mut decoded)
+                .await;
+            match result {
+                Ok(_) => decoded,
+                // a body cut off at the size limit still decompresses up to the cut
+                Err(_) if !decoded.is_empty() => decoded,
+                Err(e) => {
+                    log::warn!("failed to decompress gzip response body: {}", e);
+                    body.to_vec()
+                }
+            }
+        }
+        _ => body.to_vec(),
+    }
+}
+
+/// Builds the reason of a failed response, cutting it to `limit` bytes
+///
+/// A truncated reason ends with [`TRUNCATED_SUFFIX`].
+#[cfg_attr(not(feature = "client"), allow(dead_code))]
+pub(crate) fn failure_reason(
+    mut body: Vec<u8>,
+    limit: usize,
+    truncated: bool,
+) -> Result<String, std::string::FromUtf8Error> {
+    if !truncated && body.len() <= limit {
+        return String::from_utf8(body);
+    }
+    body.truncate(limit);
+    // the cut may split a character, which is replaced rather than failing the whole reason
+    Ok(String::from_utf8_lossy(&body).into_owned() + TRUNCATED_SUFFIX)
+}
+
+/// Appended to the reason of a [`Response::Failed`] whose body exceeded the size limit
+pub const TRUNCATED_SUFFIX: &str = "... [truncated]";
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    fn gzip_headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_ENCODING, "gzip".parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn decodes_gzip_bodies() {
+        let json = br#"{"error":"invalid ingestion key"}"#;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(json).unwrap();
+        let gzipped = encoder.finish().unwrap();
+
+        let decoded = tokio_test::block_on(decode_body(&gzip_headers(), &gzipped, 1024));
+        assert_eq!(decoded, json.to_vec());
+        let plain = tokio_test::block_on(decode_body(&HeaderMap::new(), json, 1024));
+        assert_eq!(plain, json.to_vec());
+    }
+
+    #[test]
+    fn keeps_undecodable_bodies() {
+        let decoded = tokio_test::block_on(decode_body(&gzip_headers(), b"not gzip", 1024));
+        assert_eq!(decoded, b"not gzip".to_vec());
+    }
+
+    #[test]
+    fn truncates_oversized_reasons() {
+        assert_eq!(
+            failure_reason(b"bad key".to_vec(), 16, false).unwrap(),
+            "bad key"
+        );
+        assert_eq!(
+            failure_reason(b"bad key".to_vec(), 3, false).unwrap(),
+            format!("bad{}", TRUNCATED_SUFFIX)
+        );
+        assert_eq!(
+            failure_reason(b"bad".to_vec(), 3, true).unwrap(),
+            format!("bad{}", TRUNCATED_SUFFIX)
+        );
+        // a multi-byte character split by the cut is replaced
+        assert_eq!(
+            failure_reason("aé".as_bytes().to_vec(), 2, false).unwrap(),
+            format!("a\u{FFFD}{}", TRUNCATED_SUFFIX)
+        );
+    }
+}
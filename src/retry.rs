@@ -0,0 +1,144 @@
+use std::time::Duration;
+
+use crate::backoff::Exponential;
+use crate::error::HttpError;
+use crate::response::{IngestResponse, Response};
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+const DEFAULT_INITIAL_INTERVAL: Duration = Duration::from_millis(500);
+const DEFAULT_MAX_INTERVAL: Duration = Duration::from_secs(30);
+const DEFAULT_MAX_ELAPSED_TIME: Duration = Duration::from_secs(60);
+
+/// Decides how often and for how long [`Client::send_with_retry`](crate::client::Client::send_with_retry)
+/// retries a body
+///
+/// Retries stop at whichever comes first, the maximum number of attempts or the total retry
+/// budget (`max_elapsed_time`). A retry is never started if its backoff delay would end past
+/// the budget.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: Option<u32>,
+    max_elapsed_time: Option<Duration>,
+    initial_interval: Duration,
+    max_interval: Duration,
+    multiplier: f64,
+}
+
+impl RetryPolicy {
+    /// Constructs a RetryPolicy with 5 attempts, a 60s budget and 500ms to 30s backoff
+    pub fn new() -> Self {
+        Self {
+            max_attempts: Some(DEFAULT_MAX_ATTEMPTS),
+            max_elapsed_time: Some(DEFAULT_MAX_ELAPSED_TIME),
+            initial_interval: DEFAULT_INITIAL_INTERVAL,
+            max_interval: DEFAULT_MAX_INTERVAL,
+            multiplier: 2.0,
+        }
+    }
+    /// Set the maximum number of attempts including the first, `None` is unlimited
+    pub fn max_attempts(mut self, max_attempts: Option<u32>) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+    /// Set the total retry budget measured from the first attempt, `None` is unlimited
+    pub fn max_elapsed_time(mut self, max_elapsed_time: Option<Duration>) -> Self {
+        self.max_elapsed_time = max_elapsed_time;
+        self
+    }
+    /// Set the delay before the first retry
+    pub fn initial_interval(mut self, initial_interval: Duration) -> Self {
+        self.initial_interval = initial_interval;
+        self
+    }
+    /// Set the upper bound of the delay between retries
+    pub fn max_interval(mut self, max_interval: Duration) -> Self {
+        self.max_interval = max_interval;
+        self
+    }
+    /// Set the factor the delay grows by after every retry
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    pub(crate) fn backoff(&self) -> Exponential {
+        Exponential::new(self.initial_interval, self.max_interval)
+            .multiplier(self.multiplier)
+            .randomization_factor(0.5)
+    }
+
+    pub(crate) fn attempts_exhausted(&self, attempt: u32) -> bool {
+        self.max_attempts.map_or(false, |max| attempt >= max)
+    }
+
+    pub(crate) fn within_budget(&self, elapsed: Duration) -> bool {
+        self.max_elapsed_time.map_or(true, |max| elapsed <= max)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether a response is worth retrying, i.e a timeout, connection error, rate limit, `429` or
+/// `5xx`
+pub fn is_retryable(response: &IngestResponse) -> bool {
+    match response {
+        Ok(Response::Sent { .. }) => false,
+        Ok(Response::Failed(_, status, _)) => {
+            status.is_server_error() || *status == http::StatusCode::TOO_MANY_REQUESTS
+        }
+        Err(HttpError::Timeout(..))
+        | Err(HttpError::Send(..))
+        | Err(HttpError::RateLimited(..))
+        | Err(HttpError::Hyper(_)) => true,
+        #[cfg(feature = "http3")]
+        Err(HttpError::Http3(..)) => true,
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stops_at_max_attempts() {
+        let policy = RetryPolicy::new().max_attempts(Some(3));
+        assert!(!policy.attempts_exhausted(2));
+        assert!(policy.attempts_exhausted(3));
+        assert!(!RetryPolicy::new()
+            .max_attempts(None)
+            .attempts_exhausted(1000));
+    }
+
+    #[test]
+    fn stops_at_budget() {
+        let policy = RetryPolicy::new().max_elapsed_time(Some(Duration::from_secs(10)));
+        assert!(policy.within_budget(Duration::from_secs(10)));
+        assert!(!policy.within_budget(Duration::from_secs(11)));
+        assert!(RetryPolicy::new()
+            .max_elapsed_time(None)
+            .within_budget(Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn retries_server_errors_only() {
+        let buffer = || {
+            crate::body::IngestBodyBuffer::from_buffer(
+                crate::segmented_buffer::SegmentedPoolBufBuilder::new().build(),
+            )
+        };
+        let failed = |status| Ok(Response::Failed(Box::new(buffer()), status, String::new()));
+        assert!(is_retryable(&failed(http::StatusCode::SERVICE_UNAVAILABLE)));
+        assert!(is_retryable(&failed(http::StatusCode::TOO_MANY_REQUESTS)));
+        assert!(!is_retryable(&failed(http::StatusCode::BAD_REQUEST)));
+        assert!(!is_retryable(&Ok(Response::Sent {
+            status: http::StatusCode::OK,
+            latency: Duration::from_millis(1),
+            bytes: 0,
+        })));
+    }
+}
@@ -0,0 +1,91 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+#[cfg(feature = "client")]
+use futures::future::{select, Either};
+
+/// A boxed sleep future returned by a [`Timer`]
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// The timer used by the client for request timeouts, retry and rate limit waits and the dns
+/// resolver's backoff
+///
+/// Defaults to [`TokioTimer`], with the `futures-timer` feature [`FuturesTimer`] doesn't need
+/// Tokio's time driver. Only the client's own waits are abstracted: the http connector, with its
+/// happy eyeballs timer, and the trust-dns resolver do their io and timeouts on Tokio, so sends
+/// must still be polled within a Tokio runtime with the io and time drivers enabled.
+pub trait Timer: Send + Sync {
+    /// Returns a future that completes after the duration
+    fn sleep(&self, duration: Duration) -> Sleep;
+}
+
+/// Timer backed by `tokio::time`, requires a Tokio runtime with the time driver enabled
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioTimer;
+
+#[cfg(feature = "tokio")]
+impl Timer for TokioTimer {
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Executor independent timer backed by the `futures-timer` crate
+#[cfg(feature = "futures-timer")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FuturesTimer;
+
+#[cfg(feature = "futures-timer")]
+impl Timer for FuturesTimer {
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Box::pin(futures_timer::Delay::new(duration))
+    }
+}
+
+/// Runs the future to completion, returning None if the timer expired first
+#[cfg(feature = "client")]
+pub(crate) async fn timeout<F>(
+    timer: &dyn Timer,
+    duration: Duration,
+    future: F,
+) -> Option<F::Output>
+where
+    F: Future,
+{
+    futures::pin_mut!(future);
+    match select(future, timer.sleep(duration)).await {
+        Either::Left((output, _)) => Some(output),
+        Either::Right(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(feature = "client")]
+    #[tokio::test]
+    async fn times_out() {
+        let pending = futures::future::pending::<()>();
+        assert_eq!(
+            timeout(&TokioTimer, Duration::from_millis(1), pending).await,
+            None
+        );
+        let ready = async { 1 };
+        assert_eq!(
+            timeout(&TokioTimer, Duration::from_secs(5), ready).await,
+            Some(1)
+        );
+    }
+
+    #[cfg(all(feature = "client", feature = "futures-timer"))]
+    #[test]
+    fn futures_timer_runs_without_tokio() {
+        let pending = futures::future::pending::<()>();
+        let result =
+            futures::executor::block_on(timeout(&FuturesTimer, Duration::from_millis(1), pending));
+        assert_eq!(result, None);
+    }
+}
@@ -0,0 +1,114 @@
+use std::borrow::Cow;
+use std::fmt::Write;
+
+use crate::body::Line;
+
+/// What is done with a control character
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlChars {
+    /// The character is removed
+    Strip,
+    /// The character is replaced with a visible escape, e.g `\x1b`
+    Escape,
+}
+
+/// Removes or escapes the control characters, other than `\n` and `\t`, of line content and
+/// label values
+///
+/// Stops terminal escape sequences in ingested logs from reaching whoever views them. Add it
+/// to a [`ProcessorChain`](crate::processor::ProcessorChain) to sanitize lines before they are
+/// serialized.
+///
+/// # Example
+///
+/// ```rust
+/// # use logdna_client::sanitize::Sanitizer;
+/// assert_eq!(Sanitizer::strip().sanitize_str("\x1b[31mred\x1b[0m"), "[31mred[0m");
+/// assert_eq!(Sanitizer::escape().sanitize_str("bell\x07"), "bell\\x07");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sanitizer {
+    mode: ControlChars,
+}
+
+impl Sanitizer {
+    /// Constructs a sanitizer with the given mode
+    pub fn new(mode: ControlChars) -> Self {
+        Self { mode }
+    }
+    /// Constructs a sanitizer removing control characters
+    pub fn strip() -> Self {
+        Self::new(ControlChars::Strip)
+    }
+    /// Constructs a sanitizer escaping control characters
+    pub fn escape() -> Self {
+        Self::new(ControlChars::Escape)
+    }
+    /// Sanitizes a string, only allocating if it contained a control character
+    pub fn sanitize_str<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        if !value.chars().any(is_unsafe) {
+            return Cow::Borrowed(value);
+        }
+        let mut sanitized = String::with_capacity(value.len());
+        for c in value.chars() {
+            if !is_unsafe(c) {
+                sanitized.push(c);
+            } else if self.mode == ControlChars::Escape {
+                if c.is_ascii() {
+                    write!(sanitized, "\\x{:02x}", c as u32)
+                } else {
+                    write!(sanitized, "\\u{{{:x}}}", c as u32)
+                }
+                .expect("String write can't fail");
+            }
+        }
+        Cow::Owned(sanitized)
+    }
+    /// Sanitizes the `line` and label values of a line in place
+    pub fn sanitize_line(&self, line: &mut Line) {
+        if let Cow::Owned(sanitized) = self.sanitize_str(&line.line) {
+            line.line = sanitized;
+        }
+        if let Some(labels) = line.labels.as_mut() {
+            for value in labels.values_mut() {
+                if let Cow::Owned(sanitized) = self.sanitize_str(value) {
+                    *value = sanitized;
+                }
+            }
+        }
+    }
+}
+
+fn is_unsafe(c: char) -> bool {
+    c.is_control() && c != '\n' && c != '\t'
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::body::KeyValueMap;
+
+    #[test]
+    fn strips_and_escapes_control_chars() {
+        let value = "a\x1b[2Jb\r\n\tc\u{7f}d\u{9b}";
+        assert_eq!(Sanitizer::strip().sanitize_str(value), "a[2Jb\n\tcd");
+        assert_eq!(
+            Sanitizer::escape().sanitize_str(value),
+            "a\\x1b[2Jb\\x0d\n\tc\\x7fd\\u{9b}"
+        );
+        assert!(matches!(
+            Sanitizer::strip().sanitize_str("plain\ttext\n"),
+            Cow::Borrowed(_)
+        ));
+
+        let mut line = Line::builder()
+            .line("\x1b]0;title\x07hello")
+            .labels(KeyValueMap::new().add("k", "v\x08"))
+            .build()
+            .unwrap();
+        Sanitizer::strip().sanitize_line(&mut line);
+        assert_eq!(line.line, "]0;titlehello");
+        assert_eq!(line.labels.unwrap()["k"], "v");
+    }
+}
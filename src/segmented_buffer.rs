@@ -2,6 +2,7 @@ use std::future::Future;
 use std::io::Write;
 use std::ops::DerefMut;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering as AtomicOrdering};
 use std::sync::Arc;
 use std::task::{Context, Poll};
 
@@ -9,8 +10,9 @@ use async_buf_pool::{ClearBuf, Pool, Reusable};
 use bytes::buf::Buf;
 use bytes::buf::BufMut;
 use bytes::buf::Limit;
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
 
+use derivative::Derivative;
 use futures::AsyncWrite;
 use pin_project::pin_project;
 
@@ -21,23 +23,171 @@ const DEFAULT_SEGMENT_SIZE: usize = 1024 * 16; // 16 KB
 const SERIALIZATION_BUF_RESERVE_SEGMENTS: usize = 100;
 const EMPTY: &[u8] = &[];
 
+#[cfg(feature = "buffer-stats")]
+static COUNTING: once_cell::sync::Lazy<()> = once_cell::sync::Lazy::new(|| countme::enable(true));
+
+// Counting is switched on by the first counted value, so it is on whenever the feature is
+#[cfg(feature = "buffer-stats")]
+fn count<T: 'static>() -> countme::Count<T> {
+    once_cell::sync::Lazy::force(&COUNTING);
+    countme::Count::new()
+}
+
+/// Counts of the buffers allocated by the crate, see [`buffer_stats`](crate::serialize::buffer_stats)
+#[cfg(feature = "buffer-stats")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferStats {
+    /// Segments currently allocated, in a buffer or waiting in a pool
+    pub live_segments: usize,
+    /// The most segments allocated at once
+    pub max_live_segments: usize,
+    /// Segments allocated since startup
+    pub total_segments: usize,
+    /// Segmented buffers currently alive
+    pub live_buffers: usize,
+    /// Segmented buffers created since startup
+    pub total_buffers: usize,
+}
+
+#[cfg(feature = "buffer-stats")]
+pub(crate) fn buffer_stats() -> BufferStats {
+    let segments = countme::get::<Buffer>();
+    let buffers = countme::get::<SegmentedBuf<Reusable<Buffer>>>();
+    BufferStats {
+        live_segments: segments.live,
+        max_live_segments: segments.max_live,
+        total_segments: segments.total,
+        live_buffers: buffers.live,
+        total_buffers: buffers.total,
+    }
+}
+
 pub(crate) type AllocBufferFn = Arc<dyn Fn() -> Buffer + std::marker::Send + std::marker::Sync>;
 
+/// Allocates the memory backing a new segment, called with the segment size
+pub type SegmentAllocator = Arc<dyn Fn(usize) -> BytesMut + std::marker::Send + std::marker::Sync>;
+
 pub(crate) type BufFut =
     Pin<Box<dyn Future<Output = Option<Reusable<Buffer>>> + std::marker::Send + std::marker::Sync>>;
 
 pub struct Buffer {
     pub(crate) buf: BytesMut,
+    #[cfg(feature = "buffer-stats")]
     _c: countme::Count<Self>,
+    _live: Option<LiveSegment>,
 }
 
 impl Buffer {
     pub fn new(bm: BytesMut) -> Self {
         Buffer {
             buf: bm,
-            _c: countme::Count::new(),
+            #[cfg(feature = "buffer-stats")]
+            _c: count(),
+            _live: None,
         }
     }
+
+    // Counts the segment against the watermarks until it is dropped
+    fn watched(mut self, watermarks: Arc<Watermarks>) -> Self {
+        watermarks.allocated();
+        self._live = Some(LiveSegment(watermarks));
+        self
+    }
+}
+
+/// Calls back when the segments allocated by a pool cross a high or low watermark
+///
+/// `on_high` fires once the pool holds `high` segments, in buffers or waiting for reuse, and
+/// `on_low` once it has dropped back down to `low`, so a host can shed load or page operators
+/// before writes start failing with `BufferFull`. Each callback is passed the number of live
+/// segments and fires once per crossing.
+///
+/// # Example
+///
+/// ```rust
+/// # use logdna_client::serialize::Watermarks;
+/// let watermarks = Watermarks::new(64, 512)
+///     .on_high(|live| log::warn!("{} segments allocated, shedding load", live))
+///     .on_low(|live| log::info!("back down to {} segments", live));
+/// ```
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct Watermarks {
+    low: usize,
+    high: usize,
+    #[derivative(Debug = "ignore")]
+    on_high: Option<WatermarkFn>,
+    #[derivative(Debug = "ignore")]
+    on_low: Option<WatermarkFn>,
+    live: AtomicUsize,
+    above: AtomicBool,
+}
+
+type WatermarkFn = Box<dyn Fn(usize) + std::marker::Send + std::marker::Sync>;
+
+impl Watermarks {
+    /// Constructs Watermarks for `low` and `high` live segments, without callbacks
+    pub fn new(low: usize, high: usize) -> Self {
+        Self {
+            low: low.min(high),
+            high,
+            on_high: None,
+            on_low: None,
+            live: AtomicUsize::new(0),
+            above: AtomicBool::new(false),
+        }
+    }
+    /// Set the function called when live segments reach the high watermark
+    pub fn on_high<F>(mut self, on_high: F) -> Self
+    where
+        F: Fn(usize) + std::marker::Send + std::marker::Sync + 'static,
+    {
+        self.on_high = Some(Box::new(on_high));
+        self
+    }
+    /// Set the function called when live segments fall back to the low watermark
+    pub fn on_low<F>(mut self, on_low: F) -> Self
+    where
+        F: Fn(usize) + std::marker::Send + std::marker::Sync + 'static,
+    {
+        self.on_low = Some(Box::new(on_low));
+        self
+    }
+
+    /// The segments currently allocated by the pools using these watermarks
+    pub fn live(&self) -> usize {
+        self.live.load(AtomicOrdering::Relaxed)
+    }
+    /// Whether the high watermark was reached and live segments haven't fallen back to low
+    pub fn is_high(&self) -> bool {
+        self.above.load(AtomicOrdering::Relaxed)
+    }
+
+    fn allocated(&self) {
+        let live = self.live.fetch_add(1, AtomicOrdering::Relaxed) + 1;
+        if live >= self.high && !self.above.swap(true, AtomicOrdering::AcqRel) {
+            if let Some(on_high) = self.on_high.as_ref() {
+                on_high(live);
+            }
+        }
+    }
+
+    fn released(&self) {
+        let live = self.live.fetch_sub(1, AtomicOrdering::Relaxed) - 1;
+        if live <= self.low && self.above.swap(false, AtomicOrdering::AcqRel) {
+            if let Some(on_low) = self.on_low.as_ref() {
+                on_low(live);
+            }
+        }
+    }
+}
+
+struct LiveSegment(Arc<Watermarks>);
+
+impl Drop for LiveSegment {
+    fn drop(&mut self) {
+        self.0.released();
+    }
 }
 
 impl Buffer {
@@ -101,6 +251,7 @@ impl Buf for Buffer {
 // TODO: expose size when const generics become available
 #[derive(PartialEq)]
 pub struct SegmentedBuf<T> {
+    #[cfg(feature = "buffer-stats")]
     _c: countme::Count<Self>,
     pub(crate) bufs: SmallVec<[T; 4]>,
     pos: usize,
@@ -113,7 +264,8 @@ pub struct SegmentedBuf<T> {
 impl<T> SegmentedBuf<T> {
     pub fn new() -> Self {
         Self {
-            _c: countme::Count::new(),
+            #[cfg(feature = "buffer-stats")]
+            _c: count(),
             bufs: SmallVec::new(),
             pos: 0,
             offset: 0,
@@ -125,7 +277,8 @@ impl<T> SegmentedBuf<T> {
 
     pub fn with_segment_size(segment_size: usize) -> Self {
         Self {
-            _c: countme::Count::new(),
+            #[cfg(feature = "buffer-stats")]
+            _c: count(),
             bufs: SmallVec::new(),
             pos: 0,
             offset: 0,
@@ -317,6 +470,37 @@ impl futures::io::AsyncBufRead for SegmentedBuf<Reusable<Buffer>> {
     }
 }
 
+#[cfg(feature = "tokio-io")]
+impl tokio::io::AsyncRead for SegmentedBuf<Reusable<Buffer>> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        while buf.remaining() > 0 {
+            let chunk = self.chunk();
+            let n = chunk.len().min(buf.remaining());
+            if n == 0 {
+                break;
+            }
+            buf.put_slice(&chunk[..n]);
+            self.deref_mut().advance(n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "tokio-io")]
+impl tokio::io::AsyncBufRead for SegmentedBuf<Reusable<Buffer>> {
+    fn poll_fill_buf(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<&[u8]>> {
+        Poll::Ready(Ok(self.get_mut().chunk()))
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        self.deref_mut().advance(amt)
+    }
+}
+
 #[pin_project]
 pub struct SegmentedPoolBuf<Fut, T, Fi>
 where
@@ -376,6 +560,79 @@ impl<F> SegmentedPoolBuf<F, Buffer, AllocBufferFn> {
         self.buf.is_empty()
     }
 
+    /// The bytes the attached segments can hold without pulling more from the pool
+    pub fn capacity(&self) -> usize {
+        self.buf
+            .bufs
+            .iter()
+            .map(|segment| segment.buf.capacity())
+            .sum()
+    }
+
+    /// The number of segments attached, written or not
+    pub fn segment_count(&self) -> usize {
+        self.buf.bufs.len()
+    }
+
+    /// Discards the written bytes, keeping the segments attached for the next writes
+    pub fn clear(&mut self) {
+        for segment in self.buf.bufs.iter_mut() {
+            segment.deref_mut().clear();
+        }
+        self.buf.pos = 0;
+        self.buf.offset = 0;
+        self.buf.reset_read();
+    }
+
+    /// Returns attached segments beyond `n_segments` to the pool
+    ///
+    /// Segments holding written bytes, and the one being written to, are kept, so a buffer
+    /// reused after one large batch doesn't hold on to its worst case memory.
+    pub fn shrink_to(&mut self, n_segments: usize) {
+        let in_use = if self.buf.bufs.is_empty() {
+            0
+        } else {
+            self.buf.pos.max(self.buf.read_pos) + 1
+        };
+        // dropped segments go back to the pool
+        self.buf.bufs.truncate(n_segments.max(in_use));
+    }
+
+    /// Moves the written bytes into an immutable [`FrozenBuf`]
+    ///
+    /// Clones of the frozen buffer share its segments instead of copying them. The written
+    /// memory leaves the pool, see [`take_frozen`](Self::take_frozen).
+    pub fn freeze(mut self) -> FrozenBuf {
+        self.take_frozen()
+    }
+
+    /// Moves the written bytes into a [`FrozenBuf`], leaving this buffer empty
+    ///
+    /// The written memory now belongs to the frozen buffer, so every emptied segment is given
+    /// a full segment of capacity again before it goes back to the pool.
+    pub fn take_frozen(&mut self) -> FrozenBuf {
+        let segment_size = self.buf.segment_size;
+        let frozen = FrozenBuf {
+            segments: self
+                .buf
+                .bufs
+                .iter_mut()
+                .map(|segment| {
+                    let buf = &mut segment.deref_mut().buf;
+                    let frozen = buf.split().freeze();
+                    // without this the pool would hand out segments with no capacity left
+                    buf.reserve(segment_size);
+                    frozen
+                })
+                .filter(|segment| !segment.is_empty())
+                .collect(),
+            pos: 0,
+        };
+        self.buf.bufs.clear();
+        self.clear();
+        frozen
+    }
+
     fn duplicate(&self) -> Self {
         let buf = SegmentedBuf::with_segment_size(self.buf.segment_size);
         Self {
@@ -517,10 +774,14 @@ impl AsyncWrite for SegmentedPoolBuf<BufFut, Buffer, AllocBufferFn> {
     }
 }
 
+#[derive(Clone)]
 pub struct SegmentedPoolBufBuilder {
     initial_capacity: Option<usize>,
     segment_size: Option<usize>,
     max_size: Option<usize>,
+    max_reserve: Option<usize>,
+    allocator: Option<SegmentAllocator>,
+    watermarks: Option<Arc<Watermarks>>,
 }
 
 impl SegmentedPoolBufBuilder {
@@ -529,6 +790,9 @@ impl SegmentedPoolBufBuilder {
             initial_capacity: None,
             segment_size: None,
             max_size: None,
+            max_reserve: Some(SERIALIZATION_BUF_RESERVE_SEGMENTS),
+            allocator: None,
+            watermarks: None,
         }
     }
 
@@ -548,17 +812,61 @@ impl SegmentedPoolBufBuilder {
         self
     }
 
+    /// Set the function allocating new segments, instead of the global allocator
+    ///
+    /// Lets embedders carve segments out of an arena, huge pages or buffers registered with
+    /// the kernel. It is called with the segment size and should return a buffer with at least
+    /// that capacity. Only applies to the pool created by [`build`](Self::build).
+    pub fn allocator<F>(mut self, allocator: F) -> Self
+    where
+        F: Fn(usize) -> BytesMut + std::marker::Send + std::marker::Sync + 'static,
+    {
+        self.allocator = Some(Arc::new(allocator));
+        self
+    }
+
+    /// Set the watermarks the segments allocated by the pool are counted against
+    ///
+    /// Like the allocator, only applies to the pool created by [`build`](Self::build).
+    pub fn watermarks(mut self, watermarks: Arc<Watermarks>) -> Self {
+        self.watermarks = Some(watermarks);
+        self
+    }
+
+    /// Set how many returned segments the pool keeps for reuse, None keeps them all
+    ///
+    /// Default is 100 segments, only applies to the pool created by [`build`](Self::build).
+    pub fn max_reserve(mut self, max_reserve: Option<usize>) -> Self {
+        self.max_reserve = max_reserve;
+        self
+    }
+
     pub fn build(self) -> SegmentedPoolBuf<BufFut, Buffer, AllocBufferFn> {
-        let segment_size = self.segment_size.unwrap_or(DEFAULT_SEGMENT_SIZE);
-        let pool =
-            Pool::<Arc<dyn Fn() -> Buffer + std::marker::Send + std::marker::Sync>, Buffer>::with_max_reserve(
-                self.initial_capacity.unwrap_or(DEFAULT_SEGMENT_SIZE) / segment_size + 1,
-                SERIALIZATION_BUF_RESERVE_SEGMENTS,
-                Arc::new(move || Buffer::new(BytesMut::with_capacity(segment_size))),
-            ).unwrap();
+        let pool = self.pool();
         self.with_pool(pool)
     }
 
+    /// Creates the pool [`build`](Self::build) would, to share between several buffers
+    pub(crate) fn pool(&self) -> Pool<AllocBufferFn, Buffer> {
+        let segment_size = self.segment_size.unwrap_or(DEFAULT_SEGMENT_SIZE);
+        let initial_segments =
+            self.initial_capacity.unwrap_or(DEFAULT_SEGMENT_SIZE) / segment_size + 1;
+        let alloc = alloc_buffer_fn(
+            segment_size,
+            self.allocator.clone(),
+            self.watermarks.clone(),
+        );
+        match self.max_reserve {
+            Some(max_reserve) => Pool::<AllocBufferFn, Buffer>::with_max_reserve(
+                initial_segments,
+                max_reserve,
+                alloc,
+            )
+            .unwrap(),
+            None => Pool::<AllocBufferFn, Buffer>::new(initial_segments, alloc),
+        }
+    }
+
     pub fn with_pool(
         self,
         pool: Pool<AllocBufferFn, Buffer>,
@@ -575,12 +883,107 @@ impl SegmentedPoolBufBuilder {
     }
 }
 
+/// The pool's allocation function for segments of `segment_size`
+pub(crate) fn alloc_buffer_fn(
+    segment_size: usize,
+    allocator: Option<SegmentAllocator>,
+    watermarks: Option<Arc<Watermarks>>,
+) -> AllocBufferFn {
+    Arc::new(move || {
+        let bytes = match allocator.as_ref() {
+            Some(allocator) => allocator(segment_size),
+            None => BytesMut::with_capacity(segment_size),
+        };
+        match watermarks.as_ref() {
+            Some(watermarks) => Buffer::new(bytes).watched(watermarks.clone()),
+            None => Buffer::new(bytes),
+        }
+    })
+}
+
 impl Default for SegmentedPoolBufBuilder {
     fn default() -> Self {
         Self::new()
     }
 }
 
+/// An immutable chain of [`Bytes`] segments, cheap to clone
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrozenBuf {
+    segments: SmallVec<[Bytes; 4]>,
+    pos: usize,
+}
+
+impl FrozenBuf {
+    /// Chains already frozen segments, e.g the output of an encoder
+    #[cfg(feature = "client")]
+    pub(crate) fn from_segments<I: IntoIterator<Item = Bytes>>(segments: I) -> Self {
+        Self {
+            segments: segments
+                .into_iter()
+                .filter(|segment| !segment.is_empty())
+                .collect(),
+            pos: 0,
+        }
+    }
+
+    /// The bytes in `range` of the segments not read yet, shared rather than copied
+    #[cfg(feature = "client")]
+    pub(crate) fn slice(&self, range: std::ops::Range<usize>) -> Self {
+        let mut offset = 0;
+        Self::from_segments(self.segments().iter().filter_map(|segment| {
+            let (seg_start, seg_end) = (offset, offset + segment.len());
+            offset = seg_end;
+            let start = range.start.clamp(seg_start, seg_end);
+            let end = range.end.clamp(seg_start, seg_end);
+            (start < end).then(|| segment.slice(start - seg_start..end - seg_start))
+        }))
+    }
+
+    /// The segments not read yet
+    pub fn segments(&self) -> &[Bytes] {
+        &self.segments[self.pos..]
+    }
+
+    /// The number of bytes not read yet
+    pub fn len(&self) -> usize {
+        self.segments().iter().map(Bytes::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Buf for FrozenBuf {
+    fn remaining(&self) -> usize {
+        self.len()
+    }
+
+    fn chunk(&self) -> &[u8] {
+        self.segments()
+            .first()
+            .map_or(EMPTY, |segment| segment.as_ref())
+    }
+
+    fn advance(&mut self, mut cnt: usize) {
+        if cnt > self.remaining() {
+            panic!("cnt is larger than the remaining bytes")
+        }
+        while cnt > 0 {
+            let segment = &mut self.segments[self.pos];
+            let avail = segment.len();
+            if cnt < avail {
+                segment.advance(cnt);
+                break;
+            }
+            segment.advance(avail);
+            cnt -= avail;
+            self.pos += 1;
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct SegmentedBufBytesReader<'a> {
     buf: &'a SmallVec<[Reusable<Buffer>; 4]>,
@@ -713,6 +1116,137 @@ impl futures::io::AsyncBufRead for SegmentedBufBytesReader<'_> {
     }
 }
 
+#[cfg(feature = "tokio-io")]
+impl tokio::io::AsyncRead for SegmentedBufBytesReader<'_> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        while buf.remaining() > 0 {
+            let chunk = self.chunk();
+            let n = chunk.len().min(buf.remaining());
+            if n == 0 {
+                break;
+            }
+            buf.put_slice(&chunk[..n]);
+            self.deref_mut().advance(n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "tokio-io")]
+impl tokio::io::AsyncBufRead for SegmentedBufBytesReader<'_> {
+    fn poll_fill_buf(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<&[u8]>> {
+        Poll::Ready(Ok(self.get_mut().chunk()))
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        self.deref_mut().advance(amt)
+    }
+}
+
+/// Reads the bytes of a pooled buffer, or of one that was frozen
+pub enum BodyReader<'a> {
+    Pooled(SegmentedBufBytesReader<'a>),
+    Frozen(FrozenBuf),
+}
+
+impl Buf for BodyReader<'_> {
+    fn remaining(&self) -> usize {
+        match self {
+            BodyReader::Pooled(reader) => reader.remaining(),
+            BodyReader::Frozen(frozen) => frozen.remaining(),
+        }
+    }
+
+    fn chunk(&self) -> &[u8] {
+        match self {
+            BodyReader::Pooled(reader) => reader.chunk(),
+            BodyReader::Frozen(frozen) => frozen.chunk(),
+        }
+    }
+
+    fn advance(&mut self, cnt: usize) {
+        match self {
+            BodyReader::Pooled(reader) => reader.advance(cnt),
+            BodyReader::Frozen(frozen) => frozen.advance(cnt),
+        }
+    }
+}
+
+impl std::io::Read for BodyReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut total_written = 0;
+        while total_written < buf.len() {
+            let bytes: &[u8] = self.chunk();
+            let amt = std::cmp::min(buf.len() - total_written, bytes.len());
+            if amt == 0 {
+                break;
+            }
+            buf[total_written..total_written + amt].copy_from_slice(&bytes[..amt]);
+            self.advance(amt);
+            total_written += amt;
+        }
+        Ok(total_written)
+    }
+}
+
+impl futures::io::AsyncRead for BodyReader<'_> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<futures::io::Result<usize>> {
+        Poll::Ready(std::io::Read::read(self.get_mut(), buf))
+    }
+}
+
+impl futures::io::AsyncBufRead for BodyReader<'_> {
+    fn poll_fill_buf(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<&[u8], futures::io::Error>> {
+        Poll::Ready(Ok(self.get_mut().chunk()))
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        self.deref_mut().advance(amt)
+    }
+}
+
+#[cfg(feature = "tokio-io")]
+impl tokio::io::AsyncRead for BodyReader<'_> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        while buf.remaining() > 0 {
+            let chunk = self.chunk();
+            let n = chunk.len().min(buf.remaining());
+            if n == 0 {
+                break;
+            }
+            buf.put_slice(&chunk[..n]);
+            self.deref_mut().advance(n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "tokio-io")]
+impl tokio::io::AsyncBufRead for BodyReader<'_> {
+    fn poll_fill_buf(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<&[u8]>> {
+        Poll::Ready(Ok(self.get_mut().chunk()))
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        self.deref_mut().advance(amt)
+    }
+}
+
 pub struct SegmentedPoolBufIter<'a, F, T, Fi>
 where
     T: std::marker::Send + ClearBuf,
@@ -761,8 +1295,7 @@ where
 #[cfg(test)]
 mod test {
     use super::*;
-    use serial_test::serial;
-    use std::sync::atomic::{fence, Ordering};
+    use std::sync::atomic::Ordering;
     use tokio_util::compat::{FuturesAsyncReadCompatExt, FuturesAsyncWriteCompatExt};
 
     macro_rules! aw {
@@ -821,6 +1354,177 @@ mod test {
         assert_eq!(reader.remaining(), 0);
     }
 
+    #[test]
+    fn shrink_keeps_written_segments() {
+        let mut buf = SegmentedPoolBufBuilder::new()
+            .segment_size(1024)
+            .initial_capacity(8192)
+            .build();
+        let values: Vec<u8> = (0..2500).map(|x| (x % 256) as u8).collect();
+        buf.write_all(values.as_slice()).unwrap();
+        buf.write_all(values.as_slice()).unwrap();
+        assert_eq!(buf.segment_count(), 5);
+        assert!(buf.capacity() >= 5 * 1024);
+
+        // written segments are kept
+        buf.shrink_to(1);
+        assert_eq!(buf.segment_count(), 5);
+
+        buf.clear();
+        assert!(buf.is_empty());
+        buf.shrink_to(2);
+        assert_eq!(buf.segment_count(), 2);
+
+        buf.write_all(values.as_slice()).unwrap();
+        assert_eq!(buf.segment_count(), 3);
+        assert_eq!(buf.len(), 2500);
+        let mut written = Vec::new();
+        std::io::copy(&mut buf.buf.bytes_reader(), &mut written).unwrap();
+        assert_eq!(written, values);
+    }
+
+    #[test]
+    fn segments_come_from_the_allocator() {
+        use std::sync::atomic::AtomicUsize;
+
+        let allocated = Arc::new(AtomicUsize::new(0));
+        let counter = allocated.clone();
+        let mut buf = SegmentedPoolBufBuilder::new()
+            .segment_size(1024)
+            .initial_capacity(1024)
+            .allocator(move |size| {
+                counter.fetch_add(1, Ordering::Relaxed);
+                BytesMut::with_capacity(size)
+            })
+            .build();
+
+        let values: Vec<u8> = (0..10_000).map(|x| (x % 256) as u8).collect();
+        buf.write_all(values.as_slice()).unwrap();
+        // every attached segment was allocated by the hook
+        assert!(allocated.load(Ordering::Relaxed) >= buf.segment_count());
+        let mut written = Vec::new();
+        std::io::copy(&mut buf.buf.bytes_reader(), &mut written).unwrap();
+        assert_eq!(written, values);
+    }
+
+    #[test]
+    fn take_frozen_returns_full_segments_to_the_pool() {
+        let mut buf = SegmentedPoolBufBuilder::new()
+            .segment_size(256)
+            .initial_capacity(1024)
+            .build();
+        let values: Vec<u8> = (0..1000).map(|x| (x % 256) as u8).collect();
+        buf.write_all(values.as_slice()).unwrap();
+
+        let frozen = buf.take_frozen();
+        assert_eq!(frozen.segments().concat(), values);
+        assert_eq!(buf.segment_count(), 0);
+
+        // the segments pulled for the next body have their full capacity
+        buf.write_all(values.as_slice()).unwrap();
+        assert!(buf
+            .buf
+            .bufs
+            .iter()
+            .all(|segment| segment.buf.capacity() >= 256));
+        assert_eq!(frozen.segments().concat(), values);
+    }
+
+    #[test]
+    fn watermarks_fire_once_per_crossing() {
+        let highs = Arc::new(AtomicUsize::new(0));
+        let lows = Arc::new(AtomicUsize::new(0));
+        let (h, l) = (highs.clone(), lows.clone());
+        let watermarks = Arc::new(
+            Watermarks::new(2, 4)
+                .on_high(move |_| {
+                    h.fetch_add(1, Ordering::Relaxed);
+                })
+                .on_low(move |_| {
+                    l.fetch_add(1, Ordering::Relaxed);
+                }),
+        );
+        let alloc = alloc_buffer_fn(1024, None, Some(watermarks.clone()));
+
+        let mut segments: Vec<Buffer> = (0..6).map(|_| alloc()).collect();
+        assert_eq!(watermarks.live(), 6);
+        assert!(watermarks.is_high());
+        assert_eq!(highs.load(Ordering::Relaxed), 1);
+
+        segments.truncate(3);
+        assert_eq!(lows.load(Ordering::Relaxed), 0);
+        segments.truncate(2);
+        assert!(!watermarks.is_high());
+        assert_eq!(lows.load(Ordering::Relaxed), 1);
+
+        segments.extend((0..2).map(|_| alloc()));
+        assert_eq!(highs.load(Ordering::Relaxed), 2);
+        drop(segments);
+        assert_eq!(watermarks.live(), 0);
+        assert_eq!(lows.load(Ordering::Relaxed), 2);
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn slices_across_segments() {
+        let frozen = FrozenBuf::from_segments(vec![
+            Bytes::from_static(b"{\"li"),
+            Bytes::from_static(b"nes\":[1,"),
+            Bytes::from_static(b"2]}"),
+        ]);
+        let sliced = frozen.slice(9..frozen.len() - 1);
+        assert_eq!(sliced.segments().len(), 2);
+        assert_eq!(sliced.segments().concat(), b"[1,2]");
+        assert!(frozen.slice(3..3).is_empty());
+    }
+
+    #[test]
+    fn buffer_source_applies_builder_config() {
+        use futures::StreamExt;
+
+        let mut source = Box::pin(crate::serialize::buffer_source(
+            SegmentedPoolBufBuilder::new()
+                .segment_size(1024)
+                .initial_capacity(1024)
+                .max_capacity(Some(4096))
+                .max_reserve(None),
+        ));
+        let values: Vec<u8> = (0..10_000).map(|x| (x % 256) as u8).collect();
+
+        let mut first = aw!(source.next()).unwrap();
+        assert!(first.write_all(&values).is_err());
+        assert_eq!(first.segment_count(), 4);
+        drop(first);
+
+        // the second buffer reuses the segments the first returned to the shared pool
+        let mut second = aw!(source.next()).unwrap();
+        second.write_all(&values[..3000]).unwrap();
+        assert_eq!(second.len(), 3000);
+    }
+
+    #[test]
+    fn freeze_shares_segments() {
+        let mut buf = SegmentedPoolBufBuilder::new()
+            .segment_size(1024)
+            .initial_capacity(8192)
+            .build();
+
+        let values: Vec<u8> = (0..3000).map(|x| (x % 256) as u8).collect();
+        buf.write_all(values.as_slice()).unwrap();
+
+        let mut frozen = buf.freeze();
+        assert_eq!(frozen.segments().len(), 3);
+        let copy = frozen.clone();
+        assert_eq!(copy.segments()[0].as_ptr(), frozen.segments()[0].as_ptr());
+
+        frozen.advance(1500);
+        assert_eq!(frozen.remaining(), 1500);
+        assert_eq!(frozen.chunk(), &values[1500..2048]);
+        assert_eq!(frozen.copy_to_bytes(1500), &values[1500..]);
+        assert!(frozen.is_empty());
+        assert_eq!(copy.len(), 3000);
+    }
+
     use proptest::prelude::*;
 
     #[cfg(test)]
@@ -1041,9 +1745,12 @@ mod test {
 
     }
 
+    #[cfg(feature = "buffer-stats")]
     #[test]
-    #[serial]
+    #[serial_test::serial]
     fn write_to_segmented_bool_buf_no_garbage_in_pool() {
+        use std::sync::atomic::fence;
+
         let inp = vec![0; 16384];
 
         countme::enable(true);
@@ -1113,5 +1820,6 @@ mod test {
         fence(Ordering::SeqCst);
         let counts = countme::get::<Buffer>();
         assert!(counts.live <= 1);
+        assert!(buffer_stats().total_segments >= counts.total);
     }
 }
@@ -1,22 +1,82 @@
+use std::collections::HashMap;
 use std::io;
 use std::sync::Arc;
 
 use async_trait::async_trait;
 use bytes::BytesMut;
+use futures::StreamExt;
 use serde::{Serialize, Serializer};
 use serde_json::ser::{CharEscape, Formatter};
 use thiserror::Error;
 
-use crate::segmented_buffer::{AllocBufferFn, BufFut, Buffer, SegmentedPoolBufBuilder};
+use crate::body::IngestBodyBuffer;
+use crate::segmented_buffer::{AllocBufferFn, BufFut, Buffer};
 
 pub type IngestBuffer = crate::segmented_buffer::SegmentedPoolBuf<BufFut, Buffer, AllocBufferFn>;
 
+#[cfg(feature = "buffer-stats")]
+pub use crate::segmented_buffer::BufferStats;
+pub use crate::segmented_buffer::{
+    FrozenBuf, SegmentAllocator, SegmentedPoolBufBuilder, Watermarks,
+};
+
+/// Counts of the buffer segments allocated so far, and of those still alive
+///
+/// Only available with the `buffer-stats` feature, release builds without it don't pay for
+/// the counting.
+#[cfg(feature = "buffer-stats")]
+pub fn buffer_stats() -> BufferStats {
+    crate::segmented_buffer::buffer_stats()
+}
+
 #[derive(Debug, Error)]
 pub enum IngestLineSerializeError {
     #[error("{0}")]
     Io(#[from] std::io::Error),
     #[error("{0}")]
     SerdeError(#[from] serde_json::Error),
+    #[error("{0}")]
+    InvalidUtf8(#[from] std::string::FromUtf8Error),
+}
+
+/// The label marking lines whose invalid utf-8 was replaced, see [`Utf8Policy::ReplaceAndFlag`]
+pub const INVALID_UTF8_LABEL: &str = "invalid_utf8";
+
+/// How line content read from bytes that aren't valid utf-8 is handled
+///
+/// Applies to lines serialized from bytes, through [`SerializationProfile::utf8_policy`], and to
+/// the buffers set on a [`LineBuilder`](crate::body::LineBuilder::utf8_policy).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Utf8Policy {
+    /// Invalid sequences are replaced with U+FFFD
+    Lossy,
+    /// The line fails with an error
+    Strict,
+    /// Invalid sequences are replaced and the line gets an [`INVALID_UTF8_LABEL`] label
+    ReplaceAndFlag,
+}
+
+impl Utf8Policy {
+    /// Decodes `bytes`, returning whether the line should be flagged
+    pub(crate) fn decode(
+        self,
+        bytes: Vec<u8>,
+    ) -> Result<(String, bool), std::string::FromUtf8Error> {
+        match String::from_utf8(bytes) {
+            Ok(line) => Ok((line, false)),
+            Err(e) if self == Utf8Policy::Strict => Err(e),
+            Err(e) => Ok((
+                String::from_utf8_lossy(e.as_bytes()).into_owned(),
+                self == Utf8Policy::ReplaceAndFlag,
+            )),
+        }
+    }
+}
+
+impl Default for Utf8Policy {
+    fn default() -> Self {
+        Utf8Policy::Lossy
+    }
 }
 
 // Trait to allow a type containing Line data to serialize itself into a caller provided buffer
@@ -133,8 +193,211 @@ pub trait SerializeMap<'a, T: ?Sized + 'a> {
         'a: 'async_trait;
 }
 
+/// The fields of a log line, a simpler alternative to implementing [`IngestLineSerialize`]
+///
+/// References to any `LineData` implement `IngestLineSerialize`, so custom record types can be
+/// written with [`IngestBodySerializer::write_line`] without an intermediate
+/// [`Line`](crate::body::Line). Only `line` and `timestamp` are required, optional fields
+/// default to being left out.
+///
+/// # Example
+///
+/// ```rust
+/// # use logdna_client::serialize::LineData;
+/// struct Record {
+///     message: String,
+///     level: &'static str,
+///     time: i64,
+/// }
+///
+/// impl LineData for Record {
+///     fn line(&self) -> &[u8] {
+///         self.message.as_bytes()
+///     }
+///     fn timestamp(&self) -> i64 {
+///         self.time
+///     }
+///     fn level(&self) -> Option<&str> {
+///         Some(self.level)
+///     }
+/// }
+/// ```
+pub trait LineData {
+    /// The log message, invalid utf-8 is replaced when serialized
+    fn line(&self) -> &[u8];
+    /// Seconds since the unix epoch, like [`Line::timestamp`](crate::body::Line::timestamp)
+    fn timestamp(&self) -> i64;
+    /// The app name
+    fn app(&self) -> Option<&str> {
+        None
+    }
+    /// The environment
+    fn env(&self) -> Option<&str> {
+        None
+    }
+    /// The file the line was read from
+    fn file(&self) -> Option<&str> {
+        None
+    }
+    /// The hostname, overriding the one in the query parameters
+    fn host(&self) -> Option<&str> {
+        None
+    }
+    /// The log level
+    fn level(&self) -> Option<&str> {
+        None
+    }
+    /// Labels, indexed for search
+    fn labels(&self) -> Option<&HashMap<String, String>> {
+        None
+    }
+    /// Annotations, stored but not indexed
+    fn annotations(&self) -> Option<&HashMap<String, String>> {
+        None
+    }
+    /// Arbitrary metadata
+    fn meta(&self) -> Option<&serde_json::Value> {
+        None
+    }
+}
+
+#[async_trait]
+impl<'a, D> IngestLineSerialize<&'a str, &'a [u8], HashMap<String, String>> for &'a D
+where
+    D: LineData + Sync + ?Sized,
+{
+    type Ok = ();
+
+    fn has_annotations(&self) -> bool {
+        LineData::annotations(*self).is_some()
+    }
+    async fn annotations<'b, S>(
+        &mut self,
+        ser: &mut S,
+    ) -> Result<Self::Ok, IngestLineSerializeError>
+    where
+        S: SerializeMap<'b, HashMap<String, String>> + std::marker::Send,
+    {
+        if let Some(annotations) = LineData::annotations(*self) {
+            ser.serialize_map(annotations).await?;
+        }
+        Ok(())
+    }
+    fn has_app(&self) -> bool {
+        LineData::app(*self).is_some()
+    }
+    async fn app<S>(&mut self, writer: &mut S) -> Result<Self::Ok, IngestLineSerializeError>
+    where
+        S: SerializeStr<&'a str> + std::marker::Send,
+    {
+        if let Some(app) = LineData::app(*self) {
+            writer.serialize_str(&app).await?;
+        }
+        Ok(())
+    }
+    fn has_env(&self) -> bool {
+        LineData::env(*self).is_some()
+    }
+    async fn env<S>(&mut self, writer: &mut S) -> Result<Self::Ok, IngestLineSerializeError>
+    where
+        S: SerializeStr<&'a str> + std::marker::Send,
+    {
+        if let Some(env) = LineData::env(*self) {
+            writer.serialize_str(&env).await?;
+        }
+        Ok(())
+    }
+    fn has_file(&self) -> bool {
+        LineData::file(*self).is_some()
+    }
+    async fn file<S>(&mut self, writer: &mut S) -> Result<Self::Ok, IngestLineSerializeError>
+    where
+        S: SerializeStr<&'a str> + std::marker::Send,
+    {
+        if let Some(file) = LineData::file(*self) {
+            writer.serialize_str(&file).await?;
+        }
+        Ok(())
+    }
+    fn has_host(&self) -> bool {
+        LineData::host(*self).is_some()
+    }
+    async fn host<S>(&mut self, writer: &mut S) -> Result<Self::Ok, IngestLineSerializeError>
+    where
+        S: SerializeStr<&'a str> + std::marker::Send,
+    {
+        if let Some(host) = LineData::host(*self) {
+            writer.serialize_str(&host).await?;
+        }
+        Ok(())
+    }
+    fn has_labels(&self) -> bool {
+        LineData::labels(*self).is_some()
+    }
+    async fn labels<'b, S>(&mut self, ser: &mut S) -> Result<Self::Ok, IngestLineSerializeError>
+    where
+        S: SerializeMap<'b, HashMap<String, String>> + std::marker::Send,
+    {
+        if let Some(labels) = LineData::labels(*self) {
+            ser.serialize_map(labels).await?;
+        }
+        Ok(())
+    }
+    fn has_level(&self) -> bool {
+        LineData::level(*self).is_some()
+    }
+    async fn level<S>(&mut self, writer: &mut S) -> Result<Self::Ok, IngestLineSerializeError>
+    where
+        S: SerializeStr<&'a str> + std::marker::Send,
+    {
+        if let Some(level) = LineData::level(*self) {
+            writer.serialize_str(&level).await?;
+        }
+        Ok(())
+    }
+    fn has_meta(&self) -> bool {
+        LineData::meta(*self).is_some()
+    }
+    async fn meta<S>(&mut self, writer: &mut S) -> Result<Self::Ok, IngestLineSerializeError>
+    where
+        S: SerializeValue + std::marker::Send,
+    {
+        if let Some(meta) = LineData::meta(*self) {
+            writer.serialize(meta).await?;
+        }
+        Ok(())
+    }
+    async fn line<S>(&mut self, writer: &mut S) -> Result<Self::Ok, IngestLineSerializeError>
+    where
+        S: SerializeUtf8<&'a [u8]> + std::marker::Send,
+    {
+        writer.serialize_utf8(LineData::line(*self)).await?;
+        Ok(())
+    }
+    async fn timestamp<S>(&mut self, writer: &mut S) -> Result<Self::Ok, IngestLineSerializeError>
+    where
+        S: SerializeI64 + std::marker::Send,
+    {
+        writer.serialize_i64(&LineData::timestamp(*self)).await?;
+        Ok(())
+    }
+    fn field_count(&self) -> usize {
+        let line: &D = self;
+        2 + usize::from(line.annotations().is_some())
+            + usize::from(line.app().is_some())
+            + usize::from(line.env().is_some())
+            + usize::from(line.file().is_some())
+            + usize::from(line.host().is_some())
+            + usize::from(line.labels().is_some())
+            + usize::from(line.level().is_some())
+            + usize::from(line.meta().is_some())
+    }
+}
+
 pub struct IngestBytesSerializer {
     pub(crate) ser: Option<IngestLineSerializer>,
+    // written after the entries of the next map, used to flag lines
+    extra_entry: Option<(&'static str, &'static str)>,
 }
 
 impl IngestBytesSerializer {
@@ -164,7 +427,7 @@ impl<'a, I, K, V> SerializeMap<'a, I> for IngestBytesSerializer
 where
     for<'b> &'b I: IntoIterator<Item = (&'b K, &'b V)>,
     I: Send + Sync + 'a,
-    K: Serialize + 'a,
+    K: Serialize + Ord + 'a,
     V: Serialize + 'a,
 {
     type Ok = ();
@@ -176,8 +439,16 @@ where
         // Infallible
         use serde::ser::SerializeMap;
         let mut _ser = self.ser.take().unwrap();
-        let mut ser = _ser.buf.serialize_map(None)?;
-        for (k, v) in bytes.into_iter() {
+        // entries are written in key order so equal maps serialize to equal bytes
+        let mut entries: Vec<_> = bytes.into_iter().collect();
+        entries.sort_unstable_by(|a, b| a.0.cmp(b.0));
+        let extra = self.extra_entry.take();
+        let len = entries.len() + usize::from(extra.is_some());
+        let mut ser = _ser.buf.serialize_map(Some(len))?;
+        for (k, v) in entries {
+            ser.serialize_entry(k, v)?;
+        }
+        if let Some((k, v)) = extra {
             ser.serialize_entry(k, v)?;
         }
         ser.end()?;
@@ -232,14 +503,17 @@ where
 
         fmt.begin_string(&mut wtr)?;
 
-        while bytes.remaining() != 0 {
-            let chunk = bytes.chunk();
-            let chunk_len = chunk.len();
-            utf8::LossyDecoder::new(|s| {
+        {
+            // one decoder for every chunk, so a char split between chunks is kept
+            let mut decoder = utf8::LossyDecoder::new(|s| {
                 format_escaped_str_contents(&mut wtr, &mut fmt, s).expect("Buf write can't fail")
-            })
-            .feed(chunk);
-            bytes.advance(chunk_len)
+            });
+            while bytes.remaining() != 0 {
+                let chunk = bytes.chunk();
+                let chunk_len = chunk.len();
+                decoder.feed(chunk);
+                bytes.advance(chunk_len)
+            }
         }
         fmt.end_string(&mut wtr)?;
 
@@ -248,6 +522,33 @@ where
     }
 }
 
+// Collects the bytes of a line so they can be checked before it is written
+#[derive(Default)]
+struct Utf8Capture {
+    bytes: Vec<u8>,
+}
+
+#[async_trait]
+impl<T> SerializeUtf8<T> for Utf8Capture
+where
+    T: bytes::buf::Buf + Send,
+{
+    type Ok = ();
+
+    async fn serialize_utf8(&mut self, mut bytes: T) -> Result<Self::Ok, IngestLineSerializeError>
+    where
+        T: 'async_trait,
+    {
+        while bytes.remaining() != 0 {
+            let chunk = bytes.chunk();
+            let chunk_len = chunk.len();
+            self.bytes.extend_from_slice(chunk);
+            bytes.advance(chunk_len)
+        }
+        Ok(())
+    }
+}
+
 #[inline]
 fn from_escape_table(escape: u8, byte: u8) -> CharEscape {
     match escape {
@@ -359,8 +660,93 @@ where
     Ok(wtr)
 }
 
+/// The fields of a serialized line
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum LineField {
+    Annotations,
+    App,
+    Env,
+    File,
+    Host,
+    Labels,
+    Level,
+    Meta,
+    Line,
+    Timestamp,
+}
+
+impl LineField {
+    /// The key the field is serialized as by default
+    pub fn default_name(&self) -> &'static str {
+        match self {
+            LineField::Annotations => "annotation",
+            LineField::App => "app",
+            LineField::Env => "env",
+            LineField::File => "file",
+            LineField::Host => "host",
+            LineField::Labels => "label",
+            LineField::Level => "level",
+            LineField::Meta => "meta",
+            LineField::Line => "line",
+            LineField::Timestamp => "timestamp",
+        }
+    }
+}
+
+/// Renames or omits the keys of serialized lines, for ingest endpoints with a different schema
+///
+/// # Example
+///
+/// ```rust
+/// # use logdna_client::serialize::{LineField, SerializationProfile};
+/// let profile = SerializationProfile::new()
+///     .rename(LineField::Labels, "labels")
+///     .rename(LineField::Line, "message")
+///     .omit(LineField::Annotations);
+/// assert_eq!(profile.name(LineField::Line), Some("message"));
+/// assert_eq!(profile.name(LineField::Annotations), None);
+/// assert_eq!(profile.name(LineField::App), Some("app"));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SerializationProfile {
+    overrides: HashMap<LineField, Option<String>>,
+    utf8_policy: Utf8Policy,
+}
+
+impl SerializationProfile {
+    /// Constructs a SerializationProfile using the default key names
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Serialize the field under a different key
+    pub fn rename<T: Into<String>>(mut self, field: LineField, name: T) -> Self {
+        self.overrides.insert(field, Some(name.into()));
+        self
+    }
+    /// Leave the field out of serialized lines
+    pub fn omit(mut self, field: LineField) -> Self {
+        self.overrides.insert(field, None);
+        self
+    }
+    /// Set how invalid utf-8 in the line field is handled, default is lossy
+    ///
+    /// Any other policy reads the line before the other fields are written, so it can be
+    /// rejected or flagged.
+    pub fn utf8_policy(mut self, utf8_policy: Utf8Policy) -> Self {
+        self.utf8_policy = utf8_policy;
+        self
+    }
+    /// The key the field is serialized as, None if it is omitted
+    pub fn name(&self, field: LineField) -> Option<&str> {
+        match self.overrides.get(&field) {
+            Some(name) => name.as_deref(),
+            None => Some(field.default_name()),
+        }
+    }
+}
+
 macro_rules! serialize {
-    ($a:ident, $b:ident, $c:ident, $d:literal, $f:ident) => {
+    ($a:ident, $b:ident, $c:ident, $d:expr, $f:ident) => {
         let mut fmt = serde_json::ser::CompactFormatter {};
 
         let wtr = serde_serialize_key_to_buf(&mut fmt, $a, &mut $f, $d)?;
@@ -387,12 +773,30 @@ impl IngestLineSerializer {
     }
 
     pub fn into_serialize_value(self) -> IngestBytesSerializer {
-        IngestBytesSerializer { ser: Some(self) }
+        IngestBytesSerializer {
+            ser: Some(self),
+            extra_entry: None,
+        }
     }
 
     pub async fn write_line<T, U, I>(
+        self,
+        from: impl IngestLineSerialize<T, U, I>,
+    ) -> Result<IngestBuffer, IngestLineSerializeError>
+    where
+        T: AsRef<str> + std::marker::Send + Sync,
+        U: bytes::buf::Buf + std::marker::Send,
+        I: Send + Sync,
+        for<'a> &'a I: IntoIterator<Item = (&'a String, &'a String)> + std::marker::Send,
+    {
+        self.write_line_with_profile(from, &SerializationProfile::default())
+            .await
+    }
+
+    pub async fn write_line_with_profile<T, U, I>(
         self,
         mut from: impl IngestLineSerialize<T, U, I>,
+        profile: &SerializationProfile,
     ) -> Result<IngestBuffer, IngestLineSerializeError>
     where
         T: AsRef<str> + std::marker::Send + Sync,
@@ -403,75 +807,158 @@ impl IngestLineSerializer {
         let mut fmt = serde_json::ser::CompactFormatter {};
         let mut first = true;
         let mut s_wtr = self.into_inner();
+
+        let mut line = None;
+        let mut flagged = false;
+        if profile.utf8_policy != Utf8Policy::Lossy && profile.name(LineField::Line).is_some() {
+            let mut capture = Utf8Capture::default();
+            from.line(&mut capture).await?;
+            let (decoded, replaced) = profile.utf8_policy.decode(capture.bytes)?;
+            line = Some(decoded);
+            flagged = replaced;
+        }
+
         fmt.begin_object(&mut s_wtr)?;
 
-        if from.has_annotations() {
-            serialize!(s_wtr, from, annotations, "annotation", first);
+        if let Some(name) = profile.name(LineField::Annotations) {
+            if from.has_annotations() {
+                serialize!(s_wtr, from, annotations, name, first);
+            }
         }
 
-        if from.has_app() {
-            serialize!(s_wtr, from, app, "app", first);
+        if let Some(name) = profile.name(LineField::App) {
+            if from.has_app() {
+                serialize!(s_wtr, from, app, name, first);
+            }
         }
 
-        if from.has_env() {
-            serialize!(s_wtr, from, env, "env", first);
+        if let Some(name) = profile.name(LineField::Env) {
+            if from.has_env() {
+                serialize!(s_wtr, from, env, name, first);
+            }
         }
 
-        if from.has_file() {
-            serialize!(s_wtr, from, file, "file", first);
+        if let Some(name) = profile.name(LineField::File) {
+            if from.has_file() {
+                serialize!(s_wtr, from, file, name, first);
+            }
         }
 
-        if from.has_host() {
-            serialize!(s_wtr, from, host, "host", first);
+        if let Some(name) = profile.name(LineField::Host) {
+            if from.has_host() {
+                serialize!(s_wtr, from, host, name, first);
+            }
         }
 
-        if from.has_labels() {
-            serialize!(s_wtr, from, labels, "label", first);
+        if let Some(name) = profile.name(LineField::Labels) {
+            if from.has_labels() {
+                let wtr = serde_serialize_key_to_buf(&mut fmt, s_wtr, &mut first, name)?;
+                let mut ser = IngestLineSerializer::from_buffer(wtr).into_serialize_value();
+                if flagged {
+                    ser.extra_entry = Some((INVALID_UTF8_LABEL, "true"));
+                }
+                from.labels(&mut ser).await?;
+                s_wtr = ser.into_buffer().unwrap();
+                fmt.end_object_value(&mut s_wtr)?;
+            } else if flagged {
+                let mut wtr = serde_serialize_key_to_buf(&mut fmt, s_wtr, &mut first, name)?;
+                let flag: HashMap<&str, &str> =
+                    vec![(INVALID_UTF8_LABEL, "true")].into_iter().collect();
+                serde_json::to_writer(&mut wtr, &flag)?;
+                fmt.end_object_value(&mut wtr)?;
+                s_wtr = wtr;
+            }
         }
 
-        if from.has_level() {
-            serialize!(s_wtr, from, level, "level", first);
+        if let Some(name) = profile.name(LineField::Level) {
+            if from.has_level() {
+                serialize!(s_wtr, from, level, name, first);
+            }
         }
 
-        if from.has_meta() {
-            serialize!(s_wtr, from, meta, "meta", first);
+        if let Some(name) = profile.name(LineField::Meta) {
+            if from.has_meta() {
+                serialize!(s_wtr, from, meta, name, first);
+            }
         }
 
-        serialize!(s_wtr, from, line, "line", first);
-        serialize!(s_wtr, from, timestamp, "timestamp", first);
+        if let Some(name) = profile.name(LineField::Line) {
+            match line {
+                Some(line) => {
+                    let mut wtr = serde_serialize_key_to_buf(&mut fmt, s_wtr, &mut first, name)?;
+                    serde_json::to_writer(&mut wtr, &line)?;
+                    fmt.end_object_value(&mut wtr)?;
+                    s_wtr = wtr;
+                }
+                None => {
+                    serialize!(s_wtr, from, line, name, first);
+                }
+            }
+        }
+        if let Some(name) = profile.name(LineField::Timestamp) {
+            serialize!(s_wtr, from, timestamp, name, first);
+        }
 
         fmt.end_object(&mut s_wtr)?;
         Ok(s_wtr)
     }
 }
 
+// The `]}` written by IngestBodySerializer::end
+const BODY_SUFFIX_LEN: usize = 2;
+
+// Writes the `{"lines":` IngestBodySerializer puts before the array of lines
+fn write_body_prefix<W: ?Sized + io::Write>(
+    fmt: &mut serde_json::ser::CompactFormatter,
+    buf: &mut W,
+) -> io::Result<()> {
+    fmt.begin_object(buf)?;
+
+    fmt.begin_object_key(buf, true)?;
+    fmt.begin_string(buf)?;
+    fmt.write_string_fragment(buf, "lines")?;
+    fmt.end_string(buf)?;
+    fmt.end_object_key(buf)?;
+
+    fmt.begin_object_value(buf)
+}
+
+/// The bytes a serialized body starts with, before the array of lines
+#[cfg(feature = "client")]
+pub(crate) fn body_prefix() -> Vec<u8> {
+    let mut prefix = Vec::new();
+    write_body_prefix(&mut serde_json::ser::CompactFormatter {}, &mut prefix)
+        .expect("Vec write can't fail");
+    prefix
+}
+
 pub struct IngestBodySerializer {
     pub(crate) buf: Option<IngestBuffer>,
     count: usize,
     first: bool,
+    profile: SerializationProfile,
 }
 
 impl IngestBodySerializer {
     pub fn from_buffer(mut buf: IngestBuffer) -> Result<Self, IngestLineSerializeError> {
         let mut fmt = serde_json::ser::CompactFormatter {};
-        fmt.begin_object(&mut buf)?;
-
-        fmt.begin_object_key(&mut buf, true)?;
-        fmt.begin_string(&mut buf)?;
-        fmt.write_string_fragment(&mut buf, "lines")?;
-        fmt.end_string(&mut buf)?;
-        fmt.end_object_key(&mut buf)?;
-
-        fmt.begin_object_value(&mut buf)?;
+        write_body_prefix(&mut fmt, &mut buf)?;
         fmt.begin_array(&mut buf)?;
 
         Ok(Self {
             buf: Some(buf),
             first: true,
             count: 0,
+            profile: SerializationProfile::default(),
         })
     }
 
+    /// Set the profile used to name the fields of every line written after this call
+    pub fn with_profile(mut self, profile: SerializationProfile) -> Self {
+        self.profile = profile;
+        self
+    }
+
     pub async fn write_line<T, U, I>(
         &mut self,
         from: impl IngestLineSerialize<T, U, I>,
@@ -489,7 +976,7 @@ impl IngestBodySerializer {
         fmt.begin_array_value(&mut buf, self.first)?;
         self.first = false;
         let ser = IngestLineSerializer::from_buffer(buf);
-        let mut buf = ser.write_line(from).await?;
+        let mut buf = ser.write_line_with_profile(from, &self.profile).await?;
         fmt.end_array_value(&mut buf)?;
         self.buf = Some(buf);
         self.count += 1;
@@ -507,6 +994,17 @@ impl IngestBodySerializer {
         Ok(wtr)
     }
 
+    /// Discards the partly written body, returning its buffer cleared for reuse
+    ///
+    /// The buffer keeps its pooled segments, pass it back to
+    /// [`from_buffer`](Self::from_buffer) to start a new body.
+    pub fn abort(mut self) -> IngestBuffer {
+        // Infallible
+        let mut buf = self.buf.take().unwrap();
+        buf.clear();
+        buf
+    }
+
     pub fn count(&self) -> usize {
         self.count
     }
@@ -514,6 +1012,138 @@ impl IngestBodySerializer {
     pub fn bytes_len(&self) -> usize {
         self.buf.as_ref().map(|b| b.len()).unwrap_or(0)
     }
+
+    /// The number of lines written so far
+    pub fn line_count(&self) -> usize {
+        self.count
+    }
+
+    /// The size of the body [`end`](Self::end) would return now, including the closing `]}`
+    ///
+    /// Lets batching layers end a body on its serialized size rather than the length of its
+    /// input strings.
+    pub fn bytes_written(&self) -> usize {
+        self.bytes_len() + BODY_SUFFIX_LEN
+    }
+}
+
+/// A stream of empty buffers configured by `builder`, all pulling segments from one pool
+///
+/// The builder's max capacity, reserve, allocator and watermarks apply to every buffer, so
+/// their backpressure covers everything serialized from the stream.
+///
+/// # Example
+///
+/// ```rust
+/// # use logdna_client::serialize::{buffer_source, SegmentedPoolBufBuilder};
+/// let buffers = buffer_source(
+///     SegmentedPoolBufBuilder::new()
+///         .segment_size(16 * 1024)
+///         .initial_capacity(64 * 1024)
+///         .max_capacity(Some(2 * 1024 * 1024))
+///         .max_reserve(Some(256)),
+/// );
+/// ```
+pub fn buffer_source(
+    builder: SegmentedPoolBufBuilder,
+) -> impl futures::stream::Stream<Item = IngestBuffer> {
+    let pool = builder.pool();
+    futures::stream::unfold((builder, pool), |(builder, pool)| async move {
+        let buf = builder.clone().with_pool(pool.clone());
+        Some((buf, (builder, pool)))
+    })
+}
+
+/// Like [`line_serializer_source`], with the buffers configured by `builder`, see [`buffer_source`]
+pub fn line_serializer_source_with(
+    builder: SegmentedPoolBufBuilder,
+) -> impl futures::stream::Stream<Item = IngestLineSerializer> {
+    buffer_source(builder).map(|buf| IngestLineSerializer {
+        buf: serde_json::Serializer::new(buf),
+    })
+}
+
+/// Like [`body_serializer_source`], with the buffers configured by `builder`, see [`buffer_source`]
+pub fn body_serializer_source_with(
+    builder: SegmentedPoolBufBuilder,
+) -> impl futures::stream::Stream<Item = Result<IngestBodySerializer, IngestLineSerializeError>> {
+    buffer_source(builder).map(IngestBodySerializer::from_buffer)
+}
+
+/// Serializes a stream of lines into bodies of about `max_bytes` or `max_lines`
+///
+/// A body is closed, and the next one started, as soon as the line just written takes it to
+/// `max_bytes` serialized bytes or `max_lines` lines, so a body can exceed `max_bytes` by up to
+/// one line. The last body holds whatever lines are left when `lines` ends. A line that fails to
+/// serialize fails the body it was written to, the stream carries on with the next body.
+///
+/// Buffers are configured by `builder` and share one pool, see [`buffer_source`].
+///
+/// # Example
+///
+/// ```rust
+/// # use futures::StreamExt;
+/// # use logdna_client::body::Line;
+/// # use logdna_client::serialize::{body_stream, SegmentedPoolBufBuilder};
+/// # tokio_test::block_on(async {
+/// let lines: Vec<Line> = (0..10)
+///     .map(|i| Line::builder().line(format!("line {}", i)).build().unwrap())
+///     .collect();
+/// let bodies = body_stream(
+///     futures::stream::iter(lines.iter()),
+///     SegmentedPoolBufBuilder::new(),
+///     2 * 1024 * 1024,
+///     4,
+/// );
+/// assert_eq!(bodies.count().await, 3);
+/// # })
+/// ```
+pub fn body_stream<S, L, T, U, I>(
+    lines: S,
+    builder: SegmentedPoolBufBuilder,
+    max_bytes: usize,
+    max_lines: usize,
+) -> impl futures::stream::Stream<Item = Result<IngestBodyBuffer, IngestLineSerializeError>>
+where
+    S: futures::stream::Stream<Item = L>,
+    L: IngestLineSerialize<T, U, I> + std::marker::Send,
+    T: AsRef<str> + std::marker::Send + Sync,
+    U: bytes::buf::Buf + std::marker::Send,
+    for<'a> &'a I: IntoIterator<Item = (&'a String, &'a String)> + std::marker::Send,
+    I: Send + Sync,
+{
+    let max_lines = max_lines.max(1);
+    let state = (Box::pin(lines), Box::pin(buffer_source(builder)), false);
+    futures::stream::unfold(state, move |(mut lines, mut buffers, done)| async move {
+        if done {
+            return None;
+        }
+        let mut body: Option<IngestBodySerializer> = None;
+        // stays true unless the body fills up before the lines run out
+        let mut done = true;
+        while let Some(line) = lines.next().await {
+            let se = match body.as_mut() {
+                Some(se) => se,
+                None => match IngestBodySerializer::from_buffer(buffers.next().await?) {
+                    Ok(se) => body.insert(se),
+                    Err(e) => return Some((Err(e), (lines, buffers, false))),
+                },
+            };
+            if let Err(e) = se.write_line(line).await {
+                return Some((Err(e), (lines, buffers, false)));
+            }
+            if se.line_count() >= max_lines || se.bytes_written() >= max_bytes {
+                done = false;
+                break;
+            }
+        }
+        let se = body?;
+        let line_count = se.line_count();
+        let body = se
+            .end()
+            .map(|buf| IngestBodyBuffer::from_buffer(buf).with_line_count(line_count));
+        Some((body, (lines, buffers, done)))
+    })
 }
 
 pub fn line_serializer_source(
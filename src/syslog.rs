@@ -0,0 +1,293 @@
+use serde_json::{Map, Value};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::body::Line;
+use crate::error::SyslogError;
+use crate::level::severity_level;
+
+const NIL: &str = "-";
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Parses an RFC 5424 or RFC 3164 syslog message into a Line
+///
+/// The severity becomes the level, APP-NAME (or the 3164 TAG) the app and HOSTNAME the host.
+/// The facility, PROCID, MSGID and structured data are stored in meta.
+///
+/// RFC 3164 timestamps carry no year or zone, so those lines keep the time they were parsed at.
+///
+/// # Example
+///
+/// ```rust
+/// # use logdna_client::syslog;
+/// let line = syslog::parse(
+///     r#"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 [exampleSDID@32473 iut="3"] An application event"#,
+/// ).expect("syslog::parse()");
+/// assert_eq!(line.level.as_deref(), Some("NOTICE"));
+/// assert_eq!(line.app.as_deref(), Some("evntslog"));
+/// assert_eq!(line.line, "An application event");
+/// ```
+pub fn parse(input: &str) -> Result<Line, SyslogError> {
+    let (pri, rest) = parse_pri(input)?;
+    match rest.strip_prefix("1 ") {
+        Some(rest) => parse_5424(pri, rest),
+        None => parse_3164(pri, rest),
+    }
+}
+
+fn parse_pri(input: &str) -> Result<(u8, &str), SyslogError> {
+    let rest = input
+        .strip_prefix('<')
+        .ok_or(SyslogError::Invalid("missing PRI"))?;
+    let end = rest
+        .find('>')
+        .filter(|end| (1..=3).contains(end))
+        .ok_or(SyslogError::Invalid("malformed PRI"))?;
+    let pri = rest[..end]
+        .parse::<u8>()
+        .ok()
+        .filter(|pri| *pri <= 191)
+        .ok_or(SyslogError::Invalid("malformed PRI"))?;
+    Ok((pri, &rest[end + 1..]))
+}
+
+fn new_meta(pri: u8) -> Map<String, Value> {
+    let mut meta = Map::new();
+    meta.insert("facility".into(), Value::from(pri >> 3));
+    meta
+}
+
+fn parse_5424(pri: u8, input: &str) -> Result<Line, SyslogError> {
+    let mut fields = input.splitn(6, ' ');
+    let mut next = || {
+        fields
+            .next()
+            .ok_or(SyslogError::Invalid("missing header field"))
+    };
+    let timestamp = next()?;
+    let hostname = next()?;
+    let app = next()?;
+    let procid = next()?;
+    let msgid = next()?;
+    let rest = fields.next().unwrap_or("");
+
+    let (structured_data, message) = parse_structured_data(rest)?;
+    let message = message.strip_prefix('\u{feff}').unwrap_or(message);
+
+    let mut meta = new_meta(pri);
+    if procid != NIL {
+        meta.insert("procid".into(), Value::from(procid));
+    }
+    if msgid != NIL {
+        meta.insert("msgid".into(), Value::from(msgid));
+    }
+    if let Some(structured_data) = structured_data {
+        meta.insert("structured_data".into(), Value::Object(structured_data));
+    }
+
+    let mut builder = Line::builder()
+        .line(message)
+        .level(severity_level(pri & 7))
+        .meta(Value::Object(meta));
+    if hostname != NIL {
+        builder = builder.host(hostname);
+    }
+    if app != NIL {
+        builder = builder.app(app);
+    }
+    let mut line = builder.build()?;
+    if timestamp != NIL {
+        line.timestamp = OffsetDateTime::parse(timestamp, &Rfc3339)
+            .map_err(|_| SyslogError::Invalid("malformed TIMESTAMP"))?
+            .unix_timestamp();
+    }
+    Ok(line)
+}
+
+// Returns the structured data as {SD-ID: {PARAM-NAME: PARAM-VALUE}} and the remaining message
+fn parse_structured_data(input: &str) -> Result<(Option<Map<String, Value>>, &str), SyslogError> {
+    if let Some(rest) = input.strip_prefix(NIL) {
+        return Ok((None, rest.strip_prefix(' ').unwrap_or(rest)));
+    }
+    if !input.starts_with('[') {
+        return Err(SyslogError::Invalid("malformed STRUCTURED-DATA"));
+    }
+
+    let mut elements = Map::new();
+    let mut rest = input;
+    while let Some(element) = rest.strip_prefix('[') {
+        let id_end = element
+            .find(|c: char| c == ' ' || c == ']')
+            .ok_or(SyslogError::Invalid("unterminated SD-ELEMENT"))?;
+        let id = &element[..id_end];
+        let mut params = Map::new();
+        rest = &element[id_end..];
+        loop {
+            if let Some(after) = rest.strip_prefix(']') {
+                rest = after;
+                break;
+            }
+            let param = rest
+                .strip_prefix(' ')
+                .ok_or(SyslogError::Invalid("malformed SD-PARAM"))?;
+            let name_end = param
+                .find('=')
+                .ok_or(SyslogError::Invalid("malformed SD-PARAM"))?;
+            let name = &param[..name_end];
+            let value = param[name_end + 1..]
+                .strip_prefix('"')
+                .ok_or(SyslogError::Invalid("malformed SD-PARAM"))?;
+            let (value, after) = parse_param_value(value)?;
+            params.insert(name.to_string(), Value::from(value));
+            rest = after;
+        }
+        elements.insert(id.to_string(), Value::Object(params));
+    }
+    Ok((Some(elements), rest.strip_prefix(' ').unwrap_or(rest)))
+}
+
+// Reads an escaped PARAM-VALUE up to its closing quote
+fn parse_param_value(input: &str) -> Result<(String, &str), SyslogError> {
+    let mut value = String::new();
+    let mut chars = input.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((value, &input[i + 1..])),
+            '\\' => match chars.next() {
+                Some((_, c)) if c == '"' || c == '\\' || c == ']' => value.push(c),
+                Some((_, c)) => {
+                    value.push('\\');
+                    value.push(c);
+                }
+                None => break,
+            },
+            c => value.push(c),
+        }
+    }
+    Err(SyslogError::Invalid("unterminated PARAM-VALUE"))
+}
+
+fn parse_3164(pri: u8, input: &str) -> Result<Line, SyslogError> {
+    let mut meta = new_meta(pri);
+    let mut builder = Line::builder().level(severity_level(pri & 7));
+
+    // TIMESTAMP is fixed width, e.g `Oct 11 22:14:15 `
+    let rest = match input.get(..16) {
+        Some(timestamp)
+            if MONTHS.iter().any(|month| timestamp.starts_with(month))
+                && timestamp.ends_with(' ') =>
+        {
+            let rest = &input[16..];
+            match rest.split_once(' ') {
+                Some((hostname, rest)) => {
+                    builder = builder.host(hostname);
+                    rest
+                }
+                None => rest,
+            }
+        }
+        _ => input,
+    };
+
+    // TAG is alphanumeric and terminated by `[`, `:` or a space
+    let tag_end = rest
+        .find(|c: char| {
+            !(c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' || c == '/')
+        })
+        .unwrap_or(rest.len());
+    let message = match rest[tag_end..].chars().next() {
+        Some('[') if tag_end > 0 => {
+            let after = &rest[tag_end + 1..];
+            match after.split_once(']') {
+                Some((procid, after)) => {
+                    builder = builder.app(&rest[..tag_end]);
+                    meta.insert("procid".into(), Value::from(procid));
+                    after.strip_prefix(':').unwrap_or(after)
+                }
+                None => rest,
+            }
+        }
+        Some(':') if tag_end > 0 => {
+            builder = builder.app(&rest[..tag_end]);
+            &rest[tag_end + 1..]
+        }
+        _ => rest,
+    };
+
+    Ok(builder
+        .line(message.strip_prefix(' ').unwrap_or(message))
+        .meta(Value::Object(meta))
+        .build()?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_rfc5424() {
+        let line = parse(
+            r#"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog 1234 ID47 [exampleSDID@32473 iut="3" eventSource="App\"lication"][examplePriority@32473 class="high"] An application event"#,
+        )
+        .unwrap();
+        assert_eq!(line.level.as_deref(), Some("NOTICE"));
+        assert_eq!(line.host.as_deref(), Some("mymachine.example.com"));
+        assert_eq!(line.app.as_deref(), Some("evntslog"));
+        assert_eq!(line.line, "An application event");
+        assert_eq!(line.timestamp, 1065910455);
+        assert_eq!(
+            line.meta.unwrap(),
+            serde_json::json!({
+                "facility": 20,
+                "procid": "1234",
+                "msgid": "ID47",
+                "structured_data": {
+                    "exampleSDID@32473": {"iut": "3", "eventSource": "App\"lication"},
+                    "examplePriority@32473": {"class": "high"}
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn parse_rfc5424_nil_fields() {
+        let line = parse("<34>1 - - - - - - 'su root' failed").unwrap();
+        assert_eq!(line.level.as_deref(), Some("CRITICAL"));
+        assert_eq!(line.host, None);
+        assert_eq!(line.app, None);
+        assert_eq!(line.line, "'su root' failed");
+        assert_eq!(line.meta.unwrap(), serde_json::json!({"facility": 4}));
+    }
+
+    #[test]
+    fn parse_rfc3164() {
+        let line =
+            parse("<34>Oct 11 22:14:15 mymachine su[123]: 'su root' failed for lonvick").unwrap();
+        assert_eq!(line.level.as_deref(), Some("CRITICAL"));
+        assert_eq!(line.host.as_deref(), Some("mymachine"));
+        assert_eq!(line.app.as_deref(), Some("su"));
+        assert_eq!(line.line, "'su root' failed for lonvick");
+        assert_eq!(
+            line.meta.unwrap(),
+            serde_json::json!({"facility": 4, "procid": "123"})
+        );
+    }
+
+    #[test]
+    fn parse_rfc3164_without_header() {
+        let line = parse("<13>just a message").unwrap();
+        assert_eq!(line.level.as_deref(), Some("NOTICE"));
+        assert_eq!(line.app, None);
+        assert_eq!(line.line, "just a message");
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(parse("no pri").is_err());
+        assert!(parse("<999>1 - - - - - -").is_err());
+        assert!(parse("<13>1 - - - - - [unterminated").is_err());
+    }
+}
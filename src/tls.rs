@@ -0,0 +1,314 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "dangerous-tls")]
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use hyper_rustls::ConfigBuilderExt;
+use rustls::client::ClientConfig;
+#[cfg(feature = "dangerous-tls")]
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::{Certificate, ConfigBuilder, PrivateKey, RootCertStore, WantsClientCert};
+
+use crate::error::ClientError;
+
+/// PEM files with a private CA bundle and/or a client certificate for mutual TLS
+///
+/// The files are read when the client is built and again whenever one of them changes, see
+/// [`ClientBuilder::tls_reload_interval`](crate::client::ClientBuilder::tls_reload_interval).
+///
+/// # Example
+///
+/// ```rust
+/// # use logdna_client::tls::TlsFiles;
+/// let files = TlsFiles::new()
+///     .ca_bundle("/etc/logdna/ca.pem")
+///     .client_auth("/etc/logdna/client.pem", "/etc/logdna/client.key");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TlsFiles {
+    ca_bundle: Option<PathBuf>,
+    client_auth: Option<(PathBuf, PathBuf)>,
+}
+
+impl TlsFiles {
+    /// Constructs TlsFiles validating against the system roots without a client certificate
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Validate the server against the certificates in this bundle instead of the system roots
+    pub fn ca_bundle<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.ca_bundle = Some(path.into());
+        self
+    }
+    /// Authenticate with the certificate chain and private key in these files
+    pub fn client_auth<C, K>(mut self, cert_chain: C, key: K) -> Self
+    where
+        C: Into<PathBuf>,
+        K: Into<PathBuf>,
+    {
+        self.client_auth = Some((cert_chain.into(), key.into()));
+        self
+    }
+
+    fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.ca_bundle.iter().chain(
+            self.client_auth
+                .iter()
+                .flat_map(|(cert_chain, key)| vec![cert_chain, key]),
+        )
+    }
+
+    // The latest modification time of the files, None if none could be read
+    fn modified(&self) -> Option<SystemTime> {
+        self.paths()
+            .filter_map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+            .max()
+    }
+}
+
+fn read_pem(path: &Path) -> Result<Vec<rustls_pemfile::Item>, ClientError> {
+    let file = File::open(path).map_err(|e| ClientError::TlsFile(path.to_owned(), e))?;
+    rustls_pemfile::read_all(&mut BufReader::new(file))
+        .map_err(|e| ClientError::TlsFile(path.to_owned(), e))
+}
+
+fn load_certs(path: &Path) -> Result<Vec<Certificate>, ClientError> {
+    Ok(read_pem(path)?
+        .into_iter()
+        .filter_map(|item| match item {
+            rustls_pemfile::Item::X509Certificate(der) => Some(Certificate(der)),
+            _ => None,
+        })
+        .collect())
+}
+
+fn load_key(path: &Path) -> Result<PrivateKey, ClientError> {
+    read_pem(path)?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(der)
+            | rustls_pemfile::Item::RSAKey(der)
+            | rustls_pemfile::Item::ECKey(der) => Some(PrivateKey(der)),
+            _ => None,
+        })
+        .ok_or_else(|| ClientError::NoPrivateKey(path.to_owned()))
+}
+
+/// The HTTP versions offered to the ingest host during the TLS handshake (ALPN)
+///
+/// The server picks among the offered versions, `h2` is offered first when both are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AlpnProtocols {
+    /// Offer `h2` and `http/1.1`
+    #[default]
+    Http1AndHttp2,
+    /// Offer only `http/1.1`, e.g for gateways with a broken HTTP/2 implementation
+    Http1Only,
+    /// Offer only `h2`, plain http connections use HTTP/2 with prior knowledge
+    Http2Only,
+}
+
+/// TLS settings collected by the ClientBuilder
+#[derive(Clone, Default)]
+pub(crate) struct TlsOptions {
+    pub(crate) files: Option<TlsFiles>,
+    pub(crate) alpn_protocols: AlpnProtocols,
+    #[cfg(feature = "dangerous-tls")]
+    pub(crate) verifier: Option<Arc<dyn ServerCertVerifier>>,
+}
+
+impl TlsOptions {
+    pub(crate) fn client_config(&self) -> Result<ClientConfig, ClientError> {
+        let files = self.files.clone().unwrap_or_default();
+        let builder = ClientConfig::builder().with_safe_defaults();
+
+        #[cfg(feature = "dangerous-tls")]
+        if let Some(verifier) = self.verifier.as_ref() {
+            let builder = builder.with_custom_certificate_verifier(verifier.clone());
+            return with_client_auth(builder, &files);
+        }
+
+        let builder = match files.ca_bundle.as_ref() {
+            Some(ca_bundle) => {
+                let mut roots = RootCertStore::empty();
+                for cert in load_certs(ca_bundle)? {
+                    roots.add(&cert)?;
+                }
+                builder.with_root_certificates(roots)
+            }
+            None => builder.with_native_roots(),
+        };
+        with_client_auth(builder, &files)
+    }
+}
+
+fn with_client_auth(
+    builder: ConfigBuilder<ClientConfig, WantsClientCert>,
+    files: &TlsFiles,
+) -> Result<ClientConfig, ClientError> {
+    match files.client_auth.as_ref() {
+        Some((cert_chain, key)) => {
+            Ok(builder.with_client_auth_cert(load_certs(cert_chain)?, load_key(key)?)?)
+        }
+        None => Ok(builder.with_no_client_auth()),
+    }
+}
+
+/// Watches the TLS files for changes, checking at most once per interval
+pub(crate) struct TlsReload {
+    files: TlsFiles,
+    interval: Duration,
+    state: Mutex<(Instant, Option<SystemTime>)>,
+}
+
+impl TlsReload {
+    pub(crate) fn new(files: TlsFiles, interval: Duration) -> Self {
+        let modified = files.modified();
+        Self {
+            files,
+            interval,
+            state: Mutex::new((Instant::now(), modified)),
+        }
+    }
+
+    /// Whether the files are due to be checked, without touching the filesystem
+    pub(crate) fn due(&self) -> bool {
+        let state = self.state.lock().expect("tls reload lock poisoned");
+        state.0.elapsed() >= self.interval
+    }
+
+    /// Whether a file changed since the last call that returned true
+    pub(crate) fn changed(&self) -> bool {
+        let mut state = self.state.lock().expect("tls reload lock poisoned");
+        if state.0.elapsed() < self.interval {
+            return false;
+        }
+        state.0 = Instant::now();
+        let modified = self.files.modified();
+        if modified == state.1 {
+            return false;
+        }
+        state.1 = modified;
+        true
+    }
+}
+
+/// Accepts any server certificate
+#[cfg(feature = "dangerous-tls")]
+pub(crate) struct NoVerification;
+
+#[cfg(feature = "dangerous-tls")]
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: std::time::SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// Accepts only a server presenting exactly the pinned DER encoded certificate
+#[cfg(feature = "dangerous-tls")]
+pub(crate) struct PinnedCertificate(pub(crate) Vec<u8>);
+
+#[cfg(feature = "dangerous-tls")]
+impl ServerCertVerifier for PinnedCertificate {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: std::time::SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if end_entity.0 == self.0 {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(
+                "server certificate does not match the pinned certificate".into(),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("logdna-tls-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn reports_unreadable_files() {
+        let options = TlsOptions {
+            files: Some(TlsFiles::new().ca_bundle("/nonexistent/ca.pem")),
+            ..Default::default()
+        };
+        assert!(matches!(
+            options.client_config(),
+            Err(ClientError::TlsFile(..))
+        ));
+
+        let empty = temp_file("empty.pem", "");
+        let options = TlsOptions {
+            files: Some(TlsFiles::new().client_auth(&empty, &empty)),
+            ..Default::default()
+        };
+        assert!(matches!(
+            options.client_config(),
+            Err(ClientError::NoPrivateKey(_))
+        ));
+        std::fs::remove_file(empty).unwrap();
+    }
+
+    #[test]
+    fn detects_changed_files() {
+        let path = temp_file("reload.pem", "first");
+        let reload = TlsReload::new(TlsFiles::new().ca_bundle(&path), Duration::from_secs(0));
+        assert!(!reload.changed());
+
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        assert!(reload.changed());
+        assert!(!reload.changed());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(feature = "dangerous-tls")]
+    fn verify(verifier: &dyn ServerCertVerifier, cert: &[u8]) -> bool {
+        use std::convert::TryFrom;
+
+        verifier
+            .verify_server_cert(
+                &rustls::Certificate(cert.to_vec()),
+                &[],
+                &rustls::ServerName::try_from("logs.logdna.com").unwrap(),
+                &mut std::iter::empty(),
+                &[],
+                std::time::SystemTime::now(),
+            )
+            .is_ok()
+    }
+
+    #[cfg(feature = "dangerous-tls")]
+    #[test]
+    fn pinned_certificate_must_match() {
+        let pinned = PinnedCertificate(vec![1, 2, 3]);
+        assert!(verify(&pinned, &[1, 2, 3]));
+        assert!(!verify(&pinned, &[1, 2, 4]));
+        assert!(verify(&NoVerification, &[9]));
+    }
+}
@@ -0,0 +1,129 @@
+#[cfg(feature = "client")]
+use std::sync::Arc;
+
+/// A W3C trace context propagated as the `traceparent` and `tracestate` headers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    traceparent: String,
+    tracestate: Option<String>,
+}
+
+impl TraceContext {
+    /// Constructs a TraceContext from its trace and span ids, returning None if either is invalid
+    ///
+    /// Ids are lower-case hex, 32 characters for the trace id and 16 for the span id, and must
+    /// not be all zeros.
+    pub fn new(trace_id: &str, span_id: &str, sampled: bool) -> Option<Self> {
+        if !is_id(trace_id, 32) || !is_id(span_id, 16) {
+            return None;
+        }
+        Some(Self {
+            traceparent: format!("00-{}-{}-0{}", trace_id, span_id, sampled as u8),
+            tracestate: None,
+        })
+    }
+    /// Parses a `traceparent` header value, returning None if it is invalid
+    pub fn parse(traceparent: &str) -> Option<Self> {
+        let mut fields = traceparent.split('-');
+        let (version, trace_id, span_id, flags) = (
+            fields.next()?,
+            fields.next()?,
+            fields.next()?,
+            fields.next()?,
+        );
+        if fields.next().is_some()
+            || version != "00"
+            || flags.len() != 2
+            || !flags.bytes().all(|b| b.is_ascii_hexdigit())
+        {
+            return None;
+        }
+        Self::new(trace_id, span_id, false).map(|_| Self {
+            traceparent: traceparent.to_string(),
+            tracestate: None,
+        })
+    }
+    /// Set the vendor specific `tracestate`
+    pub fn tracestate<T: Into<String>>(mut self, tracestate: T) -> Self {
+        self.tracestate = Some(tracestate.into());
+        self
+    }
+    /// The `traceparent` header value
+    pub fn traceparent_header(&self) -> &str {
+        &self.traceparent
+    }
+    /// The `tracestate` header value
+    pub fn tracestate_header(&self) -> Option<&str> {
+        self.tracestate.as_deref()
+    }
+}
+
+fn is_id(id: &str, len: usize) -> bool {
+    id.len() == len
+        && id.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        && id.bytes().any(|b| b != b'0')
+}
+
+/// Supplies the trace context current when an ingest request is built
+///
+/// Implemented for closures, e.g one reading the active OpenTelemetry span context.
+pub trait TraceContextProvider: Send + Sync {
+    /// Returns the current trace context, None if there is no active trace
+    fn current(&self) -> Option<TraceContext>;
+}
+
+impl<F> TraceContextProvider for F
+where
+    F: Fn() -> Option<TraceContext> + Send + Sync,
+{
+    fn current(&self) -> Option<TraceContext> {
+        self()
+    }
+}
+
+#[cfg(feature = "client")]
+pub(crate) type SharedTraceContextProvider = Arc<dyn TraceContextProvider>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn builds_traceparent() {
+        let context =
+            TraceContext::new("4bf92f3577b34da6a3ce929d0e0e4736", "00f067aa0ba902b7", true)
+                .unwrap()
+                .tracestate("congo=t61rcWkgMzE");
+        assert_eq!(
+            context.traceparent_header(),
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+        );
+        assert_eq!(context.tracestate_header(), Some("congo=t61rcWkgMzE"));
+    }
+
+    #[test]
+    fn rejects_invalid_ids() {
+        assert!(
+            TraceContext::new("00000000000000000000000000000000", "00f067aa0ba902b7", true)
+                .is_none()
+        );
+        assert!(
+            TraceContext::new("4BF92F3577B34DA6A3CE929D0E0E4736", "00f067aa0ba902b7", true)
+                .is_none()
+        );
+        assert!(TraceContext::new("4bf92f3577b34da6a3ce929d0e0e4736", "00f067aa", true).is_none());
+    }
+
+    #[test]
+    fn parses_traceparent() {
+        let raw = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00";
+        assert_eq!(TraceContext::parse(raw).unwrap().traceparent_header(), raw);
+        assert!(
+            TraceContext::parse("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00")
+                .is_none()
+        );
+        assert!(
+            TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7").is_none()
+        );
+    }
+}
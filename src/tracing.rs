@@ -0,0 +1,196 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use serde_json::{Map, Value};
+use tracing::field::{Field, Visit};
+use tracing::Event;
+
+use crate::body::{KeyValueMap, LineBuilder};
+
+/// Records the fields of a tracing event or span as json values
+#[derive(Debug, Default)]
+pub struct FieldVisitor(pub Map<String, Value>);
+
+impl Visit for FieldVisitor {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().into(), Value::from(value));
+    }
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().into(), Value::from(value));
+    }
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().into(), Value::from(value));
+    }
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().into(), Value::from(value));
+    }
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().into(), Value::from(format!("{:?}", value)));
+    }
+}
+
+/// The name and recorded fields of a span an event occurred in
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpanFields {
+    pub name: String,
+    pub fields: Map<String, Value>,
+}
+
+/// Decides how the fields of tracing events and their spans are mapped into a Line
+///
+/// The message field becomes the line, label fields become labels and every other field is
+/// stored in meta. Span fields are mapped the same way, from the outermost span inwards, with
+/// the event's own fields winning. The level is the event level, the target and the names of
+/// the enclosing spans are stored in meta under `target` and `spans`.
+///
+/// A tracing Layer can record [`SpanFields`] with a [`FieldVisitor`] when spans are created and
+/// call [`FieldMapping::line_builder`] for every event.
+#[derive(Debug, Clone)]
+pub struct FieldMapping {
+    message_field: String,
+    label_fields: HashSet<String>,
+    ignored_fields: HashSet<String>,
+}
+
+impl FieldMapping {
+    /// Constructs a FieldMapping using `message` as the message field and no label fields
+    pub fn new() -> Self {
+        Self {
+            message_field: "message".into(),
+            label_fields: HashSet::new(),
+            ignored_fields: HashSet::new(),
+        }
+    }
+    /// Set the field used as the line
+    pub fn message_field<T: Into<String>>(mut self, field: T) -> Self {
+        self.message_field = field.into();
+        self
+    }
+    /// Set the fields stored as labels
+    pub fn label_fields<T: Into<String>>(mut self, fields: impl IntoIterator<Item = T>) -> Self {
+        self.label_fields = fields.into_iter().map(Into::into).collect();
+        self
+    }
+    /// Set the fields that are dropped
+    pub fn ignored_fields<T: Into<String>>(mut self, fields: impl IntoIterator<Item = T>) -> Self {
+        self.ignored_fields = fields.into_iter().map(Into::into).collect();
+        self
+    }
+    /// Maps an event and the spans it occurred in, ordered from the root, into a LineBuilder
+    pub fn line_builder(&self, event: &Event<'_>, spans: &[SpanFields]) -> LineBuilder {
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+
+        let mut message = None;
+        let mut labels = KeyValueMap::new();
+        let mut meta = Map::new();
+        let span_fields = spans.iter().flat_map(|span| span.fields.iter());
+        for (key, value) in span_fields.chain(visitor.0.iter()) {
+            if self.ignored_fields.contains(key) {
+                continue;
+            }
+            if *key == self.message_field {
+                message = Some(value_to_string(value));
+            } else if self.label_fields.contains(key) {
+                labels.insert(key.clone(), value_to_string(value));
+            } else {
+                meta.insert(key.clone(), value.clone());
+            }
+        }
+
+        let metadata = event.metadata();
+        meta.insert("target".into(), Value::from(metadata.target()));
+        if !spans.is_empty() {
+            let names = spans.iter().map(|span| Value::from(span.name.as_str()));
+            meta.insert("spans".into(), Value::Array(names.collect()));
+        }
+
+        let mut builder = LineBuilder::new()
+            .line(message.unwrap_or_default())
+            .level(metadata.level().as_str())
+            .meta(Value::Object(meta));
+        if !labels.is_empty() {
+            builder = builder.labels(labels);
+        }
+        builder
+    }
+}
+
+impl Default for FieldMapping {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use tracing::subscriber::with_default;
+    use tracing::{Id, Metadata, Subscriber};
+
+    use super::*;
+    use crate::body::Line;
+
+    // Minimal subscriber mapping every event with a fixed span context
+    struct Capture {
+        mapping: FieldMapping,
+        spans: Vec<SpanFields>,
+        lines: Arc<Mutex<Vec<Line>>>,
+    }
+
+    impl Subscriber for Capture {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, _: &tracing::span::Attributes<'_>) -> Id {
+            Id::from_u64(1)
+        }
+        fn record(&self, _: &Id, _: &tracing::span::Record<'_>) {}
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, event: &Event<'_>) {
+            let line = self.mapping.line_builder(event, &self.spans).build();
+            self.lines.lock().unwrap().push(line.unwrap());
+        }
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn maps_event_fields() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let mut fields = Map::new();
+        fields.insert("tenant".into(), Value::from("acme"));
+        fields.insert("request_id".into(), Value::from(7));
+        let subscriber = Capture {
+            mapping: FieldMapping::new()
+                .label_fields(vec!["tenant"])
+                .ignored_fields(vec!["secret"]),
+            spans: vec![SpanFields {
+                name: "request".into(),
+                fields,
+            }],
+            lines: lines.clone(),
+        };
+        with_default(subscriber, || {
+            tracing::warn!(target: "app", request_id = 8, secret = "x", "slow {}", "query");
+        });
+
+        let line = lines.lock().unwrap().pop().unwrap();
+        assert_eq!(line.line, "slow query");
+        assert_eq!(line.level.as_deref(), Some("WARN"));
+        assert_eq!(line.labels.unwrap()["tenant"], "acme");
+        assert_eq!(
+            line.meta.unwrap(),
+            serde_json::json!({"request_id": 8, "target": "app", "spans": ["request"]})
+        );
+    }
+}
@@ -0,0 +1,419 @@
+use std::io;
+#[cfg(feature = "tokio")]
+use std::pin::Pin;
+#[cfg(feature = "tokio")]
+use std::task::{Context, Poll};
+
+use crate::body::{Line, LineBuilder};
+
+/// A destination for the Lines produced by a [`LineWriter`]
+pub trait LineSink {
+    /// Hands a line over to the sink, failing if the sink was closed
+    fn send_line(&mut self, line: Line) -> io::Result<()>;
+}
+
+impl LineSink for std::sync::mpsc::Sender<Line> {
+    fn send_line(&mut self, line: Line) -> io::Result<()> {
+        self.send(line)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "line receiver dropped"))
+    }
+}
+
+impl LineSink for futures::channel::mpsc::UnboundedSender<Line> {
+    fn send_line(&mut self, line: Line) -> io::Result<()> {
+        self.unbounded_send(line)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "line receiver dropped"))
+    }
+}
+
+/// An [`io::Write`] adapter that splits the bytes written to it into Lines
+///
+/// Every newline terminated chunk (a trailing `\r` is stripped) becomes a Line built from the
+/// template, invalid utf8 is replaced. Empty lines are skipped. An unterminated trailing chunk
+/// is held until the next newline, [`LineWriter::finish`] or drop.
+///
+/// Combined with an unbounded channel a task can batch and send the Lines captured from e.g a
+/// child process' stdout.
+///
+/// # Example
+///
+/// ```rust
+/// # use std::io::Write;
+/// # use logdna_client::body::Line;
+/// # use logdna_client::writer::LineWriter;
+/// let (tx, rx) = std::sync::mpsc::channel();
+/// let mut writer = LineWriter::new(Line::builder().app("child").level("INFO"), tx);
+/// write!(writer, "first\nsecond\n").unwrap();
+///
+/// let lines: Vec<Line> = rx.try_iter().collect();
+/// assert_eq!(lines.len(), 2);
+/// assert_eq!(lines[1].line, "second");
+/// assert_eq!(lines[1].app.as_deref(), Some("child"));
+/// ```
+#[derive(Debug)]
+pub struct LineWriter<S: LineSink> {
+    template: LineBuilder,
+    sink: S,
+    buf: Vec<u8>,
+    max_line_size: usize,
+}
+
+impl<S: LineSink> LineWriter<S> {
+    /// Constructs a LineWriter, the template's line field is replaced for every Line
+    pub fn new(template: LineBuilder, sink: S) -> Self {
+        Self {
+            template,
+            sink,
+            buf: Vec::new(),
+            max_line_size: usize::MAX,
+        }
+    }
+    /// Set the maximum size of a Line, longer chunks are split between characters, default is
+    /// unlimited
+    pub fn max_line_size(mut self, max_line_size: usize) -> Self {
+        self.max_line_size = max_line_size.max(1);
+        self
+    }
+    /// Emits any unterminated trailing chunk
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.emit_remaining()
+    }
+
+    // Sends a chunk as one Line per part, on failure the bytes of the parts already sent are
+    // returned with the error
+    fn emit(&mut self, chunk: &[u8]) -> Result<(), (usize, io::Error)> {
+        let mut rest = chunk.strip_suffix(b"\r").unwrap_or(chunk);
+        let mut sent = 0;
+        while !rest.is_empty() {
+            let (part, next) = rest.split_at(char_boundary(rest, self.max_line_size));
+            let line = self
+                .template
+                .clone()
+                .line(String::from_utf8_lossy(part))
+                .build()
+                .map_err(|e| (sent, io::Error::new(io::ErrorKind::InvalidData, e)))?;
+            self.sink.send_line(line).map_err(|e| (sent, e))?;
+            sent += part.len();
+            rest = next;
+        }
+        Ok(())
+    }
+
+    // Keeps the part of the chunk that wasn't sent, so finishing again doesn't duplicate lines
+    fn emit_remaining(&mut self) -> io::Result<()> {
+        let remaining = std::mem::take(&mut self.buf);
+        self.emit(&remaining).map_err(|(sent, e)| {
+            self.buf = remaining[sent..].to_vec();
+            e
+        })
+    }
+
+    // Emits the held chunk followed by `chunk`. On failure the unsent part of the held chunk is
+    // kept and the bytes of `chunk` in the parts already sent are returned with the error.
+    fn emit_line(&mut self, chunk: &[u8]) -> Result<(), (usize, io::Error)> {
+        if self.buf.is_empty() {
+            return self.emit(chunk);
+        }
+        let mut line = std::mem::take(&mut self.buf);
+        let held = line.len();
+        line.extend_from_slice(chunk);
+        match self.emit(&line) {
+            Ok(()) => Ok(()),
+            Err((sent, e)) => {
+                if sent < held {
+                    line.truncate(held);
+                    line.drain(..sent);
+                    self.buf = line;
+                }
+                Err((sent.saturating_sub(held), e))
+            }
+        }
+    }
+
+    // Emits the parts of the held chunk that can't grow any more, keeping the rest
+    fn emit_full_parts(&mut self) -> io::Result<()> {
+        let held = std::mem::take(&mut self.buf);
+        let mut emitted = 0;
+        let mut result = Ok(());
+        // a part of exactly max_line_size bytes could still end inside a character
+        while held.len() - emitted > self.max_line_size {
+            let at = emitted + char_boundary(&held[emitted..], self.max_line_size);
+            // a single part, nothing was sent if it failed
+            result = self.emit(&held[emitted..at]).map_err(|(_, e)| e);
+            if result.is_err() {
+                break;
+            }
+            emitted = at;
+        }
+        self.buf = held;
+        self.buf.drain(..emitted);
+        result
+    }
+}
+
+// The length of the first part of a chunk split at `max` bytes, moved back to the start of a
+// utf8 character so it isn't split in two. Invalid utf8 is split at `max`.
+fn char_boundary(chunk: &[u8], max: usize) -> usize {
+    if chunk.len() <= max {
+        return chunk.len();
+    }
+    match (1..=max).rev().find(|&i| !is_continuation(chunk[i])) {
+        Some(i) => i,
+        None => max,
+    }
+}
+
+fn is_continuation(byte: u8) -> bool {
+    byte & 0b1100_0000 == 0b1000_0000
+}
+
+impl<S: LineSink> io::Write for LineWriter<S> {
+    /// Emits every newline terminated chunk of `buf`
+    ///
+    /// If the sink fails after some Lines were emitted the bytes they were built from are
+    /// reported as written, including the parts of a chunk split by the maximum line size,
+    /// otherwise the error is returned and nothing was consumed. Retrying the rest of `buf`
+    /// doesn't duplicate lines.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut written = 0;
+        while let Some(i) = buf[written..].iter().position(|b| *b == b'\n') {
+            if let Err((sent, e)) = self.emit_line(&buf[written..written + i]) {
+                written += sent;
+                return if written == 0 { Err(e) } else { Ok(written) };
+            }
+            written += i + 1;
+        }
+        let held = self.buf.len();
+        self.buf.extend_from_slice(&buf[written..]);
+        if let Err(e) = self.emit_full_parts() {
+            if written == 0 && self.buf.len() >= held + buf.len() {
+                self.buf.truncate(held);
+                return Err(e);
+            }
+            // some of the held bytes went out, the rest is kept for the next write
+        }
+        Ok(buf.len())
+    }
+
+    // Unterminated chunks are kept so a flush between writes doesn't split a line
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<S: LineSink> Drop for LineWriter<S> {
+    fn drop(&mut self) {
+        let _ = self.emit_remaining();
+    }
+}
+
+/// A [`tokio::io::AsyncWrite`] adapter with the same line splitting as [`LineWriter`]
+///
+/// Writes never block as the sink is expected to be a channel, shutting down the writer
+/// emits any unterminated trailing chunk.
+///
+/// # Example
+///
+/// ```rust
+/// # use logdna_client::body::Line;
+/// # use logdna_client::writer::AsyncLineWriter;
+/// # tokio_test::block_on(async {
+/// let (tx, rx) = std::sync::mpsc::channel();
+/// let mut writer = AsyncLineWriter::new(Line::builder().app("socket"), tx);
+/// tokio::io::copy(&mut &b"first\nsecond"[..], &mut writer).await.unwrap();
+/// tokio::io::AsyncWriteExt::shutdown(&mut writer).await.unwrap();
+/// assert_eq!(rx.try_iter().count(), 2);
+/// # })
+/// ```
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct AsyncLineWriter<S: LineSink>(LineWriter<S>);
+
+#[cfg(feature = "tokio")]
+impl<S: LineSink> AsyncLineWriter<S> {
+    /// Constructs an AsyncLineWriter, the template's line field is replaced for every Line
+    pub fn new(template: LineBuilder, sink: S) -> Self {
+        Self(LineWriter::new(template, sink))
+    }
+    /// Set the maximum size of a Line, longer chunks are split, default is unlimited
+    pub fn max_line_size(self, max_line_size: usize) -> Self {
+        Self(self.0.max_line_size(max_line_size))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<S: LineSink + Unpin> tokio::io::AsyncWrite for AsyncLineWriter<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(io::Write::write(&mut self.get_mut().0, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(io::Write::flush(&mut self.get_mut().0))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(self.get_mut().0.finish())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+    use std::sync::mpsc::{channel, Receiver};
+
+    use super::*;
+    use crate::body::KeyValueMap;
+
+    fn lines(rx: &Receiver<Line>) -> Vec<String> {
+        rx.try_iter().map(|line| line.line).collect()
+    }
+
+    #[test]
+    fn splits_on_newlines() {
+        let (tx, rx) = channel();
+        let mut writer = LineWriter::new(
+            Line::builder()
+                .level("ERROR")
+                .labels(KeyValueMap::new().add("stream", "stderr")),
+            tx,
+        );
+        writer.write_all(b"one\r\ntw").unwrap();
+        assert_eq!(lines(&rx), vec!["one"]);
+        writer.write_all(b"o\n\nthree").unwrap();
+        assert_eq!(lines(&rx), vec!["two"]);
+        writer.flush().unwrap();
+        assert!(lines(&rx).is_empty());
+
+        writer.finish().unwrap();
+        let line = rx.try_recv().unwrap();
+        assert_eq!(line.line, "three");
+        assert_eq!(line.level.as_deref(), Some("ERROR"));
+        assert_eq!(line.labels.unwrap()["stream"], "stderr");
+    }
+
+    #[test]
+    fn emits_remaining_on_drop() {
+        let (tx, rx) = channel();
+        let mut writer = LineWriter::new(Line::builder(), tx);
+        writer.write_all(b"partial").unwrap();
+        drop(writer);
+        assert_eq!(lines(&rx), vec!["partial"]);
+    }
+
+    #[test]
+    fn respects_max_line_size() {
+        let (tx, rx) = channel();
+        let mut writer = LineWriter::new(Line::builder(), tx).max_line_size(4);
+        writer.write_all(b"abcdefghij\n").unwrap();
+        assert_eq!(lines(&rx), vec!["abcd", "efgh", "ij"]);
+        writer.write_all(b"klmnop").unwrap();
+        assert_eq!(lines(&rx), vec!["klmn"]);
+    }
+
+    #[test]
+    fn splits_on_char_boundaries() {
+        let (tx, rx) = channel();
+        let mut writer = LineWriter::new(Line::builder(), tx).max_line_size(4);
+        writer.write_all("ab\u{20ac}cd\n".as_bytes()).unwrap();
+        assert_eq!(lines(&rx), vec!["ab", "\u{20ac}c", "d"]);
+
+        // the character is completed by the next write
+        let euro = "\u{20ac}".as_bytes();
+        writer.write_all(b"ab").unwrap();
+        writer.write_all(&euro[..2]).unwrap();
+        writer.write_all(&euro[2..]).unwrap();
+        writer.write_all(b"cd\n").unwrap();
+        assert_eq!(lines(&rx), vec!["ab", "\u{20ac}c", "d"]);
+    }
+
+    struct ClosingSink {
+        open_for: usize,
+        lines: Vec<String>,
+    }
+
+    impl LineSink for ClosingSink {
+        fn send_line(&mut self, line: Line) -> io::Result<()> {
+            if self.lines.len() == self.open_for {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            self.lines.push(line.line);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn reports_bytes_written_before_a_failure() {
+        let sink = ClosingSink {
+            open_for: 1,
+            lines: Vec::new(),
+        };
+        let mut writer = LineWriter::new(Line::builder(), sink);
+        assert_eq!(writer.write(b"one\ntwo\nthree").unwrap(), 4);
+        assert_eq!(
+            writer.write(b"two\nthree").unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+        assert!(writer.buf.is_empty());
+        assert_eq!(writer.sink.lines, vec!["one"]);
+    }
+
+    #[test]
+    fn counts_split_parts_sent_before_a_failure() {
+        let sink = ClosingSink {
+            open_for: 1,
+            lines: Vec::new(),
+        };
+        let mut writer = LineWriter::new(Line::builder(), sink).max_line_size(4);
+        assert_eq!(writer.write(b"abcdefgh\nxyz").unwrap(), 4);
+        assert_eq!(
+            writer.write(b"efgh\nxyz").unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+        assert_eq!(writer.sink.lines, vec!["abcd"]);
+
+        // the first part started in the held chunk
+        let sink = ClosingSink {
+            open_for: 1,
+            lines: Vec::new(),
+        };
+        let mut writer = LineWriter::new(Line::builder(), sink).max_line_size(4);
+        assert_eq!(writer.write(b"ab").unwrap(), 2);
+        assert_eq!(writer.write(b"cdefgh\n").unwrap(), 2);
+        assert!(writer.buf.is_empty());
+        assert_eq!(writer.sink.lines, vec!["abcd"]);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn async_writer() {
+        use tokio::io::AsyncWriteExt;
+
+        let (tx, mut rx) = futures::channel::mpsc::unbounded();
+        let mut writer = AsyncLineWriter::new(Line::builder().app("tcp"), tx);
+        writer.write_all(b"one\ntwo\nthr").await.unwrap();
+        writer.write_all(b"ee").await.unwrap();
+        writer.shutdown().await.unwrap();
+
+        let mut received = Vec::new();
+        while let Ok(Some(line)) = rx.try_next() {
+            assert_eq!(line.app.as_deref(), Some("tcp"));
+            received.push(line.line);
+        }
+        assert_eq!(received, vec!["one", "two", "three"]);
+    }
+
+    #[test]
+    fn closed_sink_is_an_error() {
+        let (tx, rx) = channel();
+        drop(rx);
+        let mut writer = LineWriter::new(Line::builder(), tx);
+        assert_eq!(
+            writer.write_all(b"lost\n").unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+    }
+}